@@ -0,0 +1,122 @@
+//! Timing and baseline checks shared by the `harness = false` benches.
+//!
+//! Baselines are stored relative to a fixed calibration workload timed in the
+//! same run, so a slower or faster machine than the one that saved them moves
+//! every figure together and doesn't read as a regression.
+
+use serde_json::{Map, Value};
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const ITERATIONS: usize = 10;
+const TOLERANCE: f64 = 2.0;
+/// Differences smaller than this are timer noise, however large the ratio.
+const NOISE_MICROS: f64 = 100.0;
+
+/// One benchmark's median time, and the characters it handles per run for
+/// its throughput, where that means something.
+pub struct Measurement {
+    pub name: String,
+    pub time: Duration,
+    pub chars: Option<usize>,
+}
+
+impl Measurement {
+    pub fn new(name: String, time: Duration, chars: Option<usize>) -> Self {
+        Measurement { name, time, chars }
+    }
+}
+
+/// The median time of `ITERATIONS` runs of `run`, after `setup` prepares
+/// each run's input outside the timing.
+pub fn median<T>(mut setup: impl FnMut() -> T, mut run: impl FnMut(T)) -> Duration {
+    let mut times: Vec<Duration> = (0..ITERATIONS)
+        .map(|_| {
+            let input = setup();
+            let start = Instant::now();
+            run(input);
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[ITERATIONS / 2]
+}
+
+/// The median time of sorting the same pseudo-random numbers, as a measure
+/// of how fast this machine is right now.
+fn calibration() -> Duration {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let numbers: Vec<u64> = (0..200_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+        .collect();
+    median(
+        || numbers.clone(),
+        |mut numbers| {
+            numbers.sort_unstable();
+            black_box(numbers);
+        },
+    )
+}
+
+fn baseline_path(file: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("benches").join(file)
+}
+
+/// Prints `results` with their throughput and against the baseline in
+/// `benches/<file>`, writing that file from them first when run with
+/// `--save-baseline`, and exits with an error when one takes more than
+/// `TOLERANCE` times as long as its baseline, scaled to this machine.
+pub fn report(file: &str, results: &[Measurement]) {
+    let path = baseline_path(file);
+    let calibration = calibration().as_secs_f64() * 1e6;
+    if std::env::args().any(|arg| arg == "--save-baseline") {
+        let map: Map<String, Value> = results
+            .iter()
+            .map(|result| {
+                let relative = result.time.as_secs_f64() * 1e6 / calibration;
+                (result.name.clone(), Value::from((relative * 1e4).round() / 1e4))
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&Value::Object(map)).unwrap();
+        std::fs::write(&path, json + "\n").unwrap();
+    }
+
+    let baseline: Map<String, Value> = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default();
+    println!("{:<44} {:>10.0} us", "calibration", calibration);
+    let mut regressions = 0;
+    for result in results {
+        let micros = result.time.as_secs_f64() * 1e6;
+        let throughput = match result.chars {
+            Some(chars) => format!("{:>9.2} Mchar/s", chars as f64 / micros),
+            None => String::new(),
+        };
+        let Some(relative) = baseline.get(&result.name).and_then(Value::as_f64) else {
+            println!("{:<44} {:>10.0} us {:>16}", result.name, micros, throughput);
+            continue;
+        };
+        let expected = relative * calibration;
+        let ratio = micros / expected.max(1.0);
+        let regressed = ratio > TOLERANCE && micros - expected > NOISE_MICROS;
+        let flag = if regressed { "  REGRESSION" } else { "" };
+        println!(
+            "{:<44} {:>10.0} us {:>16}  {:>5.2}x baseline{}",
+            result.name, micros, throughput, ratio, flag
+        );
+        if regressed {
+            regressions += 1;
+        }
+    }
+    if regressions > 0 {
+        eprintln!("{} benchmark(s) over {}x their baseline", regressions, TOLERANCE);
+        std::process::exit(1);
+    }
+}
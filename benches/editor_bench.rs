@@ -0,0 +1,108 @@
+//! Timings for typing, word motions and finding search matches in a large
+//! buffer.
+//!
+//! Like `highlight_bench`, a plain `harness = false` bench reporting medians
+//! and throughput: `cargo bench --bench editor_bench` compares them with
+//! `benches/editor_baseline.json`, and `-- --save-baseline` writes that file
+//! again from this run.
+
+mod common;
+
+use cim::buffer::{char_to_byte_offset, RopeTextBuffer};
+use cim::editor::{CimEditor, EditorMode};
+use cim::types::CursorPos;
+use common::{median, Measurement};
+use std::hint::black_box;
+use std::path::Path;
+use std::time::Duration;
+use xi_rope::Rope;
+
+const LINES: usize = 10_000;
+/// Characters typed per run of the insert benchmarks.
+const TYPED: usize = 100;
+/// Words moved over per run of the word motion benchmark.
+const WORDS: usize = 1000;
+
+fn rust_source(lines: usize) -> String {
+    (0..lines)
+        .map(|n| format!("    let value_{n} = compute(\"item {n}\", {n}) + offset; // step {n}\n"))
+        .collect()
+}
+
+/// An editor on `LINES` lines of Rust, highlighted and drawn 50 by 120.
+fn editor() -> CimEditor {
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from(rust_source(LINES)));
+    editor.highlighter.set_syntax_for_file(Some(Path::new("bench.rs")));
+    editor.highlighted_lines = editor.highlighter.highlight(editor.buffer.rope());
+    editor.viewport_height = 50;
+    editor.viewport_width = 120;
+    editor
+}
+
+/// Types `TYPED` characters into one long-lived editor, starting at `row`.
+fn bench_insert(row: usize) -> Duration {
+    let mut editor = editor();
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(4, row as u16);
+    editor.update_viewport();
+    median(
+        || (),
+        |()| {
+            for _ in 0..TYPED {
+                editor.insert_char('x');
+            }
+        },
+    )
+}
+
+/// Moves forward over `WORDS` words from the top of the file, returning the
+/// time with the number of characters moved over.
+fn bench_word_motion() -> (Duration, usize) {
+    let mut editor = editor();
+    let time = median(
+        || (),
+        |()| {
+            editor.cursor_position = CursorPos::new(0, 0);
+            for _ in 0..WORDS {
+                editor.move_cursor_word(1);
+            }
+            black_box(editor.cursor_position);
+        },
+    );
+    let CursorPos { col, row } = editor.cursor_position;
+    let rope = editor.buffer.rope();
+    let end = char_to_byte_offset(rope, row as usize, col as usize);
+    (time, rope.slice_to_cow(0..end).chars().count())
+}
+
+/// Finds every match of `pattern` in the file.
+fn bench_search(pattern: &str) -> Duration {
+    let mut editor = editor();
+    editor.search_query = Some(pattern.to_string());
+    median(
+        || (),
+        |()| {
+            editor.compute_search_matches();
+            black_box(editor.search_matches.len());
+        },
+    )
+}
+
+fn main() {
+    let file_chars = rust_source(LINES).chars().count();
+    let mut results = Vec::new();
+    for (position, row) in [("top", 0), ("middle", LINES / 2), ("bottom", LINES - 1)] {
+        let name = format!("insert_char_{}/rust_{}", position, LINES);
+        results.push(Measurement::new(name, bench_insert(row), Some(TYPED)));
+    }
+    let (time, chars) = bench_word_motion();
+    let name = format!("move_cursor_word/{}_words", WORDS);
+    results.push(Measurement::new(name, time, Some(chars)));
+    for (name, pattern) in [("literal", "offset"), ("regex", r"value_\d*7\b")] {
+        let name = format!("compute_search_matches_{}/rust_{}", name, LINES);
+        results.push(Measurement::new(name, bench_search(pattern), Some(file_chars)));
+    }
+
+    common::report("editor_baseline.json", &results);
+}
@@ -0,0 +1,217 @@
+//! Timings for syntax highlighting, for building the visible text and for
+//! reading the buffer line by line.
+//!
+//! criterion isn't part of the build, so this is a plain `harness = false`
+//! bench: each case runs for a fixed number of iterations and reports the
+//! median and, where it means something, the throughput in characters per
+//! second. `cargo bench --bench highlight_bench` compares the medians with
+//! `benches/baseline.json` and fails when one is more than twice its
+//! baseline, by more than timer noise; `-- --save-baseline` writes the file
+//! again from this run.
+
+mod common;
+
+use cim::buffer::{RopeLineIter, RopeTextBuffer};
+use cim::editor::CimEditor;
+use cim::highlight::Highlighter;
+use cim::types::CursorPos;
+use cim::ui::build_highlighted_text;
+use common::{median, Measurement};
+use std::hint::black_box;
+use std::path::Path;
+use std::time::Duration;
+use xi_rope::{LinesMetric, Rope};
+
+struct Sample {
+    name: &'static str,
+    path: &'static str,
+    text: String,
+}
+
+fn rust_source(lines: usize) -> String {
+    let mut text = String::from("use std::collections::HashMap;\n\n");
+    let mut n = 0;
+    while text.lines().count() < lines {
+        text.push_str(&format!(
+            "/// Adds up the values in `map` under keys starting with {n}.\n\
+             pub fn sum_{n}(map: &HashMap<String, i64>) -> i64 {{\n\
+             \x20   let prefix = \"{n}\"; // the key prefix\n\
+             \x20   map.iter()\n\
+             \x20       .filter(|(key, _)| key.starts_with(prefix))\n\
+             \x20       .map(|(_, value)| *value * 0x{n:x})\n\
+             \x20       .sum()\n\
+             }}\n\n"
+        ));
+        n += 1;
+    }
+    text.lines().take(lines).map(|line| format!("{}\n", line)).collect()
+}
+
+fn cpp_source(lines: usize) -> String {
+    let mut text = String::from("#include <string>\n#include <vector>\n\n");
+    let mut n = 0;
+    while text.lines().count() < lines {
+        text.push_str(&format!(
+            "/* A counter that starts at {n}. */\n\
+             class Counter{n} {{\n\
+             public:\n\
+             \x20   explicit Counter{n}(int start = {n}) : value_(start) {{}}\n\
+             \x20   std::string name() const {{ return \"counter {n}\"; }}\n\
+             \x20   int next() {{ return ++value_; }} // pre-increment\n\
+             private:\n\
+             \x20   int value_;\n\
+             }};\n\n"
+        ));
+        n += 1;
+    }
+    text.lines().take(lines).map(|line| format!("{}\n", line)).collect()
+}
+
+fn plain_text(lines: usize) -> String {
+    (0..lines)
+        .map(|n| format!("Line {} of plain text, with nothing in it to highlight.\n", n))
+        .collect()
+}
+
+fn samples() -> Vec<Sample> {
+    vec![
+        Sample { name: "rust_1000", path: "bench.rs", text: rust_source(1000) },
+        Sample { name: "cpp_5000", path: "bench.cpp", text: cpp_source(5000) },
+        Sample { name: "text_10000", path: "bench.txt", text: plain_text(10000) },
+    ]
+}
+
+/// A new `Highlighter` per run, highlighting the whole file.
+fn bench_cold(sample: &Sample) -> Duration {
+    let rope = Rope::from(sample.text.as_str());
+    median(
+        || {
+            let mut highlighter = Highlighter::new();
+            highlighter.set_syntax_for_file(Some(Path::new(sample.path)));
+            highlighter
+        },
+        |mut highlighter| {
+            black_box(highlighter.highlight(&rope));
+        },
+    )
+}
+
+/// One `Highlighter` for every run, bringing the lines up to date after a
+/// character typed in the middle of the file.
+fn bench_warm(sample: &Sample) -> Duration {
+    let mut highlighter = Highlighter::new();
+    highlighter.set_syntax_for_file(Some(Path::new(sample.path)));
+    let mut buffer = RopeTextBuffer::new(Rope::from(sample.text.as_str()));
+    let mut lines = highlighter.highlight(buffer.rope());
+    let middle = buffer.rope().offset_of_line(buffer.line_count() / 2);
+    median(
+        || (),
+        |()| {
+            buffer.edit(middle..middle, "x");
+            let edits = buffer.take_edits();
+            highlighter.highlight_incremental(buffer.rope(), &edits, &mut lines);
+        },
+    )
+}
+
+/// Builds the visible text with the view at `fraction` of the way down.
+fn bench_view(sample: &Sample, fraction: f64) -> Duration {
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from(sample.text.as_str()));
+    editor.highlighter.set_syntax_for_file(Some(Path::new(sample.path)));
+    editor.highlighted_lines = editor.highlighter.highlight(editor.buffer.rope());
+    editor.viewport_height = 50;
+    editor.viewport_width = 120;
+    let last_top = editor.total_lines() - editor.viewport_height;
+    editor.scroll_offset = (last_top as f64 * fraction) as usize;
+    editor.cursor_position = CursorPos::new(0, editor.scroll_offset as u16);
+    median(
+        || (),
+        |()| {
+            black_box(build_highlighted_text(&editor));
+        },
+    )
+}
+
+/// Types a character in the middle of the file and builds the visible text
+/// again, bringing the highlighting up to date with `highlight_incremental`
+/// or highlighting the whole file again.
+fn bench_edit_and_view(sample: &Sample, incremental: bool) -> Duration {
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from(sample.text.as_str()));
+    editor.highlighter.set_syntax_for_file(Some(Path::new(sample.path)));
+    editor.highlighted_lines = editor.highlighter.highlight(editor.buffer.rope());
+    editor.viewport_height = 50;
+    editor.viewport_width = 120;
+    let middle_line = editor.total_lines() / 2;
+    editor.scroll_offset = middle_line - editor.viewport_height / 2;
+    editor.cursor_position = CursorPos::new(0, middle_line as u16);
+    let middle = editor.buffer.rope().offset_of_line(middle_line);
+    median(
+        || (),
+        |()| {
+            editor.buffer.edit(middle..middle, "x");
+            let edits = editor.buffer.take_edits();
+            if incremental {
+                let lines = &mut editor.highlighted_lines;
+                editor.highlighter.highlight_incremental(editor.buffer.rope(), &edits, lines);
+            } else {
+                editor.highlighted_lines = editor.highlighter.highlight(editor.buffer.rope());
+            }
+            black_box(build_highlighted_text(&editor));
+        },
+    )
+}
+
+/// Characters on the 50 lines shown from `fraction` of the way down.
+fn view_chars(sample: &Sample, fraction: f64) -> usize {
+    let lines: Vec<&str> = sample.text.lines().collect();
+    let top = ((lines.len() - 50) as f64 * fraction) as usize;
+    lines[top..top + 50].iter().map(|line| line.chars().count()).sum()
+}
+
+/// Reads every line of the file, into a new `String` each or borrowed through
+/// `iter_lines`.
+fn bench_lines(sample: &Sample, borrowed: bool) -> Duration {
+    let rope = Rope::from(sample.text.as_str());
+    let count = rope.measure::<LinesMetric>() + 1;
+    median(
+        || (),
+        |()| {
+            if borrowed {
+                for line in rope.iter_lines(0, count) {
+                    black_box(line.len());
+                }
+            } else {
+                for line in 0..count {
+                    let range = rope.offset_of_line(line)..rope.offset_of_line(line + 1);
+                    black_box(rope.slice(range).to_string().len());
+                }
+            }
+        },
+    )
+}
+
+fn main() {
+    let mut results = Vec::new();
+    for sample in &samples() {
+        let chars = Some(sample.text.chars().count());
+        let name = |bench: &str| format!("{}/{}", bench, sample.name);
+        results.push(Measurement::new(name("highlight_cold"), bench_cold(sample), chars));
+        results.push(Measurement::new(name("highlight_warm"), bench_warm(sample), None));
+        for (position, fraction) in [("top", 0.0), ("middle", 0.5), ("bottom", 1.0)] {
+            let bench = format!("build_highlighted_text_{}", position);
+            let time = bench_view(sample, fraction);
+            results.push(Measurement::new(name(&bench), time, Some(view_chars(sample, fraction))));
+        }
+        for (kind, incremental) in [("incremental", true), ("full", false)] {
+            let bench = format!("edit_and_view_{}", kind);
+            let time = bench_edit_and_view(sample, incremental);
+            results.push(Measurement::new(name(&bench), time, None));
+        }
+        results.push(Measurement::new(name("lines_to_string"), bench_lines(sample, false), chars));
+        results.push(Measurement::new(name("iter_lines"), bench_lines(sample, true), chars));
+    }
+
+    common::report("baseline.json", &results);
+}
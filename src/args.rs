@@ -1,8 +1,70 @@
 use clap::Parser;
+use std::ffi::OsString;
 use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(version = "0.1", about = "A fast C++ editor like Neovim")]
 pub struct CliArgs {
     pub file_path: Option<PathBuf>,
+
+    /// More files for the argument list, which `:argdo` visits
+    pub more_files: Vec<PathBuf>,
+
+    /// Write the file as syntax-highlighted HTML to <file>.html and exit
+    #[arg(long)]
+    pub export_html: bool,
+
+    /// Open the file read-only
+    #[arg(short = 'R', long)]
+    pub readonly: bool,
+
+    /// Start on line N (also `+N`)
+    #[arg(long, value_name = "N")]
+    pub line: Option<usize>,
+
+    /// Start in column N
+    #[arg(long, value_name = "N")]
+    pub column: Option<usize>,
+
+    /// Run an ex command once the file is loaded (also `+cmd`, or `+/pattern` to search)
+    #[arg(long, value_name = "EX_COMMAND")]
+    pub cmd: Option<String>,
+
+    /// Run a script of ex commands on each file without the UI, then write the
+    /// files it changed
+    #[arg(long)]
+    pub headless: bool,
+
+    /// The script for --headless; read from stdin if not given
+    #[arg(long, value_name = "PATH", requires = "headless")]
+    pub script: Option<PathBuf>,
+
+    /// With --headless, print the changed files instead of writing them
+    #[arg(long, requires = "headless")]
+    pub dry_run: bool,
+
+    /// Log every action the editor dispatches to FILE
+    #[arg(long, value_name = "FILE")]
+    pub debug: Option<PathBuf>,
+}
+
+impl CliArgs {
+    /// Parses the process arguments, accepting vim's `+N`, `+cmd` and `+` (last
+    /// line) as shorthands for `--line` and `--cmd`.
+    pub fn parse_with_plus() -> Self {
+        Self::parse_from(std::env::args_os().enumerate().flat_map(|(i, arg)| {
+            let plus = arg
+                .to_str()
+                .filter(|_| i > 0)
+                .and_then(|arg| arg.strip_prefix('+'));
+            match plus {
+                Some("") => vec![OsString::from("--cmd"), OsString::from("$")],
+                Some(n) if n.bytes().all(|b| b.is_ascii_digit()) => {
+                    vec![OsString::from("--line"), OsString::from(n)]
+                }
+                Some(cmd) => vec![OsString::from("--cmd"), OsString::from(cmd)],
+                None => vec![arg],
+            }
+        }))
+    }
 }
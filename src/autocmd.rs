@@ -0,0 +1,90 @@
+/// When an autocommand runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutocmdEvent {
+    /// A file was opened, matched against its path.
+    BufEnter,
+    /// A file is about to be written, matched against its path.
+    BufWrite,
+    /// A file type was detected, matched against its name, such as `rust`.
+    FileType,
+}
+
+impl AutocmdEvent {
+    pub fn parse(name: &str) -> Option<AutocmdEvent> {
+        match name.to_ascii_lowercase().as_str() {
+            "bufenter" => Some(AutocmdEvent::BufEnter),
+            "bufwrite" | "bufwritepre" => Some(AutocmdEvent::BufWrite),
+            "filetype" => Some(AutocmdEvent::FileType),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            AutocmdEvent::BufEnter => "BufEnter",
+            AutocmdEvent::BufWrite => "BufWrite",
+            AutocmdEvent::FileType => "FileType",
+        }
+    }
+}
+
+/// An ex command run on an event, from `:autocmd` or the `[[autocmd]]` tables
+/// of `config.toml`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Autocmd {
+    pub event: AutocmdEvent,
+    /// Comma-separated globs for the file path, or file type names for
+    /// `FileType`. A glob without `/` is matched against the file name alone.
+    pub pattern: String,
+    pub command: String,
+}
+
+impl Autocmd {
+    /// Whether the autocommand runs for `event` on `name`, a path or file type.
+    pub fn matches(&self, event: AutocmdEvent, name: &str) -> bool {
+        if self.event != event {
+            return false;
+        }
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        self.pattern.split(',').any(|pattern| {
+            let target = if pattern.contains('/') { name } else { file_name };
+            glob_match(pattern, target)
+        })
+    }
+
+    /// The autocommand as `:autocmd` lists it.
+    pub fn summary(&self) -> String {
+        format!("{:<10} {:<12} {}", self.event.name(), self.pattern, self.command)
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` stands for any run of
+/// characters and `?` for any one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // After a `*`, where it was in the pattern and how much of the text it took.
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -1,17 +1,37 @@
-use std::io::{self, Write};
+use std::borrow::Cow;
+use std::io;
+use std::iter;
+use std::ops::Range;
+use std::path::Path;
 use xi_rope::Rope;
 
+/// One edit to the buffer: the byte range it replaced, in the text as it was
+/// before, and the byte length of what replaced it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RopeEdit {
+    pub old_range: Range<usize>,
+    pub new_len: usize,
+}
+
 #[derive(Debug)]
 pub struct RopeTextBuffer {
     rope: Rope,
     modified: bool,
+    /// The text ended with a line break, or was empty, when it was read.
+    had_trailing_newline: bool,
+    /// Edits since the highlighter last took them, oldest first.
+    edits: Vec<RopeEdit>,
 }
 
 impl RopeTextBuffer {
     pub fn new(rope: Rope) -> Self {
+        let len = rope.len();
+        let had_trailing_newline = len == 0 || rope.byte_at(len - 1) == b'\n';
         Self {
             rope,
             modified: false,
+            had_trailing_newline,
+            edits: Vec::new(),
         }
     }
 
@@ -23,8 +43,31 @@ impl RopeTextBuffer {
         &self.rope
     }
 
-    pub fn rope_mut(&mut self) -> &mut Rope {
-        &mut self.rope
+    /// Number of lines, counting a final line that has no trailing newline.
+    pub fn line_count(&self) -> usize {
+        let newlines = self.rope.measure::<xi_rope::LinesMetric>();
+        let len = self.rope.len();
+        if len == 0 || self.rope.byte_at(len - 1) != b'\n' {
+            newlines + 1
+        } else {
+            newlines
+        }
+    }
+
+    /// Replaces `range` with `text`, recording the edit for `take_edits`.
+    pub fn edit(&mut self, range: Range<usize>, text: impl Into<Rope>) {
+        let text: Rope = text.into();
+        self.edits.push(RopeEdit {
+            old_range: range.clone(),
+            new_len: text.len(),
+        });
+        self.rope.edit(range, text);
+    }
+
+    /// The edits made since the last call, so highlighting can redo only the
+    /// lines they touched.
+    pub fn take_edits(&mut self) -> Vec<RopeEdit> {
+        std::mem::take(&mut self.edits)
     }
 
     pub fn set_modified(&mut self, modified: bool) {
@@ -36,18 +79,82 @@ impl RopeTextBuffer {
     }
 
     pub fn insert_char(&mut self, pos: usize, c: char) {
-        self.rope.edit(pos..pos, &c.to_string());
+        self.edit(pos..pos, c.to_string());
         self.modified = true;
     }
 
-    pub fn remove_char(&mut self, pos: usize) {
-        if pos < self.rope.len() {
-            self.rope.edit(pos..pos + 1, "");
-            self.modified = true;
+    pub fn line_ending(&self) -> &'static str {
+        let first_line_end = self.rope.offset_of_line(1);
+        if self.rope.slice(0..first_line_end).to_string().ends_with("\r\n") {
+            "dos"
+        } else {
+            "unix"
+        }
+    }
+
+    /// Writes the text to `path`. With `ensure_trailing_newline`, a line break
+    /// deleted from the end of text that was read with one is put back in the
+    /// file, though not in the buffer; text read without one is left as it is.
+    pub fn save_to_file(&self, path: &Path, ensure_trailing_newline: bool) -> io::Result<()> {
+        let mut text = self.text();
+        if ensure_trailing_newline
+            && self.had_trailing_newline
+            && !text.is_empty()
+            && !text.ends_with('\n')
+        {
+            text.push_str(if self.line_ending() == "dos" { "\r\n" } else { "\n" });
         }
+        std::fs::write(path, text)
     }
+}
+
+/// Reads the file at `path` as text, reading the raw bytes so any path the
+/// system accepts can be opened, and naming the file and the first bad byte
+/// when the contents aren't UTF-8.
+pub fn read_file(path: &Path) -> io::Result<String> {
+    let bytes = std::fs::read(path)?;
+    String::from_utf8(bytes).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "\"{}\" is not UTF-8 text: invalid byte at offset {}",
+                path.display(),
+                e.utf8_error().valid_up_to()
+            ),
+        )
+    })
+}
+
+/// Byte offset in `rope` of character column `char_col` on `line`. A column
+/// past the last character gives the end of the line, before its line ending.
+pub fn char_to_byte_offset(rope: &Rope, line: usize, char_col: usize) -> usize {
+    let line_start = rope.offset_of_line(line);
+    let last_line = rope.measure::<xi_rope::LinesMetric>() + 1;
+    let text = rope.slice_to_cow(line_start..rope.offset_of_line((line + 1).min(last_line)));
+    let body = text.trim_end_matches(&['\r', '\n'][..]);
+    line_start + body.char_indices().nth(char_col).map_or(body.len(), |(idx, _)| idx)
+}
 
-    pub fn save_to_file(&self, path: &std::path::Path) -> io::Result<()> {
-        std::fs::write(path, self.rope.to_string())
+/// Character column on `line` of `byte_offset`, an offset in `rope` that
+/// falls on that line.
+pub fn byte_to_char_col(rope: &Rope, line: usize, byte_offset: usize) -> usize {
+    let line_start = rope.offset_of_line(line);
+    rope.slice_to_cow(line_start..byte_offset.max(line_start)).chars().count()
+}
+
+/// Line by line reading of a rope without a `String` per line.
+pub trait RopeLineIter {
+    /// Lines `from..to` with their line endings, borrowed from the rope unless a
+    /// line spans two of its chunks. The empty line after a final line ending is
+    /// included, so there is always one item per line.
+    fn iter_lines(&self, from: usize, to: usize) -> impl Iterator<Item = Cow<'_, str>>;
+}
+
+impl RopeLineIter for Rope {
+    fn iter_lines(&self, from: usize, to: usize) -> impl Iterator<Item = Cow<'_, str>> {
+        let range = self.offset_of_line(from)..self.offset_of_line(to);
+        self.lines_raw(range)
+            .chain(iter::repeat(Cow::Borrowed("")))
+            .take(to.saturating_sub(from))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,93 @@
+use tui::style::Color;
+
+/// How many colours the terminal can show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSupport {
+    TrueColor,
+    C256,
+    C16,
+    Mono,
+}
+
+impl ColorSupport {
+    /// Guesses from `$COLORTERM` and `$TERM`, as most terminals don't say.
+    pub fn detect() -> Self {
+        let colorterm = std::env::var("COLORTERM").unwrap_or_default();
+        let term = std::env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorSupport::TrueColor
+        } else if term.ends_with("256color") {
+            ColorSupport::C256
+        } else if term.is_empty() || term == "dumb" {
+            ColorSupport::Mono
+        } else {
+            ColorSupport::C16
+        }
+    }
+}
+
+/// The xterm defaults for the 16 ANSI colours.
+const ANSI_COLORS: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black, (0, 0, 0)),
+    (Color::Red, (205, 0, 0)),
+    (Color::Green, (0, 205, 0)),
+    (Color::Yellow, (205, 205, 0)),
+    (Color::Blue, (0, 0, 238)),
+    (Color::Magenta, (205, 0, 205)),
+    (Color::Cyan, (0, 205, 205)),
+    (Color::Gray, (229, 229, 229)),
+    (Color::DarkGray, (127, 127, 127)),
+    (Color::LightRed, (255, 0, 0)),
+    (Color::LightGreen, (0, 255, 0)),
+    (Color::LightYellow, (255, 255, 0)),
+    (Color::LightBlue, (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan, (0, 255, 255)),
+    (Color::White, (255, 255, 255)),
+];
+
+/// Reads a `#RRGGBB` colour, as `:highlight` takes.
+pub fn parse_hex_color(hex: &str) -> Option<Color> {
+    let digits = hex.strip_prefix('#').filter(|digits| digits.len() == 6)?;
+    let component = |i: usize| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok();
+    Some(Color::Rgb(component(0)?, component(2)?, component(4)?))
+}
+
+/// Levels of each component in the xterm 6x6x6 colour cube.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The colour closest to `color` that `support` can show. Only `Color::Rgb`
+/// changes, except on a monochrome terminal where every colour is dropped.
+pub fn nearest_color(color: Color, support: ColorSupport) -> Color {
+    let Color::Rgb(r, g, b) = color else {
+        return if support == ColorSupport::Mono { Color::Reset } else { color };
+    };
+    match support {
+        ColorSupport::TrueColor => color,
+        ColorSupport::C256 => Color::Indexed(nearest_xterm_index((r, g, b))),
+        ColorSupport::C16 => ANSI_COLORS
+            .iter()
+            .min_by_key(|(_, rgb)| distance((r, g, b), *rgb))
+            .map_or(color, |&(ansi, _)| ansi),
+        ColorSupport::Mono => Color::Reset,
+    }
+}
+
+/// The nearest of the xterm-256 colours from the cube (16-231) or the grey
+/// ramp (232-255). The first 16 are left out, since terminals theme them.
+fn nearest_xterm_index(rgb: (u8, u8, u8)) -> u8 {
+    let cube = (0..216u8).map(|i| {
+        let level = |n: u8| CUBE_LEVELS[n as usize];
+        (16 + i, (level(i / 36), level(i / 6 % 6), level(i % 6)))
+    });
+    let greys = (0..24u8).map(|i| (232 + i, (8 + 10 * i, 8 + 10 * i, 8 + 10 * i)));
+    cube.chain(greys)
+        .min_by_key(|&(_, candidate)| distance(rgb, candidate))
+        .map_or(16, |(index, _)| index)
+}
+
+/// Squared Euclidean distance between two colours in RGB space.
+fn distance((r1, g1, b1): (u8, u8, u8), (r2, g2, b2): (u8, u8, u8)) -> u32 {
+    let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    d(r1, r2) + d(g1, g2) + d(b1, b2)
+}
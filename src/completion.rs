@@ -0,0 +1,115 @@
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans, Text};
+
+/// Most candidates the completion menu shows at once.
+const MAX_MENU_ROWS: usize = 10;
+
+/// An Insert mode completion popup, offering replacements for the text between
+/// `start` and the cursor.
+///
+/// Candidates are collected once when the popup opens; cycling through them with
+/// Ctrl-N and Ctrl-P only moves the selection, so the buffer is not rescanned.
+pub struct CompletionState {
+    /// Column on the cursor line where the completed text begins.
+    pub start: usize,
+    pub prefix: String,
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    /// Set for line completion, where a candidate replaces the whole line rather
+    /// than only the text before the cursor.
+    pub whole_line: bool,
+}
+
+impl CompletionState {
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.candidates.len();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self
+            .selected
+            .checked_sub(1)
+            .unwrap_or(self.candidates.len() - 1);
+    }
+
+    pub fn selected(&self) -> &str {
+        &self.candidates[self.selected]
+    }
+
+    /// The candidates around the selection as menu rows, with the typed prefix in
+    /// bold. Each row is padded by a space, so the menu starts one column left of
+    /// the text being completed to line the candidates up with it.
+    pub fn menu(&self) -> Text<'static> {
+        let first = (self.selected + 1).saturating_sub(MAX_MENU_ROWS);
+        let shown = &self.candidates[first..self.candidates.len().min(first + MAX_MENU_ROWS)];
+        let width = shown.iter().map(|c| c.chars().count() + 2).max().unwrap_or(0);
+
+        let lines: Vec<Spans> = shown
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| {
+                let style = if first + i == self.selected {
+                    Style::default().fg(Color::Black).bg(Color::LightBlue)
+                } else {
+                    Style::default().fg(Color::White).bg(Color::DarkGray)
+                };
+                let (typed, rest) = match candidate.strip_prefix(self.prefix.as_str()) {
+                    Some(rest) => (self.prefix.as_str(), rest),
+                    None => ("", candidate.as_str()),
+                };
+                let padding = width.saturating_sub(1 + candidate.chars().count());
+                Spans::from(vec![
+                    Span::styled(format!(" {}", typed), style.add_modifier(Modifier::BOLD)),
+                    Span::styled(format!("{}{}", rest, " ".repeat(padding)), style),
+                ])
+            })
+            .collect();
+        Text::from(lines)
+    }
+}
+
+/// Whether `c` belongs to a word, matching the Ctrl-W and Ctrl-B motions.
+pub fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Distinct words in `text` that start with `prefix`, nearest first, searching
+/// forward from byte `offset` and wrapping around, or backward when `backward`
+/// is set. The word containing `offset` itself is skipped.
+pub fn word_candidates(text: &str, prefix: &str, offset: usize, backward: bool) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word_start = None;
+    for (idx, c) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (word_start, is_word_char(c)) {
+            (None, true) => word_start = Some(idx),
+            (Some(start), false) => {
+                if !(start..=idx).contains(&offset) {
+                    words.push((start, &text[start..idx]));
+                }
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let split = words.partition_point(|&(start, _)| start < offset);
+    words.rotate_left(split);
+    if backward {
+        words.reverse();
+    }
+
+    let mut candidates: Vec<String> = Vec::new();
+    for (_, word) in words {
+        if word.starts_with(prefix) && word != prefix && !candidates.iter().any(|c| c == word) {
+            candidates.push(word.to_string());
+        }
+    }
+    candidates
+}
+
+/// Whether the characters of `pattern` appear in `text` in order, not
+/// necessarily next to each other.
+pub fn fuzzy_match(text: &str, pattern: &str) -> bool {
+    let mut chars = text.chars();
+    pattern.chars().all(|p| chars.any(|c| c == p))
+}
@@ -0,0 +1,110 @@
+use crate::autocmd::{Autocmd, AutocmdEvent};
+use crate::statusline::{segment_named, StatusSegment, TextSegment};
+use std::path::PathBuf;
+
+/// Settings read from `~/.cim/config.toml` at startup.
+pub struct AppConfig {
+    /// The status bar from left to right.
+    pub status_segments: Vec<Box<dyn StatusSegment>>,
+    /// Autocommands from `[[autocmd]]` tables in both files and `:autocmd`, in
+    /// the order they run.
+    pub autocmds: Vec<Autocmd>,
+    /// `program` and `errorformat` from the `[make]` table, for `makeprg` and
+    /// `errorformat`.
+    pub make_program: Option<String>,
+    pub error_format: Option<String>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            status_segments: status_layout(&[
+                "mode", ": ", "filename", "modified", " | ", "position", " | ", "word_count",
+            ]),
+            autocmds: Vec::new(),
+            make_program: None,
+            error_format: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Reads `~/.cim/config.toml`, then `.cim/config.toml` in the working
+    /// directory for settings of the project there, keeping the defaults for
+    /// anything neither sets. A missing or malformed file is skipped.
+    ///
+    /// ```toml
+    /// [statusline]
+    /// segments = ["mode", " | ", "filename", "modified", " | ", "git_branch"]
+    ///
+    /// [[autocmd]]
+    /// event = "FileType"
+    /// pattern = "rust"
+    /// command = "set spell"
+    ///
+    /// [make]
+    /// program = "cargo build"
+    /// errorformat = "%f:%l:%c: %m"
+    /// ```
+    pub fn load() -> Self {
+        let mut config = Self::default();
+        let paths = [config_path(), Some(PathBuf::from(".cim").join("config.toml"))];
+        for path in paths.into_iter().flatten() {
+            if let Some(table) = std::fs::read_to_string(path)
+                .ok()
+                .and_then(|content| content.parse::<toml::Table>().ok())
+            {
+                config.apply(&table);
+            }
+        }
+        config
+    }
+
+    /// Takes the settings `table` has, leaving the rest as they are.
+    fn apply(&mut self, table: &toml::Table) {
+        if let Some(segments) = table
+            .get("statusline")
+            .and_then(|statusline| statusline.get("segments"))
+            .and_then(|segments| segments.as_array())
+        {
+            let names: Vec<&str> = segments.iter().filter_map(|name| name.as_str()).collect();
+            self.status_segments = status_layout(&names);
+        }
+        if let Some(autocmds) = table.get("autocmd").and_then(|autocmds| autocmds.as_array()) {
+            self.autocmds.extend(autocmds.iter().filter_map(autocmd_from_table));
+        }
+        if let Some(make) = table.get("make") {
+            let field = |name: &str| make.get(name).and_then(|value| value.as_str());
+            if let Some(program) = field("program") {
+                self.make_program = Some(program.to_string());
+            }
+            if let Some(format) = field("errorformat") {
+                self.error_format = Some(format.to_string());
+            }
+        }
+    }
+}
+
+/// An `[[autocmd]]` table, or `None` if it lacks a field or names no event.
+fn autocmd_from_table(table: &toml::Value) -> Option<Autocmd> {
+    let field = |name: &str| table.get(name).and_then(|value| value.as_str());
+    Some(Autocmd {
+        event: AutocmdEvent::parse(field("event")?)?,
+        pattern: field("pattern")?.to_string(),
+        command: field("command")?.to_string(),
+    })
+}
+
+fn config_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".cim").join("config.toml"))
+}
+
+/// Builds a status bar from segment names. Anything that doesn't name a
+/// segment, such as `" | "`, is shown as it is.
+fn status_layout(names: &[&str]) -> Vec<Box<dyn StatusSegment>> {
+    names
+        .iter()
+        .map(|&name| segment_named(name).unwrap_or_else(|| Box::new(TextSegment(name.to_string()))))
+        .collect()
+}
@@ -0,0 +1,41 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Writes each log record to the file given with `--debug`, flushed at once so
+/// the log is complete even if the editor is killed.
+struct FileLogger {
+    file: Mutex<File>,
+}
+
+impl log::Log for FileLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let Ok(mut file) = self.file.lock() else {
+            return;
+        };
+        let time = chrono::Local::now().format("%H:%M:%S%.3f");
+        let _ = writeln!(file, "{} {:<5} {}", time, record.level(), record.args());
+        let _ = file.flush();
+    }
+
+    fn flush(&self) {
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Starts logging to `path`, replacing what it held.
+pub fn init(path: &Path) -> io::Result<()> {
+    let logger = FileLogger {
+        file: Mutex::new(File::create(path)?),
+    };
+    log::set_logger(Box::leak(Box::new(logger))).map_err(io::Error::other)?;
+    log::set_max_level(log::LevelFilter::Debug);
+    Ok(())
+}
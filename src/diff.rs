@@ -0,0 +1,88 @@
+use crate::buffer::RopeTextBuffer;
+use similar::{DiffOp, TextDiff};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiffKind {
+    /// Lines only in the other file.
+    Added,
+    /// Lines only in the buffer being edited.
+    Removed,
+    Changed,
+}
+
+/// A run of differing lines. `old_*` are 0-based lines of the buffer being
+/// edited and `new_*` lines of the file opened with `:diffsplit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+    pub kind: DiffKind,
+}
+
+impl DiffHunk {
+    /// Whether the hunk covers buffer line `line_idx`. A hunk with no lines in the
+    /// buffer belongs to the line it would be inserted before.
+    pub fn contains_old(&self, line_idx: usize) -> bool {
+        (self.old_start..self.old_start + self.old_len.max(1)).contains(&line_idx)
+    }
+}
+
+/// The file shown beside the buffer by `:diffsplit`.
+pub struct DiffPane {
+    pub path: PathBuf,
+    pub buffer: RopeTextBuffer,
+}
+
+/// Line hunks turning `old` into `new`.
+pub fn compute_hunks(old: &str, new: &str) -> Vec<DiffHunk> {
+    TextDiff::from_lines(old, new)
+        .ops()
+        .iter()
+        .filter_map(|op| {
+            let (kind, old_start, old_len, new_start, new_len) = match *op {
+                DiffOp::Equal { .. } => return None,
+                DiffOp::Delete {
+                    old_index,
+                    old_len,
+                    new_index,
+                } => (DiffKind::Removed, old_index, old_len, new_index, 0),
+                DiffOp::Insert {
+                    old_index,
+                    new_index,
+                    new_len,
+                } => (DiffKind::Added, old_index, 0, new_index, new_len),
+                DiffOp::Replace {
+                    old_index,
+                    old_len,
+                    new_index,
+                    new_len,
+                } => (DiffKind::Changed, old_index, old_len, new_index, new_len),
+            };
+            Some(DiffHunk {
+                old_start,
+                old_len,
+                new_start,
+                new_len,
+                kind,
+            })
+        })
+        .collect()
+}
+
+/// The line of the other file that lines up with buffer line `old_line`.
+pub fn new_line_for(hunks: &[DiffHunk], old_line: usize) -> usize {
+    let mut shift = 0isize;
+    for hunk in hunks {
+        if old_line < hunk.old_start {
+            break;
+        }
+        if old_line < hunk.old_start + hunk.old_len {
+            return hunk.new_start + (old_line - hunk.old_start).min(hunk.new_len.saturating_sub(1));
+        }
+        shift += hunk.new_len as isize - hunk.old_len as isize;
+    }
+    old_line.saturating_add_signed(shift)
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Digraph table bundled with the editor, parsed on first use.
+pub fn table() -> &'static HashMap<(char, char), char> {
+    static TABLE: OnceLock<HashMap<(char, char), char>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let entries: HashMap<String, String> =
+            toml::from_str(include_str!("digraphs.toml")).unwrap_or_default();
+        entries
+            .iter()
+            .filter_map(|(keys, value)| {
+                let mut keys = keys.chars();
+                let mut value = value.chars();
+                match (keys.next(), keys.next(), keys.next(), value.next(), value.next()) {
+                    (Some(first), Some(second), None, Some(c), None) => Some(((first, second), c)),
+                    _ => None,
+                }
+            })
+            .collect()
+    })
+}
+
+/// Looks up the digraph for two characters, trying them in either order as vim does.
+pub fn lookup(first: char, second: char) -> Option<char> {
+    let table = table();
+    table
+        .get(&(first, second))
+        .or_else(|| table.get(&(second, first)))
+        .copied()
+}
+
+/// The two characters of a digraph for `c`, the first in sort order when there
+/// are several.
+pub fn keys_for(c: char) -> Option<(char, char)> {
+    table()
+        .iter()
+        .filter(|&(_, &value)| value == c)
+        .map(|(&keys, _)| keys)
+        .min()
+}
+
+/// `:digraphs` listing, a few `a: ä 228` entries per line in code point order.
+pub fn summary() -> Vec<String> {
+    let mut entries: Vec<_> = table().iter().collect();
+    entries.sort_by_key(|&(keys, c)| (*c, *keys));
+    entries
+        .chunks(5)
+        .map(|row| {
+            row.iter()
+                .map(|(&(first, second), &c)| format!("{}{} {} {:<6}", first, second, c, c as u32))
+                .collect::<Vec<_>>()
+                .join(" ")
+                .trim_end()
+                .to_string()
+        })
+        .collect()
+}
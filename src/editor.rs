@@ -1,58 +1,638 @@
-use crate::input::handle_key_event;
-use crate::{buffer::RopeTextBuffer, highlight::Highlighter};
+use crate::autocmd::{Autocmd, AutocmdEvent};
+use crate::color::ColorSupport;
+use crate::completion::{self, CompletionState};
+use crate::config::AppConfig;
+use crate::diff::{self, DiffHunk, DiffKind, DiffPane};
+use crate::digraph;
+use crate::eval::{self, Variables};
+use crate::ex::{split_delimited, AlignMode, ExCommand, ExCommandKind, ExRange};
+use crate::input::{handle_key_event, handle_key_sequence, prefix_key, CTRL_W};
+use crate::keymap::{self, Keymap, Lookup, MapMode};
+use crate::message::{MessageKind, MessageQueue};
+use crate::options::{EditorOptions, InccommandMode, SearchOptions, SignColumnMode};
+use crate::popup::PopupWindow;
+use crate::quickfix::{ErrorFormat, QuickfixEntry};
+use crate::recent::RecentFiles;
+use crate::register::{Register, Registers};
+use crate::sign::{self, Sign};
+use crate::snippet::{self, Snippet, SnippetSession};
+use crate::spell::{self, SpellChecker};
+use crate::buffer::{self, byte_to_char_col, char_to_byte_offset, RopeLineIter, RopeTextBuffer};
+use crate::highlight::{HighlightedSegment, Highlighter};
+use crate::types::CursorPos;
 use crossterm::event::KeyCode;
 use crossterm::event::KeyEvent;
 use crossterm::event::KeyModifiers;
-use std::io;
-use std::path::PathBuf;
-use syntect::highlighting::Style as SyntectStyle;
+use regex::{Regex, RegexBuilder};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::time::{Duration, Instant};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans, Text};
 use xi_rope::Rope;
+
+/// Fewest rows `:resize` and `Ctrl-W -` leave the text or quickfix window.
+const MIN_SPLIT_HEIGHT: usize = 3;
+
+/// Deepest `:source` can nest, as when a script sources itself.
+const MAX_SOURCE_DEPTH: usize = 50;
+
+/// Deepest mappings can expand inside each other, as when two map to each other.
+const MAX_MAP_DEPTH: usize = 100;
+
+/// The width `:right` and `:center` use when none is given.
+const DEFAULT_ALIGN_WIDTH: usize = 80;
+
+/// How long `beep` flashes the status bar.
+const FLASH_DURATION: Duration = Duration::from_millis(150);
+
+#[derive(Clone, Debug)]
 pub enum EditorAction {
     Exit,
+    ForceExit,
     Save,
     SaveExit,
     ChangeMode(bool),
     MoveCursor((i16, i16)),
     MoveWord(i16),
+    MoveBigWord(i16),
+    MoveBigWordEnd(i16),
     LineStart,
+    /// `^`: the first non-blank character of the line.
+    FirstNonBlank,
+    /// `_`, `+` and `-`: the first non-blank character of the line this many
+    /// lines down, or up when negative.
+    FirstNonBlankLine(i16),
     LineEnd,
+    /// Jumps to a 1-based line; 0 means the last line.
+    GoToLine(usize),
+    SelectRegister(char),
+    SetMark(char),
+    JumpToMark(char),
+    JumpToMarkLine(char),
     PageUp,
     PageDown,
+    HalfPageUp,
+    HalfPageDown,
+    ScrollUp(usize),
+    ScrollDown(usize),
+    CursorScreenTop(usize),
+    CursorScreenMiddle,
+    CursorScreenBottom(usize),
+    ScrollCursorTop,
+    ScrollCursorCenter,
+    ScrollCursorBottom,
+    ScrollCursorLeft,
+    ScrollCursorRight,
+    ParagraphBackward,
+    ParagraphForward,
+    Operator(Operator),
+    Paste(bool),
+    /// `]p` below the cursor line or `[p` above it.
+    PasteIndentAdjusted(bool),
+    ToggleCase(usize),
+    /// `r`: replaces this many characters with the given one.
+    ReplaceChar(char, usize),
+    StartReplace,
+    /// Adds to the number at or after the cursor; negative to subtract.
+    Increment(i64),
+    /// `]q`/`[q`: moves this many entries through the quickfix list.
+    Quickfix(isize),
+    /// `]c`/`[c`: moves this many diff hunks forward or back.
+    JumpToHunk(isize),
+    /// `dp`: copies the hunk under the cursor into the other diff pane.
+    DiffPut,
+    /// `do`: replaces the hunk under the cursor with the other pane's lines.
+    DiffObtain,
+    OpenFold,
+    CloseFold,
+    ToggleFold,
+    OpenAllFolds,
+    CloseAllFolds,
+    /// `z=`: lists spelling suggestions for the word under the cursor.
+    SpellSuggest,
+    /// `ga`: shows the codepoints of the character under the cursor.
+    CharInfo,
     StartCommand,
+    StartSearch(bool),
+    SearchNext,
+    SearchPrevious,
+    ClearHighlight,
+    FileInfo,
+    /// `K`: shows the signs and quickfix messages on the cursor line in a popup.
+    Hover,
+    /// `Ctrl-W o`: closes the diff pane and the quickfix window.
+    CloseOtherSplits,
+    /// `Ctrl-W +` and `Ctrl-W -`: makes the focused window taller or shorter.
+    ResizeSplit(i16),
+    /// `Ctrl-W =`: shares the rows evenly between the text and quickfix window.
+    EqualizeAllSplits,
+    /// Ctrl-^: edits the alternate file.
+    SwitchToAlternate,
     InsertChar(char),
-    DeleteChar,
-    Backspace,
+    /// Backspace: deletes the character before the cursor, or at the start of a
+    /// line joins it to the line above.
+    DeleteBack,
     Tab,
+    TransposeChars,
+    /// Ctrl-Z: stops the editor until the shell resumes it.
+    Suspend,
+}
+
+impl fmt::Display for EditorAction {
+    /// As `Debug`, but with cursor moves drawn as arrows, like `MoveCursor(3↓)`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let EditorAction::MoveCursor((dx, dy)) = *self else {
+            return write!(f, "{:?}", self);
+        };
+        let arrow = |distance: i16, back: &str, forward: &str| match distance.unsigned_abs() {
+            0 => String::new(),
+            1 => (if distance < 0 { back } else { forward }).to_string(),
+            n => format!("{}{}", n, if distance < 0 { back } else { forward }),
+        };
+        write!(f, "MoveCursor({}{})", arrow(dx, "←", "→"), arrow(dy, "↑", "↓"))
+    }
 }
 
 pub struct CimEditor {
     pub buffer: RopeTextBuffer,
     pub file_path: Option<PathBuf>,
+    /// The buffer was read from stdin and has not been written to a file yet.
+    pub from_stdin: bool,
+    /// Set by `-R`; writing needs `:w!`, which clears it.
+    pub read_only: bool,
     pub mode: EditorMode,
     pub command_buffer: String,
     pub highlighter: Highlighter,
-    pub cursor_position: (u16, u16),
+    pub cursor_position: CursorPos,
     pub scroll_offset: usize,
     pub horizontal_offset: usize,
     pub viewport_height: usize,
     pub viewport_width: usize,
     pub text_changed: bool,
-    pub last_key_event: Option<KeyEvent>,
-    pub line_numbers: String,
-    pub highlighted_lines: Vec<Vec<(SyntectStyle, String)>>,
+    /// `(words, chars, lines)` for the whole buffer, cleared when the text changes.
+    pub word_count_cache: Cell<Option<(usize, usize, usize)>>,
+    /// Messages for the message line, kept for `:messages`.
+    pub messages: MessageQueue,
+    /// Variables set with `:let`.
+    pub variables: Variables,
+    /// Files run with `:source`, in the order they were first sourced.
+    pub sourced_scripts: Vec<PathBuf>,
+    /// How many `:source` commands are running inside each other.
+    source_depth: usize,
+    /// The script being sourced, which mappings made now record.
+    current_script: Option<PathBuf>,
+    /// Mappings made with `:map` and its forms.
+    pub keymap: Keymap,
+    /// Mappings made with `:map <buffer>`, which take precedence over `keymap`
+    /// and are cleared when another file is edited.
+    pub buffer_keymap: Keymap,
+    /// Keys typed so far that start the `lhs` of a mapping.
+    pending_map_keys: Vec<KeyEvent>,
+    /// Set while the keys of a `noremap` mapping run, so they aren't mapped again.
+    noremap: bool,
+    /// How many mappings are expanding inside each other.
+    map_depth: usize,
+    pub overlay: Option<Overlay>,
+    pub pending_key: Option<char>,
+    pub pending_count: usize,
+    pub pending_operator: Option<(Operator, usize)>,
+    pub pending_register: Option<char>,
+    pub pending_surround: Option<PendingSurround>,
+    /// Where the cursor and scroll offset were before a Ctrl-W word motion, and
+    /// the count typed before it, for when the next key makes it a window command
+    /// such as `Ctrl-W o`.
+    pub window_command_start: Option<(CursorPos, usize, usize)>,
+    pub half_page_lines: usize,
+    pub marks: HashMap<char, CursorPos>,
+    pub registers: Registers,
+    pub search_query: Option<String>,
+    pub search_backward: bool,
+    pub search_matches: Vec<(usize, Range<usize>)>,
+    /// The text as the `:s` being typed would leave it, shown in its place
+    /// while `inccommand` is set.
+    pub preview_rope: Option<Rope>,
+    /// The replacements, or the matches until a replacement is typed, in
+    /// `preview_rope` as lines and character columns.
+    pub preview_matches: Vec<(usize, Range<usize>)>,
+    /// Loaded by `:set spell`.
+    pub spell_checker: Option<SpellChecker>,
+    /// Misspelled words as (line, character columns), while `spell` is set.
+    pub spell_errors: Vec<(usize, Range<usize>)>,
+    pub search_highlighted: bool,
+    pub options: EditorOptions,
+    pub config: AppConfig,
+    /// Colours the terminal can show, which highlight colours are brought down to.
+    pub color_support: ColorSupport,
+    pub subst_pending: Option<SubstState>,
+    pub completion_state: Option<CompletionState>,
+    /// Tab completion of a command line argument, with `start` a byte index
+    /// into `command_buffer`.
+    pub command_completion: Option<CompletionState>,
+    /// The theme to go back to if the command line is abandoned while Tab is
+    /// previewing `:colorscheme` candidates.
+    pub preview_theme: Option<String>,
+    /// Popups drawn over the text, in the order they opened.
+    pub popups: Vec<PopupWindow>,
+    next_popup_id: usize,
+    /// The popup showing `completion_state`.
+    completion_popup: Option<usize>,
+    /// The popup listing registers after `"` in Normal mode.
+    register_popup: Option<usize>,
+    pub pending_ctrl_x: bool,
+    /// Set by Ctrl-K in Insert mode, holding the first digraph character once typed.
+    pub pending_digraph: Option<Option<char>>,
+    /// Set by Ctrl-V in Insert mode, holding the `u` or `U` and the hex digits of
+    /// a codepoint as they are typed.
+    pub pending_unicode: Option<String>,
+    /// When `beep` last flashed the status bar.
+    flashed_at: Option<Instant>,
+    /// Characters overwritten in Replace mode, for Backspace to put back. `None`
+    /// marks a character that was appended past the end of the line.
+    pub replace_undo_chars: Vec<Option<char>>,
+    pub abbreviations: HashMap<String, String>,
+    /// The file type from the syntax, such as `rust`, or `text` for plain text.
+    pub filetype: String,
+    /// Snippets for the current file type by trigger word, including those
+    /// defined with `:snippet`.
+    pub snippets: HashMap<String, Snippet>,
+    /// Snippets defined with `:snippet`, kept when another file type's are loaded.
+    defined_snippets: HashMap<String, Snippet>,
+    pub snippet_session: Option<SnippetSession>,
+    pub command_abbreviations: HashMap<String, String>,
+    pub quickfix_list: Vec<QuickfixEntry>,
+    /// The entry last jumped to, which `:cnext` and `:cprevious` move from.
+    pub quickfix_index: usize,
+    /// `:copen` shows the quickfix window below the text.
+    pub quickfix_open: bool,
+    /// Keys go to the quickfix window rather than the text.
+    pub quickfix_focused: bool,
+    /// The entry the quickfix window's cursor is on.
+    pub quickfix_cursor: usize,
+    /// Signs defined with `sign_define`, by name.
+    pub sign_definitions: HashMap<String, Sign>,
+    /// Signs placed on each 0-based line, the last one placed being shown.
+    pub signs: HashMap<usize, Vec<Sign>>,
+    /// Virtual text by 0-based line, drawn in the order it was set.
+    pub virtual_texts: HashMap<usize, Vec<VirtualText>>,
+    pub folds: Vec<FoldRange>,
+    pub diff_mode: bool,
+    pub diff_hunks: Vec<DiffHunk>,
+    /// The other file in diff mode, drawn to the right of the buffer.
+    pub diff_pane: Option<DiffPane>,
+    /// Files given on the command line or to `:args`, visited by `:argdo`.
+    pub arg_list: Vec<PathBuf>,
+    /// The file edited before the current one, with its cursor and scroll offset,
+    /// for `:e #` and Ctrl-^.
+    pub alternate_file: Option<AlternateFile>,
+    /// Files opened in earlier sessions, most recent first, as read at startup.
+    /// Vim's `v:oldfiles`.
+    pub oldfiles: Vec<PathBuf>,
+    pub highlighted_lines: Vec<Vec<HighlightedSegment>>,
+}
+
+/// A Normal mode operator waiting for the motion that defines its range.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Delete,
+    Yank,
+    Change,
+    Uppercase,
+    Lowercase,
+    /// `zf`: folds the lines the motion covers.
+    Fold,
+    /// `ys`: surrounds the text the motion covers with a pair of characters.
+    Surround,
+    /// `>`: indents the lines the motion covers by `tabstop` columns.
+    ShiftRight,
+    /// `<`: takes `tabstop` columns off the indent of the lines.
+    ShiftLeft,
+    /// `=`: indents each line one level past the line above when that opens a
+    /// bracket, and one level less when the line closes one.
+    Reindent,
+}
+
+/// A surround command waiting for the characters it works with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PendingSurround {
+    /// `ys<motion>`, holding the byte range to wrap.
+    Add(Range<usize>),
+    /// `cs`, holding the pair to replace once it has been typed.
+    Change(Option<char>),
+    /// `ds`
+    Delete,
+}
+
+/// The opening and closing characters of the pair `c` belongs to; quotes and
+/// other characters pair with themselves.
+fn surround_pair(c: char) -> (char, char) {
+    match c {
+        '(' | ')' | 'b' => ('(', ')'),
+        '[' | ']' => ('[', ']'),
+        '{' | '}' | 'B' => ('{', '}'),
+        '<' | '>' => ('<', '>'),
+        c => (c, c),
+    }
+}
+
+/// A file edited earlier, for `:e #` and Ctrl-^ to go back to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AlternateFile {
+    pub path: PathBuf,
+    pub cursor: CursorPos,
+    pub scroll_offset: usize,
+}
+
+/// Lines `start..=end` that `zf` made into a fold, shown as one line while closed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FoldRange {
+    pub start: usize,
+    pub end: usize,
+    pub open: bool,
+}
+
+/// Radix of a number found for Ctrl-A and Ctrl-X, taken from its `0x`, `0o` or
+/// `0b` prefix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NumberBase {
+    Decimal,
+    Hex,
+    Octal,
+    Binary,
+}
+
+impl NumberBase {
+    fn radix(self) -> u32 {
+        match self {
+            NumberBase::Decimal => 10,
+            NumberBase::Hex => 16,
+            NumberBase::Octal => 8,
+            NumberBase::Binary => 2,
+        }
+    }
+}
+
+/// How an operator treats the text a motion moves over.
+#[derive(Clone, Copy, PartialEq)]
+enum MotionKind {
+    Exclusive,
+    Inclusive,
+    Linewise,
+}
+
+impl EditorAction {
+    fn motion_kind(&self) -> Option<MotionKind> {
+        match self {
+            EditorAction::MoveCursor((_, 0))
+            | EditorAction::MoveWord(_)
+            | EditorAction::MoveBigWord(_)
+            | EditorAction::LineStart
+            | EditorAction::FirstNonBlank
+            | EditorAction::ParagraphBackward
+            | EditorAction::ParagraphForward
+            | EditorAction::SearchNext
+            | EditorAction::SearchPrevious
+            | EditorAction::JumpToMark(_) => Some(MotionKind::Exclusive),
+            EditorAction::LineEnd | EditorAction::MoveBigWordEnd(_) => Some(MotionKind::Inclusive),
+            EditorAction::MoveCursor(_)
+            | EditorAction::PageUp
+            | EditorAction::PageDown
+            | EditorAction::HalfPageUp
+            | EditorAction::HalfPageDown
+            | EditorAction::GoToLine(_)
+            | EditorAction::JumpToMarkLine(_)
+            | EditorAction::CursorScreenTop(_)
+            | EditorAction::CursorScreenMiddle
+            | EditorAction::CursorScreenBottom(_)
+            | EditorAction::FirstNonBlankLine(_) => Some(MotionKind::Linewise),
+            _ => None,
+        }
+    }
+}
+
+/// Text drawn into a line that isn't part of it, so the cursor and motions pass
+/// over it.
+pub struct VirtualText {
+    pub content: String,
+    pub style: Style,
+    /// The character it is drawn before, or `None` for after the end of the line.
+    pub col: Option<usize>,
+}
+
+/// Read-only text shown on top of the editor until the next keypress.
+pub struct Overlay {
+    pub title: String,
+    pub lines: Vec<String>,
+    pub scroll: usize,
+    pub dismiss_message: Option<String>,
+    pub kind: OverlayKind,
+}
+
+/// What an overlay shows, which decides how it reacts to keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverlayKind {
+    /// Command output; any key other than scrolling closes it.
+    Output,
+    /// `:registers`; typing a register name selects it for the next command.
+    Registers,
+    /// `z=`; `j`/`k` pick a suggestion and Enter puts it in place of the word.
+    SpellSuggest,
+    /// `:oldfiles`; a number and Enter opens that file.
+    OldFiles,
+}
+
+impl Overlay {
+    pub fn new(title: impl Into<String>, lines: Vec<String>) -> Self {
+        Self {
+            title: title.into(),
+            lines,
+            scroll: 0,
+            dismiss_message: None,
+            kind: OverlayKind::Output,
+        }
+    }
+}
+
+/// Progress through a `:s` substitution, one match at a time.
+pub struct SubstState {
+    pub regex: Regex,
+    /// Replacement in `regex` expansion syntax (`${1}` rather than vim's `\1`).
+    pub replacement: String,
+    /// Replacement as typed, for the confirmation prompt.
+    pub replacement_text: String,
+    pub global: bool,
+    pub line: usize,
+    pub offset: usize,
+    pub end_line: usize,
+    /// The match awaiting confirmation, as a byte range within its line.
+    pub current: Option<(usize, Range<usize>)>,
+    pub replaced: usize,
+    pub lines_changed: usize,
+    last_changed_line: Option<usize>,
+    /// End of the last non-empty match; an empty match may not start right there.
+    last_match_end: Option<(usize, usize)>,
+}
+
+/// What `:g` does with the lines it selects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GlobalCmd {
+    Delete,
+    Yank(char),
+    Print,
+}
+
+/// Whether `action` ends the session or suspends it, after which no more keys
+/// should run.
+fn is_final_action(action: &Option<EditorAction>) -> bool {
+    matches!(
+        action,
+        Some(EditorAction::Exit | EditorAction::ForceExit | EditorAction::Suspend)
+    )
+}
+
+/// Byte length of the character at `offset`, or 1 past the end of `text`.
+fn char_len_at(text: &str, offset: usize) -> usize {
+    text.get(offset..)
+        .and_then(|rest| rest.chars().next())
+        .map_or(1, char::len_utf8)
+}
+
+/// The screen column after `c` when it starts at `column`: a tab runs to the
+/// next tab stop.
+fn next_visual_column(column: usize, c: char, tab_width: usize) -> usize {
+    match c {
+        '\t' => (column / tab_width + 1) * tab_width,
+        _ => column + 1,
+    }
+}
+
+/// `line` with its blanks redone for tab stops every `tab_width` columns: tabs
+/// as spaces, or with `to_spaces` off, runs of blanks that reach a tab stop as
+/// tabs and the spaces after the last stop. A lone space is left alone.
+fn retab(line: &str, tab_width: usize, to_spaces: bool) -> String {
+    let mut retabbed = String::with_capacity(line.len());
+    let mut column = 0;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ' ' && c != '\t' {
+            retabbed.push(c);
+            column += 1;
+            continue;
+        }
+        let run_start = column;
+        let mut run = String::new();
+        let mut blank = Some(c);
+        while let Some(c) = blank {
+            run.push(c);
+            column = next_visual_column(column, c, tab_width);
+            blank = chars.next_if(|&c| c == ' ' || c == '\t');
+        }
+
+        let has_tab = run.contains('\t');
+        let last_stop = column / tab_width * tab_width;
+        if to_spaces {
+            if has_tab {
+                retabbed.push_str(&" ".repeat(column - run_start));
+            } else {
+                retabbed.push_str(&run);
+            }
+        } else if last_stop > run_start && (has_tab || run.len() > 1) {
+            let tabs = last_stop / tab_width - run_start / tab_width;
+            retabbed.push_str(&"\t".repeat(tabs));
+            retabbed.push_str(&" ".repeat(column - last_stop));
+        } else {
+            retabbed.push_str(&run);
+        }
+    }
+    retabbed
+}
+
+/// Whether `c` combines with the character before it into one grapheme: the
+/// combining diacritical marks, variation selectors and the zero-width joiner.
+fn is_combining(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x300..=0x36f
+            | 0x1ab0..=0x1aff
+            | 0x1dc0..=0x1dff
+            | 0x200d
+            | 0x20d0..=0x20ff
+            | 0xfe00..=0xfe0f
+            | 0xfe20..=0xfe2f
+            | 0x1f3fb..=0x1f3ff
+            | 0xe0100..=0xe01ef
+    )
+}
+
+/// Converts a vim replacement (`&`, `\1`, `\r`) to `regex` expansion syntax.
+fn vim_replacement(replacement: &str) -> String {
+    let mut converted = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => converted.push_str("${0}"),
+            '$' => converted.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => converted.push_str(&format!("${{{}}}", digit)),
+                Some('r' | 'n') => converted.push('\n'),
+                Some('t') => converted.push('\t'),
+                Some('$') => converted.push_str("$$"),
+                Some(other) => converted.push(other),
+                None => converted.push('\\'),
+            },
+            c => converted.push(c),
+        }
+    }
+    converted
+}
+
+/// Splits `:set` arguments at whitespace, except where `\` escapes it, as in
+/// `listchars=space:\ `.
+fn split_set_args(args: &str) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut arg = String::new();
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => arg.extend(chars.next()),
+            c if c.is_whitespace() => {
+                if !arg.is_empty() {
+                    split.push(std::mem::take(&mut arg));
+                }
+            }
+            c => arg.push(c),
+        }
+    }
+    if !arg.is_empty() {
+        split.push(arg);
+    }
+    split
 }
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum EditorMode {
     Normal,
     Insert,
     Command,
+    Search,
+    SubstConfirm,
+    Replace,
+    /// Insert mode inside an expanded snippet, where Tab moves to the next tab stop.
+    SnippetExpand,
 }
 
 impl CimEditor {
     pub fn new(file_path: Option<PathBuf>) -> io::Result<Self> {
         let buffer = if let Some(ref path) = file_path {
-            let content = std::fs::read_to_string(path)?;
+            let content = buffer::read_file(path)?;
             RopeTextBuffer::new(Rope::from(content))
         } else {
             RopeTextBuffer::new(Rope::from(""))
@@ -61,51 +641,129 @@ impl CimEditor {
         let mut highlighter = Highlighter::new();
         highlighter.set_syntax_for_file(file_path.as_deref());
 
-        let line_count = buffer.rope().measure::<xi_rope::LinesMetric>().max(1);
-        let lines: Vec<String> = (1..=line_count).map(|n| format!("{:3}", n)).collect();
-        let line_numbers = lines.join("\n");
-
         let highlighted_lines = highlighter.highlight(buffer.rope());
 
-        Ok(Self {
+        let mut editor = Self {
             buffer,
             file_path,
+            from_stdin: false,
+            read_only: false,
             mode: EditorMode::Normal,
             command_buffer: String::new(),
             highlighter,
-            cursor_position: (0, 0),
+            cursor_position: CursorPos::default(),
             scroll_offset: 0,
             horizontal_offset: 0,
             viewport_height: 0,
             viewport_width: 0,
             text_changed: true,
-            last_key_event: None,
-            line_numbers,
+            word_count_cache: Cell::new(None),
+            messages: MessageQueue::default(),
+            variables: Variables::default(),
+            sourced_scripts: Vec::new(),
+            source_depth: 0,
+            current_script: None,
+            keymap: Keymap::default(),
+            buffer_keymap: Keymap::buffer_local(),
+            pending_map_keys: Vec::new(),
+            noremap: false,
+            map_depth: 0,
+            overlay: None,
+            pending_key: None,
+            pending_count: 0,
+            pending_operator: None,
+            pending_register: None,
+            pending_surround: None,
+            window_command_start: None,
+            half_page_lines: 0,
+            marks: HashMap::new(),
+            registers: Registers::default(),
+            search_query: None,
+            search_backward: false,
+            search_matches: Vec::new(),
+            preview_rope: None,
+            preview_matches: Vec::new(),
+            spell_checker: None,
+            spell_errors: Vec::new(),
+            search_highlighted: false,
+            options: EditorOptions::default(),
+            config: AppConfig::default(),
+            color_support: ColorSupport::TrueColor,
+            subst_pending: None,
+            completion_state: None,
+            command_completion: None,
+            preview_theme: None,
+            popups: Vec::new(),
+            next_popup_id: 0,
+            completion_popup: None,
+            register_popup: None,
+            pending_ctrl_x: false,
+            pending_digraph: None,
+            pending_unicode: None,
+            flashed_at: None,
+            replace_undo_chars: Vec::new(),
+            abbreviations: HashMap::new(),
+            snippets: HashMap::new(),
+            defined_snippets: HashMap::new(),
+            snippet_session: None,
+            command_abbreviations: HashMap::new(),
+            quickfix_list: Vec::new(),
+            quickfix_index: 0,
+            quickfix_open: false,
+            quickfix_focused: false,
+            quickfix_cursor: 0,
+            sign_definitions: HashMap::new(),
+            signs: HashMap::new(),
+            virtual_texts: HashMap::new(),
+            folds: Vec::new(),
+            diff_mode: false,
+            diff_hunks: Vec::new(),
+            diff_pane: None,
+            arg_list: Vec::new(),
+            oldfiles: Vec::new(),
+            alternate_file: None,
+            filetype: String::new(),
             highlighted_lines,
-        })
+        };
+
+        editor.sign_define("GitAdd", "+", Style::default().fg(Color::Green));
+        editor.sign_define("GitChange", "~", Style::default().fg(Color::Yellow));
+        editor.sign_define("GitDelete", "_", Style::default().fg(Color::Red));
+        editor.sign_define("DiagnosticError", "E>", Style::default().fg(Color::Red));
+        editor.sign_define("DiagnosticWarn", "W>", Style::default().fg(Color::Yellow));
+        editor.update_git_signs();
+        editor.detect_filetype();
+        editor.arg_list = editor.file_path.iter().cloned().collect();
+        Ok(editor)
     }
-    pub fn update_line_numbers(&mut self) {
-        let new_line_count = self.buffer.rope().measure::<xi_rope::LinesMetric>();
-        let current_lines = self.line_numbers.lines().count();
-        
-        if new_line_count == current_lines {
-            return;
-        }
-    
-        self.line_numbers = if new_line_count > current_lines {
-            let mut new_lines = self.line_numbers.clone();
-            for n in (current_lines + 1)..=new_line_count {
-                new_lines.push_str(&format!("\n{:4}", n));
-            }
-            new_lines
+    /// A buffer holding everything read from stdin, for `cim -` and `cmd | cim`.
+    /// It counts as modified, since no file has its contents.
+    pub fn from_stdin() -> io::Result<Self> {
+        let mut content = String::new();
+        io::stdin().read_to_string(&mut content)?;
+        let mut editor = Self::new(None)?;
+        editor.buffer = RopeTextBuffer::new(Rope::from(content));
+        editor.buffer.set_modified(true);
+        editor.from_stdin = true;
+        editor.update_after_edit();
+        Ok(editor)
+    }
+
+    /// What to call the buffer when it has no file name.
+    pub fn unnamed_label(&self) -> &'static str {
+        if self.from_stdin {
+            "[stdin]"
         } else {
-            (1..=new_line_count)
-                .map(|n| format!("{:4}", n))
-                .collect::<Vec<_>>()
-                .join("\n")
-        };
+            "[No Name]"
+        }
     }
+
     pub fn update_after_edit(&mut self) {
+        let line_count = self.total_lines();
+        self.folds.retain(|fold| fold.end < line_count);
+        if self.diff_mode {
+            self.update_diff();
+        }
         self.text_changed = true;
         self.normalize_cursor();
         self.update_viewport();
@@ -115,8 +773,8 @@ impl CimEditor {
             return;
         }
 
-        let (x, y) = self.cursor_position;
-        let line_count = self.buffer.rope().measure::<xi_rope::LinesMetric>();
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line_count = self.total_lines();
 
         if line_count == 0 {
             return;
@@ -145,20 +803,11 @@ impl CimEditor {
         }
 
         if direction > 0 {
-            let mut new_x = current_x;
-
-            if chars[new_x].is_alphanumeric() || chars[new_x] == '_' {
-                while new_x < chars.len() && (chars[new_x].is_alphanumeric() || chars[new_x] == '_')
-                {
-                    new_x += 1;
-                }
-            }
-
-            while new_x < chars.len() && !(chars[new_x].is_alphanumeric() || chars[new_x] == '_') {
-                new_x += 1;
-            }
-
-            self.cursor_position = (new_x as u16, y);
+            // Past the last word, the cursor stops on the buffer's last character.
+            self.cursor_position = self.next_word_start(self.cursor_position).unwrap_or_else(|| {
+                let last_line = line_count - 1;
+                CursorPos::new(self.max_cursor_column(last_line) as u16, last_line as u16)
+            });
         } else {
             if current_x == 0 {
                 return;
@@ -174,87 +823,255 @@ impl CimEditor {
                 new_x -= 1;
             }
 
-            self.cursor_position = (new_x as u16, y);
+            self.cursor_position = CursorPos::new(new_x as u16, y);
+        }
+
+        self.update_viewport();
+    }
+
+    /// Where `w` from `(x, y)` goes: the next word on the line, or else the first
+    /// word of a later line, where an empty line counts as a word. `None` past the
+    /// buffer's last word.
+    fn next_word_start(&self, CursorPos { col: x, row: y }: CursorPos) -> Option<CursorPos> {
+        let is_word_char = |c: char| c.is_alphanumeric() || c == '_';
+        let chars: Vec<char> = self.line_text(y as usize).chars().collect();
+        let mut new_x = x as usize;
+        if chars.get(new_x).is_some_and(|&c| is_word_char(c)) {
+            while new_x < chars.len() && is_word_char(chars[new_x]) {
+                new_x += 1;
+            }
+        }
+        while new_x < chars.len() && !is_word_char(chars[new_x]) {
+            new_x += 1;
+        }
+        if new_x < chars.len() {
+            return Some(CursorPos::new(new_x as u16, y));
+        }
+        (y as usize + 1..self.total_lines()).find_map(|line| {
+            let text = self.line_text(line);
+            let first = self.first_non_blank(line);
+            let is_word = text.is_empty() || first < text.chars().count();
+            is_word.then_some(CursorPos::new(first as u16, line as u16))
+        })
+    }
+
+    /// Moves to the start of the next/previous WORD, where only whitespace separates WORDs.
+    pub fn move_cursor_big_word(&mut self, direction: i16) {
+        let chars: Vec<char> = self.line_text(self.cursor_position.row as usize).chars().collect();
+        let x = self.cursor_position.col as usize;
+        if chars.is_empty() || x >= chars.len() || direction == 0 {
+            return;
+        }
+
+        let mut new_x = x;
+        if direction > 0 {
+            while new_x < chars.len() && !chars[new_x].is_whitespace() {
+                new_x += 1;
+            }
+            while new_x < chars.len() && chars[new_x].is_whitespace() {
+                new_x += 1;
+            }
+            if new_x == chars.len() {
+                return;
+            }
+        } else {
+            while new_x > 0 && chars[new_x - 1].is_whitespace() {
+                new_x -= 1;
+            }
+            while new_x > 0 && !chars[new_x - 1].is_whitespace() {
+                new_x -= 1;
+            }
+        }
+
+        self.cursor_position.col = new_x as u16;
+        self.update_viewport();
+    }
+
+    /// Moves to the end of the current/next WORD (`E`) or of the previous WORD (`gE`).
+    pub fn move_cursor_big_word_end(&mut self, direction: i16) {
+        let chars: Vec<char> = self.line_text(self.cursor_position.row as usize).chars().collect();
+        let x = self.cursor_position.col as usize;
+        if chars.is_empty() || x >= chars.len() || direction == 0 {
+            return;
+        }
+
+        let mut new_x = x;
+        if direction > 0 {
+            new_x += 1;
+            while new_x < chars.len() && chars[new_x].is_whitespace() {
+                new_x += 1;
+            }
+            while new_x + 1 < chars.len() && !chars[new_x + 1].is_whitespace() {
+                new_x += 1;
+            }
+            if new_x >= chars.len() {
+                return;
+            }
+        } else {
+            while new_x > 0 && !chars[new_x].is_whitespace() {
+                new_x -= 1;
+            }
+            if !chars[new_x].is_whitespace() {
+                return;
+            }
+            while new_x > 0 && chars[new_x].is_whitespace() {
+                new_x -= 1;
+            }
+            if chars[new_x].is_whitespace() {
+                return;
+            }
         }
 
+        self.cursor_position.col = new_x as u16;
         self.update_viewport();
     }
 
     pub fn normalize_cursor(&mut self) {
-        let (x, y) = self.cursor_position;
-        let line_count = self.buffer.rope().measure::<xi_rope::LinesMetric>();
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line_count = self.total_lines();
 
         let new_y = if line_count == 0 {
             0
         } else {
             y.min(line_count as u16 - 1)
         };
+        // The text inside a closed fold can't hold the cursor; its first line does.
+        let new_y = self
+            .closed_fold_at(new_y as usize)
+            .map_or(new_y, |fold| fold.start as u16);
 
         let new_x = if new_y != y {
             0
-        } else if line_count > 0 {
-            let line_start = self.buffer.rope().offset_of_line(new_y as usize);
-            let line_end = self.buffer.rope().offset_of_line(new_y as usize + 1);
-            let line = self.buffer.rope().slice(line_start..line_end).to_string();
-            let line_length = line.trim_end_matches(&['\r', '\n'][..]).chars().count();
-
-            if self.mode == EditorMode::Normal && line_length > 0 {
-                x.min(line_length as u16 - 1)
-            } else {
-                x.min(line_length as u16)
-            }
         } else {
-            0
+            x.min(self.max_cursor_column(new_y as usize) as u16)
         };
 
-        self.cursor_position = (new_x, new_y);
+        self.cursor_position = CursorPos::new(new_x, new_y);
         self.update_viewport();
     }
     pub fn save(&mut self) -> io::Result<()> {
-        if let Some(path) = &self.file_path {
-            self.buffer.save_to_file(path)?;
-            self.text_changed = false;
+        if self.read_only {
+            return Err(io::Error::other(
+                "E45: 'readonly' option is set (add ! to override)",
+            ));
         }
+        let Some(path) = self.file_path.clone() else {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "E32: No file name"));
+        };
+        self.run_autocmds(AutocmdEvent::BufWrite, &path.to_string_lossy());
+        self.buffer.save_to_file(&path, self.options.ensure_trailing_newline)?;
+        self.buffer.set_modified(false);
+        self.notify(
+            &format!("\"{}\" {}L written", path.display(), self.total_lines()),
+            MessageKind::Info,
+        );
+        self.update_git_signs();
         Ok(())
     }
 
-    pub fn move_cursor(&mut self, direction: (i16, i16)) {
-        let (mut x, mut y) = self.cursor_position;
-        let total_lines = self.buffer.rope().measure::<xi_rope::LinesMetric>();
-        let max_y = total_lines.saturating_sub(1) as u16;
+    /// Runs a `--cmd`/`+cmd` startup command: an ex command, or a search for `/pattern`.
+    pub fn run_startup_command(&mut self, cmd: &str) {
+        match cmd.strip_prefix('/') {
+            Some(pattern) => {
+                self.search_backward = false;
+                self.start_search(pattern.to_string());
+            }
+            None => {
+                self.execute_command(cmd);
+            }
+        }
+    }
 
-        y = y.saturating_add_signed(direction.1).min(max_y);
+    /// `:w file`: names an unnamed buffer and saves it, or writes a copy of a
+    /// named one without renaming it.
+    fn write_to(&mut self, path: PathBuf) {
+        if self.file_path.is_none() {
+            self.highlighter.set_syntax_for_file(Some(&path));
+            self.file_path = Some(path);
+            self.detect_filetype();
+            self.run_filetype_plugins();
+            self.from_stdin = false;
+            self.text_changed = true;
+            if let Err(e) = self.save() {
+                self.notify(&e.to_string(), MessageKind::Error);
+            }
+            return;
+        }
+        match self.buffer.save_to_file(&path, self.options.ensure_trailing_newline) {
+            Ok(()) => self.notify(
+                &format!("\"{}\" {}L written", path.display(), self.total_lines()),
+                MessageKind::Info,
+            ),
+            Err(e) => self.notify(
+                &format!("E212: Can't open file for writing: {}", e),
+                MessageKind::Error,
+            ),
+        }
+    }
 
-        let line_start = self.buffer.rope().offset_of_line(y as usize);
-        let next_line_start = self.buffer.rope().offset_of_line(y as usize + 1);
-        let is_last_line = (y as usize) == (total_lines - 1);
+    pub fn file_info(&self) -> String {
+        let path = self
+            .file_path
+            .as_ref()
+            .map(|p| std::fs::canonicalize(p).unwrap_or_else(|_| p.clone()))
+            .map(|p| p.display().to_string())
+            .unwrap_or_else(|| self.unnamed_label().to_string());
 
-        let line_slice = if is_last_line {
-            self.buffer.rope().slice(line_start..next_line_start)
-        } else {
-            self.buffer.rope().slice(line_start..next_line_start - 1)
-        };
-        let line_char_len = line_slice.to_string().chars().count() as u16;
+        let read_only = self.read_only
+            || self
+                .file_path
+                .as_ref()
+                .and_then(|p| std::fs::metadata(p).ok())
+                .is_some_and(|m| m.permissions().readonly());
 
-        let line = self
-            .buffer
-            .rope()
-            .slice(line_start..next_line_start)
-            .to_string();
-        let max_x = line.chars().count().saturating_sub(1) as u16;
+        let mut flags = String::new();
+        if self.buffer.is_modified() {
+            flags.push_str(" [Modified]");
+        }
+        if read_only {
+            flags.push_str(" [readonly]");
+        }
 
+        format!(
+            "\"{}\"{} {} lines, {} bytes -- line {}, col {} [utf-8] [{}]",
+            path,
+            flags,
+            self.total_lines(),
+            self.buffer.rope().len(),
+            self.cursor_position.row + 1,
+            self.cursor_position.col + 1,
+            self.buffer.line_ending()
+        )
+    }
+
+    pub fn move_cursor(&mut self, direction: (i16, i16)) {
+        let CursorPos { col: mut x, row: mut y } = self.cursor_position;
+        let total_lines = self.total_lines();
+        let max_y = total_lines.saturating_sub(1) as u16;
+
+        if self.folds.is_empty() {
+            y = y.saturating_add_signed(direction.1).min(max_y);
+        } else {
+            // A closed fold counts as one line.
+            for _ in 0..direction.1.unsigned_abs() {
+                y = self.next_visible_line(y as usize, direction.1 > 0).min(max_y as usize) as u16;
+            }
+        }
+
+        let max_x = self.max_cursor_column(y as usize) as u16;
         x = x.saturating_add_signed(direction.0).min(max_x);
 
-        self.cursor_position = (x, y);
+        self.cursor_position = CursorPos::new(x, y);
         self.update_viewport();
     }
 
     pub fn update_viewport(&mut self) {
-        let (x, y) = self.cursor_position;
-        let line_count = self.buffer.rope().measure::<xi_rope::LinesMetric>();
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line_count = self.total_lines();
         
+        let (margin, h_margin) = self.scroll_margins();
+
         // Vertical scrolling
-        let margin = 2.min(self.viewport_height / 4);
         self.scroll_offset = match y {
             y if y < (self.scroll_offset + margin) as u16 => 
                 y.saturating_sub(margin as u16) as usize,
@@ -264,7 +1081,6 @@ impl CimEditor {
         }.min(line_count.saturating_sub(self.viewport_height));
     
         // Horizontal scrolling
-        let h_margin = 5.min(self.viewport_width / 4);
         self.horizontal_offset = match x {
             x if x < (self.horizontal_offset + h_margin) as u16 => 
                 x.saturating_sub(h_margin as u16) as usize,
@@ -274,29 +1090,383 @@ impl CimEditor {
         };
     }
 
+    /// Columns taken by the line numbers: enough for the last line's number, and
+    /// one more for the space or fold marker after it.
+    pub fn gutter_width(&self) -> u16 {
+        self.total_lines().to_string().len() as u16 + 1
+    }
+
+    /// Columns left for the buffer's text in a terminal `width` wide, after the sign
+    /// column and line numbers. Diff mode gives the other file the right half.
+    pub fn text_width(&self, width: u16) -> u16 {
+        let sign_width = if self.shows_sign_column() { 2 } else { 0 };
+        let text_width = width.saturating_sub(self.gutter_width() + sign_width);
+        if self.diff_pane.is_some() {
+            text_width / 2
+        } else {
+            text_width
+        }
+    }
+
+    /// Rows taken by the quickfix window, with its title, in a terminal `height`
+    /// tall. The text always keeps at least one row.
+    pub fn quickfix_rows(&self, height: u16) -> u16 {
+        if !self.quickfix_open {
+            return 0;
+        }
+        (self.options.quickfix_height as u16 + 1).min(height.saturating_sub(3))
+    }
+
+    /// Adopts a new terminal size, scrolling so the cursor stays on screen.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        self.viewport_height = height.saturating_sub(2 + self.quickfix_rows(height)) as usize;
+        self.viewport_width = self.text_width(width) as usize;
+        self.scroll_offset = self.scroll_offset.min(self.max_scroll_offset());
+        self.normalize_cursor();
+        self.text_changed = true;
+    }
+
+    /// Rows and columns kept between the cursor and the viewport edges.
+    fn scroll_margins(&self) -> (usize, usize) {
+        (2.min(self.viewport_height / 4), 5.min(self.viewport_width / 4))
+    }
+
+    fn max_scroll_offset(&self) -> usize {
+        self.total_lines().saturating_sub(self.viewport_height)
+    }
+
+    /// Scrolls the view by `delta` lines, dragging the cursor along only if it
+    /// would otherwise leave the visible area.
+    pub fn scroll_viewport(&mut self, delta: i32) {
+        let max_scroll = self.max_scroll_offset();
+        self.scroll_offset = (self.scroll_offset as i64 + delta as i64).clamp(0, max_scroll as i64)
+            as usize;
+
+        let y = self.cursor_position.row as usize;
+        let clamped = self.clamp_to_visible_lines(y);
+        if clamped != y {
+            self.cursor_position.row = clamped as u16;
+            self.normalize_cursor();
+        }
+    }
+
+    /// Clamps `line` to the lines the cursor can occupy without the view scrolling.
+    fn clamp_to_visible_lines(&self, line: usize) -> usize {
+        let (margin, _) = self.scroll_margins();
+        let first_visible = if self.scroll_offset == 0 {
+            0
+        } else {
+            self.scroll_offset + margin
+        };
+        let last_visible = if self.scroll_offset >= self.max_scroll_offset() {
+            self.total_lines() - 1
+        } else {
+            (self.scroll_offset + self.viewport_height).saturating_sub(margin + 1)
+        };
+        line.clamp(first_visible, last_visible.max(first_visible))
+    }
+
+    /// Moves the cursor to the `offset`-th line from the top of the screen.
+    pub fn cursor_to_screen_top(&mut self, offset: usize) {
+        let target = self.clamp_to_visible_lines(self.scroll_offset + offset);
+        self.cursor_position.row = target as u16;
+        self.normalize_cursor();
+    }
+
+    pub fn cursor_to_screen_middle(&mut self) {
+        let shown_lines = self
+            .viewport_height
+            .min(self.total_lines() - self.scroll_offset);
+        let middle = self.scroll_offset + shown_lines.saturating_sub(1) / 2;
+        let target = self.clamp_to_visible_lines(middle);
+        self.cursor_position.row = target as u16;
+        self.normalize_cursor();
+    }
+
+    /// Moves the cursor to the `offset`-th line from the bottom of the screen.
+    pub fn cursor_to_screen_bottom(&mut self, offset: usize) {
+        let bottom = (self.scroll_offset + self.viewport_height)
+            .min(self.total_lines())
+            .saturating_sub(1);
+        let target = self.clamp_to_visible_lines(bottom.saturating_sub(offset));
+        self.cursor_position.row = target as u16;
+        self.normalize_cursor();
+    }
+
+    pub fn scroll_cursor_to_top(&mut self) {
+        let (margin, _) = self.scroll_margins();
+        let y = self.cursor_position.row as usize;
+        self.scroll_offset = y.saturating_sub(margin).min(self.max_scroll_offset());
+    }
+
+    pub fn scroll_cursor_to_center(&mut self) {
+        let y = self.cursor_position.row as usize;
+        self.scroll_offset = y
+            .saturating_sub(self.viewport_height / 2)
+            .min(self.max_scroll_offset());
+    }
+
+    pub fn scroll_cursor_to_bottom(&mut self) {
+        let (margin, _) = self.scroll_margins();
+        let y = self.cursor_position.row as usize;
+        self.scroll_offset = (y + margin)
+            .saturating_sub(self.viewport_height)
+            .min(self.max_scroll_offset());
+    }
+
+    pub fn scroll_cursor_to_left(&mut self) {
+        let (_, h_margin) = self.scroll_margins();
+        self.horizontal_offset = (self.cursor_position.col as usize).saturating_sub(h_margin);
+    }
+
+    pub fn scroll_cursor_to_right(&mut self) {
+        let (_, h_margin) = self.scroll_margins();
+        self.horizontal_offset =
+            (self.cursor_position.col as usize + h_margin).saturating_sub(self.viewport_width);
+    }
+
 
     pub fn handle_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        match self.map_mode() {
+            Some(mode) => self.handle_mapped_input(mode, key),
+            None => self.handle_unmapped_input(key),
+        }
+    }
+
+    /// The mode whose mappings apply to the next key, or `None` while keys skip
+    /// mappings: inside a `noremap` mapping, in an overlay, and for the key
+    /// that completes a command such as `g` or Ctrl-K.
+    fn map_mode(&self) -> Option<MapMode> {
+        if self.noremap
+            || self.overlay.is_some()
+            || self.pending_key.is_some()
+            || self.pending_surround.is_some()
+            || self.pending_digraph.is_some()
+            || self.pending_unicode.is_some()
+        {
+            return None;
+        }
         match self.mode {
-            EditorMode::Normal | EditorMode::Command => {
-                if let Some(action) = handle_key_event(key) {
-                    self.handle_action(action)
-                } else {
-                    None
-                }
+            EditorMode::Normal => Some(MapMode::Normal),
+            EditorMode::Insert | EditorMode::Replace | EditorMode::SnippetExpand => {
+                Some(MapMode::Insert)
             }
-            EditorMode::Insert => match key {
-                KeyEvent {
-                    code: KeyCode::Esc, ..
-                } => {
-                    self.mode = EditorMode::Normal;
-                    Some(EditorAction::ChangeMode(false))
+            _ => None,
+        }
+    }
+
+    /// Collects keys while they start the `lhs` of a mapping in `mode`, then runs
+    /// the mapping's `rhs` in their place, or the keys themselves when none
+    /// matches. There is no timeout: a key that can't continue any `lhs` decides.
+    fn handle_mapped_input(&mut self, mode: MapMode, key: KeyEvent) -> Option<EditorAction> {
+        self.pending_map_keys.push(key);
+        let merged;
+        let keymap = if self.buffer_keymap.is_empty() {
+            &self.keymap
+        } else {
+            merged = self.keymap.with_buffer_override(&self.buffer_keymap);
+            &merged
+        };
+        let mapping = match keymap.lookup(mode, &self.pending_map_keys) {
+            Lookup::Pending => return None,
+            Lookup::Done(mapping) => mapping.map(|mapping| {
+                let lhs = mapping.keys.clone();
+                (lhs, keymap::parse_keys(&mapping.rhs), mapping.noremap)
+            }),
+        };
+        let mut typed = std::mem::take(&mut self.pending_map_keys);
+
+        let (mut action, rest) = match mapping {
+            Some((lhs, rhs, noremap)) => {
+                let rest = typed.split_off(lhs.len());
+                // An `rhs` that starts with its own `lhs`, as in `:nmap n nzz`,
+                // runs those keys as they are rather than mapping them forever.
+                let literal = if rhs.starts_with(&lhs) { lhs.len() } else { 0 };
+                let action = self.feed_keys(&rhs[..literal], false);
+                if is_final_action(&action) {
+                    return action;
                 }
-                KeyEvent {
-                    code: KeyCode::Backspace,
+                (self.feed_keys(&rhs[literal..], !noremap).or(action), rest)
+            }
+            None => {
+                let rest = typed.split_off(1);
+                (self.handle_unmapped_input(typed[0]), rest)
+            }
+        };
+        for key in rest {
+            if is_final_action(&action) {
+                break;
+            }
+            action = self.handle_input(key).or(action);
+        }
+        action
+    }
+
+    /// Runs `keys` as if typed, mapping them again if `remap` is set.
+    fn feed_keys(&mut self, keys: &[KeyEvent], remap: bool) -> Option<EditorAction> {
+        if self.map_depth >= MAX_MAP_DEPTH {
+            self.notify("E223: Recursive mapping", MessageKind::Error);
+            return None;
+        }
+        self.map_depth += 1;
+        let noremap = std::mem::replace(&mut self.noremap, !remap);
+        let mut action = None;
+        for &key in keys {
+            action = self.handle_input(key).or(action);
+            if is_final_action(&action) {
+                break;
+            }
+        }
+        self.noremap = noremap;
+        self.map_depth -= 1;
+        action
+    }
+
+    fn handle_unmapped_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        if key.code == KeyCode::Esc {
+            self.popups.clear();
+        }
+        let mode = self.mode;
+        let result = self.dispatch_input(key);
+        // Other modes act on their keys directly rather than through
+        // `handle_action`, which logs Normal mode's actions.
+        if let (false, Some(action)) = (mode == EditorMode::Normal, &result) {
+            log::debug!("{} in {:?} mode", action, mode);
+        }
+        let cursor = self.cursor_position;
+        self.popups.retain(|popup| popup.opened_at == cursor);
+        self.update_completion_popup();
+        result
+    }
+
+    /// Shows `content` in a popup hanging from `anchor` and returns its id for
+    /// `hide_popup`.
+    pub fn show_popup(&mut self, content: Text<'static>, anchor: CursorPos) -> usize {
+        self.next_popup_id += 1;
+        let size = (content.width() as u16, content.height() as u16);
+        self.popups.push(PopupWindow {
+            id: self.next_popup_id,
+            content,
+            position: anchor,
+            size,
+            opened_at: self.cursor_position,
+        });
+        self.next_popup_id
+    }
+
+    pub fn hide_popup(&mut self, id: usize) {
+        self.popups.retain(|popup| popup.id != id);
+    }
+
+    /// Shows the completion menu from `completion_state`, or hides it once
+    /// completion ends.
+    fn update_completion_popup(&mut self) {
+        if let Some(id) = self.completion_popup.take() {
+            self.hide_popup(id);
+        }
+        let Some(completion) = &self.completion_state else {
+            return;
+        };
+        let menu = completion.menu();
+        let anchor = CursorPos::new(
+            (completion.start as u16).saturating_sub(1),
+            self.cursor_position.row,
+        );
+        self.completion_popup = Some(self.show_popup(menu, anchor));
+    }
+
+    /// Lists the registers in a popup while `"` waits for a register name.
+    fn show_register_popup(&mut self) {
+        let lines: Vec<Spans> = self
+            .registers_summary()
+            .into_iter()
+            .map(|line| Spans::from(format!(" {} ", line)))
+            .collect();
+        if !lines.is_empty() {
+            self.register_popup = Some(self.show_popup(Text::from(lines), self.cursor_position));
+        }
+    }
+
+    /// `K`: shows the signs and quickfix messages on the cursor line.
+    fn show_hover(&mut self) {
+        let line = self.cursor_position.row as usize;
+        let mut lines: Vec<Spans> = self
+            .signs
+            .get(&line)
+            .into_iter()
+            .flatten()
+            .map(|sign| {
+                Spans::from(vec![
+                    Span::styled(format!(" {} ", sign.text), sign.highlight),
+                    Span::raw(format!("{} ", sign.name)),
+                ])
+            })
+            .collect();
+        for entry in &self.quickfix_list {
+            if entry.line == line + 1 && self.is_current_file(&entry.file) {
+                lines.push(Spans::from(format!(" {} ", entry.message.trim())));
+            }
+        }
+
+        if lines.is_empty() {
+            self.notify("Nothing to show on this line", MessageKind::Info);
+        } else {
+            self.show_popup(Text::from(lines), self.cursor_position);
+        }
+    }
+
+    fn dispatch_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        self.messages.dismiss();
+
+        if self.overlay.is_some() {
+            return self.handle_overlay_input(key);
+        }
+        if self.quickfix_focused && self.mode == EditorMode::Normal {
+            return self.handle_quickfix_input(key);
+        }
+
+        match self.mode {
+            EditorMode::Normal => self.handle_normal_input(key),
+            EditorMode::Command | EditorMode::Search => self.handle_command_input(key),
+            EditorMode::SubstConfirm => self.handle_subst_confirm_input(key),
+            EditorMode::Replace => self.handle_replace_input(key),
+            EditorMode::SnippetExpand => self.handle_snippet_input(key),
+            EditorMode::Insert if self.completion_state.is_some() => {
+                self.handle_completion_input(key)
+            }
+            EditorMode::Insert if self.pending_ctrl_x => {
+                self.pending_ctrl_x = false;
+                match key {
+                    KeyEvent {
+                        code: KeyCode::Char('l'),
+                        modifiers: KeyModifiers::CONTROL,
+                        ..
+                    } => {
+                        self.start_line_completion();
+                        None
+                    }
+                    key => self.dispatch_input(key),
+                }
+            }
+            EditorMode::Insert if self.pending_digraph.is_some() => {
+                self.handle_digraph_input(key)
+            }
+            EditorMode::Insert if self.pending_unicode.is_some() => {
+                self.handle_unicode_input(key)
+            }
+            EditorMode::Insert => match key {
+                KeyEvent {
+                    code: KeyCode::Esc, ..
+                } => {
+                    self.change_mode(false);
+                    Some(EditorAction::ChangeMode(false))
+                }
+                KeyEvent {
+                    code: KeyCode::Backspace,
                     ..
                 } => {
                     self.delete_char();
-                    Some(EditorAction::Backspace)
+                    Some(EditorAction::DeleteBack)
                 }
                 KeyEvent {
                     code: KeyCode::Char(c),
@@ -306,14 +1476,82 @@ impl CimEditor {
                     self.insert_char(c);
                     Some(EditorAction::InsertChar(c))
                 }
+                KeyEvent {
+                    code: KeyCode::Enter,
+                    modifiers: KeyModifiers::NONE,
+                    ..
+                } => {
+                    self.insert_char('\n');
+                    Some(EditorAction::InsertChar('\n'))
+                }
                 KeyEvent {
                     code: KeyCode::Tab,
                     modifiers: KeyModifiers::NONE,
                     ..
                 } => {
+                    if self.expand_snippet_before_cursor() {
+                        return None;
+                    }
                     self.insert_tab();
                     Some(EditorAction::Tab)
                 }
+                KeyEvent {
+                    code: KeyCode::Char(c @ ('n' | 'p')),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.start_word_completion(c == 'p');
+                    None
+                }
+                KeyEvent {
+                    code: KeyCode::Char('k'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.pending_digraph = Some(None);
+                    None
+                }
+                KeyEvent {
+                    code: KeyCode::Char('v'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.pending_unicode = Some(String::new());
+                    None
+                }
+                KeyEvent {
+                    code: KeyCode::Char('t'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.transpose_chars_at_cursor();
+                    Some(EditorAction::TransposeChars)
+                }
+                KeyEvent {
+                    code: KeyCode::Char('w'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.delete_word_before_cursor();
+                    None
+                }
+                KeyEvent {
+                    code: KeyCode::Char('u'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.delete_to_line_start();
+                    None
+                }
+                KeyEvent {
+                    code: KeyCode::Char('x'),
+                    modifiers: KeyModifiers::CONTROL,
+                    ..
+                } => {
+                    self.pending_ctrl_x = true;
+                    self.notify("-- ^X mode (^L)", MessageKind::Info);
+                    None
+                }
                 KeyEvent {
                     code: KeyCode::Up,
                     modifiers: KeyModifiers::NONE,
@@ -354,84 +1592,4389 @@ impl CimEditor {
         }
     }
 
+    /// Keys while the completion popup is open. Anything other than cycling,
+    /// accepting or cancelling closes the popup and is handled as usual.
+    fn handle_completion_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        let state = self.completion_state.as_mut()?;
+        match (key.code, key.modifiers) {
+            (KeyCode::Char('n'), KeyModifiers::CONTROL) => state.select_next(),
+            (KeyCode::Char('p'), KeyModifiers::CONTROL) => state.select_previous(),
+            (KeyCode::Enter, _) => self.accept_completion(),
+            (KeyCode::Char(' '), _) => {
+                self.accept_completion();
+                self.insert_char(' ');
+            }
+            (KeyCode::Esc, _) => self.completion_state = None,
+            _ => {
+                self.completion_state = None;
+                return self.dispatch_input(key);
+            }
+        }
+        None
+    }
+
+    fn handle_replace_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        match (key.code, key.modifiers) {
+            (KeyCode::Esc, _) => {
+                self.mode = EditorMode::Normal;
+                self.replace_undo_chars.clear();
+                self.normalize_cursor();
+                Some(EditorAction::ChangeMode(false))
+            }
+            (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
+                self.replace_char_at_cursor(c);
+                None
+            }
+            (KeyCode::Backspace, _) => {
+                self.undo_replace_char();
+                None
+            }
+            (KeyCode::Enter, _) => {
+                // A line break is inserted rather than replacing anything, and
+                // Backspace does not reach back across it.
+                self.insert_char('\n');
+                self.replace_undo_chars.clear();
+                None
+            }
+            (KeyCode::Left | KeyCode::Right | KeyCode::Up | KeyCode::Down, _) => {
+                let direction = match key.code {
+                    KeyCode::Left => (-1, 0),
+                    KeyCode::Right => (1, 0),
+                    KeyCode::Up => (0, -1),
+                    _ => (0, 1),
+                };
+                self.move_cursor(direction);
+                self.replace_undo_chars.clear();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The two characters after Ctrl-K. A known digraph inserts its character;
+    /// otherwise both characters are inserted as typed.
+    fn handle_digraph_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        let pending = self.pending_digraph.take()?;
+        let KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } = key
+        else {
+            return match key.code {
+                KeyCode::Esc => None,
+                _ => self.dispatch_input(key),
+            };
+        };
+
+        let Some(first) = pending else {
+            self.pending_digraph = Some(Some(c));
+            return None;
+        };
+        match digraph::lookup(first, c) {
+            Some(digraph) => self.insert_char(digraph),
+            None => {
+                self.insert_char(first);
+                self.insert_char(c);
+            }
+        }
+        None
+    }
+
+    /// The keys after Ctrl-V in Insert mode: `u` and up to 4 hex digits, or `U`
+    /// and up to 8, insert that codepoint once all are typed or Enter or another
+    /// key ends them. Any other key after Ctrl-V is inserted as it is.
+    fn handle_unicode_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        let mut pending = self.pending_unicode.take()?;
+        let c = match key.code {
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => Some(c),
+            KeyCode::Tab => Some('\t'),
+            _ => None,
+        };
+        let max_digits = match pending.chars().next() {
+            Some('u') => 4,
+            Some('U') => 8,
+            _ => {
+                match c {
+                    Some(c @ ('u' | 'U')) => self.pending_unicode = Some(c.to_string()),
+                    Some(c) => self.insert_char(c),
+                    None if key.code == KeyCode::Esc => {}
+                    None => return self.dispatch_input(key),
+                }
+                return None;
+            }
+        };
+
+        if let Some(digit) = c.filter(char::is_ascii_hexdigit) {
+            pending.push(digit);
+            if pending.len() <= max_digits {
+                self.pending_unicode = Some(pending);
+                return None;
+            }
+        }
+        let codepoint = u32::from_str_radix(&pending[1..], 16).ok().and_then(char::from_u32);
+        match codepoint {
+            Some(codepoint) => self.insert_char(codepoint),
+            None => self.beep(),
+        }
+        match key.code {
+            KeyCode::Enter | KeyCode::Esc => None,
+            _ if c.is_some_and(|c| c.is_ascii_hexdigit()) => None,
+            _ => self.dispatch_input(key),
+        }
+    }
+
+    /// Flashes the status bar, for a key that can't do anything.
+    pub fn beep(&mut self) {
+        self.flashed_at = Some(Instant::now());
+    }
+
+    /// How long the status bar has left to flash, for the redraw that ends it.
+    pub fn flash_time_left(&self) -> Option<Duration> {
+        FLASH_DURATION.checked_sub(self.flashed_at?.elapsed())
+    }
+
+    fn handle_normal_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        if let Some(id) = self.register_popup.take() {
+            self.hide_popup(id);
+        }
+        if let Some(pending) = self.pending_surround.take() {
+            self.handle_surround_input(pending, key);
+            return None;
+        }
+
+        if let Some((cursor, scroll_offset, count)) = self.window_command_start.take() {
+            if let Some(action) = handle_key_sequence(CTRL_W, key) {
+                // The Ctrl-W before this key started a window command rather than
+                // moving a word.
+                self.cursor_position = cursor;
+                self.scroll_offset = scroll_offset;
+                self.pending_count = count;
+                return self.run_normal_action(Some(action));
+            }
+        }
+        // Ctrl-W moves a word at once, but can still turn into a window command.
+        let window_command_start = (self.pending_key.is_none()
+            && self.pending_operator.is_none()
+            && key.code == KeyCode::Char('w')
+            && key.modifiers == KeyModifiers::CONTROL)
+            .then_some((self.cursor_position, self.scroll_offset, self.pending_count));
+
+        let action = if let Some(prefix) = self.pending_key.take() {
+            handle_key_sequence(prefix, key)
+        } else {
+            if let KeyEvent {
+                code: KeyCode::Char(digit @ '0'..='9'),
+                modifiers: KeyModifiers::NONE,
+                ..
+            } = key
+            {
+                if digit != '0' || self.pending_count > 0 {
+                    let value = digit.to_digit(10).unwrap_or(0) as usize;
+                    self.pending_count = self.pending_count.saturating_mul(10) + value;
+                    return None;
+                }
+            }
+            if let Some(prefix) = prefix_key(key) {
+                self.pending_key = Some(prefix);
+                if prefix == '"' {
+                    self.show_register_popup();
+                }
+                return None;
+            }
+            handle_key_event(key)
+        };
+        let result = self.run_normal_action(action);
+        self.window_command_start = window_command_start;
+        result
+    }
+
+    /// Runs the action for a Normal mode key with the pending count, operator and
+    /// register.
+    fn run_normal_action(&mut self, action: Option<EditorAction>) -> Option<EditorAction> {
+        let count = std::mem::take(&mut self.pending_count);
+        let Some(action) = action else {
+            self.pending_operator = None;
+            self.pending_register = None;
+            return None;
+        };
+
+        // `gUU` and `guu` repeat the operator's last key to mean the whole line.
+        let action = match (self.pending_operator, action) {
+            (Some((Operator::Uppercase, _)), EditorAction::InsertChar('U')) => {
+                EditorAction::Operator(Operator::Uppercase)
+            }
+            (Some((Operator::Lowercase, _)), EditorAction::InsertChar('u')) => {
+                EditorAction::Operator(Operator::Lowercase)
+            }
+            // `ys`, `cs` and `ds` start surround commands rather than a motion.
+            (Some((Operator::Yank, count)), EditorAction::InsertChar('s')) => {
+                self.pending_operator = Some((Operator::Surround, count));
+                return None;
+            }
+            // `yss` surrounds the cursor line.
+            (Some((Operator::Surround, _)), EditorAction::InsertChar('s')) => {
+                EditorAction::Operator(Operator::Surround)
+            }
+            (Some((Operator::Change, _)), EditorAction::InsertChar('s')) => {
+                self.pending_operator = None;
+                self.pending_surround = Some(PendingSurround::Change(None));
+                return None;
+            }
+            (Some((Operator::Delete, _)), EditorAction::InsertChar('s')) => {
+                self.pending_operator = None;
+                self.pending_surround = Some(PendingSurround::Delete);
+                return None;
+            }
+            // In diff mode `dp` and `do` move hunks between the panes instead.
+            (Some((Operator::Delete, _)), EditorAction::Paste(true)) if self.diff_mode => {
+                self.pending_operator = None;
+                EditorAction::DiffPut
+            }
+            (Some((Operator::Delete, _)), EditorAction::InsertChar('o')) if self.diff_mode => {
+                self.pending_operator = None;
+                EditorAction::DiffObtain
+            }
+            (_, action) => action,
+        };
+
+        if let EditorAction::SelectRegister(name) = action {
+            self.pending_register = Some(name);
+            return None;
+        }
+
+        if let EditorAction::Operator(operator) = action {
+            match self.pending_operator.take() {
+                // A doubled operator (`dd`, `yy`, `cc`) works on whole lines.
+                Some((pending, pending_count)) if pending == operator => {
+                    let lines = pending_count.max(1).saturating_mul(count.max(1));
+                    let first = self.cursor_position.row as usize;
+                    let last = first.saturating_add(lines - 1).min(self.total_lines() - 1);
+                    self.operate_on_lines(operator, first, last);
+                }
+                _ => self.pending_operator = Some((operator, count)),
+            }
+            return None;
+        }
+
+        if let Some((operator, operator_count)) = self.pending_operator.take() {
+            // Counts before and after the operator multiply, as in `2d3j`.
+            let count = if operator_count == 0 {
+                count
+            } else {
+                operator_count.saturating_mul(count.max(1))
+            };
+            let motion = self.apply_count(action, count);
+            self.apply_operator(operator, motion);
+            self.pending_register = None;
+            return None;
+        }
+
+        let action = self.apply_count(action, count);
+        let result = self.handle_action(action);
+        self.pending_register = None;
+        result
+    }
+
+    /// Applies `operator` to the text between the cursor and where `motion` moves it.
+    fn apply_operator(&mut self, operator: Operator, motion: EditorAction) {
+        let Some(mut kind) = motion.motion_kind() else {
+            return;
+        };
+        let start = self.cursor_position;
+        // `}` in the last paragraph, and `w` from the last word, stop on the final
+        // character, which is then included.
+        let word_forward = matches!(motion, EditorAction::MoveWord(n) if n > 0);
+        let past_last_word = word_forward && self.next_word_start(start).is_none();
+        if past_last_word
+            || matches!(motion, EditorAction::ParagraphForward)
+                && self.next_paragraph_start(start.row as usize).is_none()
+        {
+            kind = MotionKind::Inclusive;
+        }
+        self.handle_action(motion);
+        let end = self.cursor_position;
+        self.cursor_position = start;
+
+        let (first, mut last) = (start.min(end), start.max(end));
+
+        // `w` from a line's last word stops at the end of that line rather than
+        // taking in the line breaks and indent before the next word.
+        if word_forward && last.row > first.row {
+            let len = self.line_text(first.row as usize).chars().count();
+            last = CursorPos::new(len as u16, first.row);
+        }
+
+        // An exclusive motion ending in column 0 of a later line stops at the end of
+        // the line before it, and becomes linewise if it started at the indent.
+        if kind == MotionKind::Exclusive && last.row > first.row && last.col == 0 {
+            if first.col as usize <= self.first_non_blank(first.row as usize) {
+                kind = MotionKind::Linewise;
+                last.row -= 1;
+            } else {
+                let previous = last.row - 1;
+                let len = self.line_text(previous as usize).chars().count();
+                last = CursorPos::new(len as u16, previous);
+            }
+        }
+
+        let linewise_operator = matches!(
+            operator,
+            Operator::Fold | Operator::ShiftRight | Operator::ShiftLeft | Operator::Reindent
+        );
+        if kind == MotionKind::Linewise || linewise_operator {
+            self.operate_on_lines(operator, first.row as usize, last.row as usize);
+            return;
+        }
+
+        let from = self.offset_of_position(first);
+        let to = if kind == MotionKind::Inclusive {
+            self.offset_of_position(last.saturating_add_col(1))
+        } else {
+            self.offset_of_position(last)
+        };
+        if from < to {
+            self.operate_on_range(operator, from, to, first);
+        }
+    }
+
+    /// Applies `operator` to lines `first..=last` as a whole.
+    fn operate_on_lines(&mut self, operator: Operator, first: usize, last: usize) {
+        if operator == Operator::Fold {
+            self.create_fold(first, last);
+            return;
+        }
+        if operator == Operator::Surround {
+            // Linewise surrounds leave the indent and line break outside the pair.
+            let rope = self.buffer.rope();
+            let from = rope.offset_of_line(first) + self.first_non_blank(first);
+            let to = rope.offset_of_line(last) + self.line_text(last).len();
+            self.pending_surround = Some(PendingSurround::Add(from..to.max(from)));
+            return;
+        }
+        if let Operator::ShiftRight | Operator::ShiftLeft | Operator::Reindent = operator {
+            self.indent_keeping_cursor(operator, first, last);
+            return;
+        }
+        if let Operator::Uppercase | Operator::Lowercase = operator {
+            let rope = self.buffer.rope();
+            let range = rope.offset_of_line(first)..rope.offset_of_line(last + 1);
+            self.cursor_position.row = first as u16;
+            self.change_case_range(range, operator == Operator::Uppercase);
+            return;
+        }
+
+        let register = self.yank_lines(first, last);
+        if operator == Operator::Yank {
+            self.mark_change(CursorPos::new(0, first as u16), CursorPos::new(0, last as u16));
+        } else {
+            self.mark_change(CursorPos::new(0, first as u16), CursorPos::new(0, first as u16));
+        }
+
+        match operator {
+            Operator::Yank => {
+                self.store_register(register, true);
+                self.cursor_position.row = first as u16;
+                self.normalize_cursor();
+                return;
+            }
+            Operator::Delete => {
+                self.store_register(register, false);
+                let range = self.line_deletion_range(first, last);
+                self.buffer.edit(range, "");
+                self.cursor_position = CursorPos::new(0, first as u16);
+                self.normalize_cursor();
+                self.go_to_first_nonblank();
+            }
+            Operator::Uppercase
+            | Operator::Lowercase
+            | Operator::Fold
+            | Operator::Surround
+            | Operator::ShiftRight
+            | Operator::ShiftLeft
+            | Operator::Reindent => unreachable!(),
+            Operator::Change => {
+                self.store_register(register, false);
+                let from = self.buffer.rope().offset_of_line(first);
+                let to = self.buffer.rope().offset_of_line(last) + self.line_text(last).len();
+                self.buffer.edit(from..to, "");
+                self.cursor_position = CursorPos::new(0, first as u16);
+                self.mode = EditorMode::Insert;
+            }
+        }
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    /// `>`, `<` and `=` on lines `first..=last`. The cursor stays on the same
+    /// character of its line, its visual column moving with the indent; when the
+    /// indent shrinks by more than the cursor's visual column, it lands in
+    /// column 0.
+    fn indent_keeping_cursor(&mut self, operator: Operator, first: usize, last: usize) {
+        let y = self.cursor_position.row as usize;
+        let visual_column = self.cursor_visual_column_before_indent();
+        let old_indent = self.indent_width(&self.line_text(y));
+        self.indent_lines(operator, first, last);
+
+        let line = self.line_text(y);
+        let target = (visual_column + self.indent_width(&line)).saturating_sub(old_indent);
+        let tab_width = self.options.tab_width.max(1);
+        let mut width = 0;
+        let column = line
+            .chars()
+            .take_while(|&c| {
+                width = next_visual_column(width, c, tab_width);
+                width <= target
+            })
+            .count();
+        self.cursor_position.col = column as u16;
+        self.normalize_cursor();
+    }
+
+    /// The screen column of the cursor on its line, counting tabs to the next
+    /// tab stop.
+    fn cursor_visual_column_before_indent(&self) -> usize {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let tab_width = self.options.tab_width.max(1);
+        self.line_text(y as usize)
+            .chars()
+            .take(x as usize)
+            .fold(0, |width, c| next_visual_column(width, c, tab_width))
+    }
+
+    /// The width of the leading blanks of `line`.
+    fn indent_width(&self, line: &str) -> usize {
+        let tab_width = self.options.tab_width.max(1);
+        line.chars()
+            .take_while(|&c| c == ' ' || c == '\t')
+            .fold(0, |width, c| next_visual_column(width, c, tab_width))
+    }
+
+    /// Gives the non-blank lines of `first..=last` a new indent for `>`, `<` or
+    /// `=`, made of spaces with `expandtab` and of tabs then spaces without.
+    fn indent_lines(&mut self, operator: Operator, first: usize, last: usize) {
+        let shift = self.options.tab_width.max(1);
+        // For `=`, the indent and last character of the nearest line above with text.
+        let mut above = (0..first).rev().map(|i| self.line_text(i)).find(|line| {
+            !line.trim().is_empty()
+        });
+        let mut changed = false;
+        for line_idx in first..=last {
+            let line = self.line_text(line_idx);
+            let content = line.trim_start_matches([' ', '\t']);
+            if content.is_empty() {
+                continue;
+            }
+            let width = self.indent_width(&line);
+            let new_width = match operator {
+                Operator::ShiftRight => width + shift,
+                Operator::ShiftLeft => width.saturating_sub(shift),
+                _ => {
+                    let (above_width, opens) = above.as_ref().map_or((0, false), |above| {
+                        let opens = above.trim_end().ends_with(['{', '(', '[']);
+                        (self.indent_width(above), opens)
+                    });
+                    let closes = content.starts_with(['}', ')', ']']);
+                    match (opens, closes) {
+                        (true, false) => above_width + shift,
+                        (false, true) => above_width.saturating_sub(shift),
+                        _ => above_width,
+                    }
+                }
+            };
+            let indent = if self.options.expand_tab {
+                " ".repeat(new_width)
+            } else {
+                "\t".repeat(new_width / shift) + &" ".repeat(new_width % shift)
+            };
+            let leading = line.len() - content.len();
+            if line[..leading] != indent {
+                let line_start = self.buffer.rope().offset_of_line(line_idx);
+                self.buffer.edit(line_start..line_start + leading, indent.as_str());
+                changed = true;
+            }
+            above = Some(format!("{}{}", indent, content));
+        }
+        if changed {
+            self.buffer.set_modified(true);
+            self.mark_change(CursorPos::new(0, first as u16), CursorPos::new(0, last as u16));
+            self.update_after_edit();
+        }
+    }
+
+    /// Saves yanked or deleted text in the register picked with `"x`, if any.
+    fn store_register(&mut self, register: Register, yank: bool) {
+        match self.pending_register.take() {
+            Some(name) => self.registers.set(name, register),
+            None if yank => self.registers.yank(register),
+            None => self.registers.delete(register),
+        }
+    }
+
+    /// Lines `first..=last` as a linewise register, always ending in a newline.
+    fn yank_lines(&self, first: usize, last: usize) -> Register {
+        let rope = self.buffer.rope();
+        let from = rope.offset_of_line(first);
+        let to = rope.offset_of_line(last + 1);
+        let mut text = rope.slice(from..to).to_string();
+        if !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Register {
+            text,
+            linewise: true,
+        }
+    }
+
+    /// The given (not necessarily adjacent) lines as one linewise register.
+    fn yank_each_line(&self, lines: &[usize]) -> Register {
+        Register {
+            text: lines
+                .iter()
+                .map(|&line| self.yank_lines(line, line).text)
+                .collect(),
+            linewise: true,
+        }
+    }
+
+    /// Bytes removed when deleting lines `first..=last`. Deleting through an
+    /// unterminated last line takes the line break before it instead.
+    fn line_deletion_range(&self, first: usize, last: usize) -> Range<usize> {
+        let rope = self.buffer.rope();
+        let mut from = rope.offset_of_line(first);
+        let to = rope.offset_of_line(last + 1);
+        let terminated = to > from && rope.byte_at(to - 1) == b'\n';
+        if !terminated && from > 0 {
+            from -= 1;
+            if from > 0 && rope.byte_at(from - 1) == b'\r' {
+                from -= 1;
+            }
+        }
+        from..to
+    }
+
+    /// Applies `operator` to the bytes `from..to`, leaving the cursor at `start`.
+    fn operate_on_range(&mut self, operator: Operator, from: usize, to: usize, start: CursorPos) {
+        if operator == Operator::Surround {
+            self.pending_surround = Some(PendingSurround::Add(from..to));
+            return;
+        }
+        if let Operator::Uppercase | Operator::Lowercase = operator {
+            self.cursor_position = start;
+            self.change_case_range(from..to, operator == Operator::Uppercase);
+            return;
+        }
+
+        let register = Register {
+            text: self.buffer.rope().slice(from..to).to_string(),
+            linewise: false,
+        };
+        self.cursor_position = start;
+        if operator == Operator::Yank {
+            let last_char = register.text.char_indices().last().map_or(0, |(i, _)| i);
+            self.mark_change(start, self.position_of_offset(from + last_char));
+        } else {
+            self.mark_change(start, start);
+        }
+
+        match operator {
+            Operator::Yank => {
+                self.store_register(register, true);
+                self.normalize_cursor();
+                return;
+            }
+            Operator::Delete => self.store_register(register, false),
+            Operator::Change => {
+                self.store_register(register, false);
+                self.mode = EditorMode::Insert;
+            }
+            Operator::Uppercase
+            | Operator::Lowercase
+            | Operator::Fold
+            | Operator::Surround
+            | Operator::ShiftRight
+            | Operator::ShiftLeft
+            | Operator::Reindent => unreachable!(),
+        }
+        self.buffer.edit(from..to, "");
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    /// Takes the characters a pending `ys`, `cs` or `ds` is waiting for; Esc cancels.
+    fn handle_surround_input(&mut self, pending: PendingSurround, key: KeyEvent) {
+        let KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } = key
+        else {
+            return;
+        };
+        let position = self.cursor_position;
+        match pending {
+            PendingSurround::Add(range) => {
+                let (open, close) = surround_pair(c);
+                self.surround_add(range, open, close);
+            }
+            PendingSurround::Change(None) => {
+                self.pending_surround = Some(PendingSurround::Change(Some(c)));
+            }
+            PendingSurround::Change(Some(old)) => {
+                let (open, close) = surround_pair(c);
+                self.surround_change(position, old, open, close);
+            }
+            PendingSurround::Delete => self.surround_delete(position, c),
+        }
+    }
+
+    /// Wraps the bytes in `range` in `open` and `close`, leaving the cursor on `open`.
+    pub fn surround_add(&mut self, range: Range<usize>, open: char, close: char) {
+        self.buffer.edit(range.end..range.end, close.to_string());
+        self.buffer.edit(range.start..range.start, open.to_string());
+        let start = self.position_of_offset(range.start);
+        let end = self.position_of_offset(range.end + open.len_utf8());
+        self.cursor_position = start;
+        self.mark_change(start, end);
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    /// Replaces the pair around `pos` that `old_open` names with `new_open` and `new_close`.
+    pub fn surround_change(
+        &mut self,
+        pos: CursorPos,
+        old_open: char,
+        new_open: char,
+        new_close: char,
+    ) {
+        let Some((open_at, close_at)) = self.find_surrounding_pair(pos, old_open) else {
+            return;
+        };
+        let (open, close) = surround_pair(old_open);
+        self.buffer.edit(close_at..close_at + close.len_utf8(), new_close.to_string());
+        self.buffer.edit(open_at..open_at + open.len_utf8(), new_open.to_string());
+        self.finish_surround_edit(open_at, close_at);
+    }
+
+    /// Deletes the pair around `pos` that `open` names, as `ds(` or `ds"`.
+    pub fn surround_delete(&mut self, pos: CursorPos, open: char) {
+        let Some((open_at, close_at)) = self.find_surrounding_pair(pos, open) else {
+            return;
+        };
+        let (open, close) = surround_pair(open);
+        self.buffer.edit(close_at..close_at + close.len_utf8(), "");
+        self.buffer.edit(open_at..open_at + open.len_utf8(), "");
+        self.finish_surround_edit(open_at, close_at);
+    }
+
+    fn finish_surround_edit(&mut self, open_at: usize, close_at: usize) {
+        let start = self.position_of_offset(open_at);
+        self.cursor_position = start;
+        self.mark_change(start, self.position_of_offset(close_at));
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    /// Byte offsets of the nearest `c` pair enclosing `pos`, or starting or ending
+    /// there. Brackets nest and may span lines; quotes pair up within the line.
+    fn find_surrounding_pair(&self, pos: CursorPos, c: char) -> Option<(usize, usize)> {
+        let (open, close) = surround_pair(c);
+        let cursor = self.offset_of_position(pos);
+
+        if open == close {
+            let line_start = self.buffer.rope().offset_of_line(pos.row as usize);
+            let line = self.line_text(pos.row as usize);
+            let quotes: Vec<usize> =
+                line.match_indices(open).map(|(i, _)| line_start + i).collect();
+            let before = quotes.iter().rposition(|&at| at <= cursor)?;
+            // On a closing quote, the pair is the one it ends.
+            let first = if before % 2 == 1 { before - 1 } else { before };
+            return Some((quotes[first], *quotes.get(first + 1)?));
+        }
+
+        let text = self.buffer.text();
+        let open_at = if text[cursor..].starts_with(open) {
+            cursor
+        } else {
+            let mut depth = 0;
+            let mut found = None;
+            for (at, ch) in text[..cursor].char_indices().rev() {
+                if ch == close {
+                    depth += 1;
+                } else if ch == open {
+                    if depth == 0 {
+                        found = Some(at);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+            found?
+        };
+
+        let mut depth = 0;
+        for (at, ch) in text[open_at + open.len_utf8()..].char_indices() {
+            if ch == open {
+                depth += 1;
+            } else if ch == close {
+                if depth == 0 {
+                    return Some((open_at, open_at + open.len_utf8() + at));
+                }
+                depth -= 1;
+            }
+        }
+        None
+    }
+
+    /// Puts the unnamed register after (`p`) or before (`P`) the cursor.
+    pub fn paste(&mut self, after: bool) {
+        let name = self.pending_register.take().unwrap_or('"');
+        let Some(register) = self.registers.get(name.to_ascii_lowercase()).cloned() else {
+            self.notify(&format!("E353: Nothing in register {}", name), MessageKind::Error);
+            return;
+        };
+        let CursorPos { col: x, row: y } = self.cursor_position;
+
+        if register.linewise {
+            self.put_lines(&register.text, after);
+            return;
+        } else {
+            let x = if after && self.current_line_length() > 0 {
+                x + 1
+            } else {
+                x
+            };
+            let at = self.offset_of_position(CursorPos::new(x, y));
+            let last_char = register.text.char_indices().last().map_or(0, |(i, _)| i);
+            self.buffer.edit(at..at, register.text);
+            self.cursor_position = self.position_of_offset(at + last_char);
+            self.mark_change(self.position_of_offset(at), self.cursor_position);
+        }
+
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    /// Puts whole lines below or above the cursor line, leaving the cursor on the
+    /// first non-blank of the first one.
+    fn put_lines(&mut self, text: &str, after: bool) {
+        let y = self.cursor_position.row;
+        let first = if after { y + 1 } else { y };
+        let last = first + text.lines().count().saturating_sub(1) as u16;
+        if after {
+            self.insert_text_after_line(y as usize, text);
+        } else {
+            let at = self.buffer.rope().offset_of_line(y as usize);
+            self.buffer.edit(at..at, text);
+        }
+        self.mark_change(CursorPos::new(0, first), CursorPos::new(0, last));
+        self.cursor_position = CursorPos::new(0, first);
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+        self.go_to_first_nonblank();
+    }
+
+    /// `]p` and `[p`: puts lines like `p` and `P`, but reindented so the least
+    /// indented of them lines up with the cursor line.
+    pub fn paste_with_indent_adjust(&mut self, text: &str, after: bool) {
+        let current = self.line_text(self.cursor_position.row as usize);
+        let indent: String = current.chars().take_while(|c| c.is_whitespace()).collect();
+        let strip = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.chars().take_while(|c| c.is_whitespace()).count())
+            .min()
+            .unwrap_or(0);
+
+        let mut adjusted = String::with_capacity(text.len());
+        for line in text.lines() {
+            if !line.trim().is_empty() {
+                adjusted.push_str(&indent);
+                adjusted.extend(line.chars().skip(strip));
+            }
+            adjusted.push('\n');
+        }
+        self.put_lines(&adjusted, after);
+    }
+
+    /// Upper- or lowercases the bytes in `range` with a single edit.
+    pub fn change_case_range(&mut self, range: Range<usize>, upper: bool) {
+        let text = self.buffer.rope().slice(range.clone()).to_string();
+        let changed = if upper {
+            text.to_uppercase()
+        } else {
+            text.to_lowercase()
+        };
+        if changed == text {
+            return;
+        }
+
+        let start = self.position_of_offset(range.start);
+        self.buffer.edit(range.clone(), changed.as_str());
+        let last_char = changed.char_indices().last().map_or(0, |(i, _)| i);
+        self.mark_change(start, self.position_of_offset(range.start + last_char));
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    /// Swaps the case of `count` characters from the cursor, moving past them.
+    pub fn toggle_case_at_cursor(&mut self, count: usize) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line = self.line_text(y as usize);
+        let line_len = line.chars().count();
+        if line_len == 0 {
+            return;
+        }
+        let end_x = (x as usize + count.max(1)).min(line_len);
+
+        let toggled: String = line
+            .chars()
+            .skip(x as usize)
+            .take(end_x - x as usize)
+            .map(|c| {
+                if c.is_uppercase() {
+                    c.to_lowercase().collect::<String>()
+                } else if c.is_lowercase() {
+                    c.to_uppercase().collect::<String>()
+                } else {
+                    c.to_string()
+                }
+            })
+            .collect();
+        // Some characters grow when uppercased (`ß` to `SS`), so measure the result.
+        let toggled_len = toggled.chars().count();
+        let new_line_len = line_len - (end_x - x as usize) + toggled_len;
+        let from = self.offset_of_position(CursorPos::new(x, y));
+        let to = self.offset_of_position(CursorPos::new(end_x as u16, y));
+        self.buffer.edit(from..to, toggled);
+        self.buffer.set_modified(true);
+
+        let new_end_x = x as usize + toggled_len;
+        self.mark_change(CursorPos::new(x, y), CursorPos::new(new_end_x as u16 - 1, y));
+        self.cursor_position.col = new_end_x.min(new_line_len - 1) as u16;
+        self.update_after_edit();
+    }
+
+    /// Finds the number under the cursor, or the first one after it on the same
+    /// line, as its byte range in the buffer, value and base.
+    pub fn find_number_at_or_after_cursor(&self) -> Option<(Range<usize>, i64, NumberBase)> {
+        let number = Regex::new(r"(?i)-?(?:0x[0-9a-f]+|0o[0-7]+|0b[01]+|[0-9]+)").ok()?;
+        let y = self.cursor_position.row as usize;
+        let line = self.line_text(y);
+        let line_start = self.buffer.rope().offset_of_line(y);
+        let cursor = self.offset_of_position(self.cursor_position) - line_start;
+
+        let found = number.find_iter(&line).find(|m| m.end() > cursor)?;
+        let mut start = found.start();
+        let unsigned = found.as_str().trim_start_matches('-');
+        let (base, digits) = match unsigned.get(..2).map(str::to_ascii_lowercase).as_deref() {
+            Some("0x") => (NumberBase::Hex, &unsigned[2..]),
+            Some("0o") => (NumberBase::Octal, &unsigned[2..]),
+            Some("0b") => (NumberBase::Binary, &unsigned[2..]),
+            _ => (NumberBase::Decimal, found.as_str()),
+        };
+        let value = match base {
+            NumberBase::Decimal => digits.parse::<i64>().ok()?,
+            // Like vim, other bases are unsigned and wrap around; a `-` before
+            // them is left alone as text.
+            _ => {
+                start = found.end() - unsigned.len();
+                u64::from_str_radix(digits, base.radix()).ok()? as i64
+            }
+        };
+        let range = line_start + start..line_start + found.end();
+        Some((range, value, base))
+    }
+
+    /// Writes `value` over the number in `range`, keeping its prefix, the case of
+    /// hex digits and, outside decimal, its leading zeros.
+    pub fn replace_number_in_range(&mut self, range: Range<usize>, value: i64, base: NumberBase) {
+        let old = self.buffer.rope().slice(range.clone()).to_string();
+        let (prefix, old_digits) = match base {
+            NumberBase::Decimal => ("", old.as_str()),
+            _ => old.split_at(2),
+        };
+        let width = old_digits.len();
+        let bits = value as u64;
+        let digits = match base {
+            NumberBase::Decimal => value.to_string(),
+            NumberBase::Hex if old_digits.chars().any(|c| c.is_ascii_uppercase()) => {
+                format!("{:0width$X}", bits)
+            }
+            NumberBase::Hex => format!("{:0width$x}", bits),
+            NumberBase::Octal => format!("{:0width$o}", bits),
+            NumberBase::Binary => format!("{:0width$b}", bits),
+        };
+        let new = format!("{}{}", prefix, digits);
+
+        let start = self.position_of_offset(range.start);
+        self.buffer.edit(range.clone(), new.as_str());
+        self.buffer.set_modified(true);
+        let end = start.saturating_add_col(new.len() as i16 - 1);
+        self.mark_change(start, end);
+        self.cursor_position = end;
+        self.update_after_edit();
+    }
+
+    /// Ctrl-A and Ctrl-X: adds `delta` to the number at or after the cursor.
+    pub fn increment_number(&mut self, delta: i64) {
+        let Some((range, value, base)) = self.find_number_at_or_after_cursor() else {
+            return;
+        };
+        let value = match base {
+            NumberBase::Decimal => value.saturating_add(delta),
+            _ => value.wrapping_add(delta),
+        };
+        self.replace_number_in_range(range, value, base);
+    }
+
+    /// Ctrl-N and Ctrl-P: opens the completion popup with the buffer's words that
+    /// start with the word before the cursor.
+    pub fn start_word_completion(&mut self, backward: bool) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line: Vec<char> = self.line_text(y as usize).chars().collect();
+        let x = (x as usize).min(line.len());
+        let start = x - line[..x]
+            .iter()
+            .rev()
+            .take_while(|&&c| completion::is_word_char(c))
+            .count();
+        let prefix: String = line[start..x].iter().collect();
+
+        let offset = self.offset_of_position(self.cursor_position);
+        let text = self.buffer.text();
+        let candidates = completion::word_candidates(&text, &prefix, offset, backward);
+        if candidates.is_empty() {
+            self.notify("Pattern not found", MessageKind::Error);
+            return;
+        }
+        self.completion_state = Some(CompletionState {
+            start,
+            prefix,
+            candidates,
+            selected: 0,
+            whole_line: false,
+        });
+    }
+
+    /// Other lines that start with, or fuzzy-match, the current line's text up to
+    /// the cursor, ignoring indentation. Prefix matches come first, and within
+    /// each group the lines nearest the cursor line.
+    pub fn complete_line_candidates(&self) -> Vec<String> {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let y = y as usize;
+        let typed: String = self.line_text(y).chars().take(x as usize).collect();
+        let typed = typed.trim_start();
+
+        let mut matches: Vec<(bool, usize, String)> = (0..self.total_lines())
+            .filter(|&line_idx| line_idx != y)
+            .filter_map(|line_idx| {
+                let line = self.line_text(line_idx);
+                let text = line.trim_start();
+                if text.is_empty() {
+                    return None;
+                }
+                let prefix = text.starts_with(typed);
+                let fuzzy = !prefix && completion::fuzzy_match(text, typed);
+                (prefix || fuzzy).then(|| (fuzzy, line_idx.abs_diff(y), line))
+            })
+            .collect();
+        matches.sort_by_key(|&(fuzzy, distance, _)| (fuzzy, distance));
+
+        let mut candidates: Vec<String> = Vec::new();
+        for (_, _, line) in matches {
+            if !candidates.contains(&line) {
+                candidates.push(line);
+            }
+        }
+        candidates
+    }
+
+    /// Ctrl-X Ctrl-L: opens the completion popup with whole lines from the buffer.
+    pub fn start_line_completion(&mut self) {
+        let candidates = self.complete_line_candidates();
+        if candidates.is_empty() {
+            self.notify("Pattern not found", MessageKind::Error);
+            return;
+        }
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        self.completion_state = Some(CompletionState {
+            start: 0,
+            prefix: self.line_text(y as usize).chars().take(x as usize).collect(),
+            candidates,
+            selected: 0,
+            whole_line: true,
+        });
+    }
+
+    /// Replaces the text between the completion's start and the cursor, or the
+    /// whole line for line completion, with the selected candidate and closes the
+    /// popup.
+    pub fn accept_completion(&mut self) {
+        let Some(state) = self.completion_state.take() else {
+            return;
+        };
+        let y = self.cursor_position.row;
+        let from = self.offset_of_position(CursorPos::new(state.start as u16, y));
+        let to = if state.whole_line {
+            self.offset_of_position(CursorPos::new(u16::MAX, y))
+        } else {
+            self.offset_of_position(self.cursor_position)
+        };
+        let text = state.selected();
+        self.buffer.edit(from..to, text);
+        self.buffer.set_modified(true);
+
+        let end_x = (state.start + text.chars().count()) as u16;
+        self.mark_change(
+            CursorPos::new(state.start as u16, y),
+            CursorPos::new(end_x.saturating_sub(1), y),
+        );
+        self.cursor_position.col = end_x;
+        self.update_after_edit();
+    }
+
+    /// Ctrl-W in Insert mode: deletes back over whitespace and then the word, or
+    /// run of punctuation, before the cursor. At the start of a line it joins the
+    /// line to the previous one like Backspace.
+    pub fn delete_word_before_cursor(&mut self) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        if x == 0 {
+            self.delete_char();
+            return;
+        }
+        let line: Vec<char> = self.line_text(y as usize).chars().collect();
+        let mut start = (x as usize).min(line.len());
+        while start > 0 && line[start - 1].is_whitespace() {
+            start -= 1;
+        }
+        if start > 0 {
+            let in_word = completion::is_word_char(line[start - 1]);
+            while start > 0
+                && !line[start - 1].is_whitespace()
+                && completion::is_word_char(line[start - 1]) == in_word
+            {
+                start -= 1;
+            }
+        }
+        self.delete_before_cursor(start as u16);
+    }
+
+    /// Ctrl-U in Insert mode: deletes from the cursor back to the start of the line.
+    pub fn delete_to_line_start(&mut self) {
+        self.delete_before_cursor(0);
+    }
+
+    /// Deletes from column `start` up to the cursor in one edit, keeping the text in
+    /// the unnamed register.
+    fn delete_before_cursor(&mut self, start: u16) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        if start >= x {
+            return;
+        }
+        let from = self.offset_of_position(CursorPos::new(start, y));
+        let to = self.offset_of_position(CursorPos::new(x, y));
+        let text = self.buffer.rope().slice(from..to).to_string();
+        self.buffer.edit(from..to, "");
+        self.buffer.set_modified(true);
+        self.registers.delete(Register {
+            text,
+            linewise: false,
+        });
+
+        self.cursor_position.col = start;
+        self.mark_change(self.cursor_position, self.cursor_position);
+        self.update_after_edit();
+    }
+
+    /// Ctrl-T in Insert mode: swaps the two characters before the cursor, as
+    /// whole graphemes so accents and emoji modifiers move with their base. At
+    /// the start of a line, swaps the first character with the previous line's last.
+    pub fn transpose_chars_at_cursor(&mut self) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line_start = self.buffer.rope().offset_of_line(y as usize);
+        let line = self.line_text(y as usize);
+        // The byte ranges of the two graphemes and where the first one starts.
+        let (first, second, first_pos) = if x == 0 {
+            if y == 0 {
+                return;
+            }
+            let previous = self.line_text(y as usize - 1);
+            let (Some(last), Some(next)) =
+                (previous.graphemes(true).next_back(), line.graphemes(true).next())
+            else {
+                return;
+            };
+            let previous_end = self.buffer.rope().offset_of_line(y as usize - 1) + previous.len();
+            let col = previous.chars().count() - last.chars().count();
+            (
+                previous_end - last.len()..previous_end,
+                line_start..line_start + next.len(),
+                CursorPos::new(col as u16, y - 1),
+            )
+        } else {
+            let cursor = self.offset_of_position(self.cursor_position) - line_start;
+            let mut before = line[..cursor].grapheme_indices(true).rev();
+            let (Some((second, b)), Some((first, a))) = (before.next(), before.next()) else {
+                return;
+            };
+            let col = line[..first].chars().count();
+            (
+                line_start + first..line_start + first + a.len(),
+                line_start + second..line_start + second + b.len(),
+                CursorPos::new(col as u16, y),
+            )
+        };
+
+        let rope = self.buffer.rope();
+        let moved_back = rope.slice(second.clone()).to_string();
+        let swapped = format!(
+            "{}{}{}",
+            moved_back,
+            rope.slice(first.end..second.start),
+            rope.slice(first.clone())
+        );
+        let second_pos = if first_pos.row == y {
+            first_pos.saturating_add_col(moved_back.chars().count() as i16)
+        } else {
+            CursorPos::new(0, y)
+        };
+        self.buffer.edit(first.start..second.end, swapped);
+        self.buffer.set_modified(true);
+        self.mark_change(first_pos, second_pos);
+        self.update_after_edit();
+    }
+
+    /// Replace mode typing: overwrites the character under the cursor with `c`, or
+    /// appends it at the end of the line, and moves past it.
+    pub fn replace_char_at_cursor(&mut self, c: char) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let original = self.line_text(y as usize).chars().nth(x as usize);
+        let from = self.offset_of_position(CursorPos::new(x, y));
+        let to = from + original.map_or(0, char::len_utf8);
+        self.buffer.edit(from..to, c.to_string());
+        self.buffer.set_modified(true);
+        self.replace_undo_chars.push(original);
+
+        self.mark_change(CursorPos::new(x, y), CursorPos::new(x, y));
+        self.cursor_position.col += 1;
+        self.update_after_edit();
+    }
+
+    /// Replace mode Backspace: moves left, restoring the character that was there
+    /// before Replace mode overwrote it.
+    pub fn undo_replace_char(&mut self) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        if x == 0 {
+            return;
+        }
+        self.cursor_position.col -= 1;
+        if let Some(original) = self.replace_undo_chars.pop() {
+            let from = self.offset_of_position(CursorPos::new(x - 1, y));
+            let to = self.offset_of_position(CursorPos::new(x, y));
+            let restored = original.map(String::from).unwrap_or_default();
+            self.buffer.edit(from..to, restored);
+            self.buffer.set_modified(true);
+        }
+        self.update_after_edit();
+    }
+
+    /// `r`: replaces `count` characters from the cursor with `c`, leaving the cursor
+    /// on the last of them. Nothing changes if the line is too short.
+    pub fn replace_chars(&mut self, c: char, count: usize) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let count = count.max(1);
+        let line_len = self.line_text(y as usize).chars().count();
+        if x as usize + count > line_len {
+            return;
+        }
+
+        let end_x = x + count as u16;
+        let from = self.offset_of_position(CursorPos::new(x, y));
+        let to = self.offset_of_position(CursorPos::new(end_x, y));
+        let replacement: String = std::iter::repeat_n(c, count).collect();
+        self.buffer.edit(from..to, replacement);
+        self.buffer.set_modified(true);
+
+        self.mark_change(CursorPos::new(x, y), CursorPos::new(end_x - 1, y));
+        self.cursor_position.col = end_x - 1;
+        self.update_after_edit();
+    }
+
+    /// Applies a Normal mode count prefix (0 when none was typed) to `action`.
+    fn apply_count(&mut self, action: EditorAction, count: usize) -> EditorAction {
+        if count == 0 {
+            return action;
+        }
+
+        let count16 = count.min(i16::MAX as usize) as i16;
+        match action {
+            EditorAction::MoveCursor((dx, dy)) => EditorAction::MoveCursor((
+                dx.saturating_mul(count16),
+                dy.saturating_mul(count16),
+            )),
+            EditorAction::ScrollUp(n) => EditorAction::ScrollUp(n.saturating_mul(count)),
+            EditorAction::ScrollDown(n) => EditorAction::ScrollDown(n.saturating_mul(count)),
+            EditorAction::GoToLine(_) => EditorAction::GoToLine(count),
+            EditorAction::ToggleCase(_) => EditorAction::ToggleCase(count),
+            EditorAction::ReplaceChar(c, _) => EditorAction::ReplaceChar(c, count),
+            EditorAction::Increment(n) => EditorAction::Increment(n.saturating_mul(count as i64)),
+            EditorAction::Quickfix(n) => EditorAction::Quickfix(n.saturating_mul(count as isize)),
+            EditorAction::ResizeSplit(n) => EditorAction::ResizeSplit(n.saturating_mul(count16)),
+            EditorAction::JumpToHunk(n) => {
+                EditorAction::JumpToHunk(n.saturating_mul(count as isize))
+            }
+            // `3_` is two lines down, while `3+` is three.
+            EditorAction::FirstNonBlankLine(0) => {
+                EditorAction::FirstNonBlankLine(count16.saturating_sub(1))
+            }
+            EditorAction::FirstNonBlankLine(n) => {
+                EditorAction::FirstNonBlankLine(n.saturating_mul(count16))
+            }
+            EditorAction::CursorScreenTop(_) => EditorAction::CursorScreenTop(count - 1),
+            EditorAction::CursorScreenBottom(_) => EditorAction::CursorScreenBottom(count - 1),
+            EditorAction::HalfPageUp | EditorAction::HalfPageDown => {
+                // Like vim, a count sets the distance for later Ctrl-U/Ctrl-D as well.
+                self.half_page_lines = count;
+                action
+            }
+            action => action,
+        }
+    }
+
+    fn handle_overlay_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        let overlay = self.overlay.as_mut()?;
+        // Letters name registers in the `:registers` list, so only arrows scroll there.
+        let letters_scroll = overlay.kind != OverlayKind::Registers;
+        match key.code {
+            KeyCode::Down => {
+                overlay.scroll = (overlay.scroll + 1).min(overlay.lines.len().saturating_sub(1));
+                None
+            }
+            KeyCode::Char('j') if letters_scroll => {
+                overlay.scroll = (overlay.scroll + 1).min(overlay.lines.len().saturating_sub(1));
+                None
+            }
+            KeyCode::Up => {
+                overlay.scroll = overlay.scroll.saturating_sub(1);
+                None
+            }
+            KeyCode::Char('k') if letters_scroll => {
+                overlay.scroll = overlay.scroll.saturating_sub(1);
+                None
+            }
+            KeyCode::Enter if overlay.kind == OverlayKind::SpellSuggest => {
+                let replacement = overlay.lines.get(overlay.scroll).cloned();
+                self.overlay = None;
+                if let Some(replacement) = replacement {
+                    self.replace_spell_word(&replacement);
+                }
+                None
+            }
+            KeyCode::Char(digit @ '0'..='9') if overlay.kind == OverlayKind::OldFiles => {
+                let value = digit.to_digit(10).unwrap_or(0) as usize;
+                self.pending_count = self.pending_count.saturating_mul(10) + value;
+                overlay.title = format!(":oldfiles {}", self.pending_count);
+                None
+            }
+            KeyCode::Enter if overlay.kind == OverlayKind::OldFiles => {
+                self.overlay = None;
+                let number = std::mem::take(&mut self.pending_count);
+                if let Some(path) = number.checked_sub(1).and_then(|i| self.oldfiles.get(i)) {
+                    if let Err(e) = self.edit_file(path.clone()) {
+                        self.notify(&e.to_string(), MessageKind::Error);
+                    }
+                }
+                None
+            }
+            KeyCode::Char(name) if overlay.kind == OverlayKind::Registers && name != 'q' => {
+                self.overlay = None;
+                self.pending_register = Some(name);
+                self.notify(&format!("\"{}", name), MessageKind::Info);
+                None
+            }
+            code => {
+                let overlay = self.overlay.take()?;
+                self.pending_count = 0;
+                if let Some(message) = overlay.dismiss_message {
+                    self.notify(&message, MessageKind::Info);
+                }
+                if code == KeyCode::Char(':') {
+                    self.handle_action(EditorAction::StartCommand)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// Keys in the quickfix window: `j`/`k` move between entries, Enter jumps to
+    /// one, Esc goes back to the text and `q` closes the window.
+    fn handle_quickfix_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        match self.pending_key.take() {
+            Some(CTRL_W) => {
+                return handle_key_sequence(CTRL_W, key).and_then(|a| self.handle_action(a));
+            }
+            Some('g') if key.code == KeyCode::Char('g') => {
+                self.quickfix_cursor = 0;
+                return None;
+            }
+            _ => {}
+        }
+        let last = self.quickfix_list.len().saturating_sub(1);
+        let half_page = (self.options.quickfix_height / 2).max(1);
+        match key.code {
+            KeyCode::Char('w') if key.modifiers == KeyModifiers::CONTROL => {
+                self.pending_key = Some(CTRL_W);
+                None
+            }
+            KeyCode::Char('d') if key.modifiers == KeyModifiers::CONTROL => {
+                self.quickfix_cursor = (self.quickfix_cursor + half_page).min(last);
+                None
+            }
+            KeyCode::Char('u') if key.modifiers == KeyModifiers::CONTROL => {
+                self.quickfix_cursor = self.quickfix_cursor.saturating_sub(half_page);
+                None
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                self.quickfix_cursor = (self.quickfix_cursor + 1).min(last);
+                None
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.quickfix_cursor = self.quickfix_cursor.saturating_sub(1);
+                None
+            }
+            KeyCode::Char('g') => {
+                self.pending_key = Some('g');
+                None
+            }
+            KeyCode::Char('G') => {
+                self.quickfix_cursor = last;
+                None
+            }
+            KeyCode::Enter => {
+                self.quickfix_focused = false;
+                self.jump_to_quickfix(self.quickfix_cursor);
+                None
+            }
+            KeyCode::Esc => {
+                self.quickfix_focused = false;
+                None
+            }
+            KeyCode::Char('q') => {
+                self.close_quickfix_split();
+                None
+            }
+            KeyCode::Char(':') => self.handle_action(EditorAction::StartCommand),
+            _ => None,
+        }
+    }
+
+    fn handle_command_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        match key.code {
+            KeyCode::Tab if self.mode == EditorMode::Command => {
+                self.complete_command_line(false);
+                return None;
+            }
+            KeyCode::BackTab if self.mode == EditorMode::Command => {
+                self.complete_command_line(true);
+                return None;
+            }
+            KeyCode::Backspace if self.command_completion.is_some() => {
+                self.cancel_command_completion();
+                return None;
+            }
+            _ => self.command_completion = None,
+        }
+
+        match key.code {
+            KeyCode::Esc => {
+                self.end_theme_preview();
+                self.end_substitute_preview();
+                self.mode = EditorMode::Normal;
+                self.command_buffer.clear();
+                None
+            }
+            KeyCode::Enter => {
+                self.end_theme_preview();
+                self.end_substitute_preview();
+                self.expand_command_abbreviation();
+                let searching = self.mode == EditorMode::Search;
+                self.mode = EditorMode::Normal;
+                let command = std::mem::take(&mut self.command_buffer);
+                if searching {
+                    self.start_search(command);
+                    None
+                } else {
+                    self.execute_command(&command)
+                }
+            }
+            KeyCode::Backspace => {
+                if self.command_buffer.pop().is_none() {
+                    self.end_theme_preview();
+                    self.end_substitute_preview();
+                    self.mode = EditorMode::Normal;
+                } else {
+                    self.update_substitute_preview();
+                }
+                None
+            }
+            KeyCode::Char(c) => {
+                if !completion::is_word_char(c) {
+                    self.expand_command_abbreviation();
+                }
+                self.command_buffer.push(c);
+                self.update_substitute_preview();
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Tab on the command line: fills in the argument of `:colorscheme` with the
+    /// next matching theme, or the previous one for Shift-Tab, and shows it on
+    /// the buffer straight away.
+    fn complete_command_line(&mut self, backward: bool) {
+        if let Some(completion) = self.command_completion.as_mut() {
+            if backward {
+                completion.select_previous();
+            } else {
+                completion.select_next();
+            }
+        } else {
+            let Some((name, arg)) = self.command_buffer.split_once(' ') else {
+                return;
+            };
+            if ExCommand::parse(name).command != ExCommandKind::ColorScheme {
+                return;
+            }
+            let prefix = arg.trim_start().to_string();
+            let candidates: Vec<String> = self
+                .highlighter
+                .theme_names()
+                .into_iter()
+                .filter(|theme| theme.starts_with(&prefix))
+                .collect();
+            if candidates.is_empty() {
+                return;
+            }
+            self.command_completion = Some(CompletionState {
+                start: self.command_buffer.len() - prefix.len(),
+                selected: if backward { candidates.len() - 1 } else { 0 },
+                prefix,
+                candidates,
+                whole_line: false,
+            });
+        }
+
+        let Some(completion) = &self.command_completion else {
+            return;
+        };
+        let theme = completion.selected().to_string();
+        self.command_buffer.truncate(completion.start);
+        self.command_buffer.push_str(&theme);
+        if self.preview_theme.is_none() {
+            self.preview_theme = Some(self.highlighter.current_theme_name.clone());
+        }
+        self.highlighter.set_theme(&theme);
+        self.text_changed = true;
+    }
+
+    /// Backspace during completion: puts back what was typed before Tab.
+    fn cancel_command_completion(&mut self) {
+        if let Some(completion) = self.command_completion.take() {
+            self.command_buffer.truncate(completion.start);
+            self.command_buffer.push_str(&completion.prefix);
+        }
+        self.end_theme_preview();
+    }
+
+    /// Puts back the theme that was showing before Tab started previewing
+    /// `:colorscheme` candidates. Running the command sets the chosen one.
+    fn end_theme_preview(&mut self) {
+        if let Some(theme) = self.preview_theme.take() {
+            self.highlighter.set_theme(&theme);
+            self.text_changed = true;
+        }
+    }
+
+    /// With `inccommand` set, shows what the `:s` on the command line would do:
+    /// the text with its replacements, or only its matches highlighted until
+    /// the replacement is begun.
+    fn update_substitute_preview(&mut self) {
+        self.end_substitute_preview();
+        if self.options.inccommand == InccommandMode::Off || self.mode != EditorMode::Command {
+            return;
+        }
+        let ExCommand {
+            range,
+            command,
+            args,
+        } = ExCommand::parse(&self.command_buffer);
+        if command != ExCommandKind::Substitute {
+            return;
+        }
+        let args = args.join(" ");
+        let y = self.cursor_position.row as usize;
+        let Ok((start, end)) = self.range_lines(&range, (y, y)) else {
+            return;
+        };
+        let Some(delimiter) = args.chars().next() else {
+            return;
+        };
+        let mut parts = split_delimited(&args[delimiter.len_utf8()..], delimiter).into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().map(|replacement| vim_replacement(&replacement));
+        let flags = parts.next().unwrap_or_default();
+        let pattern = match (pattern.is_empty(), &self.search_query) {
+            (false, _) => pattern,
+            (true, Some(query)) if replacement.is_some() => query.clone(),
+            (true, _) => return,
+        };
+        let Some(regex) = self.substitute_regex(&pattern, &flags) else {
+            return;
+        };
+
+        let rope = self.buffer.rope();
+        let range_start = rope.offset_of_line(start);
+        let range_end = rope.offset_of_line(end + 1);
+        let old = rope.slice(range_start..range_end).to_string();
+        let mut new = String::new();
+        let mut line = start;
+        for old_line in old.split_inclusive('\n') {
+            let body = old_line.strip_suffix('\n').unwrap_or(old_line);
+            let mut copied = 0;
+            let mut line_start = new.len();
+            for captures in regex.captures_iter(body) {
+                let Some(found) = captures.get(0) else {
+                    continue;
+                };
+                new.push_str(&body[copied..found.start()]);
+                let from = new.len();
+                match &replacement {
+                    Some(replacement) => captures.expand(replacement, &mut new),
+                    None => new.push_str(found.as_str()),
+                }
+                // Only the part on this line is highlighted when `\r` splits it.
+                let to = new[from..].find('\n').map_or(new.len(), |newline| from + newline);
+                let column = new[line_start..from].chars().count();
+                let width = new[from..to].chars().count();
+                self.preview_matches.push((line, column..column + width));
+                if let Some(newline) = new[line_start..].rfind('\n') {
+                    line += new[line_start..].matches('\n').count();
+                    line_start += newline + 1;
+                }
+                copied = found.end();
+                if !flags.contains('g') {
+                    break;
+                }
+            }
+            new.push_str(&old_line[copied..]);
+            line += 1;
+        }
+
+        let mut preview = rope.clone();
+        preview.edit(range_start..range_end, new.as_str());
+        self.preview_rope = Some(preview);
+        self.text_changed = true;
+    }
+
+    fn end_substitute_preview(&mut self) {
+        if self.preview_rope.take().is_some() {
+            self.preview_matches.clear();
+            self.text_changed = true;
+        }
+    }
+
+    pub fn execute_command(&mut self, command: &str) -> Option<EditorAction> {
+        let ExCommand {
+            range,
+            command,
+            args,
+        } = ExCommand::parse(command);
+        let text = args.join(" ");
+
+        match command {
+            ExCommandKind::Goto => {
+                match range {
+                    ExRange::None => {}
+                    ExRange::LineNumber(line) => self.go_to_line(line.max(1)),
+                    range => {
+                        if let Some((_, end)) = self.resolve_range(&range, (0, 0)) {
+                            self.go_to_line(end + 1);
+                        }
+                    }
+                }
+                None
+            }
+            ExCommandKind::Quit => self.handle_action(EditorAction::Exit),
+            ExCommandKind::ForceQuit => self.handle_action(EditorAction::ForceExit),
+            ExCommandKind::Write { force } => {
+                // Like vim, `:w!` on a read-only buffer makes it writable again.
+                if force {
+                    self.read_only = false;
+                }
+                match text.strip_prefix(">>") {
+                    Some(path) => {
+                        self.append_range_to_file(range, Path::new(path.trim()));
+                        None
+                    }
+                    None if !text.is_empty() => {
+                        self.write_to(PathBuf::from(&text));
+                        None
+                    }
+                    None => self.handle_action(EditorAction::Save),
+                }
+            }
+            ExCommandKind::WriteQuit => self.handle_action(EditorAction::SaveExit),
+            ExCommandKind::File => self.handle_action(EditorAction::FileInfo),
+            ExCommandKind::NoHighlight => self.handle_action(EditorAction::ClearHighlight),
+            ExCommandKind::Registers => {
+                let mut overlay = Overlay::new(":registers", self.registers_summary());
+                overlay.kind = OverlayKind::Registers;
+                self.overlay = Some(overlay);
+                None
+            }
+            ExCommandKind::Marks => {
+                let summary = self.marks_summary();
+                if summary.lines().count() > 1 {
+                    let lines = summary.lines().map(str::to_string).collect();
+                    self.overlay = Some(Overlay::new(":marks", lines));
+                } else {
+                    self.notify("E283: No marks matched", MessageKind::Error);
+                }
+                None
+            }
+            ExCommandKind::Set => {
+                self.set_options(&text);
+                None
+            }
+            ExCommandKind::Snippet => {
+                self.snippet_command(&text);
+                None
+            }
+            ExCommandKind::Abbreviate { insert, command } => {
+                self.abbreviate(insert, command, &text);
+                None
+            }
+            ExCommandKind::Unabbreviate { insert, command } => {
+                self.unabbreviate(insert, command, &text);
+                None
+            }
+            ExCommandKind::Edit { force } => {
+                self.edit_command(&text, force);
+                None
+            }
+            ExCommandKind::Buffers => {
+                let lines = self.buffers_summary().lines().map(str::to_string).collect();
+                self.overlay = Some(Overlay::new(":ls", lines));
+                None
+            }
+            ExCommandKind::Echo | ExCommandKind::EchoMsg | ExCommandKind::EchoErr => {
+                match eval::eval_list(&text, &self.variables) {
+                    Ok(message) if command == ExCommandKind::Echo => {
+                        self.messages.show(&message, MessageKind::Info)
+                    }
+                    Ok(message) if command == ExCommandKind::EchoMsg => {
+                        self.notify(&message, MessageKind::Info)
+                    }
+                    Ok(message) => self.notify(&message, MessageKind::Error),
+                    Err(e) => self.notify(&e, MessageKind::Error),
+                }
+                None
+            }
+            ExCommandKind::Let => {
+                self.let_command(&text);
+                None
+            }
+            ExCommandKind::Messages => {
+                self.show_messages();
+                None
+            }
+            ExCommandKind::OldFiles => {
+                self.show_oldfiles();
+                None
+            }
+            ExCommandKind::ColorScheme => {
+                if text.is_empty() {
+                    self.notify(&self.highlighter.current_theme_name.clone(), MessageKind::Info);
+                } else if self.highlighter.set_theme(&text) {
+                    self.text_changed = true;
+                } else {
+                    self.notify(
+                        &format!("E185: Cannot find color scheme '{}'", text),
+                        MessageKind::Error,
+                    );
+                }
+                None
+            }
+            ExCommandKind::Highlight => {
+                self.highlight_command(&args);
+                None
+            }
+            ExCommandKind::Ascii => {
+                let info = self.char_info_at_cursor();
+                self.notify(&info, MessageKind::Info);
+                None
+            }
+            ExCommandKind::Digraphs => {
+                self.overlay = Some(Overlay::new(":digraphs", digraph::summary()));
+                None
+            }
+            ExCommandKind::Map {
+                mode,
+                noremap,
+                verbose,
+            } => {
+                self.map_command(mode, noremap, verbose, &text);
+                None
+            }
+            ExCommandKind::Autocmd { clear } => {
+                self.autocmd_command(clear, &text);
+                None
+            }
+            ExCommandKind::Source => {
+                if text.is_empty() {
+                    self.notify("E471: Argument required", MessageKind::Error);
+                } else if let Err(e) = self.source_file(Path::new(&text)) {
+                    self.notify(
+                        &format!("E484: Can't open file {}: {}", text, e),
+                        MessageKind::Error,
+                    );
+                }
+                None
+            }
+            ExCommandKind::ScriptNames => {
+                let lines = self
+                    .sourced_scripts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, path)| format!("{:3}: {}", i + 1, path.display()))
+                    .collect();
+                self.overlay = Some(Overlay::new(":scriptnames", lines));
+                None
+            }
+            ExCommandKind::Read => {
+                self.read_into_buffer(&text);
+                None
+            }
+            ExCommandKind::Sort { reverse } => {
+                self.sort_lines(range, reverse, text.contains('i'), text.contains('u'));
+                None
+            }
+            ExCommandKind::Retab { bang } => {
+                let whole_buffer = (0, self.total_lines() - 1);
+                if let Some((start, end)) = self.resolve_range(&range, whole_buffer) {
+                    let to_spaces = !bang || self.options.expand_tab;
+                    let changed = self.retab_lines(start, end, to_spaces);
+                    if changed > 2 {
+                        self.notify(&format!("{} lines changed", changed), MessageKind::Info);
+                    }
+                }
+                None
+            }
+            ExCommandKind::ToHtml => {
+                let path = match text.as_str() {
+                    "" => self.default_html_path(),
+                    path => PathBuf::from(path),
+                };
+                match self.export_to_html(&path) {
+                    Ok(()) => {
+                        self.notify(&format!("\"{}\" written", path.display()), MessageKind::Info)
+                    }
+                    Err(e) => self.notify(
+                        &format!("E212: Can't open file for writing: {}", e),
+                        MessageKind::Error,
+                    ),
+                }
+                None
+            }
+            ExCommandKind::AlignLines(mode) => {
+                let default_width = match mode {
+                    AlignMode::Left => 0,
+                    AlignMode::Right | AlignMode::Center => DEFAULT_ALIGN_WIDTH,
+                };
+                let width = match text.as_str() {
+                    "" => default_width,
+                    width => match width.parse() {
+                        Ok(width) => width,
+                        Err(_) => {
+                            self.notify(
+                                &format!("E475: Invalid argument: {}", width),
+                                MessageKind::Error,
+                            );
+                            return None;
+                        }
+                    },
+                };
+                let y = self.cursor_position.row as usize;
+                let (start, end) = self.resolve_range(&range, (y, y))?;
+                let changed = self.align_lines(start, end, mode, width);
+                self.notify(&format!("{} lines changed", changed), MessageKind::Info);
+                None
+            }
+            ExCommandKind::Align => {
+                let y = self.cursor_position.row as usize;
+                let Some(align_char) = text.chars().next() else {
+                    self.notify("E471: Argument required", MessageKind::Error);
+                    return None;
+                };
+                let (start, end) = self.resolve_range(&range, (y, y))?;
+                self.align_lines_on(start, end, align_char);
+                None
+            }
+            ExCommandKind::WordCount => {
+                self.show_word_count(range);
+                None
+            }
+            ExCommandKind::Delete => {
+                let register = text.chars().next().unwrap_or('"');
+                self.delete_lines(range, register);
+                None
+            }
+            ExCommandKind::Copy | ExCommandKind::Move => {
+                let y = self.cursor_position.row as usize;
+                let (start, end) = self.resolve_range(&range, (y, y))?;
+                let dest = self.resolve_destination(&text)?;
+                if command == ExCommandKind::Copy {
+                    self.copy_lines(start, end, dest);
+                } else {
+                    self.move_lines(start, end, dest);
+                }
+                None
+            }
+            ExCommandKind::Substitute => {
+                self.substitute(range, &text);
+                None
+            }
+            ExCommandKind::Global { invert } => {
+                self.run_global(range, invert, &text);
+                None
+            }
+            ExCommandKind::Shell => {
+                self.run_shell_command(&text);
+                None
+            }
+            ExCommandKind::DiffSplit => {
+                self.diff_split(&text);
+                None
+            }
+            ExCommandKind::DiffUpdate => {
+                self.update_diff();
+                None
+            }
+            ExCommandKind::DiffOff => {
+                self.diff_mode = false;
+                self.diff_hunks.clear();
+                self.diff_pane = None;
+                None
+            }
+            ExCommandKind::Resize => {
+                self.resize_command(&text);
+                None
+            }
+            ExCommandKind::Only { force } => {
+                self.close_other_splits(force);
+                None
+            }
+            ExCommandKind::WriteAll => {
+                let _ = self.save_all_buffers();
+                None
+            }
+            ExCommandKind::QuitAll => match self.modified_buffer_name() {
+                Some(name) => {
+                    self.notify(
+                        &format!("E162: No write since last change for buffer \"{}\"", name),
+                        MessageKind::Error,
+                    );
+                    None
+                }
+                None => Some(EditorAction::Exit),
+            },
+            ExCommandKind::WriteQuitAll => match self.save_all_buffers() {
+                Ok(_) => Some(EditorAction::Exit),
+                Err(_) => None,
+            },
+            ExCommandKind::BufDo | ExCommandKind::WinDo => {
+                self.execute_ex_over_buffers(&text);
+                None
+            }
+            ExCommandKind::ArgDo => {
+                self.execute_ex_over_args(&text);
+                None
+            }
+            ExCommandKind::Args => {
+                self.set_arg_list(&args);
+                None
+            }
+            ExCommandKind::Sign => {
+                self.sign_command(&args);
+                None
+            }
+            ExCommandKind::Grep => {
+                self.grep(&args);
+                None
+            }
+            ExCommandKind::Make => {
+                self.make(&text);
+                None
+            }
+            ExCommandKind::QuickfixNext | ExCommandKind::QuickfixPrevious => {
+                let count = text.parse::<isize>().unwrap_or(1);
+                let delta = if command == ExCommandKind::QuickfixNext {
+                    count
+                } else {
+                    -count
+                };
+                self.handle_action(EditorAction::Quickfix(delta))
+            }
+            ExCommandKind::QuickfixFirst => {
+                self.jump_to_quickfix(0);
+                None
+            }
+            ExCommandKind::QuickfixLast => {
+                self.jump_to_quickfix(self.quickfix_list.len().saturating_sub(1));
+                None
+            }
+            ExCommandKind::QuickfixOpen => {
+                match text.parse() {
+                    Ok(height) => self.set_quickfix_height(height),
+                    Err(_) if !text.is_empty() => {
+                        self.notify(
+                            &format!("E475: Invalid argument: {}", text),
+                            MessageKind::Error,
+                        );
+                        return None;
+                    }
+                    Err(_) => {}
+                }
+                self.open_quickfix_split();
+                None
+            }
+            ExCommandKind::QuickfixClose => {
+                self.close_quickfix_split();
+                None
+            }
+            ExCommandKind::Filter if range == ExRange::All => {
+                match self.pipe_through_command(&text) {
+                    Ok(()) => {
+                        self.notify(
+                            &format!("{} lines filtered", self.total_lines()),
+                            MessageKind::Info,
+                        )
+                    }
+                    Err(e) => self.notify(&format!("E485: {}", e), MessageKind::Error),
+                }
+                None
+            }
+            ExCommandKind::Filter => {
+                self.notify("E16: Invalid range", MessageKind::Error);
+                None
+            }
+            ExCommandKind::Unknown(command) => {
+                self.notify(
+                    &format!("E492: Not an editor command: {}", command),
+                    MessageKind::Error,
+                );
+                None
+            }
+        }
+    }
+
+    /// Parses `:g/pattern/cmd` over `range`, the whole buffer by default.
+    fn run_global(&mut self, range: ExRange, invert: bool, args: &str) {
+        let Some(delimiter) = args.chars().next() else {
+            return;
+        };
+        let mut parts = split_delimited(&args[delimiter.len_utf8()..], delimiter).into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let command = parts.next().unwrap_or_default();
+        let (name, register) = match command.trim().split_once(' ') {
+            Some((name, register)) => (name, register.trim().chars().next()),
+            None => (command.trim(), None),
+        };
+
+        let cmd = match name {
+            "d" | "delete" => GlobalCmd::Delete,
+            "y" | "yank" => GlobalCmd::Yank(register.unwrap_or('"')),
+            "" | "p" | "print" => GlobalCmd::Print,
+            _ => {
+                self.notify(
+                    &format!("E492: Not an editor command: {}", command),
+                    MessageKind::Error,
+                );
+                return;
+            }
+        };
+        let pattern = if pattern.is_empty() {
+            match &self.search_query {
+                Some(query) => query.clone(),
+                None => {
+                    self.notify("E35: No previous regular expression", MessageKind::Error);
+                    return;
+                }
+            }
+        } else {
+            pattern
+        };
+
+        if self.search_regex(&pattern).is_none() {
+            self.notify(&format!("E383: Invalid search string: {}", pattern), MessageKind::Error);
+            return;
+        }
+
+        let Some(affected) = self.global_command(range, &pattern, invert, cmd) else {
+            return;
+        };
+        match cmd {
+            _ if affected == 0 => {
+                self.notify(&format!("Pattern not found: {}", pattern), MessageKind::Error)
+            }
+            GlobalCmd::Delete => {
+                self.notify(&format!("{} fewer lines", affected), MessageKind::Info)
+            }
+            GlobalCmd::Yank(_) => {
+                self.notify(&format!("{} lines yanked", affected), MessageKind::Info)
+            }
+            GlobalCmd::Print => {}
+        }
+    }
+
+    /// Runs `cmd` on every line in `range` (the whole buffer by default) that
+    /// matches `pattern`, or doesn't when `invert` is set. Returns how many lines
+    /// it touched, or `None` if the range is invalid.
+    pub fn global_command(
+        &mut self,
+        range: ExRange,
+        pattern: &str,
+        invert: bool,
+        cmd: GlobalCmd,
+    ) -> Option<usize> {
+        let whole_buffer = (0, self.total_lines() - 1);
+        let lines = self.resolve_range(&range, whole_buffer)?;
+        let Some(regex) = self.search_regex(pattern) else {
+            return Some(0);
+        };
+        self.search_query = Some(pattern.to_string());
+
+        let matching: Vec<usize> = (lines.0..=lines.1)
+            .filter(|&line| regex.is_match(&self.line_text(line)) != invert)
+            .collect();
+        let Some(&last) = matching.last() else {
+            return Some(0);
+        };
+
+        match cmd {
+            GlobalCmd::Delete => {
+                let register = self.yank_each_line(&matching);
+                // Delete bottom-up so the remaining indices stay valid.
+                for &line in matching.iter().rev() {
+                    let range = self.line_deletion_range(line, line);
+                    self.buffer.edit(range, "");
+                }
+                self.registers.delete(register);
+                self.buffer.set_modified(true);
+                self.cursor_position = CursorPos::new(0, (last + 1 - matching.len()) as u16);
+                self.mark_change(self.cursor_position, self.cursor_position);
+                self.update_after_edit();
+                self.go_to_first_nonblank();
+            }
+            GlobalCmd::Yank(name) => {
+                let register = self.yank_each_line(&matching);
+                self.registers.set(name, register);
+            }
+            GlobalCmd::Print => {
+                let lines = matching.iter().map(|&line| self.line_text(line)).collect();
+                self.overlay = Some(Overlay::new(format!(":g/{}/p", pattern), lines));
+            }
+        }
+        Some(matching.len())
+    }
+
+    /// Handles `:s/pattern/replacement/flags` over `range`, the cursor line by default.
+    fn substitute(&mut self, range: ExRange, args: &str) {
+        let y = self.cursor_position.row as usize;
+        let Some((start, end)) = self.resolve_range(&range, (y, y)) else {
+            return;
+        };
+        let Some(delimiter) = args.chars().next() else {
+            return;
+        };
+        let mut parts = split_delimited(&args[delimiter.len_utf8()..], delimiter).into_iter();
+        let pattern = parts.next().unwrap_or_default();
+        let replacement_text = parts.next().unwrap_or_default();
+        let flags = parts.next().unwrap_or_default();
+
+        let pattern = if pattern.is_empty() {
+            match &self.search_query {
+                Some(query) => query.clone(),
+                None => {
+                    self.notify("E35: No previous regular expression", MessageKind::Error);
+                    return;
+                }
+            }
+        } else {
+            pattern
+        };
+        let Some(regex) = self.substitute_regex(&pattern, &flags) else {
+            self.notify(
+                &format!("E383: Invalid search string: {}", pattern),
+                MessageKind::Error,
+            );
+            return;
+        };
+        self.search_query = Some(pattern.clone());
+
+        let mut state = SubstState {
+            regex,
+            replacement: vim_replacement(&replacement_text),
+            replacement_text,
+            global: flags.contains('g'),
+            line: start,
+            offset: 0,
+            end_line: end,
+            current: None,
+            replaced: 0,
+            lines_changed: 0,
+            last_changed_line: None,
+            last_match_end: None,
+        };
+        if !self.find_substitution_match(&mut state) {
+            self.notify(&format!("E486: Pattern not found: {}", pattern), MessageKind::Error);
+            return;
+        }
+
+        if flags.contains('c') {
+            self.prompt_substitution(state);
+        } else {
+            self.substitute_remaining(&mut state);
+            self.finish_substitution(state);
+        }
+    }
+
+    /// The regex for a `:s` pattern, ignoring case by the `i` and `I` flags or
+    /// else by `ignorecase` and `smartcase`.
+    fn substitute_regex(&self, pattern: &str, flags: &str) -> Option<Regex> {
+        let ignore_case = if flags.contains('I') {
+            false
+        } else {
+            flags.contains('i') || SearchOptions::effective_case(pattern, &self.options)
+        };
+        RegexBuilder::new(pattern).case_insensitive(ignore_case).build().ok()
+    }
+
+    /// Answers the `replace with ...? [y/n/a/q/l]` prompt of `:s///c`.
+    fn handle_subst_confirm_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        let Some(mut state) = self.subst_pending.take() else {
+            self.mode = EditorMode::Normal;
+            return None;
+        };
+
+        match key.code {
+            KeyCode::Char('y') => {
+                self.replace_current_match(&mut state);
+                self.find_substitution_match(&mut state);
+            }
+            KeyCode::Char('n') => {
+                self.skip_current_match(&mut state);
+                self.find_substitution_match(&mut state);
+            }
+            KeyCode::Char('a') => self.substitute_remaining(&mut state),
+            KeyCode::Char('l') => self.replace_current_match(&mut state),
+            KeyCode::Char('q') | KeyCode::Esc => state.current = None,
+            _ => {}
+        }
+
+        if state.current.is_some() {
+            self.prompt_substitution(state);
+        } else {
+            self.finish_substitution(state);
+        }
+        None
+    }
+
+    /// Finds the next match at or after the state's position, storing it as `current`.
+    fn find_substitution_match(&self, state: &mut SubstState) -> bool {
+        state.current = None;
+        while state.line <= state.end_line {
+            let text = self.line_text(state.line);
+            while state.offset <= text.len() {
+                let Some(m) = state.regex.find_at(&text, state.offset) else {
+                    break;
+                };
+                if m.is_empty() && state.last_match_end == Some((state.line, m.start())) {
+                    state.offset = m.start() + char_len_at(&text, m.start());
+                    continue;
+                }
+                state.current = Some((state.line, m.range()));
+                return true;
+            }
+            state.line += 1;
+            state.offset = 0;
+        }
+        false
+    }
+
+    fn replace_current_match(&mut self, state: &mut SubstState) {
+        let Some((line, range)) = state.current.take() else {
+            return;
+        };
+        let text = self.line_text(line);
+        let Some(captures) = state.regex.captures_at(&text, range.start) else {
+            return;
+        };
+        let mut replaced = String::new();
+        captures.expand(&state.replacement, &mut replaced);
+
+        let line_start = self.buffer.rope().offset_of_line(line);
+        self.buffer.edit(line_start + range.start..line_start + range.end, replaced.as_str());
+        self.buffer.set_modified(true);
+        self.text_changed = true;
+
+        state.replaced += 1;
+        if state.last_changed_line != Some(line) {
+            state.lines_changed += 1;
+        }
+        if state.replaced == 1 {
+            self.marks.insert('[', CursorPos::new(0, line as u16));
+        }
+        let added_lines = replaced.matches('\n').count();
+        self.marks.insert(']', CursorPos::new(0, (line + added_lines) as u16));
+        state.end_line += added_lines;
+        state.last_changed_line = Some(line + added_lines);
+        let end = match replaced.rfind('\n') {
+            Some(newline) => replaced.len() - newline - 1,
+            None => range.start + replaced.len(),
+        };
+        self.step_past_match(state, line + added_lines, end, range.is_empty());
+    }
+
+    fn skip_current_match(&self, state: &mut SubstState) {
+        if let Some((line, range)) = state.current.take() {
+            self.step_past_match(state, line, range.end, range.is_empty());
+        }
+    }
+
+    /// Moves the search position past a handled match, to the next line unless `g` was given.
+    fn step_past_match(&self, state: &mut SubstState, line: usize, end: usize, empty: bool) {
+        if !state.global {
+            state.line = line + 1;
+            state.offset = 0;
+            return;
+        }
+        state.line = line;
+        state.offset = end;
+        if !empty {
+            state.last_match_end = Some((line, end));
+        } else {
+            // Step over a character so an empty match can't repeat forever.
+            state.offset += char_len_at(&self.line_text(line), end);
+        }
+    }
+
+    fn substitute_remaining(&mut self, state: &mut SubstState) {
+        while state.current.is_some() {
+            self.replace_current_match(state);
+            self.find_substitution_match(state);
+        }
+    }
+
+    fn prompt_substitution(&mut self, state: SubstState) {
+        if let Some((line, range)) = &state.current {
+            let column = self.line_text(*line)[..range.start].chars().count();
+            self.cursor_position = CursorPos::new(column as u16, *line as u16);
+            self.update_viewport();
+        }
+        self.notify(
+            &format!("replace with {}? [y/n/a/q/l]", state.replacement_text),
+            MessageKind::Info,
+        );
+        self.mode = EditorMode::SubstConfirm;
+        self.subst_pending = Some(state);
+    }
+
+    fn finish_substitution(&mut self, state: SubstState) {
+        self.mode = EditorMode::Normal;
+        if let Some(line) = state.last_changed_line {
+            self.cursor_position = CursorPos::new(0, line as u16);
+            self.normalize_cursor();
+            self.go_to_first_nonblank();
+        }
+        if state.replaced > 0 {
+            let plural = |n: usize| if n == 1 { "" } else { "s" };
+            self.notify(
+                &format!(
+                    "{} substitution{} on {} line{}",
+                    state.replaced,
+                    plural(state.replaced),
+                    state.lines_changed,
+                    plural(state.lines_changed)
+                ),
+                MessageKind::Info,
+            );
+        }
+        self.update_after_edit();
+    }
+
+    /// Handles `:set` arguments; with none, shows the current settings.
+    fn set_options(&mut self, args: &str) {
+        if args.is_empty() {
+            self.notify(&self.options.summary(), MessageKind::Info);
+            return;
+        }
+        let mut shown = Vec::new();
+        for arg in split_set_args(args) {
+            match self.options.set(&arg) {
+                Ok(Some(value)) => shown.push(value),
+                Ok(None) => {}
+                Err(e) => {
+                    self.notify(&e, MessageKind::Error);
+                    return;
+                }
+            }
+        }
+        if !shown.is_empty() {
+            self.notify(&shown.join("  "), MessageKind::Info);
+        }
+        if self.search_highlighted {
+            self.compute_search_matches();
+        }
+        self.update_spell_checker();
+    }
+
+    /// Loads the dictionary for `spelllang` once `spell` is set, turning `spell`
+    /// back off if it can't be read.
+    fn update_spell_checker(&mut self) {
+        let lang = &self.options.spell_lang;
+        if self.options.spell && self.spell_checker.as_ref().is_none_or(|c| &c.lang != lang) {
+            match SpellChecker::load(lang) {
+                Ok(checker) => self.spell_checker = Some(checker),
+                Err(e) => {
+                    self.notify(
+                        &format!("E484: Can't open file ~/.cim/spell/{}.dic: {}", lang, e),
+                        MessageKind::Error,
+                    );
+                    self.options.spell = false;
+                }
+            }
+        }
+        self.text_changed = true;
+    }
+
+    /// Finds the misspelled words in prose: everything in a plain text file, and
+    /// the strings and comments in code.
+    pub fn compute_spell_errors(&mut self) {
+        self.spell_errors.clear();
+        let Some(checker) = self.spell_checker.as_ref().filter(|_| self.options.spell) else {
+            return;
+        };
+        let text = self.buffer.text();
+        for (line_idx, (line, prose)) in text
+            .split_inclusive('\n')
+            .zip(self.highlighter.prose_ranges(&text))
+            .enumerate()
+        {
+            for word in spell::words(line) {
+                let in_prose = prose
+                    .iter()
+                    .any(|range| range.start <= word.start && word.end <= range.end);
+                if in_prose && !checker.check_word(&line[word.clone()]) {
+                    let start = line[..word.start].chars().count();
+                    let end = start + line[word].chars().count();
+                    self.spell_errors.push((line_idx, start..end));
+                }
+            }
+        }
+    }
+
+    /// The word under the cursor as a byte range of its line.
+    fn spell_word_under_cursor(&self) -> Option<Range<usize>> {
+        let y = self.cursor_position.row as usize;
+        let line = self.line_text(y);
+        let line_start = self.buffer.rope().offset_of_line(y);
+        let offset = self.offset_of_position(self.cursor_position) - line_start;
+        spell::words(&line)
+            .into_iter()
+            .find(|word| word.start <= offset && offset < word.end)
+    }
+
+    /// `ga` and `:ascii`: the decimal, hex and octal codepoint of the character
+    /// under the cursor and a digraph for it, such as `<A> 65, Hex 41, Oct 101,
+    /// Digraph A A`, for each codepoint when combining marks follow it.
+    pub fn char_info_at_cursor(&self) -> String {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line = self.line_text(y as usize);
+        let mut chars = line.chars().skip(x as usize).peekable();
+        let Some(first) = chars.next() else {
+            return "NUL".to_string();
+        };
+        let mut codepoints = vec![first];
+        while let Some(c) =
+            chars.next_if(|&c| is_combining(c) || codepoints.ends_with(&['\u{200d}']))
+        {
+            codepoints.push(c);
+        }
+
+        let info: Vec<String> = codepoints
+            .into_iter()
+            .map(|c| {
+                let shown = match c {
+                    c if (c as u32) < 0x20 => format!("^{}", (c as u8 + b'@') as char),
+                    '\u{7f}' => "^?".to_string(),
+                    c if is_combining(c) => format!(" {}", c),
+                    c => c.to_string(),
+                };
+                let digraph = digraph::keys_for(c)
+                    .map(|(first, second)| format!(", Digraph {} {}", first, second))
+                    .unwrap_or_default();
+                let code = c as u32;
+                format!("<{}> {}, Hex {:02x}, Oct {:o}{}", shown, code, code, code, digraph)
+            })
+            .collect();
+        info.join(", ")
+    }
+
+    /// `z=`: lists suggestions for the word under the cursor; Enter on one
+    /// replaces the word with it.
+    fn spell_suggest(&mut self) {
+        let Some(checker) = self.spell_checker.as_ref().filter(|_| self.options.spell) else {
+            self.notify("E756: Spell checking is not enabled", MessageKind::Error);
+            return;
+        };
+        let Some(word) = self.spell_word_under_cursor() else {
+            self.notify("E348: No string under cursor", MessageKind::Error);
+            return;
+        };
+        let word = self.line_text(self.cursor_position.row as usize)[word].to_string();
+        let suggestions = checker.suggest(&word);
+        if suggestions.is_empty() {
+            self.notify(&format!("Sorry, no suggestions for \"{}\"", word), MessageKind::Info);
+            return;
+        }
+        let mut overlay = Overlay::new(format!("Change \"{}\" to:", word), suggestions);
+        overlay.kind = OverlayKind::SpellSuggest;
+        self.overlay = Some(overlay);
+    }
+
+    /// Replaces the word under the cursor with `replacement`.
+    fn replace_spell_word(&mut self, replacement: &str) {
+        let Some(word) = self.spell_word_under_cursor() else {
+            return;
+        };
+        let y = self.cursor_position.row;
+        let line_start = self.buffer.rope().offset_of_line(y as usize);
+        let line = self.line_text(y as usize);
+        let start = line[..word.start].chars().count() as u16;
+        self.buffer.edit(line_start + word.start..line_start + word.end, replacement);
+        self.buffer.set_modified(true);
+        let end = start + replacement.chars().count().saturating_sub(1) as u16;
+        self.mark_change(CursorPos::new(start, y), CursorPos::new(end, y));
+        self.cursor_position.col = start;
+        self.update_after_edit();
+    }
+
+    /// Runs a `/` or `?` search; an empty pattern reuses the last one.
+    fn start_search(&mut self, pattern: String) {
+        if !pattern.is_empty() {
+            if Regex::new(&pattern).is_err() {
+                self.notify(
+                    &format!("E383: Invalid search string: {}", pattern),
+                    MessageKind::Error,
+                );
+                return;
+            }
+            self.search_query = Some(pattern);
+        }
+        self.search(self.search_backward);
+    }
+
+    /// Moves to the next match of the last search pattern in the given direction,
+    /// wrapping around the ends of the buffer.
+    pub fn search(&mut self, backward: bool) {
+        let Some(query) = self.search_query.clone() else {
+            self.notify("E35: No previous regular expression", MessageKind::Error);
+            return;
+        };
+        self.compute_search_matches();
+        if self.search_matches.is_empty() {
+            self.notify(&format!("E486: Pattern not found: {}", query), MessageKind::Error);
+            return;
+        }
+
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let cursor = (y as usize, x as usize);
+        let position = |(line, columns): &(usize, Range<usize>)| (*line, columns.start);
+        let found = if backward {
+            self.search_matches.iter().rev().find(|m| position(m) < cursor)
+        } else {
+            self.search_matches.iter().find(|m| position(m) > cursor)
+        };
+        let (target, wrapped) = match found {
+            Some(m) => (position(m), false),
+            None if backward => (position(self.search_matches.last().unwrap()), true),
+            None => (position(&self.search_matches[0]), true),
+        };
+
+        if wrapped {
+            let message = if backward {
+                "search hit TOP, continuing at BOTTOM"
+            } else {
+                "search hit BOTTOM, continuing at TOP"
+            };
+            self.notify(message, MessageKind::Warning);
+        }
+        self.push_jump();
+        self.cursor_position = CursorPos::new(target.1 as u16, target.0 as u16);
+        self.normalize_cursor();
+    }
+
+    /// Finds every match of the search pattern, line by line, as character columns.
+    pub fn compute_search_matches(&mut self) {
+        self.search_matches.clear();
+        self.search_highlighted = true;
+        let Some(regex) = self.search_query.as_deref().and_then(|q| self.search_regex(q)) else {
+            return;
+        };
+
+        let rope = self.buffer.rope();
+        for (line_idx, line) in rope.iter_lines(0, self.total_lines()).enumerate() {
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            let line_start = rope.offset_of_line(line_idx);
+            for m in regex.find_iter(line) {
+                let start = byte_to_char_col(rope, line_idx, line_start + m.start());
+                let end = start + m.as_str().chars().count();
+                self.search_matches.push((line_idx, start..end));
+            }
+        }
+    }
+
+    /// Compiles `pattern` with the case rules from `ignorecase`/`smartcase`.
+    fn search_regex(&self, pattern: &str) -> Option<Regex> {
+        RegexBuilder::new(pattern)
+            .case_insensitive(SearchOptions::effective_case(pattern, &self.options))
+            .build()
+            .ok()
+    }
+
+    /// Turns off match highlighting until the next search, keeping the pattern for `n`.
+    pub fn clear_search_highlight(&mut self) {
+        self.search_matches.clear();
+        self.search_highlighted = false;
+    }
+
+    fn resolve_address(&self, address: &ExRange) -> Result<usize, &'static str> {
+        let line = match address {
+            ExRange::LineNumber(n) => n.saturating_sub(1) as isize,
+            ExRange::CurrentLine => self.cursor_position.row as isize,
+            ExRange::LastLine => self.total_lines() as isize - 1,
+            ExRange::Mark(c) => self.marks.get(c).ok_or("E20: Mark not set")?.row as isize,
+            ExRange::Offset(base, n) => self.resolve_address(base)? as isize + n,
+            ExRange::None | ExRange::All | ExRange::VisualMarks | ExRange::Pair(..) => {
+                return Err("E16: Invalid range")
+            }
+        };
+        if (0..self.total_lines() as isize).contains(&line) {
+            Ok(line as usize)
+        } else {
+            Err("E16: Invalid range")
+        }
+    }
+
+    /// Resolves `range` to first and last line indices, or `default` when no range
+    /// was given. Sets the status message and returns `None` if it is invalid.
+    fn resolve_range(
+        &mut self,
+        range: &ExRange,
+        default: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        match self.range_lines(range, default) {
+            Ok(lines) => Some(lines),
+            Err(e) => {
+                self.notify(e, MessageKind::Error);
+                None
+            }
+        }
+    }
+
+    /// `resolve_range` without the message, for ranges still being typed.
+    fn range_lines(
+        &self,
+        range: &ExRange,
+        default: (usize, usize),
+    ) -> Result<(usize, usize), &'static str> {
+        let resolved = match range {
+            ExRange::None => return Ok(default),
+            ExRange::All => Ok((0, self.total_lines() - 1)),
+            ExRange::VisualMarks => self
+                .resolve_address(&ExRange::Mark('<'))
+                .and_then(|start| Ok((start, self.resolve_address(&ExRange::Mark('>'))?))),
+            ExRange::Pair(start, end) => self
+                .resolve_address(start)
+                .and_then(|start| Ok((start, self.resolve_address(end)?))),
+            address => self.resolve_address(address).map(|line| (line, line)),
+        };
+        resolved.map(|(start, end)| (start.min(end), start.max(end)))
+    }
+
+    /// `:w >> file`: appends the lines in `range`, the whole buffer by default.
+    fn append_range_to_file(&mut self, range: ExRange, path: &Path) {
+        let result = match range {
+            ExRange::None => self.append_to_file(path).map(|()| self.total_lines()),
+            range => match self.resolve_range(&range, (0, 0)) {
+                Some((start, end)) => self.append_lines_to_file(path, start, end),
+                None => return,
+            },
+        };
+        match result {
+            Ok(lines) => self.notify(
+                &format!("\"{}\" {}L appended", path.display(), lines),
+                MessageKind::Info,
+            ),
+            Err(e) => self.notify(
+                &format!("E212: Can't open file for writing: {}", e),
+                MessageKind::Error,
+            ),
+        }
+    }
+
+    /// Appends the whole buffer to `path`, creating the file if needed.
+    pub fn append_to_file(&self, path: &Path) -> io::Result<()> {
+        self.append_lines_to_file(path, 0, self.total_lines() - 1).map(|_| ())
+    }
+
+    /// Appends lines `start..=end` to `path`, returning how many were written.
+    pub fn append_lines_to_file(&self, path: &Path, start: usize, end: usize) -> io::Result<usize> {
+        let mut text = String::new();
+        for line in start..=end {
+            text.push_str(&self.line_text(line));
+            text.push_str(self.line_ending());
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(text.as_bytes())?;
+        Ok(end - start + 1)
+    }
+
+    /// Resolves the destination address of `:copy` or `:move` to the number of lines
+    /// that will be above the new lines, so `0` puts them at the top.
+    fn resolve_destination(&mut self, address: &str) -> Option<usize> {
+        match ExRange::parse(address.trim()) {
+            (ExRange::LineNumber(0), "") => Some(0),
+            (range, "") if range != ExRange::None => {
+                self.resolve_range(&range, (0, 0)).map(|(_, end)| end + 1)
+            }
+            _ => {
+                self.notify("E14: Invalid address", MessageKind::Error);
+                None
+            }
+        }
+    }
+
+    /// `:copy`: puts a copy of lines `src_start..=src_end` below the first `dest`
+    /// lines in one edit. `dest` may fall inside the copied lines.
+    pub fn copy_lines(&mut self, src_start: usize, src_end: usize, dest: usize) {
+        let text: Vec<String> = (src_start..=src_end).map(|line| self.line_text(line)).collect();
+        let text = text.join(self.line_ending());
+        if dest == 0 {
+            let text = text + self.line_ending();
+            self.buffer.edit(0..0, text);
+            self.buffer.set_modified(true);
+            self.update_after_edit();
+        } else {
+            self.insert_text_after_line(dest - 1, &text);
+        }
+
+        let count = src_end - src_start + 1;
+        self.mark_change(
+            CursorPos::new(0, dest as u16),
+            CursorPos::new(0, (dest + count - 1) as u16),
+        );
+        self.cursor_position = CursorPos::new(0, (dest + count - 1) as u16);
+        self.go_to_first_nonblank();
+        if count > 2 {
+            self.notify(&format!("{} more lines", count), MessageKind::Info);
+        }
+    }
+
+    /// `:move`: moves lines `src_start..=src_end` below the first `dest` lines,
+    /// rewriting the span between the two places in one edit.
+    pub fn move_lines(&mut self, src_start: usize, src_end: usize, dest: usize) {
+        if dest > src_start && dest <= src_end {
+            self.notify("E134: Cannot move a range of lines into itself", MessageKind::Error);
+            return;
+        }
+        let count = src_end - src_start + 1;
+        let (first, last) = (src_start.min(dest), (src_end + 1).max(dest));
+        let mut lines: Vec<String> = (first..last).map(|line| self.line_text(line)).collect();
+        // A `dest` just above the lines leaves them where they are.
+        if dest <= src_start {
+            lines.rotate_right(count);
+        } else {
+            lines.rotate_left(count);
+        }
+
+        let rope = self.buffer.rope();
+        let range = rope.offset_of_line(first)..rope.offset_of_line(last);
+        let terminated = rope.slice(range.clone()).to_string().ends_with('\n');
+        let mut text = lines.join(self.line_ending());
+        if terminated {
+            text.push_str(self.line_ending());
+        }
+        self.buffer.edit(range, text);
+        self.buffer.set_modified(true);
+
+        let new_start = if dest <= src_start { dest } else { dest - count };
+        let new_end = (new_start + count - 1) as u16;
+        self.mark_change(CursorPos::new(0, new_start as u16), CursorPos::new(0, new_end));
+        self.cursor_position = CursorPos::new(0, new_end);
+        self.update_after_edit();
+        self.go_to_first_nonblank();
+        if count > 2 {
+            self.notify(&format!("{} lines moved", count), MessageKind::Info);
+        }
+    }
+
+    fn line_ending(&self) -> &'static str {
+        if self.buffer.line_ending() == "dos" {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+
+    /// `:d [x]`: deletes the lines in `range`, the cursor line by default, into
+    /// register `x`.
+    pub fn delete_lines(&mut self, range: ExRange, register: char) {
+        let y = self.cursor_position.row as usize;
+        let Some((start, end)) = self.resolve_range(&range, (y, y)) else {
+            return;
+        };
+        if register != '"' {
+            self.pending_register = Some(register);
+        }
+        self.operate_on_lines(Operator::Delete, start, end);
+        let deleted = end - start + 1;
+        if deleted > 2 {
+            self.notify(&format!("{} fewer lines", deleted), MessageKind::Info);
+        }
+    }
+
+    /// Sorts the lines in `range`, the whole buffer by default, (stable) and writes
+    /// them back with a single edit.
+    pub fn sort_lines(&mut self, range: ExRange, reverse: bool, ignore_case: bool, unique: bool) {
+        let whole_buffer = (0, self.total_lines() - 1);
+        let Some((start, end)) = self.resolve_range(&range, whole_buffer) else {
+            return;
+        };
+        let rope = self.buffer.rope();
+        let range_start = rope.offset_of_line(start);
+        let range_end = rope.offset_of_line(end + 1);
+        let text: Vec<Cow<str>> = rope.iter_lines(start, end + 1).collect();
+        let line_ending = self.line_ending();
+
+        let key = |line: &str| {
+            if ignore_case {
+                line.to_lowercase()
+            } else {
+                line.to_string()
+            }
+        };
+
+        let mut lines: Vec<&str> =
+            text.iter().map(|line| line.trim_end_matches(&['\r', '\n'][..])).collect();
+        lines.sort_by_cached_key(|line| key(line));
+        if reverse {
+            lines.reverse();
+        }
+        if unique {
+            lines.dedup_by(|a, b| key(a) == key(b));
+        }
+
+        let mut sorted = lines.join(line_ending);
+        if text.last().is_some_and(|line| line.ends_with('\n')) {
+            sorted.push_str(line_ending);
+        }
+
+        self.buffer.edit(range_start..range_end, sorted);
+        self.buffer.set_modified(true);
+        self.mark_change(CursorPos::new(0, start as u16), CursorPos::new(0, end as u16));
+        self.cursor_position = CursorPos::new(0, start as u16);
+        self.update_after_edit();
+    }
+
+    /// `:retab`: redoes the blanks of lines `start..=end` for `tabstop`. With
+    /// `to_spaces` each tab becomes the spaces up to its tab stop; otherwise runs
+    /// of blanks that reach a tab stop become tabs. Returns how many lines changed.
+    pub fn retab_lines(&mut self, start: usize, end: usize, to_spaces: bool) -> usize {
+        let tab_width = self.options.tab_width.max(1);
+        let mut changed = 0;
+        for line_idx in start..=end {
+            let line = self.line_text(line_idx);
+            let retabbed = retab(&line, tab_width, to_spaces);
+            if retabbed != line {
+                let line_start = self.buffer.rope().offset_of_line(line_idx);
+                self.buffer.edit(line_start..line_start + line.len(), retabbed);
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.buffer.set_modified(true);
+            self.mark_change(CursorPos::new(0, start as u16), CursorPos::new(0, end as u16));
+        }
+        self.normalize_cursor();
+        self.update_after_edit();
+        changed
+    }
+
+    /// `:left`, `:right` and `:center`: re-indents the non-blank lines of
+    /// `start..=end` with spaces so each starts at `width` (for `Left`), ends at
+    /// it or is centred in it. Returns how many lines changed.
+    pub fn align_lines(
+        &mut self,
+        start: usize,
+        end: usize,
+        mode: AlignMode,
+        width: usize,
+    ) -> usize {
+        let mut changed = 0;
+        for line_idx in start..=end {
+            let line = self.line_text(line_idx);
+            let content = line.trim_start();
+            if content.is_empty() {
+                continue;
+            }
+            let len = content.trim_end().chars().count();
+            let indent = match mode {
+                AlignMode::Left => width,
+                AlignMode::Right => width.saturating_sub(len),
+                AlignMode::Center => width.saturating_sub(len) / 2,
+            };
+            let leading = line.len() - content.len();
+            if line[..leading] != *" ".repeat(indent) {
+                let line_start = self.buffer.rope().offset_of_line(line_idx);
+                self.buffer.edit(line_start..line_start + leading, " ".repeat(indent));
+                changed += 1;
+            }
+        }
+        if changed > 0 {
+            self.buffer.set_modified(true);
+            self.mark_change(CursorPos::new(0, start as u16), CursorPos::new(0, end as u16));
+        }
+        self.normalize_cursor();
+        self.update_after_edit();
+        changed
+    }
+
+    /// `:Align c`: pads lines `start..=end` with spaces so the first `align_char`
+    /// on each lines up with the rightmost one. Lines without it are left alone.
+    pub fn align_lines_on(&mut self, start: usize, end: usize, align_char: char) {
+        let columns: Vec<Option<(usize, usize)>> = (start..=end)
+            .map(|line_idx| {
+                let line = self.line_text(line_idx);
+                let offset = line.find(align_char)?;
+                Some((offset, line[..offset].chars().count()))
+            })
+            .collect();
+        let Some(target) = columns.iter().flatten().map(|&(_, column)| column).max() else {
+            self.notify(&format!("E486: Pattern not found: {}", align_char), MessageKind::Error);
+            return;
+        };
+
+        // Bottom up, so earlier offsets stay valid.
+        for (line_idx, found) in (start..end + 1).zip(columns).rev() {
+            if let Some((offset, column)) = found.filter(|&(_, column)| column < target) {
+                let at = self.buffer.rope().offset_of_line(line_idx) + offset;
+                self.buffer.edit(at..at, " ".repeat(target - column));
+            }
+        }
+        self.buffer.set_modified(true);
+        self.mark_change(CursorPos::new(0, start as u16), CursorPos::new(0, end as u16));
+        self.update_after_edit();
+    }
+
+    /// Handles `:r <file>` and `:r !<command>`, inserting below the cursor line.
+    fn read_into_buffer(&mut self, arg: &str) {
+        let text = if let Some(shell_command) = arg.strip_prefix('!') {
+            shell_output(shell_command, None)
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        } else if arg.is_empty() {
+            match &self.file_path {
+                Some(path) => buffer::read_file(path),
+                None => {
+                    self.notify("E32: No file name", MessageKind::Error);
+                    return;
+                }
+            }
+        } else {
+            buffer::read_file(Path::new(arg))
+        };
+
+        match text {
+            Ok(text) if text.is_empty() => {}
+            Ok(text) => {
+                let line_idx = self.cursor_position.row as usize;
+                let added = text.lines().count();
+                self.insert_text_after_line(line_idx, &text);
+                self.cursor_position = CursorPos::new(0, line_idx as u16 + 1);
+                self.normalize_cursor();
+                self.notify(&format!("{} lines added", added), MessageKind::Info);
+            }
+            Err(e) => self.notify(
+                &format!("E484: Can't open file {}: {}", arg, e),
+                MessageKind::Error,
+            ),
+        }
+    }
+
+    /// Inserts `text` as new lines directly below `line_idx` in a single edit.
+    pub fn insert_text_after_line(&mut self, line_idx: usize, text: &str) {
+        let rope = self.buffer.rope();
+        let line_start = rope.offset_of_line(line_idx);
+        let next_line_start = rope.offset_of_line(line_idx + 1);
+        let line = rope.slice(line_start..next_line_start).to_string();
+        let line_end = line_start + line.trim_end_matches(&['\r', '\n'][..]).len();
+
+        let mut inserted = String::with_capacity(text.len() + 1);
+        inserted.push('\n');
+        inserted.push_str(text.strip_suffix('\n').unwrap_or(text));
+        let added = inserted.matches('\n').count();
+
+        self.buffer.edit(line_end..line_end, inserted);
+        self.mark_change(
+            CursorPos::new(0, line_idx as u16 + 1),
+            CursorPos::new(0, (line_idx + added) as u16),
+        );
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+    }
+
+    pub fn run_shell_command(&mut self, cmd: &str) {
+        let (lines, dismiss_message) = match shell_output(cmd, None) {
+            Ok(output) => {
+                let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+                text.push_str(&String::from_utf8_lossy(&output.stderr));
+                let status = match output.status.code() {
+                    Some(code) => format!("shell returned {}", code),
+                    None => "shell terminated by signal".to_string(),
+                };
+                (text.lines().map(str::to_string).collect(), status)
+            }
+            Err(e) => (vec![e.to_string()], "E282: Cannot execute shell".to_string()),
+        };
+
+        let mut overlay = Overlay::new(format!(":!{}", cmd), lines);
+        overlay.dismiss_message = Some(dismiss_message);
+        self.overlay = Some(overlay);
+    }
+
+    pub fn pipe_through_command(&mut self, cmd: &str) -> io::Result<()> {
+        let output = shell_output(cmd, Some(self.buffer.text()))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(stderr.trim().to_string()));
+        }
+
+        let filtered = String::from_utf8_lossy(&output.stdout).into_owned();
+        let len = self.buffer.rope().len();
+        self.buffer.edit(0..len, filtered);
+        let last = self.total_lines() - 1;
+        self.mark_change(CursorPos::new(0, 0), CursorPos::new(0, last as u16));
+        self.buffer.set_modified(true);
+        self.update_after_edit();
+        Ok(())
+    }
+
+    /// `:grep pattern [files]`: runs `grep -rn` (in the current directory when no
+    /// files are given), fills the quickfix list and jumps to the first match.
+    pub fn grep(&mut self, args: &[String]) {
+        let Some((pattern, files)) = args.split_first() else {
+            self.notify("E471: Argument required", MessageKind::Error);
+            return;
+        };
+        let mut grep = Command::new("grep");
+        grep.arg("-rnH").arg("--").arg(pattern).stdin(Stdio::null());
+        if files.is_empty() {
+            grep.arg(".");
+        } else {
+            grep.args(files);
+        }
+        let output = match grep.output() {
+            Ok(output) if output.status.code() == Some(2) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                self.notify(
+                    &format!("E40: {}", stderr.lines().next().unwrap_or("")),
+                    MessageKind::Error,
+                );
+                return;
+            }
+            Ok(output) => output,
+            Err(e) => {
+                self.notify(&format!("E282: Cannot execute grep: {}", e), MessageKind::Error);
+                return;
+            }
+        };
+
+        let regex = Regex::new(pattern).ok();
+        self.quickfix_list = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(QuickfixEntry::parse)
+            .map(|mut entry| {
+                // grep prints no column, so point at the first match in the line.
+                if let Some(found) = regex.as_ref().and_then(|re| re.find(&entry.message)) {
+                    entry.column = entry.message[..found.start()].chars().count() + 1;
+                }
+                entry
+            })
+            .collect();
+
+        if self.quickfix_list.is_empty() {
+            self.notify(&format!("E480: No match: {}", pattern), MessageKind::Error);
+        } else {
+            self.jump_to_quickfix(0);
+        }
+    }
+
+    /// `:make args`: runs `makeprg` with `args` and fills the quickfix list from
+    /// its output by `errorformat`, jumping to the first entry.
+    pub fn make(&mut self, args: &str) {
+        let command = format!("{} {}", self.options.make_program, args);
+        let output = match shell_output(command.trim(), None) {
+            Ok(output) => output,
+            Err(e) => {
+                self.notify(&format!("E282: Cannot execute make: {}", e), MessageKind::Error);
+                return;
+            }
+        };
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        self.quickfix_list = stderr
+            .lines()
+            .chain(stdout.lines())
+            .filter_map(|line| ErrorFormat::parse_line(&self.options.error_format, line))
+            .collect();
+        self.show_make_messages();
+
+        if self.quickfix_list.is_empty() {
+            let status = if output.status.success() { "done" } else { "failed" };
+            self.notify(&format!("make: {}, no errors", status), MessageKind::Info);
+            return;
+        }
+        self.jump_to_quickfix(0);
+        let warnings = self.quickfix_list.iter().filter(|e| ErrorFormat::is_warning(e)).count();
+        let errors = self.quickfix_list.len() - warnings;
+        let kind = if errors > 0 {
+            MessageKind::Error
+        } else {
+            MessageKind::Warning
+        };
+        self.notify(&format!("make: {} errors, {} warnings", errors, warnings), kind);
+    }
+
+    /// Whether `path` names the file being edited.
+    fn is_current_file(&self, path: &Path) -> bool {
+        self.file_path.as_ref().is_some_and(|file| {
+            file == path || std::fs::canonicalize(file).ok() == std::fs::canonicalize(path).ok()
+        })
+    }
+
+    /// Jumps to quickfix entry `index`, opening its file if it isn't the one being edited.
+    pub fn jump_to_quickfix(&mut self, index: usize) {
+        let Some(entry) = self.quickfix_list.get(index).cloned() else {
+            self.notify("E42: No Errors", MessageKind::Error);
+            return;
+        };
+        if !self.is_current_file(&entry.file) {
+            if let Err(e) = self.edit_file(entry.file.clone()) {
+                self.notify(&e.to_string(), MessageKind::Error);
+                return;
+            }
+        }
+
+        self.quickfix_index = index;
+        self.quickfix_cursor = index;
+        if entry.line > self.total_lines() {
+            self.notify("Invalid line number", MessageKind::Error);
+            return;
+        }
+        self.push_jump();
+        let column = entry.column.saturating_sub(1) as u16;
+        self.cursor_position = CursorPos::new(column, entry.line.saturating_sub(1) as u16);
+        self.normalize_cursor();
+        self.notify(
+            &format!("({} of {}): {}", index + 1, self.quickfix_list.len(), entry.message.trim()),
+            MessageKind::Info,
+        );
+    }
+
+    /// `:cnext` and `:cprevious`, `delta` entries on from the current one.
+    pub fn step_quickfix(&mut self, delta: isize) {
+        if self.quickfix_list.is_empty() {
+            self.notify("E42: No Errors", MessageKind::Error);
+            return;
+        }
+        let target = self.quickfix_index as isize + delta;
+        if target < 0 || target as usize >= self.quickfix_list.len() {
+            self.notify("E553: No more items", MessageKind::Error);
+            return;
+        }
+        self.jump_to_quickfix(target as usize);
+    }
+
+    /// Replaces the buffer with the contents of `path`, refusing to drop unsaved changes.
+    pub fn edit_file(&mut self, path: PathBuf) -> io::Result<()> {
+        if self.buffer.is_modified() {
+            return Err(io::Error::other(
+                "E37: No write since last change (add ! to override)",
+            ));
+        }
+        let content = buffer::read_file(&path)?;
+        if let Some(previous) = self.file_path.take().filter(|previous| previous != &path) {
+            self.alternate_file = Some(AlternateFile {
+                path: previous,
+                cursor: self.cursor_position,
+                scroll_offset: self.scroll_offset,
+            });
+        }
+        self.buffer = RopeTextBuffer::new(Rope::from(content));
+        self.highlighter.set_syntax_for_file(Some(&path));
+        self.file_path = Some(path);
+        self.detect_filetype();
+        self.cursor_position = CursorPos::new(0, 0);
+        self.scroll_offset = 0;
+        self.horizontal_offset = 0;
+        self.marks.clear();
+        self.search_matches.clear();
+        self.signs.clear();
+        self.virtual_texts.clear();
+        self.variables.buffer.clear();
+        self.buffer_keymap = Keymap::buffer_local();
+        self.update_git_signs();
+        self.update_after_edit();
+        if let Some(path) = &self.file_path {
+            // Remembering the file is best effort; failing to isn't worth an error.
+            let _ = RecentFiles::push(path);
+        }
+        self.enter_buffer();
+        Ok(())
+    }
+
+    /// `:e[dit] file`. `#` is the alternate file and `#N` or `#<N` the Nth of
+    /// `:oldfiles`; with no file, the current one is read again. `!` discards
+    /// changes.
+    fn edit_command(&mut self, target: &str, force: bool) {
+        let modified = self.buffer.is_modified();
+        if force {
+            self.buffer.set_modified(false);
+        }
+        let result = match target {
+            "#" => self.edit_alternate(),
+            "" => match self.file_path.clone() {
+                Some(path) => {
+                    let cursor = self.cursor_position;
+                    let result = self.edit_file(path);
+                    self.cursor_position = cursor;
+                    self.normalize_cursor();
+                    result
+                }
+                None => {
+                    self.notify("E32: No file name", MessageKind::Error);
+                    return;
+                }
+            },
+            _ => match target.strip_prefix('#') {
+                Some(number) => {
+                    let number = number.strip_prefix('<').unwrap_or(number);
+                    let path = number
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|i| self.oldfiles.get(i).cloned());
+                    match path {
+                        Some(path) => self.edit_file(path),
+                        None => {
+                            self.notify(
+                                &format!(
+                                    "E194: No alternate file name to substitute for '{}'",
+                                    target
+                                ),
+                                MessageKind::Error,
+                            );
+                            return;
+                        }
+                    }
+                }
+                None => self.edit_file(PathBuf::from(target)),
+            },
+        };
+        match result {
+            Ok(()) => self.notify(&self.file_info(), MessageKind::Info),
+            Err(e) => {
+                // The buffer is still the one on screen, so `!` mustn't have
+                // thrown its changes away.
+                self.buffer.set_modified(modified);
+                self.notify(&e.to_string(), MessageKind::Error);
+            }
+        }
+    }
+
+    /// Ctrl-^: edits the alternate file where its cursor was left, and makes the
+    /// current file the alternate one.
+    pub fn switch_to_alternate(&mut self) {
+        match self.edit_alternate() {
+            Ok(()) => self.notify(&self.file_info(), MessageKind::Info),
+            Err(e) => self.notify(&e.to_string(), MessageKind::Error),
+        }
+    }
+
+    /// Ctrl-^ and `:e #` without their messages.
+    fn edit_alternate(&mut self) -> io::Result<()> {
+        let Some(alternate) = self.alternate_file.clone() else {
+            return Err(io::Error::other("E23: No alternate file"));
+        };
+        self.edit_file(alternate.path)?;
+        self.cursor_position = alternate.cursor;
+        self.scroll_offset = alternate.scroll_offset;
+        self.normalize_cursor();
+        Ok(())
+    }
+
+    /// `:ls`: the current file marked `%a` and the alternate file `#`.
+    fn buffers_summary(&self) -> String {
+        let mut lines = vec![format!(
+            "  1 %a   \"{}\" line {}",
+            self.file_path
+                .as_ref()
+                .map_or(self.unnamed_label().to_string(), |p| p.display().to_string()),
+            self.cursor_position.row + 1
+        )];
+        if let Some(alternate) = &self.alternate_file {
+            lines.push(format!(
+                "  2 #    \"{}\" line {}",
+                alternate.path.display(),
+                alternate.cursor.row + 1
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// Shows `message` on the message line and keeps it for `:messages`.
+    pub fn notify(&mut self, message: &str, kind: MessageKind) {
+        self.messages.push(message, kind);
+    }
+
+    /// `:let name = expr` sets a variable; `:let` alone lists them.
+    fn let_command(&mut self, args: &str) {
+        if args.is_empty() {
+            self.overlay = Some(Overlay::new(":let", self.variables.summary()));
+            return;
+        }
+        let Some((name, expr)) = args.split_once('=') else {
+            self.notify(&format!("E15: Invalid expression: \"{}\"", args), MessageKind::Error);
+            return;
+        };
+        let result = eval::eval(expr, &self.variables)
+            .and_then(|value| self.variables.set(name.trim(), value));
+        if let Err(e) = result {
+            self.notify(&e, MessageKind::Error);
+        }
+    }
+
+    /// `:messages`: lists the messages given so far, oldest first.
+    fn show_messages(&mut self) {
+        let lines: Vec<String> = self
+            .messages
+            .messages
+            .iter()
+            .map(|(message, _, _)| message.clone())
+            .collect();
+        self.overlay = Some(Overlay::new(":messages", lines));
+    }
+
+    /// `:oldfiles`: lists the files from earlier sessions, numbered for opening.
+    fn show_oldfiles(&mut self) {
+        if self.oldfiles.is_empty() {
+            self.notify("No old files", MessageKind::Info);
+            return;
+        }
+        let lines = self
+            .oldfiles
+            .iter()
+            .enumerate()
+            .map(|(i, path)| format!("{:3}: {}", i + 1, path.display()))
+            .collect();
+        let mut overlay = Overlay::new(":oldfiles", lines);
+        overlay.kind = OverlayKind::OldFiles;
+        self.overlay = Some(overlay);
+    }
+
+    /// `:diffsplit file`: shows `file` beside the buffer and highlights where they differ.
+    fn diff_split(&mut self, path: &str) {
+        if path.is_empty() {
+            self.notify("E471: Argument required", MessageKind::Error);
+            return;
+        }
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                self.diff_pane = Some(DiffPane {
+                    path: PathBuf::from(path),
+                    buffer: RopeTextBuffer::new(Rope::from(content)),
+                });
+                self.diff_mode = true;
+                self.update_diff();
+            }
+            Err(e) => self.notify(
+                &format!("E484: Can't open file {}: {}", path, e),
+                MessageKind::Error,
+            ),
+        }
+    }
+
+    /// Recomputes the diff hunks between the buffer and the diff pane.
+    pub fn update_diff(&mut self) {
+        self.diff_hunks = match &self.diff_pane {
+            Some(pane) => diff::compute_hunks(&self.buffer.text(), &pane.buffer.text()),
+            None => Vec::new(),
+        };
+    }
+
+    /// The kind of difference on buffer line `line_idx`, if it is in a hunk.
+    pub fn diff_kind_at(&self, line_idx: usize) -> Option<DiffKind> {
+        self.diff_hunks
+            .iter()
+            .find(|hunk| (hunk.old_start..hunk.old_start + hunk.old_len).contains(&line_idx))
+            .map(|hunk| hunk.kind)
+    }
+
+    /// `]c` and `[c`: moves the cursor to the start of the `count`th hunk after or
+    /// before the cursor line.
+    pub fn jump_to_hunk(&mut self, count: isize) {
+        let y = self.cursor_position.row as usize;
+        let last_line = self.total_lines() - 1;
+        let starts = self.diff_hunks.iter().map(|hunk| hunk.old_start.min(last_line));
+        let target = if count > 0 {
+            starts.filter(|&start| start > y).nth(count as usize - 1)
+        } else {
+            let before: Vec<usize> = starts.filter(|&start| start < y).collect();
+            before.iter().rev().nth(count.unsigned_abs().saturating_sub(1)).copied()
+        };
+        if let Some(line) = target {
+            self.push_jump();
+            self.cursor_position = CursorPos::new(0, line as u16);
+            self.normalize_cursor();
+        }
+    }
+
+    /// `dp` (`put`) and `do`: makes the hunk under the cursor the same in both panes.
+    pub fn diff_transfer(&mut self, put: bool) {
+        let y = self.cursor_position.row as usize;
+        let Some(hunk) = self.diff_hunks.iter().find(|hunk| hunk.contains_old(y)).copied() else {
+            self.notify("E102: No differences here", MessageKind::Error);
+            return;
+        };
+        let Some(pane) = self.diff_pane.as_mut() else {
+            return;
+        };
+        let (from, to, from_lines, to_lines) = if put {
+            (&self.buffer, &mut pane.buffer, hunk.old_start, hunk.new_start)
+        } else {
+            (&pane.buffer, &mut self.buffer, hunk.new_start, hunk.old_start)
+        };
+        let (from_len, to_len) = if put {
+            (hunk.old_len, hunk.new_len)
+        } else {
+            (hunk.new_len, hunk.old_len)
+        };
+
+        let source = from.rope();
+        let mut text = source
+            .slice(source.offset_of_line(from_lines)..source.offset_of_line(from_lines + from_len))
+            .to_string();
+        let target = to.rope();
+        let range = target.offset_of_line(to_lines)..target.offset_of_line(to_lines + to_len);
+        // The source may end without a newline where the target goes on past it.
+        if !text.is_empty() && !text.ends_with('\n') && range.end < target.len() {
+            text.push('\n');
+        }
+        to.edit(range, text);
+        to.set_modified(true);
+
+        if put {
+            self.update_diff();
+        } else {
+            self.mark_change(CursorPos::new(0, y as u16), CursorPos::new(0, y as u16));
+            self.update_after_edit();
+        }
+    }
+
+    /// `:wall`: writes the buffer and the diff pane's file if they have changes,
+    /// returning the paths written. A failure doesn't stop the other write; the
+    /// status line reports each one and the first is returned.
+    pub fn save_all_buffers(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut saved = Vec::new();
+        let mut errors = Vec::new();
+        if self.buffer.is_modified() {
+            match self.save() {
+                Ok(()) => saved.extend(self.file_path.clone()),
+                Err(e) => errors.push(e),
+            }
+        }
+        if let Some(pane) = self.diff_pane.as_mut().filter(|pane| pane.buffer.is_modified()) {
+            match pane.buffer.save_to_file(&pane.path, self.options.ensure_trailing_newline) {
+                Ok(()) => {
+                    pane.buffer.set_modified(false);
+                    saved.push(pane.path.clone());
+                }
+                Err(e) => errors.push(io::Error::new(
+                    e.kind(),
+                    format!("E212: Can't open file for writing: {}", e),
+                )),
+            }
+        }
+
+        if !errors.is_empty() {
+            let messages: Vec<String> = errors.iter().map(io::Error::to_string).collect();
+            self.notify(&messages.join("; "), MessageKind::Error);
+        } else if saved.len() > 1 {
+            self.notify(&format!("{} files written", saved.len()), MessageKind::Info);
+        }
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(saved),
+        }
+    }
+
+    /// `:only`: closes the diff pane and the quickfix window, leaving the text the
+    /// whole screen. Unsaved changes in the diff pane need `:only!` to drop.
+    pub fn close_other_splits(&mut self, force: bool) {
+        if self.diff_pane.is_none() && !self.quickfix_open {
+            self.notify("Already only one window", MessageKind::Info);
+            return;
+        }
+        if !force && self.diff_pane.as_ref().is_some_and(|pane| pane.buffer.is_modified()) {
+            self.notify("E445: Other window contains changes", MessageKind::Error);
+            return;
+        }
+        self.diff_mode = false;
+        self.diff_hunks.clear();
+        self.diff_pane = None;
+        self.quickfix_open = false;
+        self.quickfix_focused = false;
+        self.text_changed = true;
+    }
+
+    /// Rows shared by the text and the quickfix window, its title included.
+    fn split_rows(&self) -> usize {
+        self.viewport_height + self.quickfix_rows(u16::MAX) as usize
+    }
+
+    /// `:copen`: shows the quickfix list in a read-only window below the text and
+    /// moves to it, on the entry last jumped to. Enter there jumps to an entry.
+    pub fn open_quickfix_split(&mut self) {
+        self.quickfix_open = true;
+        self.quickfix_focused = true;
+        self.quickfix_cursor = self.quickfix_index.min(self.quickfix_list.len().saturating_sub(1));
+        self.text_changed = true;
+    }
+
+    /// `:cclose`: removes the quickfix window, returning to the text.
+    pub fn close_quickfix_split(&mut self) {
+        self.quickfix_open = false;
+        self.quickfix_focused = false;
+        self.text_changed = true;
+    }
+
+    /// Sets the quickfix window's height, leaving both windows at least
+    /// `MIN_SPLIT_HEIGHT` rows.
+    fn set_quickfix_height(&mut self, height: usize) {
+        let max = self.split_rows().saturating_sub(1 + MIN_SPLIT_HEIGHT);
+        self.options.quickfix_height = height.min(max).max(MIN_SPLIT_HEIGHT);
+        self.text_changed = true;
+    }
+
+    /// Height of the focused window: the quickfix window's, or the text's.
+    fn focused_split_height(&self) -> usize {
+        if self.quickfix_focused {
+            self.options.quickfix_height
+        } else {
+            self.viewport_height
+        }
+    }
+
+    /// Sets the focused window's height, the other window taking the rest.
+    fn set_focused_split_height(&mut self, height: usize) {
+        if !self.quickfix_open {
+            return;
+        }
+        if self.quickfix_focused {
+            self.set_quickfix_height(height);
+        } else {
+            self.set_quickfix_height(self.split_rows().saturating_sub(height).saturating_sub(1));
+        }
+    }
+
+    /// Makes the focused window `delta` rows taller, or shorter if negative.
+    pub fn resize_split(&mut self, delta: i16) {
+        let height = self.focused_split_height().saturating_add_signed(delta as isize);
+        self.set_focused_split_height(height);
+    }
+
+    /// `:resize N` sets the focused window's height, `:resize +N` and `:resize -N`
+    /// change it, and `:resize` alone makes it as tall as it can be.
+    fn resize_command(&mut self, arg: &str) {
+        let delta = |n: &str| n.parse::<i16>().ok();
+        match arg.chars().next() {
+            None => self.set_focused_split_height(usize::MAX),
+            Some('+') => match delta(&arg[1..]) {
+                Some(n) => self.resize_split(n),
+                None => self.notify(
+                    &format!("E475: Invalid argument: {}", arg),
+                    MessageKind::Error,
+                ),
+            },
+            Some('-') => match delta(&arg[1..]) {
+                Some(n) => self.resize_split(-n),
+                None => self.notify(
+                    &format!("E475: Invalid argument: {}", arg),
+                    MessageKind::Error,
+                ),
+            },
+            Some(_) => match arg.parse() {
+                Ok(height) => self.set_focused_split_height(height),
+                Err(_) => self.notify(
+                    &format!("E475: Invalid argument: {}", arg),
+                    MessageKind::Error,
+                ),
+            },
+        }
+    }
+
+    /// The name of a buffer with unsaved changes, which `:qa` refuses to lose.
+    fn modified_buffer_name(&self) -> Option<String> {
+        if self.buffer.is_modified() {
+            return Some(self.file_path.as_ref().map_or(self.unnamed_label().to_string(), |p| {
+                p.display().to_string()
+            }));
+        }
+        self.diff_pane
+            .as_ref()
+            .filter(|pane| pane.buffer.is_modified())
+            .map(|pane| pane.path.display().to_string())
+    }
+
+    /// `:bufdo` and `:windo`: runs `cmd` on the buffer, then on the `:diffsplit`
+    /// file if one is shown, coming back to the buffer where it was.
+    pub fn execute_ex_over_buffers(&mut self, cmd: &str) {
+        let (cursor, scroll) = (self.cursor_position, self.scroll_offset);
+        // A buffer without a file name can't trade places with the pane's file.
+        let buffers = if self.diff_pane.is_some() && self.file_path.is_some() { 2 } else { 1 };
+        let mut failures = Vec::new();
+        for i in 0..buffers {
+            if i == 1 {
+                self.swap_with_diff_pane();
+                self.cursor_position = CursorPos::new(0, 0);
+                self.scroll_offset = 0;
+            }
+            failures.extend(self.execute_collecting_errors(cmd));
+        }
+        if buffers == 2 {
+            self.swap_with_diff_pane();
+            self.update_git_signs();
+            self.update_after_edit();
+        }
+        self.cursor_position = cursor;
+        self.scroll_offset = scroll;
+        self.normalize_cursor();
+        self.report_ex_over(buffers, "buffers", &failures);
+    }
+
+    /// `:argdo`: edits each file in the argument list in turn and runs `cmd` on
+    /// it, stopping if a file can't be edited. Like vim, it ends on the last file.
+    pub fn execute_ex_over_args(&mut self, cmd: &str) {
+        let mut failures = Vec::new();
+        for path in self.arg_list.clone() {
+            let current = self.file_path.as_ref().is_some_and(|current| {
+                current == &path
+                    || std::fs::canonicalize(current).ok() == std::fs::canonicalize(&path).ok()
+            });
+            if !current {
+                if let Err(e) = self.edit_file(path.clone()) {
+                    failures.push(format!("{}: {}", path.display(), e));
+                    break;
+                }
+            }
+            failures.extend(self.execute_collecting_errors(cmd));
+        }
+        self.report_ex_over(self.arg_list.len(), "files", &failures);
+    }
+
+    /// Runs one ex command for `:bufdo` and friends, returning its error, if any.
+    fn execute_collecting_errors(&mut self, cmd: &str) -> Option<String> {
+        let start = Instant::now();
+        self.execute_command(cmd);
+        self.update_after_edit();
+        self.messages.last_error_since(start).map(str::to_string)
+    }
+
+    /// `:source`: runs each line of `path` as an ex command. Blank lines and lines
+    /// starting with `"` are skipped, and a failing line doesn't stop the rest;
+    /// the errors are reported together at the end.
+    pub fn source_file(&mut self, path: &Path) -> io::Result<()> {
+        // A script that sources itself would otherwise never finish.
+        if self.source_depth >= MAX_SOURCE_DEPTH {
+            self.notify("E169: Command too recursive", MessageKind::Error);
+            return Ok(());
+        }
+        let file = std::fs::File::open(path)?;
+        let script = std::fs::canonicalize(path)?;
+        if !self.sourced_scripts.contains(&script) {
+            self.sourced_scripts.push(script.clone());
+        }
+
+        let outer_script = self.current_script.replace(script);
+        self.source_depth += 1;
+        let result = self.run_script_lines(BufReader::new(file), &path.display().to_string());
+        self.source_depth -= 1;
+        self.current_script = outer_script;
+        result
+    }
+
+    /// `--headless`: runs each line of `reader` as an ex command, as `:source`
+    /// runs a file.
+    pub fn run_script(&mut self, reader: impl BufRead) -> io::Result<()> {
+        self.run_script_lines(reader, "script")
+    }
+
+    /// Runs the lines of a script called `name`, reporting the errors of failing
+    /// lines together at the end.
+    fn run_script_lines(&mut self, reader: impl BufRead, name: &str) -> io::Result<()> {
+        let mut errors = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let command = line.trim_start().trim_start_matches(':');
+            if command.trim().is_empty() || command.starts_with('"') {
+                continue;
+            }
+            if let Some(e) = self.execute_collecting_errors(command) {
+                errors.push(format!("line {}: {}", number + 1, e));
+            }
+        }
+
+        if !errors.is_empty() {
+            let message =
+                format!("Error detected while processing {}: {}", name, errors.join("; "));
+            self.notify(&message, MessageKind::Error);
+        }
+        Ok(())
+    }
+
+    /// Sets `filetype` from the syntax of the file, loading its snippets.
+    fn detect_filetype(&mut self) {
+        self.filetype = self.highlighter.filetype();
+        self.load_snippets();
+    }
+
+    /// Runs what opening a file runs: the `FileType` autocommands and
+    /// `~/.cim/ftplugin/<filetype>.cim`, then the `BufEnter` autocommands.
+    pub fn enter_buffer(&mut self) {
+        self.run_filetype_plugins();
+        if let Some(path) = self.file_path.clone() {
+            self.run_autocmds(AutocmdEvent::BufEnter, &path.to_string_lossy());
+        }
+    }
+
+    /// Runs the `FileType` autocommands for `filetype`, then
+    /// `~/.cim/ftplugin/<filetype>.cim` if there is one.
+    fn run_filetype_plugins(&mut self) {
+        let filetype = self.filetype.clone();
+        self.run_autocmds(AutocmdEvent::FileType, &filetype);
+        if let Some(home) = std::env::var_os("HOME") {
+            let ftplugin = PathBuf::from(home)
+                .join(".cim")
+                .join("ftplugin")
+                .join(format!("{}.cim", filetype));
+            if ftplugin.exists() {
+                if let Err(e) = self.source_file(&ftplugin) {
+                    self.notify(
+                        &format!("E484: Can't open file {}: {}", ftplugin.display(), e),
+                        MessageKind::Error,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Runs the commands of the autocommands for `event` that match `name`.
+    fn run_autocmds(&mut self, event: AutocmdEvent, name: &str) {
+        let commands: Vec<String> = self
+            .config
+            .autocmds
+            .iter()
+            .filter(|autocmd| autocmd.matches(event, name))
+            .map(|autocmd| autocmd.command.clone())
+            .collect();
+        for command in commands {
+            self.execute_command(&command);
+        }
+    }
+
+    /// `:highlight scope attributes` overrides the theme for a syntax scope;
+    /// `:highlight` alone lists the overrides and `:highlight scope` shows one.
+    fn highlight_command(&mut self, args: &[String]) {
+        let Some((scope, attributes)) = args.split_first() else {
+            let lines = self.highlighter.custom_highlight_summary("");
+            self.overlay = Some(Overlay::new(":highlight", lines));
+            return;
+        };
+        if attributes.is_empty() {
+            match self.highlighter.custom_highlight_summary(scope).pop() {
+                Some(line) => self.notify(&line, MessageKind::Info),
+                None => self.notify(
+                    &format!("E411: Highlight group not found: {}", scope),
+                    MessageKind::Error,
+                ),
+            }
+            return;
+        }
+        let attributes: Vec<&str> = attributes.iter().map(String::as_str).collect();
+        match self.highlighter.set_custom_highlight(scope, &attributes) {
+            Ok(()) => self.text_changed = true,
+            Err(e) => self.notify(&e, MessageKind::Error),
+        }
+    }
+
+    /// `:autocmd Event pattern command` adds an autocommand; with less, lists
+    /// those for the event and pattern given. `:autocmd!` removes them instead.
+    fn autocmd_command(&mut self, clear: bool, args: &str) {
+        let mut parts = args.splitn(3, char::is_whitespace).filter(|part| !part.is_empty());
+        let event = match parts.next() {
+            Some(name) => match AutocmdEvent::parse(name) {
+                Some(event) => Some(event),
+                None => {
+                    self.notify(
+                        &format!("E216: No such group or event: {}", name),
+                        MessageKind::Error,
+                    );
+                    return;
+                }
+            },
+            None => None,
+        };
+        let pattern = parts.next();
+        let command = parts.next().map(str::trim);
+
+        let selected = |autocmd: &Autocmd| {
+            event.is_none_or(|event| autocmd.event == event)
+                && pattern.is_none_or(|pattern| autocmd.pattern == pattern)
+        };
+        if clear {
+            self.config.autocmds.retain(|autocmd| !selected(autocmd));
+        }
+        match (event, pattern, command) {
+            (Some(event), Some(pattern), Some(command)) => {
+                self.config.autocmds.push(Autocmd {
+                    event,
+                    pattern: pattern.to_string(),
+                    command: command.to_string(),
+                });
+            }
+            _ if clear => {}
+            _ => {
+                let lines: Vec<String> = self
+                    .config
+                    .autocmds
+                    .iter()
+                    .filter(|autocmd| selected(autocmd))
+                    .map(Autocmd::summary)
+                    .collect();
+                match lines.len() {
+                    0 => self.notify("No matching autocommands", MessageKind::Info),
+                    1 => self.notify(&lines[0], MessageKind::Info),
+                    _ => self.overlay = Some(Overlay::new(":autocmd", lines)),
+                }
+            }
+        }
+    }
+
+    /// Sources `~/.cim/init.cim` at startup, if there is one.
+    pub fn source_init_file(&mut self) {
+        let Some(home) = std::env::var_os("HOME") else {
+            return;
+        };
+        let path = PathBuf::from(home).join(".cim").join("init.cim");
+        if path.exists() {
+            if let Err(e) = self.source_file(&path) {
+                self.notify(
+                    &format!("E484: Can't open file {}: {}", path.display(), e),
+                    MessageKind::Error,
+                );
+            }
+        }
+    }
+
+    fn report_ex_over(&mut self, count: usize, what: &str, failures: &[String]) {
+        match failures {
+            [] => self.notify(&format!("{} {} processed", count, what), MessageKind::Info),
+            [first, ..] => self.notify(
+                &format!("{} of {} {} failed: {}", failures.len(), count, what, first),
+                MessageKind::Error,
+            ),
+        }
+    }
+
+    /// Trades the buffer and its file name with the diff pane's, so ex commands
+    /// act on the pane's file.
+    fn swap_with_diff_pane(&mut self) {
+        if let (Some(pane), Some(path)) = (self.diff_pane.as_mut(), self.file_path.as_mut()) {
+            std::mem::swap(&mut self.buffer, &mut pane.buffer);
+            std::mem::swap(path, &mut pane.path);
+        }
+    }
+
+    /// `:args`: lists the argument list with the current file in brackets, or
+    /// replaces it with `files` and edits the first of them.
+    fn set_arg_list(&mut self, files: &[String]) {
+        if files.is_empty() {
+            let listed: Vec<String> = self
+                .arg_list
+                .iter()
+                .map(|path| match &self.file_path {
+                    Some(current) if current == path => format!("[{}]", path.display()),
+                    _ => path.display().to_string(),
+                })
+                .collect();
+            self.notify(&listed.join(" "), MessageKind::Info);
+            return;
+        }
+        if self.buffer.is_modified() {
+            self.notify(
+                "E37: No write since last change (add ! to override)",
+                MessageKind::Error,
+            );
+            return;
+        }
+        self.arg_list = files.iter().map(PathBuf::from).collect();
+        if let Err(e) = self.edit_file(self.arg_list[0].clone()) {
+            self.notify(&e.to_string(), MessageKind::Error);
+        }
+    }
+
+    /// Defines sign `name`, replacing any earlier definition. `text` is cut to
+    /// the two columns the sign column has.
+    pub fn sign_define(&mut self, name: &str, text: &str, style: Style) {
+        let sign = Sign {
+            name: name.to_string(),
+            text: text.chars().take(2).collect(),
+            highlight: style,
+        };
+        self.sign_definitions.insert(name.to_string(), sign);
+    }
+
+    /// Places the sign defined as `name` on 0-based `line`.
+    pub fn sign_place(&mut self, name: &str, line: usize) {
+        let Some(sign) = self.sign_definitions.get(name).cloned() else {
+            self.notify(&format!("E155: Unknown sign: {}", name), MessageKind::Error);
+            return;
+        };
+        let placed = self.signs.entry(line).or_default();
+        placed.retain(|placed| placed.name != name);
+        placed.push(sign);
+    }
+
+    /// Removes sign `name` from 0-based `line`.
+    pub fn sign_unplace(&mut self, name: &str, line: usize) {
+        if let Some(placed) = self.signs.get_mut(&line) {
+            placed.retain(|placed| placed.name != name);
+            if placed.is_empty() {
+                self.signs.remove(&line);
+            }
+        }
+    }
+
+    /// Adds `text` after the end of 0-based `line`, after any already there.
+    pub fn set_virtual_text(&mut self, line: usize, text: &str, style: Style) {
+        self.virtual_texts.entry(line).or_default().push(VirtualText {
+            content: text.to_string(),
+            style,
+            col: None,
+        });
+    }
+
+    /// Removes the virtual text of 0-based `line`.
+    pub fn clear_virtual_text(&mut self, line: usize) {
+        self.virtual_texts.remove(&line);
+    }
+
+    /// Shows the message of each quickfix entry in the current file after its
+    /// line, replacing the messages of the previous `:make`.
+    fn show_make_messages(&mut self) {
+        let lines: Vec<usize> = self.virtual_texts.keys().copied().collect();
+        for line in lines {
+            self.clear_virtual_text(line);
+        }
+        let entries: Vec<QuickfixEntry> = self
+            .quickfix_list
+            .iter()
+            .filter(|entry| self.is_current_file(&entry.file))
+            .cloned()
+            .collect();
+        for entry in entries {
+            let color = if ErrorFormat::is_warning(&entry) {
+                Color::Yellow
+            } else {
+                Color::Red
+            };
+            let style = Style::default().fg(color).add_modifier(Modifier::ITALIC);
+            let line = entry.line.saturating_sub(1);
+            self.set_virtual_text(line, &entry.message, style);
+        }
+    }
+
+    /// Whether to draw the sign column, given `signcolumn` and the placed signs.
+    pub fn shows_sign_column(&self) -> bool {
+        match self.options.sign_column {
+            SignColumnMode::Auto => !self.signs.is_empty(),
+            SignColumnMode::Yes => true,
+            SignColumnMode::No => false,
+        }
+    }
+
+    /// Marks lines changed since the git index, replacing the previous git signs.
+    pub fn update_git_signs(&mut self) {
+        let lines: Vec<usize> = self.signs.keys().copied().collect();
+        for line in lines {
+            for name in sign::GIT_SIGNS {
+                self.sign_unplace(name, line);
+            }
+        }
+        let Some(path) = self.file_path.clone() else {
+            return;
+        };
+        // Outside a git repository, or without git, there is nothing to show.
+        for (line, name) in sign::git_changes(&path).unwrap_or_default() {
+            self.sign_place(name, line);
+        }
+    }
+
+    /// `:sign place {name} [{line}]` and `:sign unplace {name} [{line}]`, on the
+    /// cursor line when no line is given.
+    fn sign_command(&mut self, args: &[String]) {
+        let (Some(subcommand), Some(name)) = (args.first(), args.get(1)) else {
+            self.notify("E471: Argument required", MessageKind::Error);
+            return;
+        };
+        let line = match args.get(2).map(|line| line.parse::<usize>()) {
+            None => self.cursor_position.row as usize,
+            Some(Ok(line)) if (1..=self.total_lines()).contains(&line) => line - 1,
+            Some(_) => {
+                self.notify(&format!("E885: Invalid line number: {}", args[2]), MessageKind::Error);
+                return;
+            }
+        };
+        match subcommand.as_str() {
+            "place" => self.sign_place(name, line),
+            "unplace" => self.sign_unplace(name, line),
+            _ => self.notify(
+                &format!("E160: Unknown sign command: {}", subcommand),
+                MessageKind::Error,
+            ),
+        }
+    }
+
+    /// `:ab lhs rhs` and its Insert-only and Command-only forms. Without a
+    /// `rhs`, lists the abbreviations starting with `lhs`.
+    fn abbreviate(&mut self, insert: bool, command: bool, args: &str) {
+        let (lhs, rhs) = match args.split_once(char::is_whitespace) {
+            Some((lhs, rhs)) => (lhs, rhs.trim()),
+            None => (args, ""),
+        };
+        if rhs.is_empty() {
+            let lines = self.abbreviations_summary(lhs, insert, command);
+            match lines.len() {
+                0 => self.notify("No abbreviation found", MessageKind::Info),
+                1 => self.notify(&lines[0], MessageKind::Info),
+                _ => self.overlay = Some(Overlay::new(":abbreviate", lines)),
+            }
+            return;
+        }
+        if insert {
+            self.abbreviations.insert(lhs.to_string(), rhs.to_string());
+        }
+        if command {
+            self.command_abbreviations.insert(lhs.to_string(), rhs.to_string());
+        }
+    }
+
+    /// `:map lhs rhs` and its forms, or with no `rhs`, lists the mappings in
+    /// `mode`, only that of `lhs` if given. `:map` makes a mapping in both Normal
+    /// and Visual mode. `<buffer>` before `lhs` makes or lists the mappings of
+    /// the current buffer alone.
+    fn map_command(&mut self, mode: Option<MapMode>, noremap: bool, verbose: bool, args: &str) {
+        let (buffer_local, args) = match args.strip_prefix("<buffer>") {
+            Some(args) => (true, args.trim_start()),
+            None => (false, args),
+        };
+        let (lhs, rhs) = match args.split_once(char::is_whitespace) {
+            Some((lhs, rhs)) => (lhs, rhs.trim()),
+            None => (args, ""),
+        };
+        if rhs.is_empty() {
+            let lines = if buffer_local {
+                self.buffer_keymap.display(mode, lhs, verbose)
+            } else {
+                let keymap = self.keymap.with_buffer_override(&self.buffer_keymap);
+                keymap.display(mode, lhs, verbose)
+            };
+            match lines.len() {
+                0 => self.notify("No mapping found", MessageKind::Info),
+                1 => self.notify(&lines[0], MessageKind::Info),
+                _ => self.overlay = Some(Overlay::new(":map", lines)),
+            }
+            return;
+        }
+        let modes = match mode {
+            Some(mode) => vec![mode],
+            None => vec![MapMode::Normal, MapMode::Visual],
+        };
+        let keymap = if buffer_local {
+            &mut self.buffer_keymap
+        } else {
+            &mut self.keymap
+        };
+        for mode in modes {
+            keymap.set(mode, lhs, rhs, noremap, self.current_script.clone());
+        }
+    }
+
+    fn unabbreviate(&mut self, insert: bool, command: bool, lhs: &str) {
+        let removed_insert = insert && self.abbreviations.remove(lhs).is_some();
+        let removed_command = command && self.command_abbreviations.remove(lhs).is_some();
+        if !removed_insert && !removed_command {
+            self.notify(&format!("E24: No such abbreviation: {}", lhs), MessageKind::Error);
+        }
+    }
+
+    /// One line per abbreviation starting with `lhs`, marked `i` or `c` for the
+    /// mode it applies in, or `!` when it is the same in both.
+    pub fn abbreviations_summary(&self, lhs: &str, insert: bool, command: bool) -> Vec<String> {
+        let mut names: Vec<&String> = self
+            .abbreviations
+            .keys()
+            .chain(self.command_abbreviations.keys())
+            .filter(|name| name.starts_with(lhs))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        let mut lines = Vec::new();
+        for name in names {
+            let in_insert = self.abbreviations.get(name).filter(|_| insert);
+            let in_command = self.command_abbreviations.get(name).filter(|_| command);
+            match (in_insert, in_command) {
+                (Some(i), Some(c)) if i == c => lines.push(format!("!  {:<12} {}", name, i)),
+                (i, c) => {
+                    if let Some(i) = i {
+                        lines.push(format!("i  {:<12} {}", name, i));
+                    }
+                    if let Some(c) = c {
+                        lines.push(format!("c  {:<12} {}", name, c));
+                    }
+                }
+            }
+        }
+        lines
+    }
+
+    /// Replaces the word just before the cursor with its Insert mode abbreviation,
+    /// unless it sits in a string or comment. Returns whether it expanded.
+    pub fn expand_abbreviation_before_cursor(&mut self) -> bool {
+        if self.abbreviations.is_empty() {
+            return false;
+        }
+        let y = self.cursor_position.row;
+        let (start, word) = self.word_before_cursor();
+        let x = start + word.chars().count();
+        let Some(expansion) = self.abbreviations.get(&word).cloned() else {
+            return false;
+        };
+
+        let from = self.offset_of_position(CursorPos::new(start as u16, y));
+        let line_start = self.buffer.rope().offset_of_line(y as usize);
+        let text = self.buffer.text();
+        if self.highlighter.in_string_or_comment(&text, y as usize, from - line_start) {
+            return false;
+        }
+
+        let to = self.offset_of_position(CursorPos::new(x as u16, y));
+        self.buffer.edit(from..to, expansion.as_str());
+        self.buffer.set_modified(true);
+        let end_x = (start + expansion.chars().count()) as u16;
+        self.mark_change(
+            CursorPos::new(start as u16, y),
+            CursorPos::new(end_x.saturating_sub(1), y),
+        );
+        self.cursor_position.col = end_x;
+        self.update_after_edit();
+        true
+    }
+
+    /// The column where the word ending at the cursor starts, and the word.
+    fn word_before_cursor(&self) -> (usize, String) {
+        let CursorPos { col: x, row: y } = self.cursor_position;
+        let line: Vec<char> = self.line_text(y as usize).chars().collect();
+        let x = (x as usize).min(line.len());
+        let start = x - line[..x]
+            .iter()
+            .rev()
+            .take_while(|&&c| completion::is_word_char(c))
+            .count();
+        (start, line[start..x].iter().collect())
+    }
+
+    /// Loads the snippets for the current file type, keeping those defined with
+    /// `:snippet`.
+    fn load_snippets(&mut self) {
+        self.snippets = snippet::load(&self.filetype);
+        self.snippets
+            .extend(self.defined_snippets.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Tab in Insert mode: replaces the word before the cursor with its snippet
+    /// and moves to the first tab stop.
+    fn expand_snippet_before_cursor(&mut self) -> bool {
+        let (start, word) = self.word_before_cursor();
+        let Some(snippet) = self.snippets.get(&word) else {
+            return false;
+        };
+        let y = self.cursor_position.row;
+        let indent: String = self
+            .line_text(y as usize)
+            .chars()
+            .take_while(|&c| c == ' ' || c == '\t')
+            .collect();
+        let snippet = snippet.indented(&indent);
+
+        let from = self.offset_of_position(CursorPos::new(start as u16, y));
+        let to = self.offset_of_position(self.cursor_position);
+        self.buffer.edit(from..to, snippet.body.as_str());
+        self.buffer.set_modified(true);
+        let end = self.position_of_offset(from + snippet.body.len());
+        self.mark_change(CursorPos::new(start as u16, y), end.saturating_add_col(-1));
+        self.update_after_edit();
+
+        let stops: Vec<Range<usize>> = snippet
+            .tab_stops
+            .iter()
+            .map(|stop| from + stop.start..from + stop.end)
+            .collect();
+        self.snippet_session = Some(SnippetSession {
+            stops,
+            current: 0,
+            placeholder_pending: false,
+        });
+        self.mode = EditorMode::SnippetExpand;
+        self.jump_to_tab_stop(0);
+        true
+    }
+
+    /// Moves to tab stop `index` of the snippet being filled in, leaving snippet
+    /// mode for Insert mode on reaching the last.
+    fn jump_to_tab_stop(&mut self, index: usize) {
+        let Some(session) = self.snippet_session.as_mut() else {
+            return;
+        };
+        let Some(stop) = session.stops.get(index).cloned() else {
+            return;
+        };
+        session.current = index;
+        session.placeholder_pending = !stop.is_empty();
+        if index + 1 == session.stops.len() {
+            self.snippet_session = None;
+            self.mode = EditorMode::Insert;
+        }
+        self.cursor_position = self.position_of_offset(stop.start);
+        self.update_viewport();
+    }
+
+    /// Keys while filling in a snippet: Tab and Shift-Tab move between tab stops
+    /// and everything else works as in Insert mode. The first character typed at
+    /// a stop replaces its placeholder text.
+    fn handle_snippet_input(&mut self, key: KeyEvent) -> Option<EditorAction> {
+        let session = self.snippet_session.as_mut()?;
+        match key.code {
+            KeyCode::Tab if self.completion_state.is_none() => {
+                let next = session.current + 1;
+                self.jump_to_tab_stop(next);
+                return None;
+            }
+            KeyCode::BackTab => {
+                let previous = session.current.saturating_sub(1);
+                self.jump_to_tab_stop(previous);
+                return None;
+            }
+            KeyCode::Char(_) | KeyCode::Enter | KeyCode::Backspace | KeyCode::Delete
+                if session.placeholder_pending =>
+            {
+                session.placeholder_pending = false;
+                let placeholder = session.stops[session.current].clone();
+                self.buffer.edit(placeholder.clone(), "");
+                self.buffer.set_modified(true);
+                self.snippet_session
+                    .as_mut()?
+                    .shift(placeholder.start, -(placeholder.len() as isize));
+                self.cursor_position = self.position_of_offset(placeholder.start);
+                self.update_after_edit();
+                if matches!(key.code, KeyCode::Backspace | KeyCode::Delete) {
+                    return None;
+                }
+            }
+            _ => session.placeholder_pending = false,
+        }
+
+        let offset = self.offset_of_position(self.cursor_position);
+        let length = self.buffer.rope().len();
+        self.mode = EditorMode::Insert;
+        let result = self.dispatch_input(key);
+        let delta = self.buffer.rope().len() as isize - length as isize;
+        if self.mode != EditorMode::Insert {
+            self.snippet_session = None;
+            return result;
+        }
+        if let Some(session) = self.snippet_session.as_mut() {
+            // Backspace removes text before the cursor rather than after it.
+            let edit_offset = if delta < 0 {
+                offset.saturating_add_signed(delta)
+            } else {
+                offset
+            };
+            session.shift(edit_offset, delta);
+            self.mode = EditorMode::SnippetExpand;
+        }
+        result
+    }
+
+    /// `:snippet trigger body` defines a snippet, where `\n` and `\t` in the body
+    /// stand for a newline and a tab. With no body it lists the snippets whose
+    /// triggers start with `trigger`.
+    fn snippet_command(&mut self, args: &str) {
+        let (trigger, body) = match args.split_once(char::is_whitespace) {
+            Some((trigger, body)) => (trigger, body.trim()),
+            None => (args, ""),
+        };
+        if body.is_empty() {
+            let mut triggers: Vec<&String> =
+                self.snippets.keys().filter(|t| t.starts_with(trigger)).collect();
+            triggers.sort();
+            let lines: Vec<String> = triggers
+                .into_iter()
+                .map(|t| format!("{:<10} {}", t, self.snippets[t].body.replace('\n', "^J")))
+                .collect();
+            match lines.len() {
+                0 => self.notify("No snippet found", MessageKind::Info),
+                1 => self.notify(&lines[0], MessageKind::Info),
+                _ => self.overlay = Some(Overlay::new(":snippet", lines)),
+            }
+            return;
+        }
+
+        let mut source = String::new();
+        let mut chars = body.chars();
+        while let Some(c) = chars.next() {
+            match (c, chars.clone().next()) {
+                ('\\', Some('n')) => source.push('\n'),
+                ('\\', Some('t')) => source.push('\t'),
+                ('\\', Some(next)) => {
+                    source.push('\\');
+                    source.push(next);
+                }
+                _ => {
+                    source.push(c);
+                    continue;
+                }
+            }
+            chars.next();
+        }
+        let snippet = Snippet::parse(&source);
+        self.snippets.insert(trigger.to_string(), snippet.clone());
+        self.defined_snippets.insert(trigger.to_string(), snippet);
+    }
+
+    /// Replaces the word at the end of the command line with its Command mode
+    /// abbreviation.
+    fn expand_command_abbreviation(&mut self) {
+        if self.mode != EditorMode::Command {
+            return;
+        }
+        let word_len: usize = self
+            .command_buffer
+            .chars()
+            .rev()
+            .take_while(|&c| completion::is_word_char(c))
+            .map(char::len_utf8)
+            .sum();
+        let start = self.command_buffer.len() - word_len;
+        if let Some(expansion) = self.command_abbreviations.get(&self.command_buffer[start..]) {
+            self.command_buffer.replace_range(start.., expansion);
+        }
+    }
+
     pub fn change_mode(&mut self, insert_mode: bool) {
         self.mode = if insert_mode {
             EditorMode::Insert
         } else {
             EditorMode::Normal
         };
+        // Leaving Insert mode after the last character puts the cursor back on it.
+        self.normalize_cursor();
     }
 
     pub fn insert_char(&mut self, c: char) {
-        let (x, y) = self.cursor_position;
+        if !completion::is_word_char(c) {
+            self.expand_abbreviation_before_cursor();
+        }
+        let CursorPos { col: x, row: y } = self.cursor_position;
 
         if c == '\n' {
-            let line_start = self.buffer.rope().offset_of_line(y as usize);
-            let line_end = self.buffer.rope().offset_of_line(y as usize + 1);
-            let line = self.buffer.rope().slice(line_start..line_end).to_string();
-
-            let byte_pos = if x as usize >= line.chars().count() {
-                line.len()
-            } else {
-                line.char_indices()
-                    .nth(x as usize)
-                    .map_or(line.len(), |(idx, _)| idx)
-            };
+            let insert_pos = self.offset_of_position(CursorPos::new(x, y));
+            self.buffer.edit(insert_pos..insert_pos, "\n");
 
-            let insert_pos = line_start + byte_pos;
-            self.buffer.rope_mut().edit(insert_pos..insert_pos, "\n");
-
-            self.cursor_position = (0, y + 1);
+            self.cursor_position = CursorPos::new(0, y + 1);
+            self.mark_change(CursorPos::new(x, y), self.cursor_position);
             self.buffer.set_modified(true);
             self.update_after_edit();
             return;
         }
 
-        let line_start = self.buffer.rope().offset_of_line(y as usize);
-        let line_end = self.buffer.rope().offset_of_line(y as usize + 1);
-        let line = self.buffer.rope().slice(line_start..line_end).to_string();
-
-        let byte_pos = if x as usize >= line.chars().count() {
-            line.len()
-        } else {
-            line.char_indices()
-                .nth(x as usize)
-                .map_or(line.len(), |(idx, _)| idx)
-        };
-
-        let insert_pos = line_start + byte_pos;
+        let insert_pos = self.offset_of_position(CursorPos::new(x, y));
         self.buffer.insert_char(insert_pos, c);
 
-        self.cursor_position.0 += 1;
+        self.mark_change(CursorPos::new(x, y), CursorPos::new(x, y));
+        self.cursor_position.col += 1;
         self.update_after_edit();
     }
 
     pub fn delete_char(&mut self) {
-        let (x, y) = self.cursor_position;
+        let CursorPos { col: x, row: y } = self.cursor_position;
 
         if x > 0 {
             let line_start = self.buffer.rope().offset_of_line(y as usize);
-            let line = self
-                .buffer
-                .rope()
-                .slice(line_start..self.buffer.rope().offset_of_line(y as usize + 1))
-                .to_string();
+            let line = self.line_text(y as usize);
+            let byte_pos = self.offset_of_position(CursorPos::new(x - 1, y)) - line_start;
 
-            let byte_pos = line
-                .char_indices()
-                .nth(x as usize - 1)
-                .map_or(0, |(idx, _)| idx);
-
-            let delete_pos = line_start + byte_pos;
-
-            let char_to_delete = line.chars().nth(x as usize - 1).unwrap_or(' ');
-            let char_len = char_to_delete.len_utf8();
+            // The whole grapheme goes, so a flag or an accented letter built from
+            // several codepoints isn't left half deleted.
+            let Some((start, grapheme)) = line
+                .grapheme_indices(true)
+                .take_while(|&(idx, _)| idx <= byte_pos)
+                .last()
+            else {
+                return;
+            };
 
-            self.buffer
-                .rope_mut()
-                .edit(delete_pos..(delete_pos + char_len), "");
+            let delete_pos = line_start + start;
+            self.buffer.edit(delete_pos..(delete_pos + grapheme.len()), "");
 
-            self.cursor_position.0 -= 1;
+            self.cursor_position.col = line[..start].chars().count() as u16;
+            self.mark_change(self.cursor_position, self.cursor_position);
             self.buffer.set_modified(true);
             self.update_after_edit();
         } else if y > 0 {
@@ -446,81 +5989,431 @@ impl CimEditor {
                 .to_string();
             let prev_line_len = prev_line.chars().count() as u16;
 
-            self.buffer
-                .rope_mut()
-                .edit(prev_line_end..current_line_start, "");
+            self.buffer.edit(prev_line_end..current_line_start, "");
 
-            self.cursor_position = (prev_line_len, y - 1);
+            self.cursor_position = CursorPos::new(prev_line_len, y - 1);
+            self.mark_change(self.cursor_position, self.cursor_position);
             self.buffer.set_modified(true);
             self.update_after_edit();
         }
     }
     pub fn insert_tab(&mut self) {
-        for _ in 0..4 {
+        if !self.options.expand_tab {
+            self.insert_char('\t');
+            return;
+        }
+        for _ in 0..self.options.tab_width {
             self.insert_char(' ');
         }
     }
 
+    /// Scrolls back a page, leaving the cursor on what was the top line unless
+    /// that is now too close to the bottom. At the start of the file the cursor
+    /// goes to the first line.
     pub fn page_up(&mut self) {
-        let scroll_amount = self.viewport_height.min(self.cursor_position.1 as usize);
-        self.cursor_position.1 -= scroll_amount as u16;
+        let top = self.scroll_offset;
+        self.scroll_offset = top.saturating_sub(self.viewport_height);
+        self.cursor_position.row = self.clamp_to_visible_lines(top) as u16;
+        self.normalize_cursor();
+    }
 
-        if self.scroll_offset > scroll_amount {
-            self.scroll_offset -= scroll_amount;
+    /// Scrolls forward a page, leaving the cursor on what was the bottom line
+    /// unless that is now too close to the top. At the end of the file the
+    /// cursor goes to the last line.
+    pub fn page_down(&mut self) {
+        let bottom = (self.scroll_offset + self.viewport_height)
+            .min(self.total_lines())
+            .saturating_sub(1);
+        self.scroll_offset =
+            (self.scroll_offset + self.viewport_height).min(self.max_scroll_offset());
+        self.cursor_position.row = self.clamp_to_visible_lines(bottom) as u16;
+        self.normalize_cursor();
+    }
+
+    fn half_page_distance(&self) -> usize {
+        if self.half_page_lines > 0 {
+            self.half_page_lines
         } else {
-            self.scroll_offset = 0;
+            (self.viewport_height / 2).max(1)
         }
+    }
 
+    pub fn half_page_up(&mut self) {
+        let distance = self.half_page_distance();
+        self.scroll_offset = self.scroll_offset.saturating_sub(distance);
+        let row = self.cursor_position.row as usize;
+        self.cursor_position.row = row.saturating_sub(distance) as u16;
         self.normalize_cursor();
     }
 
-    pub fn page_down(&mut self) {
-        let max_line = self
-            .buffer
-            .rope()
-            .measure::<xi_rope::LinesMetric>()
-            .saturating_sub(1) as u16;
+    pub fn half_page_down(&mut self) {
+        let distance = self.half_page_distance();
+        let max_line = self.total_lines() - 1;
+        self.scroll_offset = (self.scroll_offset + distance).min(self.max_scroll_offset());
+        let row = self.cursor_position.row as usize;
+        self.cursor_position.row = (row + distance).min(max_line) as u16;
+        self.normalize_cursor();
+    }
+
+    /// Creates a closed fold over lines `first..=last`.
+    pub fn create_fold(&mut self, first: usize, last: usize) {
+        let fold = FoldRange {
+            start: first,
+            end: last,
+            open: false,
+        };
+        if !self.folds.contains(&fold) {
+            let index = self.folds.partition_point(|f| (f.start, f.end) < (first, last));
+            self.folds.insert(index, fold);
+        }
+        self.cursor_position = CursorPos::new(0, first as u16);
+        self.normalize_cursor();
+    }
+
+    /// The outermost closed fold hiding `line_idx`, if any.
+    pub fn closed_fold_at(&self, line_idx: usize) -> Option<FoldRange> {
+        self.folds
+            .iter()
+            .filter(|fold| !fold.open && (fold.start..=fold.end).contains(&line_idx))
+            .min_by_key(|fold| (fold.start, usize::MAX - fold.end))
+            .copied()
+    }
+
+    /// The line shown after (or before) `line_idx`, stepping over closed folds.
+    fn next_visible_line(&self, line_idx: usize, forward: bool) -> usize {
+        if forward {
+            self.closed_fold_at(line_idx).map_or(line_idx, |fold| fold.end) + 1
+        } else {
+            let previous = line_idx.saturating_sub(1);
+            self.closed_fold_at(previous).map_or(previous, |fold| fold.start)
+        }
+    }
+
+    /// The buffer lines on screen from `scroll_offset` down, a closed fold
+    /// taking one row for its first line.
+    pub fn visible_lines(&self) -> Vec<usize> {
+        let mut line = self
+            .closed_fold_at(self.scroll_offset)
+            .map_or(self.scroll_offset, |fold| fold.start);
+        let mut lines = Vec::new();
+        while lines.len() < self.viewport_height && line < self.total_lines() {
+            lines.push(line);
+            line = self.next_visible_line(line, true);
+        }
+        lines
+    }
+
+    /// `zo`: opens the closed fold under the cursor.
+    pub fn open_fold(&mut self) {
+        let y = self.cursor_position.row as usize;
+        match self.closed_fold_at(y) {
+            Some(closed) => {
+                for fold in self.folds.iter_mut().filter(|fold| **fold == closed) {
+                    fold.open = true;
+                }
+                self.normalize_cursor();
+            }
+            None if self.innermost_fold_at(y).is_some() => {}
+            None => self.notify("E490: No fold found", MessageKind::Error),
+        }
+    }
+
+    /// `zc`: closes the innermost open fold under the cursor.
+    pub fn close_fold(&mut self) {
+        let y = self.cursor_position.row as usize;
+        let open = self
+            .folds
+            .iter()
+            .enumerate()
+            .filter(|(_, fold)| fold.open && (fold.start..=fold.end).contains(&y))
+            .max_by_key(|(_, fold)| (fold.start, usize::MAX - fold.end))
+            .map(|(index, _)| index);
+        match open {
+            Some(index) => {
+                self.folds[index].open = false;
+                self.normalize_cursor();
+            }
+            None if self.innermost_fold_at(y).is_some() => {}
+            None => self.notify("E490: No fold found", MessageKind::Error),
+        }
+    }
+
+    /// `za`: opens the fold under the cursor if it is closed, else closes it.
+    pub fn toggle_fold(&mut self) {
+        if self.closed_fold_at(self.cursor_position.row as usize).is_some() {
+            self.open_fold();
+        } else {
+            self.close_fold();
+        }
+    }
+
+    /// `zR` opens every fold and `zM` closes them all.
+    pub fn set_all_folds(&mut self, open: bool) {
+        for fold in &mut self.folds {
+            fold.open = open;
+        }
+        self.normalize_cursor();
+    }
+
+    fn innermost_fold_at(&self, line_idx: usize) -> Option<&FoldRange> {
+        self.folds
+            .iter()
+            .filter(|fold| (fold.start..=fold.end).contains(&line_idx))
+            .max_by_key(|fold| (fold.start, usize::MAX - fold.end))
+    }
+
+    fn is_blank_line(&self, line_idx: usize) -> bool {
+        self.line_text(line_idx).trim().is_empty()
+    }
+
+    /// First line of the paragraph after the one containing `line_idx`, if any.
+    fn next_paragraph_start(&self, line_idx: usize) -> Option<usize> {
+        let total_lines = self.total_lines();
+        let mut line = line_idx;
+        while line < total_lines && !self.is_blank_line(line) {
+            line += 1;
+        }
+        while line < total_lines && self.is_blank_line(line) {
+            line += 1;
+        }
+        (line < total_lines).then_some(line)
+    }
+
+    /// Moves to the first line of the next paragraph, or the end of the buffer.
+    pub fn move_paragraph_forward(&mut self) {
+        self.push_jump();
+        let y = self.cursor_position.row as usize;
+        match self.next_paragraph_start(y) {
+            Some(line) => self.cursor_position = CursorPos::new(0, line as u16),
+            None => {
+                self.cursor_position.row = (self.total_lines() - 1) as u16;
+                let line_len = self.current_line_length() as u16;
+                self.cursor_position.col = line_len.saturating_sub(1);
+            }
+        }
+        self.update_viewport();
+    }
+
+    /// Moves to the first line of the current paragraph, or of the previous one
+    /// when already there.
+    pub fn move_paragraph_backward(&mut self) {
+        self.push_jump();
+        let mut line = self.cursor_position.row as usize;
+        if line > 0 {
+            line -= 1;
+            while line > 0 && self.is_blank_line(line) {
+                line -= 1;
+            }
+            while line > 0 && !self.is_blank_line(line - 1) {
+                line -= 1;
+            }
+        }
+        self.cursor_position = CursorPos::new(0, line as u16);
+        self.update_viewport();
+    }
+
+    /// Records the `[` and `]` marks around the text changed last.
+    fn mark_change(&mut self, start: CursorPos, end: CursorPos) {
+        self.marks.insert('[', start);
+        self.marks.insert(']', end);
+    }
+
+    /// Remembers the cursor in the `'` mark before a jump, so `''` can return.
+    fn push_jump(&mut self) {
+        self.marks.insert('\'', self.cursor_position);
+    }
+
+    /// Sets mark `mark` (`a`-`z`) at the cursor; other names are ignored.
+    pub fn set_mark(&mut self, mark: char) {
+        if mark.is_ascii_lowercase() {
+            self.marks.insert(mark, self.cursor_position);
+        }
+    }
+
+    /// Jumps to `mark`, to its exact column (`` ` ``) or the line's first non-blank (`'`).
+    pub fn jump_to_mark(&mut self, mark: char, linewise: bool) {
+        let mark = if mark == '`' { '\'' } else { mark };
+        let Some(&position) = self.marks.get(&mark) else {
+            self.notify("E20: Mark not set", MessageKind::Error);
+            return;
+        };
+        self.push_jump();
+        self.cursor_position = position;
+        self.normalize_cursor();
+        if linewise {
+            self.go_to_first_nonblank();
+        }
+    }
+
+    /// One line per non-empty register for `:registers`, like `"a  some text`.
+    pub fn registers_summary(&self) -> Vec<String> {
+        let names = std::iter::once('"')
+            .chain('0'..='9')
+            .chain('a'..='z')
+            .chain(['-', '+', '_']);
+        let mut contents: Vec<(char, String)> = names
+            .filter_map(|name| Some((name, self.registers.get(name)?.text.clone())))
+            .collect();
+        if let Some(query) = &self.search_query {
+            contents.push(('/', query.clone()));
+        }
 
-        let new_y = (self.cursor_position.1 + self.viewport_height as u16).min(max_line);
-        let moved_amount = new_y - self.cursor_position.1;
-        self.cursor_position.1 = new_y;
+        contents
+            .into_iter()
+            .filter(|(_, text)| !text.is_empty())
+            .map(|(name, text)| {
+                let text = text.replace('\n', "^J");
+                let mut shown: String = text.chars().take(40).collect();
+                if text.chars().count() > 40 {
+                    shown.push('…');
+                }
+                format!("\"{}  {}", name, shown)
+            })
+            .collect()
+    }
 
-        self.scroll_offset += moved_amount as usize;
+    /// Table of set marks for `:marks`: name, line, column and the start of the line.
+    pub fn marks_summary(&self) -> String {
+        let order = std::iter::once('\'')
+            .chain('a'..='z')
+            .chain(['[', ']', '<', '>']);
+        let mut summary = String::from("mark line  col text");
+        for mark in order {
+            let Some(&CursorPos { col: x, row: y }) = self.marks.get(&mark) else {
+                continue;
+            };
+            let text: String = self.line_text(y as usize).chars().take(20).collect();
+            summary.push_str(&format!("\n {} {:>6} {:>4} {}", mark, y + 1, x, text));
+        }
+        summary
+    }
 
+    /// Moves to the first non-blank character of 1-based line `line`.
+    pub fn go_to_line(&mut self, line: usize) {
+        if line == 0 || line > self.total_lines() {
+            self.notify("Invalid line number", MessageKind::Error);
+            return;
+        }
+        self.push_jump();
+        self.cursor_position = CursorPos::new(0, (line - 1) as u16);
         self.normalize_cursor();
+        self.go_to_first_nonblank();
     }
 
     pub fn go_to_line_start(&mut self) {
-        self.cursor_position.0 = 0;
+        self.cursor_position.col = 0;
         self.update_viewport();
     }
 
+    pub fn go_to_first_nonblank(&mut self) {
+        self.cursor_position.col = self.first_non_blank(self.cursor_position.row as usize) as u16;
+        self.normalize_cursor();
+    }
+
+    /// Moves `lines` down, or up when negative, to the first non-blank character.
+    fn go_to_first_nonblank_of_line(&mut self, lines: i16) {
+        self.move_cursor((0, lines));
+        self.go_to_first_nonblank();
+    }
+
     pub fn go_to_line_end(&mut self) {
-        let y = self.cursor_position.1 as usize;
-        let line_start = self.buffer.rope().offset_of_line(y);
-        let next_line_start = self.buffer.rope().offset_of_line(y + 1);
-        let line = self
-            .buffer
-            .rope()
-            .slice(line_start..next_line_start)
-            .to_string();
+        self.cursor_position.col = self.max_cursor_column(self.cursor_position.row as usize) as u16;
+        self.update_viewport();
+    }
 
-        let line_len = line.trim_end_matches(&['\r', '\n'][..]).chars().count() as u16;
-        self.cursor_position.0 = if self.mode == EditorMode::Insert {
-            line_len
-        } else {
-            line_len.saturating_sub(1)
+    /// The last column the cursor can take on `line_idx`: the last character,
+    /// or just past it in the modes that insert text.
+    fn max_cursor_column(&self, line_idx: usize) -> usize {
+        let line_length = self.line_text(line_idx).chars().count();
+        match self.mode {
+            EditorMode::Insert | EditorMode::Replace | EditorMode::SnippetExpand => line_length,
+            _ => line_length.saturating_sub(1),
+        }
+    }
+
+    /// Text of line `line_idx` without its line ending.
+    pub fn line_text(&self, line_idx: usize) -> String {
+        let rope = self.buffer.rope();
+        if line_idx >= self.total_lines() {
+            return String::new();
+        }
+        let line_start = rope.offset_of_line(line_idx);
+        let line_end = rope.offset_of_line(line_idx + 1);
+        let mut line = rope.slice(line_start..line_end).to_string();
+        line.truncate(line.trim_end_matches(&['\r', '\n'][..]).len());
+        line
+    }
+
+    /// `(words, chars, lines)` in lines `start_line..=end_line`. Chars include
+    /// each line's newline.
+    pub fn word_count_region(&self, start_line: usize, end_line: usize) -> (usize, usize, usize) {
+        (start_line..=end_line).fold((0, 0, 0), |(words, chars, lines), line_idx| {
+            let line = self.line_text(line_idx);
+            (
+                words + line.split_whitespace().count(),
+                chars + line.chars().count() + 1,
+                lines + 1,
+            )
+        })
+    }
+
+    /// `word_count_region` for the whole buffer, cached between edits.
+    pub fn buffer_word_count(&self) -> (usize, usize, usize) {
+        if let Some(counts) = self.word_count_cache.get().filter(|_| !self.text_changed) {
+            return counts;
+        }
+        let counts = self.word_count_region(0, self.total_lines() - 1);
+        self.word_count_cache.set(Some(counts));
+        counts
+    }
+
+    /// `:wcount`: shows the counts for `range`, the whole buffer by default.
+    fn show_word_count(&mut self, range: ExRange) {
+        let (words, chars, lines) = match range {
+            ExRange::None => self.buffer_word_count(),
+            range => match self.resolve_range(&range, (0, 0)) {
+                Some((start, end)) => self.word_count_region(start, end),
+                None => return,
+            },
         };
+        self.notify(
+            &format!(
+                "{} words, {} chars, {} lines, {}",
+                words,
+                chars,
+                lines,
+                self.options.reading_time(words)
+            ),
+            MessageKind::Info,
+        );
+    }
 
-        self.update_viewport();
+    /// Column of the first non-whitespace character on `line_idx`.
+    fn first_non_blank(&self, line_idx: usize) -> usize {
+        self.line_text(line_idx)
+            .chars()
+            .take_while(|c| c.is_whitespace())
+            .count()
+    }
+
+    /// Byte offset of a position, clamping the column to the line.
+    fn offset_of_position(&self, CursorPos { col: x, row: y }: CursorPos) -> usize {
+        char_to_byte_offset(self.buffer.rope(), y as usize, x as usize)
+    }
+
+    fn position_of_offset(&self, offset: usize) -> CursorPos {
+        let rope = self.buffer.rope();
+        let y = rope.line_of_offset(offset);
+        CursorPos::new(byte_to_char_col(rope, y, offset) as u16, y as u16)
     }
+
     pub fn current_line_length(&self) -> usize {
         let rope = self.buffer.rope();
         if rope.is_empty() {
             return 0;
         }
-        let line_num = self.cursor_position.1 as usize;
-        if line_num >= rope.measure::<xi_rope::LinesMetric>() {
+        let line_num = self.cursor_position.row as usize;
+        if line_num >= self.total_lines() {
             return 0;
         }
         let line_start = rope.offset_of_line(line_num);
@@ -533,9 +6426,13 @@ impl CimEditor {
     }
 
     pub fn total_lines(&self) -> usize {
-        self.buffer.rope().measure::<xi_rope::LinesMetric>()
+        self.buffer.line_count()
     }
-    fn handle_action(&mut self, action: EditorAction) -> Option<EditorAction> {
+
+    /// Carries out `action`, handing back the ones only the caller can, such as
+    /// `Exit` and `Suspend`.
+    pub fn handle_action(&mut self, action: EditorAction) -> Option<EditorAction> {
+        log::debug!("{} in {:?} mode", action, self.mode);
         match action {
             EditorAction::ChangeMode(b) => {
                 self.change_mode(b);
@@ -549,14 +6446,47 @@ impl CimEditor {
                 self.move_cursor_word(dir);
                 None
             }
+            EditorAction::MoveBigWord(dir) => {
+                self.move_cursor_big_word(dir);
+                None
+            }
+            EditorAction::MoveBigWordEnd(dir) => {
+                self.move_cursor_big_word_end(dir);
+                None
+            }
             EditorAction::LineStart => {
                 self.go_to_line_start();
                 None
             }
+            EditorAction::FirstNonBlank => {
+                self.go_to_first_nonblank();
+                None
+            }
+            EditorAction::FirstNonBlankLine(lines) => {
+                self.go_to_first_nonblank_of_line(lines);
+                None
+            }
             EditorAction::LineEnd => {
                 self.go_to_line_end();
                 None
             }
+            EditorAction::GoToLine(line) => {
+                let total_lines = self.total_lines();
+                self.go_to_line(if line == 0 { total_lines } else { line.min(total_lines) });
+                None
+            }
+            EditorAction::SetMark(mark) => {
+                self.set_mark(mark);
+                None
+            }
+            EditorAction::JumpToMark(mark) => {
+                self.jump_to_mark(mark, false);
+                None
+            }
+            EditorAction::JumpToMarkLine(mark) => {
+                self.jump_to_mark(mark, true);
+                None
+            }
             EditorAction::PageUp => {
                 self.page_up();
                 None
@@ -572,24 +6502,251 @@ impl CimEditor {
                 None
             }
 
-            EditorAction::DeleteChar => {
+            EditorAction::DeleteBack => {
                 self.delete_char();
                 None
             }
+            EditorAction::Exit => {
+                if self.buffer.is_modified() {
+                    self.notify(
+                        "E37: No write since last change (add ! to override)",
+                        MessageKind::Error,
+                    );
+                    None
+                } else {
+                    Some(action)
+                }
+            }
             EditorAction::Save => {
-                self.save().ok()?;
+                if let Err(e) = self.save() {
+                    self.notify(&e.to_string(), MessageKind::Error);
+                }
+                None
+            }
+            EditorAction::SaveExit => match self.save() {
+                Ok(()) => Some(EditorAction::Exit),
+                Err(e) => {
+                    self.notify(&e.to_string(), MessageKind::Error);
+                    None
+                }
+            },
+            EditorAction::HalfPageUp => {
+                self.half_page_up();
+                None
+            }
+            EditorAction::HalfPageDown => {
+                self.half_page_down();
+                None
+            }
+            EditorAction::ScrollUp(lines) => {
+                self.scroll_viewport(-(lines.min(i32::MAX as usize) as i32));
+                None
+            }
+            EditorAction::ScrollDown(lines) => {
+                self.scroll_viewport(lines.min(i32::MAX as usize) as i32);
+                None
+            }
+            EditorAction::CursorScreenTop(offset) => {
+                self.cursor_to_screen_top(offset);
+                None
+            }
+            EditorAction::CursorScreenMiddle => {
+                self.cursor_to_screen_middle();
+                None
+            }
+            EditorAction::CursorScreenBottom(offset) => {
+                self.cursor_to_screen_bottom(offset);
+                None
+            }
+            EditorAction::ScrollCursorTop => {
+                self.scroll_cursor_to_top();
+                None
+            }
+            EditorAction::ScrollCursorCenter => {
+                self.scroll_cursor_to_center();
+                None
+            }
+            EditorAction::ScrollCursorBottom => {
+                self.scroll_cursor_to_bottom();
+                None
+            }
+            EditorAction::ScrollCursorLeft => {
+                self.scroll_cursor_to_left();
+                None
+            }
+            EditorAction::ScrollCursorRight => {
+                self.scroll_cursor_to_right();
+                None
+            }
+            EditorAction::ParagraphBackward => {
+                self.move_paragraph_backward();
+                None
+            }
+            EditorAction::ParagraphForward => {
+                self.move_paragraph_forward();
                 None
             }
-            EditorAction::SaveExit => {
-                self.save().ok()?;
-                Some(EditorAction::Exit)
+            EditorAction::Paste(after) => {
+                self.paste(after);
+                None
+            }
+            EditorAction::PasteIndentAdjusted(after) => {
+                // Only whole lines are reindented; other text goes in as with `p`.
+                let name = self.pending_register.unwrap_or('"');
+                match self.registers.get(name.to_ascii_lowercase()) {
+                    Some(register) if register.linewise => {
+                        let text = register.text.clone();
+                        self.pending_register = None;
+                        self.paste_with_indent_adjust(&text, after);
+                    }
+                    _ => self.paste(after),
+                }
+                None
+            }
+            EditorAction::ToggleCase(count) => {
+                self.toggle_case_at_cursor(count);
+                None
+            }
+            EditorAction::ReplaceChar(c, count) => {
+                self.replace_chars(c, count);
+                None
+            }
+            EditorAction::StartReplace => {
+                self.mode = EditorMode::Replace;
+                self.replace_undo_chars.clear();
+                None
+            }
+            EditorAction::Increment(delta) => {
+                self.increment_number(delta);
+                None
+            }
+            EditorAction::Quickfix(delta) => {
+                self.step_quickfix(delta);
+                None
+            }
+            EditorAction::JumpToHunk(count) => {
+                self.jump_to_hunk(count);
+                None
+            }
+            EditorAction::DiffPut => {
+                self.diff_transfer(true);
+                None
+            }
+            EditorAction::DiffObtain => {
+                self.diff_transfer(false);
+                None
+            }
+            EditorAction::OpenFold => {
+                self.open_fold();
+                None
+            }
+            EditorAction::CloseFold => {
+                self.close_fold();
+                None
+            }
+            EditorAction::ToggleFold => {
+                self.toggle_fold();
+                None
+            }
+            EditorAction::OpenAllFolds => {
+                self.set_all_folds(true);
+                None
+            }
+            EditorAction::CloseAllFolds => {
+                self.set_all_folds(false);
+                None
+            }
+            EditorAction::SpellSuggest => {
+                self.spell_suggest();
+                None
+            }
+            EditorAction::CharInfo => {
+                let info = self.char_info_at_cursor();
+                self.notify(&info, MessageKind::Info);
+                None
+            }
+            EditorAction::Hover => {
+                self.show_hover();
+                None
+            }
+            EditorAction::FileInfo => {
+                self.notify(&self.file_info(), MessageKind::Info);
+                None
+            }
+            EditorAction::CloseOtherSplits => {
+                self.close_other_splits(false);
+                None
+            }
+            EditorAction::ResizeSplit(delta) => {
+                self.resize_split(delta);
+                None
+            }
+            EditorAction::EqualizeAllSplits => {
+                if self.quickfix_open {
+                    self.set_quickfix_height(self.split_rows().saturating_sub(1) / 2);
+                }
+                None
+            }
+            EditorAction::SwitchToAlternate => {
+                self.switch_to_alternate();
+                None
             }
             EditorAction::StartCommand => {
                 self.mode = EditorMode::Command;
                 self.command_buffer.clear();
                 Some(action)
             }
+            EditorAction::StartSearch(forward) => {
+                self.mode = EditorMode::Search;
+                self.search_backward = !forward;
+                self.command_buffer.clear();
+                None
+            }
+            EditorAction::SearchNext => {
+                self.search(self.search_backward);
+                None
+            }
+            EditorAction::SearchPrevious => {
+                self.search(!self.search_backward);
+                None
+            }
+            EditorAction::ClearHighlight => {
+                self.clear_search_highlight();
+                self.notify("Search highlight cleared", MessageKind::Info);
+                None
+            }
             _ => Some(action),
         }
     }
 }
+
+/// Runs `cmd` through `sh -c`, optionally feeding `input` on stdin.
+fn shell_output(cmd: &str, input: Option<String>) -> io::Result<Output> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Write from another thread so a chatty child can't deadlock on a full stdout pipe.
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            Some(std::thread::spawn(move || stdin.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        writer
+            .join()
+            .map_err(|_| io::Error::other("failed to write to shell"))??;
+    }
+    Ok(output)
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// A value an expression evaluates to.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Number(i64),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Number(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// Variables set with `:let`, by name without their scope prefix.
+#[derive(Default)]
+pub struct Variables {
+    /// `g:name`, or a bare `name` typed on the command line.
+    pub global: HashMap<String, Value>,
+    /// `b:name`, cleared when another file is edited.
+    pub buffer: HashMap<String, Value>,
+}
+
+/// Which variables a name refers to.
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    Global,
+    Buffer,
+    /// `l:`, only meaningful inside a function, which there are none of yet.
+    Local,
+}
+
+impl Variables {
+    pub fn get(&self, name: &str) -> Result<&Value, String> {
+        let (scope, key) = split_scope(name)?;
+        let value = match scope {
+            Scope::Global => self.global.get(key),
+            Scope::Buffer => self.buffer.get(key),
+            Scope::Local => None,
+        };
+        value.ok_or_else(|| format!("E121: Undefined variable: {}", name))
+    }
+
+    /// `:let name = expr`, where `l:` names are refused outside a function.
+    pub fn set(&mut self, name: &str, value: Value) -> Result<(), String> {
+        match split_scope(name)? {
+            (Scope::Global, key) => self.global.insert(key.to_string(), value),
+            (Scope::Buffer, key) => self.buffer.insert(key.to_string(), value),
+            (Scope::Local, _) => return Err(format!("E461: Illegal variable name: {}", name)),
+        };
+        Ok(())
+    }
+
+    /// One line per variable, as `:let` with no arguments lists them.
+    pub fn summary(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .global
+            .iter()
+            .map(|(name, value)| (format!("g:{}", name), value))
+            .chain(self.buffer.iter().map(|(name, value)| (format!("b:{}", name), value)))
+            .map(|(name, value)| format!("{:<20} {}", name, value))
+            .collect();
+        lines.sort();
+        lines
+    }
+}
+
+fn split_scope(name: &str) -> Result<(Scope, &str), String> {
+    let (scope, key) = match name.split_once(':') {
+        Some(("g", key)) => (Scope::Global, key),
+        Some(("b", key)) => (Scope::Buffer, key),
+        Some(("l", key)) => (Scope::Local, key),
+        Some(_) => return Err(format!("E461: Illegal variable name: {}", name)),
+        None => (Scope::Global, name),
+    };
+    let valid = key.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && key.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if valid {
+        Ok((scope, key))
+    } else {
+        Err(format!("E461: Illegal variable name: {}", name))
+    }
+}
+
+/// Evaluates `input` as one expression: string literals in double or single
+/// quotes, numbers and variables, joined with `.` or `..`.
+pub fn eval(input: &str, vars: &Variables) -> Result<Value, String> {
+    let mut parser = Parser { input, pos: 0, vars };
+    let value = parser.expression()?;
+    parser.skip_whitespace();
+    if parser.pos < input.len() {
+        return Err(format!("E488: Trailing characters: {}", &input[parser.pos..]));
+    }
+    Ok(value)
+}
+
+/// Evaluates whitespace-separated expressions, as `:echo "a" "b"` takes, and
+/// joins their values with spaces.
+pub fn eval_list(input: &str, vars: &Variables) -> Result<String, String> {
+    let mut parser = Parser { input, pos: 0, vars };
+    let mut values = Vec::new();
+    parser.skip_whitespace();
+    while parser.pos < input.len() {
+        values.push(parser.expression()?.to_string());
+        parser.skip_whitespace();
+    }
+    Ok(values.join(" "))
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+    vars: &'a Variables,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    /// `term (("." | "..") term)*`
+    fn expression(&mut self) -> Result<Value, String> {
+        let mut value = self.term()?;
+        loop {
+            self.skip_whitespace();
+            let Some(rest) = self.rest().strip_prefix('.') else {
+                return Ok(value);
+            };
+            self.pos = self.input.len() - rest.strip_prefix('.').unwrap_or(rest).len();
+            let right = self.term()?;
+            value = Value::String(format!("{}{}", value, right));
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        match rest.chars().next() {
+            Some('"') => self.double_quoted(),
+            Some('\'') => self.single_quoted(),
+            Some(c) if c.is_ascii_digit() => {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                self.pos += len;
+                rest[..len]
+                    .parse()
+                    .map(Value::Number)
+                    .map_err(|_| format!("E15: Invalid expression: \"{}\"", rest))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                    .unwrap_or(rest.len());
+                self.pos += len;
+                self.vars.get(&rest[..len]).cloned()
+            }
+            _ => Err(format!("E15: Invalid expression: \"{}\"", rest)),
+        }
+    }
+
+    /// `"..."`, where `\n`, `\t`, `\"` and `\\` are escapes.
+    fn double_quoted(&mut self) -> Result<Value, String> {
+        let start = self.rest();
+        let mut value = String::new();
+        let mut chars = start.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(Value::String(value));
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                c => value.push(c),
+            }
+        }
+        Err(format!("E114: Missing quote: {}", start))
+    }
+
+    /// `'...'`, taken literally except that `''` is one quote.
+    fn single_quoted(&mut self) -> Result<Value, String> {
+        let start = self.rest();
+        let mut value = String::new();
+        let mut chars = start.char_indices().skip(1).peekable();
+        while let Some((i, c)) = chars.next() {
+            if c != '\'' {
+                value.push(c);
+            } else if chars.next_if(|&(_, c)| c == '\'').is_some() {
+                value.push('\'');
+            } else {
+                self.pos += i + 1;
+                return Ok(Value::String(value));
+            }
+        }
+        Err(format!("E115: Missing quote: {}", start))
+    }
+}
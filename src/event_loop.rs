@@ -0,0 +1,180 @@
+use crate::editor::{CimEditor, EditorAction, EditorMode};
+use crate::ui;
+use crossterm::{
+    cursor::SetCursorStyle,
+    event::{self, Event, KeyEventKind},
+    execute,
+};
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+use tui::backend::Backend;
+use tui::layout::Rect;
+use tui::Terminal;
+
+/// Where `run_editor` gets its key presses and resizes from.
+pub trait InputSource {
+    /// Waits for the next event.
+    fn next_event(&mut self) -> io::Result<Event>;
+
+    /// Whether an event comes within `timeout`, so `next_event` won't block.
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool>;
+}
+
+/// The terminal's events, read through crossterm.
+pub struct CrosstermInputSource;
+
+impl InputSource for CrosstermInputSource {
+    fn next_event(&mut self) -> io::Result<Event> {
+        event::read()
+    }
+
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        event::poll(timeout)
+    }
+}
+
+/// A fixed list of events, for driving the editor without a terminal. Asking
+/// for an event after the last one is an `UnexpectedEof` error, so a session
+/// that never quits ends instead of waiting forever.
+pub struct MockInputSource(pub VecDeque<Event>);
+
+impl InputSource for MockInputSource {
+    fn next_event(&mut self) -> io::Result<Event> {
+        self.0
+            .pop_front()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "no more input events"))
+    }
+
+    /// Always ready: the next event, or the end of the list, comes straight away.
+    fn poll(&mut self, _timeout: Duration) -> io::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// What `run_editor` does to the terminal besides drawing on it.
+pub trait TerminalControl {
+    /// Changes the shape of the text cursor.
+    fn set_cursor_style(&mut self, style: SetCursorStyle) -> io::Result<()>;
+
+    /// Stops the editor for Ctrl-Z, returning once it is resumed.
+    fn suspend(&mut self) -> io::Result<()>;
+}
+
+/// The real terminal on stdout, and job control through SIGTSTP.
+pub struct CrosstermTerminalControl;
+
+impl TerminalControl for CrosstermTerminalControl {
+    fn set_cursor_style(&mut self, style: SetCursorStyle) -> io::Result<()> {
+        execute!(io::stdout(), style)
+    }
+
+    fn suspend(&mut self) -> io::Result<()> {
+        suspend()
+    }
+}
+
+/// Leaves the terminal alone, counting what it was asked to do, for sessions
+/// driven without a terminal.
+#[derive(Debug, Default)]
+pub struct MockTerminalControl {
+    pub cursor_styles: Vec<SetCursorStyle>,
+    pub suspends: usize,
+}
+
+impl TerminalControl for MockTerminalControl {
+    fn set_cursor_style(&mut self, style: SetCursorStyle) -> io::Result<()> {
+        self.cursor_styles.push(style);
+        Ok(())
+    }
+
+    fn suspend(&mut self) -> io::Result<()> {
+        self.suspends += 1;
+        Ok(())
+    }
+}
+
+/// Draws `editor` and feeds it events from `input` until it exits.
+pub fn run_editor<B: Backend, I: InputSource, C: TerminalControl>(
+    terminal: &mut Terminal<B>,
+    editor: &mut CimEditor,
+    input: &mut I,
+    control: &mut C,
+) -> io::Result<()> {
+    let mut cursor_style = SetCursorStyle::DefaultUserShape;
+    loop {
+        terminal.draw(|f| ui::draw_ui(f, editor))?;
+
+        let mode_cursor_style = match editor.mode {
+            EditorMode::Replace => SetCursorStyle::SteadyUnderScore,
+            _ => SetCursorStyle::DefaultUserShape,
+        };
+        if mode_cursor_style != cursor_style {
+            cursor_style = mode_cursor_style;
+            control.set_cursor_style(cursor_style)?;
+        }
+
+        // Wake up to clear the message line once its message times out.
+        let time_left = [editor.messages.time_left(), editor.flash_time_left()];
+        if let Some(time_left) = time_left.into_iter().flatten().min() {
+            if !input.poll(time_left)? {
+                continue;
+            }
+        }
+        let event = input.next_event()?;
+        if let Event::Resize(width, height) = event {
+            terminal.resize(Rect::new(0, 0, width, height))?;
+            editor.resize(width, height);
+        }
+        if let Event::Key(key) = event {
+            if key.kind == KeyEventKind::Press {
+                match editor.handle_input(key) {
+                    Some(EditorAction::Exit | EditorAction::ForceExit) => {
+                        return Ok(());
+                    }
+                    Some(EditorAction::Suspend) => {
+                        control.suspend()?;
+                        let size = terminal.size()?;
+                        terminal.resize(size)?;
+                        // Redraw everything and restore the cursor shape after `fg`.
+                        editor.text_changed = true;
+                        cursor_style = SetCursorStyle::DefaultUserShape;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+/// Hands the terminal back to the shell and stops the process with SIGTSTP,
+/// taking the terminal over again once it is resumed.
+#[cfg(unix)]
+fn suspend() -> io::Result<()> {
+    use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+    use crossterm::terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    };
+
+    disable_raw_mode()?;
+    execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        SetCursorStyle::DefaultUserShape
+    )?;
+
+    // SAFETY: raise only sends a signal to this process; execution continues here
+    // when the shell sends SIGCONT.
+    unsafe {
+        libc::raise(libc::SIGTSTP);
+    }
+
+    enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen, EnableMouseCapture)
+}
+
+#[cfg(not(unix))]
+fn suspend() -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,395 @@
+use crate::keymap::MapMode;
+
+/// The lines an ex command applies to, as typed before the command name.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExRange {
+    /// No range given; the command falls back to its own default.
+    None,
+    /// `%`: every line.
+    All,
+    LineNumber(usize),
+    CurrentLine,
+    LastLine,
+    Mark(char),
+    /// `'<,'>`: the last Visual selection.
+    VisualMarks,
+    /// An address moved by `+N` or `-N`, as in `.+3` or `$-1`. A bare `+N` is
+    /// relative to the current line.
+    Offset(Box<ExRange>, isize),
+    /// `start,end`
+    Pair(Box<ExRange>, Box<ExRange>),
+}
+
+impl ExRange {
+    /// Splits an optional range such as `%`, `'<,'>` or `.,+3` off the front of
+    /// `input`, returning it with the remainder.
+    pub fn parse(input: &str) -> (ExRange, &str) {
+        if let Some(rest) = input.strip_prefix('%') {
+            return (ExRange::All, rest);
+        }
+        if let Some(rest) = input.strip_prefix("'<,'>") {
+            return (ExRange::VisualMarks, rest);
+        }
+        let Some((start, rest)) = parse_address(input) else {
+            return (ExRange::None, input);
+        };
+        match rest.strip_prefix(',').and_then(parse_address) {
+            Some((end, rest)) => (ExRange::Pair(Box::new(start), Box::new(end)), rest),
+            None => (start, rest),
+        }
+    }
+}
+
+/// How `:left`, `:right` and `:center` place each line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlignMode {
+    /// Indented by the width given, 0 by default.
+    Left,
+    /// Ending at the width.
+    Right,
+    /// Centred in the width.
+    Center,
+}
+
+/// Parses one line address, with any `+N`/`-N` offsets, from the front of `input`.
+fn parse_address(input: &str) -> Option<(ExRange, &str)> {
+    let (base, mut rest) = match input.chars().next() {
+        Some('.') => (Some(ExRange::CurrentLine), &input[1..]),
+        Some('$') => (Some(ExRange::LastLine), &input[1..]),
+        Some('\'') => {
+            let mark = input[1..].chars().next()?;
+            (Some(ExRange::Mark(mark)), &input[1 + mark.len_utf8()..])
+        }
+        Some(c) if c.is_ascii_digit() => {
+            let end = input
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(input.len());
+            (Some(ExRange::LineNumber(input[..end].parse().ok()?)), &input[end..])
+        }
+        _ => (None, input),
+    };
+
+    let mut offset = None;
+    while let Some(sign) = rest.chars().next().filter(|&c| c == '+' || c == '-') {
+        let end = rest[1..]
+            .find(|c: char| !c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + 1);
+        // A sign without a number counts as 1, as in `.+`.
+        let n: isize = if end == 1 { 1 } else { rest[1..end].parse().ok()? };
+        *offset.get_or_insert(0) += if sign == '+' { n } else { -n };
+        rest = &rest[end..];
+    }
+
+    let address = match (base, offset) {
+        (None, None) => return None,
+        (base, None) => base?,
+        (base, Some(n)) => {
+            ExRange::Offset(Box::new(base.unwrap_or(ExRange::CurrentLine)), n)
+        }
+    };
+    Some((address, rest))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExCommandKind {
+    /// A range on its own, such as `:$` or `:'a`, which jumps to its last line.
+    Goto,
+    Quit,
+    ForceQuit,
+    /// `:w`, or `:w!` to write even a read-only buffer.
+    Write { force: bool },
+    WriteQuit,
+    File,
+    NoHighlight,
+    Registers,
+    Marks,
+    Set,
+    Abbreviate { insert: bool, command: bool },
+    Unabbreviate { insert: bool, command: bool },
+    /// `:map` and its forms, with `mode` `None` for Normal and Visual mode.
+    /// `verbose` is set by `:verbose map`.
+    Map {
+        mode: Option<MapMode>,
+        noremap: bool,
+        verbose: bool,
+    },
+    Digraphs,
+    Ascii,
+    /// `:highlight`, overriding the theme's style for a syntax scope.
+    Highlight,
+    /// `:autocmd`, or `:autocmd!` to remove autocommands.
+    Autocmd { clear: bool },
+    Snippet,
+    ColorScheme,
+    /// `:e file`, or `:e! file` to drop unsaved changes.
+    Edit { force: bool },
+    Buffers,
+    OldFiles,
+    Messages,
+    Echo,
+    EchoMsg,
+    EchoErr,
+    Let,
+    Read,
+    Sort { reverse: bool },
+    /// `:left`, `:right` and `:center`.
+    AlignLines(AlignMode),
+    /// `:retab`, or `:retab!` to also turn spaces into tabs.
+    Retab { bang: bool },
+    Align,
+    ToHtml,
+    WordCount,
+    Delete,
+    Copy,
+    Move,
+    Substitute,
+    Global { invert: bool },
+    Sign,
+    DiffSplit,
+    DiffUpdate,
+    DiffOff,
+    WriteAll,
+    Resize,
+    /// `:only`, or `:only!` to drop changes in the windows it closes.
+    Only { force: bool },
+    /// `:qa`; `:qa!` is `ForceQuit`.
+    QuitAll,
+    WriteQuitAll,
+    BufDo,
+    WinDo,
+    ArgDo,
+    Args,
+    Source,
+    ScriptNames,
+    Grep,
+    Make,
+    QuickfixNext,
+    QuickfixPrevious,
+    QuickfixFirst,
+    QuickfixLast,
+    QuickfixOpen,
+    QuickfixClose,
+    /// `:!cmd`
+    Shell,
+    /// `:%!cmd`
+    Filter,
+    /// Anything else, holding the command as typed after its range.
+    Unknown(String),
+}
+
+/// A parsed ex command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExCommand {
+    pub range: ExRange,
+    pub command: ExCommandKind,
+    /// Arguments split on whitespace, except for commands that take free text
+    /// or a file name (`:s`, `:g`, `:!`, `:r`, `:w`, `:e`, `:so`, `:make`,
+    /// `:ab`, `:map`, `:autocmd`, `:snippet`, `:echo`, `:let`, `:diffsplit`,
+    /// `:bufdo`), whose whole argument is the one entry, spaces and all.
+    pub args: Vec<String>,
+}
+
+impl ExCommand {
+    pub fn parse(input: &str) -> ExCommand {
+        let (range, rest) = ExRange::parse(input.trim());
+        let rest = rest.trim_start();
+
+        if let Some(shell_command) = rest.strip_prefix('!') {
+            let command = match range {
+                ExRange::None => ExCommandKind::Shell,
+                _ => ExCommandKind::Filter,
+            };
+            return ExCommand {
+                range,
+                command,
+                args: vec![shell_command.to_string()],
+            };
+        }
+
+        let name_end = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let name = &rest[..name_end];
+        let (bang, args) = match rest[name_end..].strip_prefix('!') {
+            Some(args) => (true, args),
+            None => (false, &rest[name_end..]),
+        };
+
+        if let "verb" | "verbose" = name {
+            let mut command = ExCommand::parse(args);
+            if let ExCommandKind::Map { verbose, .. } = &mut command.command {
+                *verbose = true;
+            }
+            return command;
+        }
+
+        let command = match name {
+            "" if !bang => ExCommandKind::Goto,
+            "q" | "quit" if bang => ExCommandKind::ForceQuit,
+            "q" | "quit" => ExCommandKind::Quit,
+            "w" | "write" => ExCommandKind::Write { force: bang },
+            "wq" | "x" | "exit" => ExCommandKind::WriteQuit,
+            "f" | "file" => ExCommandKind::File,
+            "noh" | "nohlsearch" => ExCommandKind::NoHighlight,
+            "reg" | "registers" => ExCommandKind::Registers,
+            "marks" => ExCommandKind::Marks,
+            "se" | "set" | "setl" | "setlocal" => ExCommandKind::Set,
+            "ab" | "abbreviate" | "iab" | "iabbrev" | "cab" | "cabbrev" => {
+                ExCommandKind::Abbreviate {
+                    insert: !name.starts_with('c'),
+                    command: !name.starts_with('i'),
+                }
+            }
+            "una" | "unabbreviate" | "iuna" | "iunabbrev" | "cuna" | "cunabbrev" => {
+                ExCommandKind::Unabbreviate {
+                    insert: !name.starts_with('c'),
+                    command: !name.starts_with('i'),
+                }
+            }
+            "map" | "nm" | "nmap" | "vm" | "vmap" | "im" | "imap" => ExCommandKind::Map {
+                mode: map_mode(name),
+                noremap: false,
+                verbose: false,
+            },
+            "no" | "noremap" | "nn" | "nnoremap" | "vn" | "vnoremap" | "ino" | "inoremap" => {
+                ExCommandKind::Map {
+                    mode: map_mode(name),
+                    noremap: true,
+                    verbose: false,
+                }
+            }
+            "dig" | "digraphs" => ExCommandKind::Digraphs,
+            "as" | "ascii" => ExCommandKind::Ascii,
+            "hi" | "highlight" => ExCommandKind::Highlight,
+            "au" | "autocmd" => ExCommandKind::Autocmd { clear: bang },
+            "snip" | "snippet" => ExCommandKind::Snippet,
+            "colo" | "colorscheme" => ExCommandKind::ColorScheme,
+            "ol" | "oldfiles" => ExCommandKind::OldFiles,
+            "mes" | "messages" => ExCommandKind::Messages,
+            "ec" | "echo" => ExCommandKind::Echo,
+            "echom" | "echomsg" => ExCommandKind::EchoMsg,
+            "echoe" | "echoerr" => ExCommandKind::EchoErr,
+            "let" => ExCommandKind::Let,
+            "e" | "edit" => ExCommandKind::Edit { force: bang },
+            "ls" | "buffers" | "files" => ExCommandKind::Buffers,
+            "r" | "read" => ExCommandKind::Read,
+            "sor" | "sort" => ExCommandKind::Sort { reverse: bang },
+            "le" | "left" => ExCommandKind::AlignLines(AlignMode::Left),
+            "ri" | "right" => ExCommandKind::AlignLines(AlignMode::Right),
+            "ce" | "center" => ExCommandKind::AlignLines(AlignMode::Center),
+            "ret" | "retab" => ExCommandKind::Retab { bang },
+            "Align" => ExCommandKind::Align,
+            "TOhtml" => ExCommandKind::ToHtml,
+            "wc" | "wcount" => ExCommandKind::WordCount,
+            "d" | "delete" => ExCommandKind::Delete,
+            "co" | "copy" | "t" => ExCommandKind::Copy,
+            "m" | "mo" | "move" => ExCommandKind::Move,
+            "sig" | "sign" => ExCommandKind::Sign,
+            "diffs" | "diffsplit" => ExCommandKind::DiffSplit,
+            "diffu" | "diffupdate" => ExCommandKind::DiffUpdate,
+            "diffo" | "diffoff" => ExCommandKind::DiffOff,
+            "wa" | "wall" => ExCommandKind::WriteAll,
+            "on" | "only" => ExCommandKind::Only { force: bang },
+            "res" | "resize" => ExCommandKind::Resize,
+            "qa" | "qall" | "quita" | "quitall" if bang => ExCommandKind::ForceQuit,
+            "qa" | "qall" | "quita" | "quitall" => ExCommandKind::QuitAll,
+            "wqa" | "wqall" | "xa" | "xall" => ExCommandKind::WriteQuitAll,
+            "bufdo" | "bufd" => ExCommandKind::BufDo,
+            "windo" => ExCommandKind::WinDo,
+            "argdo" | "argd" => ExCommandKind::ArgDo,
+            "ar" | "args" => ExCommandKind::Args,
+            "so" | "source" => ExCommandKind::Source,
+            "scr" | "scriptnames" => ExCommandKind::ScriptNames,
+            "gr" | "grep" => ExCommandKind::Grep,
+            "mak" | "make" => ExCommandKind::Make,
+            "cn" | "cnext" => ExCommandKind::QuickfixNext,
+            "cp" | "cprevious" | "cN" | "cNext" => ExCommandKind::QuickfixPrevious,
+            "cfir" | "cfirst" => ExCommandKind::QuickfixFirst,
+            "cla" | "clast" => ExCommandKind::QuickfixLast,
+            "cope" | "copen" => ExCommandKind::QuickfixOpen,
+            "ccl" | "cclose" => ExCommandKind::QuickfixClose,
+            "s" | "substitute" if !bang && is_pattern_delimiter(args) => {
+                ExCommandKind::Substitute
+            }
+            "g" | "global" if is_pattern_delimiter(args) => ExCommandKind::Global { invert: bang },
+            "v" | "vglobal" if !bang && is_pattern_delimiter(args) => {
+                ExCommandKind::Global { invert: true }
+            }
+            _ => ExCommandKind::Unknown(rest.to_string()),
+        };
+
+        let args = match command {
+            ExCommandKind::Substitute | ExCommandKind::Global { .. } => vec![args.to_string()],
+            ExCommandKind::BufDo | ExCommandKind::WinDo | ExCommandKind::ArgDo => {
+                vec![args.trim().to_string()]
+            }
+            // `:r!cmd` reads a command's output, the same as `:r !cmd`.
+            ExCommandKind::Read if bang => vec![rest[name_end..].trim().to_string()],
+            ExCommandKind::Read
+            | ExCommandKind::Write { .. }
+            | ExCommandKind::Edit { .. }
+            | ExCommandKind::Source
+            | ExCommandKind::Make
+            | ExCommandKind::Abbreviate { .. }
+            | ExCommandKind::Map { .. }
+            | ExCommandKind::Autocmd { .. }
+            | ExCommandKind::Snippet
+            | ExCommandKind::Echo
+            | ExCommandKind::EchoMsg
+            | ExCommandKind::EchoErr
+            | ExCommandKind::Let
+            | ExCommandKind::DiffSplit
+                if !args.trim().is_empty() =>
+            {
+                vec![args.trim().to_string()]
+            }
+            _ => args.split_whitespace().map(str::to_string).collect(),
+        };
+        ExCommand {
+            range,
+            command,
+            args,
+        }
+    }
+}
+
+/// The mode a `:map` command's name is for, from its first letter: `:nmap`,
+/// `:vnoremap`, `:imap`, or `None` for `:map` and `:noremap`.
+fn map_mode(name: &str) -> Option<MapMode> {
+    match name.as_bytes()[0] {
+        b'i' => Some(MapMode::Insert),
+        b'v' => Some(MapMode::Visual),
+        b'n' if name.starts_with("nn") || name.starts_with("nm") => Some(MapMode::Normal),
+        _ => None,
+    }
+}
+
+/// Whether `args` starts with a character that can delimit a `:s` or `:g`
+/// pattern, as in `/a/b/` or `#a#b#`.
+fn is_pattern_delimiter(args: &str) -> bool {
+    args.chars()
+        .next()
+        .is_some_and(|c| !c.is_alphanumeric() && !c.is_whitespace() && c != '"')
+}
+
+/// Splits `input` on unescaped `delimiter`s, unescaping `\<delimiter>`.
+pub fn split_delimited(input: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        let part = parts.last_mut().unwrap();
+        match c {
+            '\\' => match chars.next() {
+                Some(next) if next == delimiter => part.push(next),
+                Some(next) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            },
+            c if c == delimiter => parts.push(String::new()),
+            c => part.push(c),
+        }
+    }
+    parts
+}
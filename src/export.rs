@@ -0,0 +1,63 @@
+use crate::editor::CimEditor;
+use std::io;
+use std::path::{Path, PathBuf};
+use syntect::html::highlighted_html_for_string;
+
+impl CimEditor {
+    /// Writes the buffer to `path` as an HTML5 page, highlighted with the current
+    /// syntax and theme.
+    pub fn export_to_html(&self, path: &Path) -> io::Result<()> {
+        let highlighter = &self.highlighter;
+        let theme = &highlighter.theme_set.themes[&highlighter.current_theme_name];
+        let body = highlighted_html_for_string(
+            &self.buffer.text(),
+            &highlighter.syntax_set,
+            &highlighter.syntax,
+            theme,
+        )
+        .map_err(io::Error::other)?;
+
+        let title = self
+            .file_path
+            .as_ref()
+            .map_or("Untitled".to_string(), |path| path.display().to_string());
+        let html = format!(
+            "<!DOCTYPE html>\n\
+             <html>\n\
+             <head>\n\
+             <meta charset=\"utf-8\">\n\
+             <title>{}</title>\n\
+             <style>\n\
+             body {{ margin: 0; }}\n\
+             pre {{ margin: 0; padding: 1em; font-family: monospace; }}\n\
+             </style>\n\
+             </head>\n\
+             <body>\n\
+             {}\
+             </body>\n\
+             </html>\n",
+            escape_html(&title),
+            body
+        );
+        std::fs::write(path, html)
+    }
+
+    /// Where `:TOhtml` writes by default: the file's name plus `.html`, beside it.
+    pub fn default_html_path(&self) -> PathBuf {
+        match &self.file_path {
+            Some(path) => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".html");
+                PathBuf::from(name)
+            }
+            None => PathBuf::from("Untitled.html"),
+        }
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
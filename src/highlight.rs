@@ -1,16 +1,45 @@
+use crate::buffer::{RopeEdit, RopeLineIter};
+use crate::color::parse_hex_color;
+use std::collections::HashMap;
+use std::ops::Range;
 use std::path::Path;
 use syntect::{
-    easy::HighlightLines,
-    highlighting::{Style as SyntectStyle, ThemeSet},
-    parsing::{SyntaxReference, SyntaxSet},
+    highlighting::{Highlighter as ThemeHighlighter, Style as SyntectStyle, ThemeSet},
+    parsing::{ParseState, Scope, ScopeStack, SyntaxReference, SyntaxSet},
     util::LinesWithEndings,
 };
+use tui::style::{Modifier, Style};
+use xi_rope::{LinesMetric, Rope};
+
+/// A run of highlighted text: its theme style, the `:highlight` override for its
+/// scope if there is one, and the text.
+pub type HighlightedSegment = (SyntectStyle, Option<Style>, String);
+
+/// The parse state before each line of the text last highlighted, so an edit
+/// only has to be parsed from the line it starts on.
+#[derive(Default)]
+pub struct ParseStateCache {
+    states: Vec<(ParseState, ScopeStack)>,
+    /// The length of the text the states are for, which catches a buffer
+    /// replaced rather than edited.
+    text_len: usize,
+}
 
 pub struct Highlighter {
     pub syntax_set: SyntaxSet,
     pub theme_set: ThemeSet,
     pub syntax: SyntaxReference,
     pub current_theme_name: String,
+    /// Styles set with `:highlight`, by scope name such as `keyword.control`,
+    /// laid over the theme's for text in that scope or one under it.
+    pub custom_highlights: HashMap<String, Style>,
+    cache: ParseStateCache,
+}
+
+impl Default for Highlighter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Highlighter {
@@ -30,11 +59,13 @@ impl Highlighter {
             theme_set,
             syntax,
             current_theme_name,
+            custom_highlights: HashMap::new(),
+            cache: ParseStateCache::default(),
         }
     }
 
     pub fn set_syntax_for_file(&mut self, file_path: Option<&Path>) {
-        
+        self.cache = ParseStateCache::default();
         if let Some(path) = file_path {
             if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
                 if let Some(syntax) = self.syntax_set.find_syntax_by_extension(ext) {
@@ -63,33 +94,331 @@ impl Highlighter {
         self.syntax = self.syntax_set.find_syntax_plain_text().clone();
     }
 
-    pub fn highlight(&mut self, rope: &xi_rope::Rope) -> Vec<Vec<(SyntectStyle, String)>> {
-        let mut result = Vec::new();
-        let theme = &self.theme_set.themes[&self.current_theme_name];
+    /// Whether byte `column` of line `line_idx` in `text` is inside a string or a
+    /// comment under the current syntax.
+    pub fn in_string_or_comment(&self, text: &str, line_idx: usize, column: usize) -> bool {
+        let (Ok(string), Ok(comment)) = (Scope::new("string"), Scope::new("comment")) else {
+            return false;
+        };
+        let mut parse_state = ParseState::new(&self.syntax);
+        let mut scopes = ScopeStack::new();
 
-        let content = rope.to_string();
-        let mut highlighter = HighlightLines::new(&self.syntax, theme);
+        for (idx, line) in LinesWithEndings::from(text).enumerate() {
+            let Ok(ops) = parse_state.parse_line(line, &self.syntax_set) else {
+                return false;
+            };
+            for (offset, op) in ops {
+                if idx == line_idx && offset > column {
+                    break;
+                }
+                if scopes.apply(&op).is_err() {
+                    return false;
+                }
+            }
+            if idx == line_idx {
+                return scopes
+                    .as_slice()
+                    .iter()
+                    .any(|scope| string.is_prefix_of(*scope) || comment.is_prefix_of(*scope));
+            }
+        }
+        false
+    }
 
-        for line in LinesWithEndings::from(&content) {
-            match highlighter.highlight_line(line, &self.syntax_set) {
-                Ok(ranges) => {
-                    let processed_ranges: Vec<(SyntectStyle, String)> = ranges
-                        .into_iter()
-                        .map(|(style, text)| (style, text.to_string()))
-                        .collect::<Vec<_>>();
+    /// Switches to the theme called `name`, if there is one.
+    pub fn set_theme(&mut self, name: &str) -> bool {
+        if !self.theme_set.themes.contains_key(name) {
+            return false;
+        }
+        self.current_theme_name = name.to_string();
+        self.cache = ParseStateCache::default();
+        true
+    }
 
-                    result.push(processed_ranges);
+    /// The themes `:colorscheme` can switch to, in alphabetical order.
+    pub fn theme_names(&self) -> Vec<String> {
+        self.theme_set.themes.keys().cloned().collect()
+    }
+
+    /// The current syntax's name in lowercase, such as `rust`, or `text` for
+    /// plain text.
+    pub fn filetype(&self) -> String {
+        if self.syntax.name == self.syntax_set.find_syntax_plain_text().name {
+            return "text".to_string();
+        }
+        self.syntax.name.to_lowercase()
+    }
+
+    /// Byte ranges of each line of `text` that hold prose: the whole line in a
+    /// plain text file, otherwise the strings and comments.
+    pub fn prose_ranges(&self, text: &str) -> Vec<Vec<Range<usize>>> {
+        let lines = LinesWithEndings::from(text);
+        if self.syntax.name == self.syntax_set.find_syntax_plain_text().name {
+            return lines.map(|line| std::iter::once(0..line.len()).collect()).collect();
+        }
+        let (Ok(string), Ok(comment)) = (Scope::new("string"), Scope::new("comment")) else {
+            return Vec::new();
+        };
+        let mut parse_state = ParseState::new(&self.syntax);
+        let mut scopes = ScopeStack::new();
+        let in_prose = |scopes: &ScopeStack| {
+            scopes
+                .as_slice()
+                .iter()
+                .any(|scope| string.is_prefix_of(*scope) || comment.is_prefix_of(*scope))
+        };
+
+        lines
+            .map(|line| {
+                let ops = parse_state.parse_line(line, &self.syntax_set).unwrap_or_default();
+                let mut ranges: Vec<Range<usize>> = Vec::new();
+                let mut start = in_prose(&scopes).then_some(0);
+                for (offset, op) in ops {
+                    if scopes.apply(&op).is_err() {
+                        break;
+                    }
+                    match (start, in_prose(&scopes)) {
+                        (None, true) => start = Some(offset),
+                        (Some(s), false) => {
+                            ranges.push(s..offset);
+                            start = None;
+                        }
+                        _ => {}
+                    }
                 }
-                Err(_) => {
-                    result.push(vec![(SyntectStyle::default(), line.to_string())]);
+                if let Some(s) = start {
+                    ranges.push(s..line.len());
                 }
+                ranges
+            })
+            .collect()
+    }
+
+    /// Highlights all of `rope`, one entry per line, and keeps the parse state
+    /// before each line for `highlight_incremental`.
+    pub fn highlight(&mut self, rope: &Rope) -> Vec<Vec<HighlightedSegment>> {
+        let theme = &self.theme_set.themes[&self.current_theme_name];
+        let highlighter = ThemeHighlighter::new(theme);
+        let custom = self.custom_scopes();
+
+        let mut state = (ParseState::new(&self.syntax), ScopeStack::new());
+        let mut states = Vec::new();
+        let mut lines = Vec::new();
+        for text in rope.iter_lines(0, line_count(rope)) {
+            states.push(state.clone());
+            lines.push(self.highlight_line(&highlighter, &custom, &text, &mut state));
+        }
+        self.cache = ParseStateCache {
+            states,
+            text_len: rope.len(),
+        };
+        lines
+    }
+
+    /// Brings `lines`, highlighted from the text before `edits`, up to date with
+    /// `rope`. Only the lines the edits touched are parsed again, and the lines
+    /// after them until the parse state is what it was before the edits.
+    pub fn highlight_incremental(
+        &mut self,
+        rope: &Rope,
+        edits: &[RopeEdit],
+        lines: &mut Vec<Vec<HighlightedSegment>>,
+    ) {
+        let old_count = self.cache.states.len();
+        let (dirty, growth) = dirty_range(edits);
+        // A buffer replaced rather than edited, or a cache cleared by a new
+        // syntax or theme, gets highlighted from scratch.
+        if lines.len() != old_count
+            || old_count == 0
+            || self.cache.text_len.checked_add_signed(growth) != Some(rope.len())
+        {
+            *lines = self.highlight(rope);
+            return;
+        }
+        let Some(dirty) = dirty else {
+            return;
+        };
+        let first = rope.line_of_offset(dirty.start);
+        let new_last = rope.line_of_offset(dirty.end.min(rope.len()));
+        let new_count = line_count(rope);
+        if (new_last + old_count).checked_sub(new_count).is_none_or(|old_last| old_last < first) {
+            *lines = self.highlight(rope);
+            return;
+        }
+
+        let theme = &self.theme_set.themes[&self.current_theme_name];
+        let highlighter = ThemeHighlighter::new(theme);
+        let custom = self.custom_scopes();
+        let mut state = self.cache.states[first].clone();
+        let mut new_states = Vec::new();
+        let mut new_lines = Vec::new();
+        let mut line = first;
+        for text in rope.iter_lines(first, new_count) {
+            // Past the edit, each line is one the cache already has a state for.
+            if line > new_last && self.cache.states[line + old_count - new_count] == state {
+                break;
             }
+            new_states.push(state.clone());
+            new_lines.push(self.highlight_line(&highlighter, &custom, &text, &mut state));
+            line += 1;
         }
+        let old_end = line + old_count - new_count;
+        self.cache.states.splice(first..old_end, new_states);
+        self.cache.text_len = rope.len();
+        lines.splice(first..old_end, new_lines);
+    }
+
+    /// The `:highlight` overrides by the scope they apply to.
+    fn custom_scopes(&self) -> Vec<(Scope, Style)> {
+        self.custom_highlights
+            .iter()
+            .filter_map(|(name, style)| Some((Scope::new(name).ok()?, *style)))
+            .collect()
+    }
 
-        if result.is_empty() {
-            result.push(vec![(SyntectStyle::default(), "".to_string())]);
+    /// Highlights one line, starting from `state` and leaving it at the state
+    /// after the line.
+    fn highlight_line(
+        &self,
+        highlighter: &ThemeHighlighter,
+        custom: &[(Scope, Style)],
+        line: &str,
+        (parse_state, scopes): &mut (ParseState, ScopeStack),
+    ) -> Vec<HighlightedSegment> {
+        let Ok(ops) = parse_state.parse_line(line, &self.syntax_set) else {
+            return vec![(SyntectStyle::default(), None, line.to_string())];
+        };
+        let mut segments = Vec::new();
+        let mut start = 0;
+        let ops = ops.iter().map(|(offset, op)| (*offset, Some(op)));
+        for (offset, op) in ops.chain([(line.len(), None)]) {
+            if offset > start {
+                let style = highlighter.style_for_stack(scopes.as_slice());
+                let custom = custom_style(custom, scopes);
+                segments.push((style, custom, line[start..offset].to_string()));
+                start = offset;
+            }
+            if let Some(op) = op {
+                let _ = scopes.apply(op);
+            }
+        }
+        segments
+    }
+
+    /// `:highlight scope attributes`: sets the override for `scope`, or clears it
+    /// for `NONE`. The attributes are `guifg=#RRGGBB`, `guibg=#RRGGBB` and `bold`,
+    /// `italic` or `underline`, alone or in `gui=bold,italic`.
+    pub fn set_custom_highlight(&mut self, scope: &str, attributes: &[&str]) -> Result<(), String> {
+        if Scope::new(scope).is_err() {
+            return Err(format!("E411: Highlight group not found: {}", scope));
+        }
+        self.cache = ParseStateCache::default();
+        if attributes == ["NONE"] {
+            self.custom_highlights.remove(scope);
+            return Ok(());
+        }
+        let mut style = self.custom_highlights.get(scope).copied().unwrap_or_default();
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or(("gui", attribute));
+            let invalid = || format!("E423: Illegal argument: {}", attribute);
+            match key {
+                "guifg" => style.fg = Some(parse_hex_color(value).ok_or_else(invalid)?),
+                "guibg" => style.bg = Some(parse_hex_color(value).ok_or_else(invalid)?),
+                "gui" => {
+                    for name in value.split(',') {
+                        style = style.add_modifier(match name {
+                            "bold" => Modifier::BOLD,
+                            "italic" => Modifier::ITALIC,
+                            "underline" => Modifier::UNDERLINED,
+                            "reverse" => Modifier::REVERSED,
+                            _ => return Err(invalid()),
+                        });
+                    }
+                }
+                _ => return Err(invalid()),
+            }
         }
+        self.custom_highlights.insert(scope.to_string(), style);
+        Ok(())
+    }
 
-        result
+    /// One line per `:highlight` override, or only that of `scope`, in the form
+    /// it was set in.
+    pub fn custom_highlight_summary(&self, scope: &str) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .custom_highlights
+            .iter()
+            .filter(|(name, _)| scope.is_empty() || *name == scope)
+            .map(|(name, style)| {
+                let mut attributes = Vec::new();
+                for (key, color) in [("guifg", style.fg), ("guibg", style.bg)] {
+                    if let Some(tui::style::Color::Rgb(r, g, b)) = color {
+                        attributes.push(format!("{}=#{:02x}{:02x}{:02x}", key, r, g, b));
+                    }
+                }
+                let modifiers: Vec<&str> = [
+                    (Modifier::BOLD, "bold"),
+                    (Modifier::ITALIC, "italic"),
+                    (Modifier::UNDERLINED, "underline"),
+                    (Modifier::REVERSED, "reverse"),
+                ]
+                .into_iter()
+                .filter(|(modifier, _)| style.add_modifier.contains(*modifier))
+                .map(|(_, name)| name)
+                .collect();
+                if !modifiers.is_empty() {
+                    attributes.push(format!("gui={}", modifiers.join(",")));
+                }
+                format!("{:<24} {}", name, attributes.join(" "))
+            })
+            .collect();
+        lines.sort();
+        lines
     }
 }
+
+/// The byte range of the text after `edits` that they changed, and how much
+/// longer they made the text.
+fn dirty_range(edits: &[RopeEdit]) -> (Option<Range<usize>>, isize) {
+    let mut dirty: Option<Range<usize>> = None;
+    let mut growth = 0isize;
+    for edit in edits {
+        let Range { start, end } = edit.old_range;
+        let new_end = start + edit.new_len;
+        // Where an offset in the text before this edit ends up after it.
+        let shift = |offset: usize, inside: usize| {
+            if offset <= start {
+                offset
+            } else if offset >= end {
+                offset - end + new_end
+            } else {
+                inside
+            }
+        };
+        dirty = Some(match dirty {
+            Some(dirty) => {
+                shift(dirty.start, start).min(start)..shift(dirty.end, new_end).max(new_end)
+            }
+            None => start..new_end,
+        });
+        growth += edit.new_len as isize - (end - start) as isize;
+    }
+    (dirty, growth)
+}
+
+/// Lines in `rope`, counting the empty one after a final newline.
+fn line_count(rope: &Rope) -> usize {
+    rope.measure::<LinesMetric>() + 1
+}
+
+/// The override for the innermost scope of `scopes` that one of `custom` covers,
+/// taking the most specific of those that do.
+fn custom_style(custom: &[(Scope, Style)], scopes: &ScopeStack) -> Option<Style> {
+    scopes.as_slice().iter().rev().find_map(|scope| {
+        custom
+            .iter()
+            .filter(|(custom, _)| custom.is_prefix_of(*scope))
+            .max_by_key(|(custom, _)| custom.len())
+            .map(|(_, style)| *style)
+    })
+}
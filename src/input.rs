@@ -1,4 +1,4 @@
-use super::editor::EditorAction;
+use super::editor::{EditorAction, Operator};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
 pub fn handle_key_event(key: KeyEvent) -> Option<EditorAction> {
@@ -15,6 +15,12 @@ pub fn handle_key_event(key: KeyEvent) -> Option<EditorAction> {
             ..
         } => Some(EditorAction::Exit),
 
+        KeyEvent {
+            code: KeyCode::Char('q'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::ForceExit),
+
         KeyEvent {
             code: KeyCode::Char('w'),
             modifiers: KeyModifiers::NONE,
@@ -33,6 +39,49 @@ pub fn handle_key_event(key: KeyEvent) -> Option<EditorAction> {
             ..
         } => Some(EditorAction::ChangeMode(false)),
 
+        KeyEvent {
+            code: KeyCode::Char('u'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::HalfPageUp),
+
+        KeyEvent {
+            code: KeyCode::Char('d'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::HalfPageDown),
+
+        KeyEvent {
+            code: KeyCode::Char('e'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::ScrollDown(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('y'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::ScrollUp(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('g'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::FileInfo),
+
+        KeyEvent {
+            code: KeyCode::Char('z'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::Suspend),
+
+        // Terminals send Ctrl-^ as either Ctrl-^ or Ctrl-6.
+        KeyEvent {
+            code: KeyCode::Char('^' | '6'),
+            modifiers,
+            ..
+        } if modifiers.contains(KeyModifiers::CONTROL) => Some(EditorAction::SwitchToAlternate),
+
         KeyEvent {
             code: KeyCode::Char(':'),
             modifiers: KeyModifiers::NONE,
@@ -75,18 +124,198 @@ pub fn handle_key_event(key: KeyEvent) -> Option<EditorAction> {
             ..
         } => Some(EditorAction::MoveWord(-1)),
 
+        KeyEvent {
+            code: KeyCode::Char('W'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::MoveBigWord(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('B'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::MoveBigWord(-1)),
+
+        KeyEvent {
+            code: KeyCode::Char('E'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::MoveBigWordEnd(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('{'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::ParagraphBackward),
+
+        KeyEvent {
+            code: KeyCode::Char('}'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::ParagraphForward),
+
+        KeyEvent {
+            code: KeyCode::Char('/'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::StartSearch(true)),
+
+        KeyEvent {
+            code: KeyCode::Char('?'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::StartSearch(false)),
+
+        KeyEvent {
+            code: KeyCode::Char('n'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::SearchNext),
+
+        KeyEvent {
+            code: KeyCode::Char('N'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::SearchPrevious),
+
+        KeyEvent {
+            code: KeyCode::Char('d'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::Operator(Operator::Delete)),
+
+        KeyEvent {
+            code: KeyCode::Char('y'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::Operator(Operator::Yank)),
+
+        KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::Operator(Operator::Change)),
+
+        KeyEvent {
+            code: KeyCode::Char('>'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::Operator(Operator::ShiftRight)),
+
+        KeyEvent {
+            code: KeyCode::Char('<'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::Operator(Operator::ShiftLeft)),
+
+        KeyEvent {
+            code: KeyCode::Char('='),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::Operator(Operator::Reindent)),
+
+        KeyEvent {
+            code: KeyCode::Char('R'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::StartReplace),
+
+        KeyEvent {
+            code: KeyCode::Char('~'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::ToggleCase(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('a'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::Increment(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('x'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        } => Some(EditorAction::Increment(-1)),
+
+        KeyEvent {
+            code: KeyCode::Char('p'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::Paste(true)),
+
+        KeyEvent {
+            code: KeyCode::Char('P'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::Paste(false)),
+
         KeyEvent {
             code: KeyCode::Home | KeyCode::Char('0'),
             modifiers: KeyModifiers::NONE,
             ..
         } => Some(EditorAction::LineStart),
 
+        KeyEvent {
+            code: KeyCode::Char('^'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::FirstNonBlank),
+
+        KeyEvent {
+            code: KeyCode::Char('_'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::FirstNonBlankLine(0)),
+
+        KeyEvent {
+            code: KeyCode::Char('+'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::FirstNonBlankLine(1)),
+
+        KeyEvent {
+            code: KeyCode::Char('-'),
+            modifiers: KeyModifiers::NONE,
+            ..
+        } => Some(EditorAction::FirstNonBlankLine(-1)),
+
         KeyEvent {
             code: KeyCode::End | KeyCode::Char('$'),
             modifiers: KeyModifiers::NONE,
             ..
         } => Some(EditorAction::LineEnd),
 
+        KeyEvent {
+            code: KeyCode::Char('G'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::GoToLine(0)),
+
+        KeyEvent {
+            code: KeyCode::Char('H'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::CursorScreenTop(0)),
+
+        KeyEvent {
+            code: KeyCode::Char('K'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::Hover),
+
+        KeyEvent {
+            code: KeyCode::Char('M'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::CursorScreenMiddle),
+
+        KeyEvent {
+            code: KeyCode::Char('L'),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } => Some(EditorAction::CursorScreenBottom(0)),
+
         KeyEvent {
             code: KeyCode::PageUp,
             modifiers: KeyModifiers::NONE,
@@ -121,8 +350,79 @@ pub fn handle_key_event(key: KeyEvent) -> Option<EditorAction> {
             code: KeyCode::Backspace,
             modifiers: KeyModifiers::NONE,
             ..
-        } => Some(EditorAction::DeleteChar),
+        } => Some(EditorAction::DeleteBack),
+
+        _ => None,
+    }
+}
+
+/// `<leader>` for two-key mappings such as `<leader>n`, vim's default backslash.
+const LEADER: char = '\\';
+
+/// Stands for Ctrl-W in window commands such as `Ctrl-W o`.
+pub const CTRL_W: char = '\u{17}';
+
+/// Keys that start a two-key Normal mode command.
+const PREFIX_KEYS: [char; 10] = ['g', 'z', 'm', 'r', '[', ']', '\'', '`', '"', LEADER];
+
+/// Returns the key if it starts a two-key Normal mode command such as `zz`.
+pub fn prefix_key(key: KeyEvent) -> Option<char> {
+    match key {
+        KeyEvent {
+            code: KeyCode::Char(c),
+            modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+            ..
+        } if PREFIX_KEYS.contains(&c) => Some(c),
+
+        _ => None,
+    }
+}
+
+/// Resolves the second key of a command started by `prefix_key`.
+pub fn handle_key_sequence(prefix: char, key: KeyEvent) -> Option<EditorAction> {
+    let KeyEvent {
+        code: KeyCode::Char(c),
+        modifiers: KeyModifiers::NONE | KeyModifiers::SHIFT,
+        ..
+    } = key
+    else {
+        return None;
+    };
 
+    match (prefix, c) {
+        ('g', 'g') => Some(EditorAction::GoToLine(1)),
+        ('g', 'U') => Some(EditorAction::Operator(Operator::Uppercase)),
+        ('g', 'u') => Some(EditorAction::Operator(Operator::Lowercase)),
+        ('g', 'E') => Some(EditorAction::MoveBigWordEnd(-1)),
+        ('g', 'a') => Some(EditorAction::CharInfo),
+        (']', 'p') => Some(EditorAction::PasteIndentAdjusted(true)),
+        (']', 'q') => Some(EditorAction::Quickfix(1)),
+        (']', 'c') => Some(EditorAction::JumpToHunk(1)),
+        ('[', 'c') => Some(EditorAction::JumpToHunk(-1)),
+        ('[', 'q') => Some(EditorAction::Quickfix(-1)),
+        ('[', 'p') => Some(EditorAction::PasteIndentAdjusted(false)),
+        ('z', 'f') => Some(EditorAction::Operator(Operator::Fold)),
+        ('z', 'o') => Some(EditorAction::OpenFold),
+        ('z', 'c') => Some(EditorAction::CloseFold),
+        ('z', 'a') => Some(EditorAction::ToggleFold),
+        ('z', 'R') => Some(EditorAction::OpenAllFolds),
+        ('z', 'M') => Some(EditorAction::CloseAllFolds),
+        ('z', '=') => Some(EditorAction::SpellSuggest),
+        ('z', 't') => Some(EditorAction::ScrollCursorTop),
+        ('z', 'z') => Some(EditorAction::ScrollCursorCenter),
+        ('z', 'b') => Some(EditorAction::ScrollCursorBottom),
+        ('z', 's') => Some(EditorAction::ScrollCursorLeft),
+        ('z', 'e') => Some(EditorAction::ScrollCursorRight),
+        (LEADER, 'n') => Some(EditorAction::ClearHighlight),
+        (CTRL_W, 'o') => Some(EditorAction::CloseOtherSplits),
+        (CTRL_W, '+') => Some(EditorAction::ResizeSplit(1)),
+        (CTRL_W, '-') => Some(EditorAction::ResizeSplit(-1)),
+        (CTRL_W, '=') => Some(EditorAction::EqualizeAllSplits),
+        ('"', name) => Some(EditorAction::SelectRegister(name)),
+        ('m', mark) => Some(EditorAction::SetMark(mark)),
+        ('r', c) => Some(EditorAction::ReplaceChar(c, 1)),
+        ('\'', mark) => Some(EditorAction::JumpToMarkLine(mark)),
+        ('`', mark) => Some(EditorAction::JumpToMark(mark)),
         _ => None,
     }
 }
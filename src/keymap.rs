@@ -0,0 +1,235 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::path::PathBuf;
+
+/// The modes a mapping can apply in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MapMode {
+    Normal,
+    /// Made with `:vmap`, though there is no Visual mode yet to use it in.
+    Visual,
+    /// Insert and Replace mode.
+    Insert,
+}
+
+impl MapMode {
+    fn letter(self) -> char {
+        match self {
+            MapMode::Normal => 'n',
+            MapMode::Visual => 'v',
+            MapMode::Insert => 'i',
+        }
+    }
+}
+
+/// A mapping made with `:map lhs rhs` or one of its mode and `noremap` forms.
+#[derive(Clone)]
+pub struct Mapping {
+    pub mode: MapMode,
+    /// The keys as written in the `:map` command, such as `<C-s>`.
+    pub lhs: String,
+    pub rhs: String,
+    /// Set by the `noremap` forms, whose `rhs` keys aren't mapped again.
+    pub noremap: bool,
+    /// The script the mapping was made in, shown by `:verbose map`.
+    pub script: Option<PathBuf>,
+    /// Made with `<buffer>`, for the current buffer only.
+    pub buffer_local: bool,
+    /// The keys `lhs` stands for.
+    pub keys: Vec<KeyEvent>,
+}
+
+/// What the keys typed so far mean to the mappings of a mode.
+pub enum Lookup<'a> {
+    /// They start a longer `lhs`, so the next key decides.
+    Pending,
+    /// The mapping whose `lhs` is the longest run of them from the start, if any.
+    Done(Option<&'a Mapping>),
+}
+
+#[derive(Clone, Default)]
+pub struct Keymap {
+    mappings: Vec<Mapping>,
+    /// Holds the `<buffer>` mappings of the current buffer.
+    buffer_local: bool,
+}
+
+impl Keymap {
+    /// An empty keymap for the mappings made with `<buffer>`.
+    pub fn buffer_local() -> Keymap {
+        Keymap {
+            mappings: Vec::new(),
+            buffer_local: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mappings.is_empty()
+    }
+
+    /// This keymap with the mappings of `buffer` added, replacing those of the
+    /// same keys and mode.
+    pub fn with_buffer_override(&self, buffer: &Keymap) -> Keymap {
+        let mut merged = self.clone();
+        merged.mappings.retain(|mapping| {
+            !buffer
+                .mappings
+                .iter()
+                .any(|local| local.mode == mapping.mode && same_keys(&local.keys, &mapping.keys))
+        });
+        merged.mappings.extend(buffer.mappings.iter().cloned());
+        merged
+    }
+
+    /// Maps `lhs` to `rhs` in `mode`, replacing any mapping of the same keys.
+    pub fn set(
+        &mut self,
+        mode: MapMode,
+        lhs: &str,
+        rhs: &str,
+        noremap: bool,
+        script: Option<PathBuf>,
+    ) {
+        let keys = parse_keys(lhs);
+        self.mappings
+            .retain(|mapping| mapping.mode != mode || !same_keys(&mapping.keys, &keys));
+        self.mappings.push(Mapping {
+            mode,
+            lhs: lhs.to_string(),
+            rhs: rhs.to_string(),
+            noremap,
+            script,
+            buffer_local: self.buffer_local,
+            keys,
+        });
+    }
+
+    pub fn lookup(&self, mode: MapMode, typed: &[KeyEvent]) -> Lookup<'_> {
+        let mappings = self.mappings.iter().filter(|mapping| mapping.mode == mode);
+        let mut longest: Option<&Mapping> = None;
+        for mapping in mappings {
+            if mapping.keys.len() > typed.len() && same_keys(&mapping.keys[..typed.len()], typed) {
+                return Lookup::Pending;
+            }
+            if mapping.keys.len() <= typed.len()
+                && same_keys(&mapping.keys, &typed[..mapping.keys.len()])
+                && longest.is_none_or(|longest| longest.keys.len() < mapping.keys.len())
+            {
+                longest = Some(mapping);
+            }
+        }
+        Lookup::Done(longest)
+    }
+
+    /// One line per mapping in `mode`, or in Normal and Visual mode when `mode`
+    /// is `None` as for `:map`, with the mode, `lhs` and `rhs` in columns. A
+    /// non-empty `lhs` shows only its mapping; `verbose` adds where each was made.
+    pub fn display(&self, mode: Option<MapMode>, lhs: &str, verbose: bool) -> Vec<String> {
+        let keys = parse_keys(lhs);
+        let mut shown: Vec<&Mapping> = self
+            .mappings
+            .iter()
+            .filter(|mapping| match mode {
+                Some(mode) => mapping.mode == mode,
+                None => mapping.mode != MapMode::Insert,
+            })
+            .filter(|mapping| lhs.is_empty() || same_keys(&mapping.keys, &keys))
+            .collect();
+        shown.sort_by(|a, b| a.lhs.cmp(&b.lhs).then(a.mode.letter().cmp(&b.mode.letter())));
+
+        let width = shown.iter().map(|mapping| mapping.lhs.chars().count()).max();
+        let mut lines = Vec::new();
+        for mapping in shown {
+            lines.push(format!(
+                "{}  {:<width$}  {}{}{}",
+                mapping.mode.letter(),
+                mapping.lhs,
+                mapping.rhs,
+                if mapping.noremap { "  [noremap]" } else { "" },
+                if mapping.buffer_local { "  [buffer]" } else { "" },
+                width = width.unwrap_or(0),
+            ));
+            if let (true, Some(script)) = (verbose, &mapping.script) {
+                lines.push(format!("        Last set from {}", script.display()));
+            }
+        }
+        lines
+    }
+}
+
+/// Reads keys written in `:map` notation, where `<CR>`, `<Esc>`, `<C-x>` and the
+/// like name special keys, and anything else stands for itself.
+pub fn parse_keys(notation: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = notation;
+    while let Some(c) = rest.chars().next() {
+        let special = rest
+            .strip_prefix('<')
+            .and_then(|name| name.split_once('>'))
+            .and_then(|(name, after)| Some((special_key(name)?, after)));
+        match special {
+            Some((key, after)) => {
+                keys.push(key);
+                rest = after;
+            }
+            None => {
+                keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    keys
+}
+
+/// The key named `name` between `<` and `>`, such as `Esc` or `C-s`.
+fn special_key(name: &str) -> Option<KeyEvent> {
+    let lower = name.to_ascii_lowercase();
+    let (modifiers, key) = match lower.get(..2) {
+        Some("c-") => (KeyModifiers::CONTROL, &name[2..]),
+        Some("a-" | "m-") => (KeyModifiers::ALT, &name[2..]),
+        Some("s-") => (KeyModifiers::SHIFT, &name[2..]),
+        _ => (KeyModifiers::NONE, name),
+    };
+    let code = match key.to_ascii_lowercase().as_str() {
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "esc" => KeyCode::Esc,
+        "tab" if modifiers == KeyModifiers::SHIFT => KeyCode::BackTab,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        "bslash" => KeyCode::Char('\\'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "insert" => KeyCode::Insert,
+        f if f.starts_with('f') && f.len() > 1 => KeyCode::F(f[1..].parse().ok()?),
+        _ => {
+            let mut chars = key.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if modifiers == KeyModifiers::CONTROL => {
+                    KeyCode::Char(c.to_ascii_lowercase())
+                }
+                (Some(c), None) if modifiers != KeyModifiers::NONE => KeyCode::Char(c),
+                _ => return None,
+            }
+        }
+    };
+    Some(KeyEvent::new(code, modifiers))
+}
+
+/// Compares keys as typed with keys from `parse_keys`, ignoring the Shift that
+/// terminals report along with an uppercase letter or Shift-Tab.
+fn same_keys(a: &[KeyEvent], b: &[KeyEvent]) -> bool {
+    let normalize = |key: &KeyEvent| match key.code {
+        KeyCode::Char(_) | KeyCode::BackTab => (key.code, key.modifiers - KeyModifiers::SHIFT),
+        code => (code, key.modifiers),
+    };
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| normalize(a) == normalize(b))
+}
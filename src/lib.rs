@@ -0,0 +1,30 @@
+#![deny(clippy::wrong_self_convention)]
+
+pub mod autocmd;
+pub mod buffer;
+pub mod color;
+pub mod completion;
+pub mod config;
+pub mod debug_log;
+pub mod diff;
+pub mod digraph;
+pub mod editor;
+pub mod event_loop;
+pub mod eval;
+pub mod ex;
+pub mod export;
+pub mod highlight;
+pub mod keymap;
+pub mod message;
+pub mod input;
+pub mod options;
+pub mod popup;
+pub mod quickfix;
+pub mod recent;
+pub mod register;
+pub mod sign;
+pub mod snippet;
+pub mod spell;
+pub mod statusline;
+pub mod types;
+pub mod ui;
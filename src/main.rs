@@ -1,25 +1,66 @@
 mod args;
-mod buffer;
-mod editor;
-mod highlight;
-mod input;
-mod ui;
 
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyEventKind},
+    cursor::SetCursorStyle,
+    event::{DisableMouseCapture, EnableMouseCapture},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
 use args::CliArgs;
-use clap::Parser;
-use std::io;
-use tui::backend::Backend;
+use cim::message::MessageKind;
+use cim::event_loop::{run_editor, CrosstermInputSource, CrosstermTerminalControl};
+use cim::{color, config, debug_log, editor, recent};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
 use tui::{backend::CrosstermBackend, Terminal};
 
 fn main() -> io::Result<()> {
-    let args = CliArgs::parse();
-    let mut editor = editor::CimEditor::new(args.file_path)?;
+    let args = CliArgs::parse_with_plus();
+    if let Some(path) = &args.debug {
+        debug_log::init(path)?;
+    }
+    // `cim -` reads the buffer from stdin, as does `cmd | cim` without a file,
+    // unless stdin holds the `--headless` script.
+    let script_on_stdin = args.headless && args.script.is_none();
+    let read_stdin = match &args.file_path {
+        Some(path) => path == Path::new("-"),
+        None => !io::stdin().is_terminal() && !script_on_stdin,
+    };
+    let mut editor = if read_stdin {
+        editor::CimEditor::from_stdin()?
+    } else {
+        editor::CimEditor::new(args.file_path)?
+    };
+    editor.arg_list.extend(args.more_files);
+    editor.oldfiles = recent::RecentFiles::load();
+    if let Some(path) = &editor.file_path {
+        let _ = recent::RecentFiles::push(path);
+    }
+    editor.read_only = args.readonly;
+    editor.config = config::AppConfig::load();
+    if let Some(program) = &editor.config.make_program {
+        editor.options.make_program = program.clone();
+    }
+    if let Some(format) = &editor.config.error_format {
+        editor.options.error_format = format.clone();
+    }
+    editor.color_support = color::ColorSupport::detect();
+
+    if args.headless {
+        let script = match &args.script {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => io::read_to_string(io::stdin())?,
+        };
+        return run_headless(&mut editor, &script, args.dry_run);
+    }
+
+    if args.export_html {
+        let path = editor.default_html_path();
+        editor.export_to_html(&path)?;
+        println!("{}", path.display());
+        return Ok(());
+    }
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -28,35 +69,77 @@ fn main() -> io::Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    let res = run_editor(&mut terminal, &mut editor);
+    // Size the viewport first so the startup jump scrolls like any other.
+    let size = terminal.size()?;
+    editor.resize(size.width, size.height);
+    editor.source_init_file();
+    editor.enter_buffer();
+    if let Some(line) = args.line {
+        editor.go_to_line(line.clamp(1, editor.total_lines()));
+    }
+    if let Some(column) = args.column {
+        editor.cursor_position.col = column.saturating_sub(1) as u16;
+        editor.normalize_cursor();
+    }
+    if let Some(cmd) = &args.cmd {
+        editor.run_startup_command(cmd);
+    }
+
+    let res = run_editor(
+        &mut terminal,
+        &mut editor,
+        &mut CrosstermInputSource,
+        &mut CrosstermTerminalControl,
+    );
 
     disable_raw_mode()?;
     execute!(
         terminal.backend_mut(),
         LeaveAlternateScreen,
-        DisableMouseCapture
+        DisableMouseCapture,
+        SetCursorStyle::DefaultUserShape
     )?;
 
     res
 }
 
-fn run_editor<B: Backend>(
-    terminal: &mut Terminal<B>,
-    editor: &mut editor::CimEditor,
-) -> io::Result<()> {
-    loop {
-        terminal.draw(|f| ui::draw_ui(f, editor))?;
-
-        if let Event::Key(key) = event::read()? {
-            if key.kind == KeyEventKind::Press {
-                if let Some(action) = editor.handle_input(key) {
-                    match action {
-                        editor::EditorAction::Exit => return Ok(()),
-
-                        _ => {}
-                    }
-                }
+/// `--headless`: runs `script` on each file of the argument list and writes the
+/// ones it changed, or prints them to stdout for `--dry-run`. A buffer read from
+/// stdin is always printed. Messages go to stderr, and any error makes the exit
+/// status 1.
+fn run_headless(editor: &mut editor::CimEditor, script: &str, dry_run: bool) -> io::Result<()> {
+    editor.resize(80, 24);
+    let files: Vec<Option<PathBuf>> = if editor.arg_list.is_empty() {
+        vec![None]
+    } else {
+        editor.arg_list.iter().cloned().map(Some).collect()
+    };
+    let mut failed = false;
+    for path in files {
+        let current = path.is_none() || editor.file_path == path;
+        if let (false, Some(path)) = (current, &path) {
+            if let Err(e) = editor.edit_file(path.clone()) {
+                eprintln!("{}: {}", path.display(), e);
+                failed = true;
+                continue;
             }
         }
+        editor.run_script(script.as_bytes())?;
+        if editor.buffer.is_modified() {
+            if dry_run || editor.file_path.is_none() {
+                io::stdout().write_all(editor.buffer.text().as_bytes())?;
+                editor.buffer.set_modified(false);
+            } else if let Err(e) = editor.save() {
+                editor.notify(&e.to_string(), MessageKind::Error);
+            }
+        }
+        for (message, kind, _) in editor.messages.messages.drain(..) {
+            failed |= kind == MessageKind::Error;
+            eprintln!("{}", message);
+        }
+    }
+    if failed {
+        std::process::exit(1);
     }
+    Ok(())
 }
@@ -0,0 +1,66 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long a message stays on the message line.
+const MESSAGE_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Most messages `:messages` remembers.
+const MAX_MESSAGES: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Messages shown to the user, newest last, kept for `:messages`.
+#[derive(Default)]
+pub struct MessageQueue {
+    pub messages: VecDeque<(String, MessageKind, Instant)>,
+    /// The message on the message line, cleared when a key is pressed.
+    showing: Option<(String, MessageKind, Instant)>,
+}
+
+impl MessageQueue {
+    pub fn push(&mut self, message: &str, kind: MessageKind) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((message.to_string(), kind, Instant::now()));
+        self.show(message, kind);
+    }
+
+    /// Shows `message` without keeping it for `:messages`, as `:echo` does.
+    pub fn show(&mut self, message: &str, kind: MessageKind) {
+        self.showing = Some((message.to_string(), kind, Instant::now()));
+    }
+
+    /// The message on the message line, unless a key was pressed or it has
+    /// timed out.
+    pub fn current(&self) -> Option<(&str, MessageKind)> {
+        let (message, kind, time) = self.showing.as_ref()?;
+        (time.elapsed() < MESSAGE_TIMEOUT).then_some((message.as_str(), *kind))
+    }
+
+    /// How long until the showing message times out, for the redraw that hides it.
+    pub fn time_left(&self) -> Option<Duration> {
+        let (_, _, time) = self.showing.as_ref()?;
+        MESSAGE_TIMEOUT.checked_sub(time.elapsed())
+    }
+
+    /// Stops showing the current message, keeping it for `:messages`.
+    pub fn dismiss(&mut self) {
+        self.showing = None;
+    }
+
+    /// The newest error given at or after `since`.
+    pub fn last_error_since(&self, since: Instant) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .take_while(|(_, _, time)| *time >= since)
+            .find(|(_, kind, _)| *kind == MessageKind::Error)
+            .map(|(message, _, _)| message.as_str())
+    }
+}
@@ -0,0 +1,383 @@
+/// Settings changed at runtime with `:set`.
+#[derive(Clone, Debug)]
+pub struct EditorOptions {
+    pub ignorecase: bool,
+    pub smartcase: bool,
+    pub sign_column: SignColumnMode,
+    /// Show the word count and reading time in the status bar.
+    pub wordcount: bool,
+    /// Reading speed in words per minute, for the reading time estimate.
+    pub reading_wpm: usize,
+    /// Underline misspelled words.
+    pub spell: bool,
+    /// The dictionary `spell` uses, from `~/.cim/spell/<spell_lang>.dic`.
+    pub spell_lang: String,
+    /// Rows in the `:copen` quickfix window.
+    pub quickfix_height: usize,
+    /// Show whitespace with the symbols in `listchars`.
+    pub list: bool,
+    pub listchars: ListChars,
+    /// The shell command `:make` runs, followed by its arguments.
+    pub make_program: String,
+    /// How `:make` reads its output, in `ErrorFormat` syntax.
+    pub error_format: String,
+    /// How a `:s` command shows its changes while it is typed.
+    pub inccommand: InccommandMode,
+    /// Columns between tab stops, for Tab and `:retab`.
+    pub tab_width: usize,
+    /// Tab inserts spaces rather than a tab character.
+    pub expand_tab: bool,
+    /// How the status bar shows the file's path.
+    pub filename_display: FilenameDisplay,
+    /// Put back a line break deleted from the end of a file when writing it.
+    pub ensure_trailing_newline: bool,
+}
+
+impl Default for EditorOptions {
+    fn default() -> Self {
+        Self {
+            ignorecase: false,
+            smartcase: false,
+            sign_column: SignColumnMode::default(),
+            wordcount: false,
+            reading_wpm: 200,
+            spell: false,
+            spell_lang: "en_US".to_string(),
+            quickfix_height: 10,
+            list: false,
+            listchars: ListChars::default(),
+            make_program: "make".to_string(),
+            error_format: "%f:%l:%c: %m,%f:%l: %m".to_string(),
+            inccommand: InccommandMode::default(),
+            tab_width: 4,
+            expand_tab: true,
+            filename_display: FilenameDisplay::default(),
+            ensure_trailing_newline: true,
+        }
+    }
+}
+
+/// Live preview of `:s`, set with `:set inccommand=`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum InccommandMode {
+    /// Set with an empty value.
+    Off,
+    /// The substitutions are shown in the text.
+    #[default]
+    Nosplit,
+    /// As `Nosplit`, with the changed lines also listed in a window below.
+    Split,
+}
+
+impl InccommandMode {
+    fn name(self) -> &'static str {
+        match self {
+            InccommandMode::Off => "",
+            InccommandMode::Nosplit => "nosplit",
+            InccommandMode::Split => "split",
+        }
+    }
+}
+
+/// The symbols `list` shows whitespace with, set with
+/// `:set listchars=tab:>-,trail:-,eol:$`. A category left out is drawn as usual.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListChars {
+    /// The first column of a tab, then the rest of it.
+    pub tab: Option<(char, char)>,
+    /// Spaces and tabs at the end of a line, in place of `space` and `tab`.
+    pub trail: Option<char>,
+    pub space: Option<char>,
+    /// Drawn after the last character of a line.
+    pub eol: Option<char>,
+    /// The last column, when the line goes on past the right edge.
+    pub extends: Option<char>,
+    /// The first column, when the line starts left of the window.
+    pub precedes: Option<char>,
+}
+
+impl Default for ListChars {
+    fn default() -> Self {
+        Self {
+            tab: None,
+            trail: None,
+            space: None,
+            eol: Some('$'),
+            extends: None,
+            precedes: None,
+        }
+    }
+}
+
+impl ListChars {
+    /// Parses a comma-separated list of `name:chars` items.
+    fn parse(value: &str) -> Option<ListChars> {
+        let mut listchars = ListChars {
+            eol: None,
+            ..ListChars::default()
+        };
+        for item in value.split(',').filter(|item| !item.is_empty()) {
+            let (name, chars) = item.split_once(':')?;
+            let chars: Vec<char> = chars.chars().collect();
+            match (name, chars.as_slice()) {
+                ("tab", &[first, rest]) => listchars.tab = Some((first, rest)),
+                ("trail", &[c]) => listchars.trail = Some(c),
+                ("space", &[c]) => listchars.space = Some(c),
+                ("eol", &[c]) => listchars.eol = Some(c),
+                ("extends", &[c]) => listchars.extends = Some(c),
+                ("precedes", &[c]) => listchars.precedes = Some(c),
+                _ => return None,
+            }
+        }
+        Some(listchars)
+    }
+}
+
+impl std::fmt::Display for ListChars {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut items = Vec::new();
+        if let Some((first, rest)) = self.tab {
+            items.push(format!("tab:{}{}", first, rest));
+        }
+        let singles = [
+            ("trail", self.trail),
+            ("space", self.space),
+            ("eol", self.eol),
+            ("extends", self.extends),
+            ("precedes", self.precedes),
+        ];
+        for (name, c) in singles {
+            if let Some(c) = c {
+                items.push(format!("{}:{}", name, c));
+            }
+        }
+        write!(f, "{}", items.join(","))
+    }
+}
+
+/// When to draw the sign column, set with `:set signcolumn=`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SignColumnMode {
+    /// Only while a sign is placed.
+    #[default]
+    Auto,
+    Yes,
+    No,
+}
+
+impl SignColumnMode {
+    fn name(self) -> &'static str {
+        match self {
+            SignColumnMode::Auto => "auto",
+            SignColumnMode::Yes => "yes",
+            SignColumnMode::No => "no",
+        }
+    }
+}
+
+/// How the status bar shows the file's path, set with `:set filenamedisplay=`.
+/// A name another file in the argument list or the alternate file would also
+/// show is given as the full path instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum FilenameDisplay {
+    /// Just the file name.
+    Basename,
+    /// Relative to the working directory, or in full outside it.
+    #[default]
+    ShortPath,
+    FullPath,
+}
+
+impl FilenameDisplay {
+    fn name(self) -> &'static str {
+        match self {
+            FilenameDisplay::Basename => "basename",
+            FilenameDisplay::ShortPath => "shortpath",
+            FilenameDisplay::FullPath => "fullpath",
+        }
+    }
+}
+
+impl EditorOptions {
+    /// Applies one `:set` argument such as `ic`, `nosmartcase` or `ignorecase?`,
+    /// returning the text to show for queries.
+    pub fn set(&mut self, arg: &str) -> Result<Option<String>, String> {
+        if let Some((name, value)) = arg.split_once('=') {
+            return self.set_value(name, value).map(|()| None);
+        }
+        if let "signcolumn?" | "scl?" | "signcolumn" | "scl" = arg {
+            return Ok(Some(format!("signcolumn={}", self.sign_column.name())));
+        }
+        if let "readingwpm?" | "readingwpm" = arg {
+            return Ok(Some(format!("readingwpm={}", self.reading_wpm)));
+        }
+        if let "spelllang?" | "spl?" | "spelllang" | "spl" = arg {
+            return Ok(Some(format!("spelllang={}", self.spell_lang)));
+        }
+        if let "quickfixheight?" | "quickfixheight" = arg {
+            return Ok(Some(format!("quickfixheight={}", self.quickfix_height)));
+        }
+        if let "listchars?" | "lcs?" | "listchars" | "lcs" = arg {
+            return Ok(Some(format!("listchars={}", self.listchars)));
+        }
+        if let "inccommand?" | "icm?" | "inccommand" | "icm" = arg {
+            return Ok(Some(format!("inccommand={}", self.inccommand.name())));
+        }
+        if let "makeprg?" | "mp?" | "makeprg" | "mp" = arg {
+            return Ok(Some(format!("makeprg={}", self.make_program)));
+        }
+        if let "errorformat?" | "efm?" | "errorformat" | "efm" = arg {
+            return Ok(Some(format!("errorformat={}", self.error_format)));
+        }
+        if let "tabstop?" | "ts?" | "tabstop" | "ts" = arg {
+            return Ok(Some(format!("tabstop={}", self.tab_width)));
+        }
+        if let "filenamedisplay?" | "filenamedisplay" = arg {
+            return Ok(Some(format!("filenamedisplay={}", self.filename_display.name())));
+        }
+        let (name, query) = match arg.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (arg, false),
+        };
+        let (name, value) = match name.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (name, true),
+        };
+
+        let (name, option) = match name {
+            "ignorecase" | "ic" => ("ignorecase", &mut self.ignorecase),
+            "smartcase" | "scs" => ("smartcase", &mut self.smartcase),
+            "wordcount" => ("wordcount", &mut self.wordcount),
+            "spell" => ("spell", &mut self.spell),
+            "list" => ("list", &mut self.list),
+            "expandtab" | "et" => ("expandtab", &mut self.expand_tab),
+            "fixendofline" | "fixeol" => ("fixendofline", &mut self.ensure_trailing_newline),
+            _ => return Err(format!("E518: Unknown option: {}", arg)),
+        };
+
+        if query {
+            return Ok(Some(flag(name, *option)));
+        }
+        *option = value;
+        Ok(None)
+    }
+
+    /// Applies a `name=value` option such as `signcolumn=yes`.
+    fn set_value(&mut self, name: &str, value: &str) -> Result<(), String> {
+        match name {
+            "signcolumn" | "scl" => {
+                self.sign_column = match value {
+                    "auto" => SignColumnMode::Auto,
+                    "yes" => SignColumnMode::Yes,
+                    "no" => SignColumnMode::No,
+                    _ => return Err(format!("E474: Invalid argument: {}={}", name, value)),
+                };
+                Ok(())
+            }
+            "spelllang" | "spl" if !value.is_empty() => {
+                self.spell_lang = value.to_string();
+                Ok(())
+            }
+            "listchars" | "lcs" => {
+                self.listchars = ListChars::parse(value)
+                    .ok_or_else(|| format!("E474: Invalid argument: {}={}", name, value))?;
+                Ok(())
+            }
+            "inccommand" | "icm" => {
+                self.inccommand = match value {
+                    "" => InccommandMode::Off,
+                    "nosplit" => InccommandMode::Nosplit,
+                    "split" => InccommandMode::Split,
+                    _ => return Err(format!("E474: Invalid argument: {}={}", name, value)),
+                };
+                Ok(())
+            }
+            "filenamedisplay" => {
+                self.filename_display = match value {
+                    "basename" => FilenameDisplay::Basename,
+                    "shortpath" => FilenameDisplay::ShortPath,
+                    "fullpath" => FilenameDisplay::FullPath,
+                    _ => return Err(format!("E474: Invalid argument: {}={}", name, value)),
+                };
+                Ok(())
+            }
+            "makeprg" | "mp" if !value.is_empty() => {
+                self.make_program = value.to_string();
+                Ok(())
+            }
+            "errorformat" | "efm" if !value.is_empty() => {
+                self.error_format = value.to_string();
+                Ok(())
+            }
+            "quickfixheight" => {
+                self.quickfix_height = value
+                    .parse()
+                    .ok()
+                    .filter(|&height| height > 0)
+                    .ok_or_else(|| format!("E521: Number required after =: {}={}", name, value))?;
+                Ok(())
+            }
+            "tabstop" | "ts" => {
+                self.tab_width = value
+                    .parse()
+                    .ok()
+                    .filter(|&width| width > 0)
+                    .ok_or_else(|| format!("E521: Number required after =: {}={}", name, value))?;
+                Ok(())
+            }
+            "readingwpm" => {
+                self.reading_wpm = value
+                    .parse()
+                    .ok()
+                    .filter(|&wpm| wpm > 0)
+                    .ok_or_else(|| format!("E521: Number required after =: {}={}", name, value))?;
+                Ok(())
+            }
+            _ => Err(format!("E518: Unknown option: {}", name)),
+        }
+    }
+
+    /// All options in `:set` form, e.g. `noignorecase smartcase`.
+    pub fn summary(&self) -> String {
+        [
+            flag("ignorecase", self.ignorecase),
+            flag("smartcase", self.smartcase),
+            format!("signcolumn={}", self.sign_column.name()),
+            flag("wordcount", self.wordcount),
+            format!("readingwpm={}", self.reading_wpm),
+            flag("spell", self.spell),
+            format!("spelllang={}", self.spell_lang),
+            format!("quickfixheight={}", self.quickfix_height),
+            flag("list", self.list),
+            format!("listchars={}", self.listchars),
+            format!("makeprg={}", self.make_program),
+            format!("errorformat={}", self.error_format),
+            format!("inccommand={}", self.inccommand.name()),
+            format!("tabstop={}", self.tab_width),
+            flag("expandtab", self.expand_tab),
+            format!("filenamedisplay={}", self.filename_display.name()),
+            flag("fixendofline", self.ensure_trailing_newline),
+        ]
+        .join("  ")
+    }
+
+    /// Estimated time to read `words` at `reading_wpm`, like `~3 min`.
+    pub fn reading_time(&self, words: usize) -> String {
+        format!("~{} min", words.div_ceil(self.reading_wpm.max(1)))
+    }
+}
+
+fn flag(name: &str, on: bool) -> String {
+    format!("{}{}", if on { "" } else { "no" }, name)
+}
+
+/// Case handling for search patterns.
+pub struct SearchOptions;
+
+impl SearchOptions {
+    /// Returns true when a search for `pattern` should ignore case: `ignorecase` is
+    /// set, and `smartcase` is off or the pattern has no uppercase letters.
+    pub fn effective_case(pattern: &str, opts: &EditorOptions) -> bool {
+        opts.ignorecase && !(opts.smartcase && pattern.chars().any(char::is_uppercase))
+    }
+}
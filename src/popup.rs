@@ -0,0 +1,16 @@
+use crate::types::CursorPos;
+use tui::text::Text;
+
+/// A box drawn over the text beside the cursor, such as the completion menu or
+/// the diagnostics shown by `K`. Moving the cursor or pressing Esc closes it.
+pub struct PopupWindow {
+    pub id: usize,
+    pub content: Text<'static>,
+    /// The position the popup hangs from: it starts at that column on the row
+    /// below the line, or above the line when there is no room below.
+    pub position: CursorPos,
+    /// Width and height of `content`.
+    pub size: (u16, u16),
+    /// Where the cursor was when the popup opened.
+    pub opened_at: CursorPos,
+}
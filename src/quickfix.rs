@@ -0,0 +1,81 @@
+use regex::Regex;
+use std::path::PathBuf;
+
+/// A location to jump to, as collected by `:grep`. Line and column are 1-based.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuickfixEntry {
+    pub file: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl QuickfixEntry {
+    /// Parses one line of `grep -n` output, `file:line:message`. grep prints no
+    /// column, so the entry starts at column 1 until the caller finds the match.
+    pub fn parse(output: &str) -> Option<QuickfixEntry> {
+        let (file, rest) = output.split_once(':')?;
+        let (line, message) = rest.split_once(':')?;
+        Some(QuickfixEntry {
+            file: PathBuf::from(file),
+            line: line.parse().ok().filter(|&line| line > 0)?,
+            column: 1,
+            message: message.to_string(),
+        })
+    }
+}
+
+/// Reads compiler output into quickfix entries, as `:make` does. A format is a
+/// line with `%f` for the file name, `%l` for the line, `%c` for the column and
+/// `%m` for the message, such as `%f:%l:%c: %m`; `%%` is a literal `%`.
+pub struct ErrorFormat;
+
+impl ErrorFormat {
+    /// Parses `line` with `format`, or with the first of several that matches
+    /// when `format` is a comma-separated list.
+    pub fn parse_line(format: &str, line: &str) -> Option<QuickfixEntry> {
+        format.split(',').find_map(|format| {
+            let captures = format_regex(format)?.captures(line)?;
+            let number = |name: &str| captures.name(name).and_then(|n| n.as_str().parse().ok());
+            Some(QuickfixEntry {
+                file: PathBuf::from(captures.name("file")?.as_str()),
+                line: number("line").filter(|&line| line > 0)?,
+                // Some tools count columns from 0; those read as the line's start.
+                column: number("column").filter(|&column| column > 0).unwrap_or(1),
+                message: captures.name("message").map_or("", |m| m.as_str()).to_string(),
+            })
+        })
+    }
+
+    /// Whether `entry` reports a warning rather than an error, going by the
+    /// `warning:` compilers put before the message.
+    pub fn is_warning(entry: &QuickfixEntry) -> bool {
+        entry.message.trim_start().to_ascii_lowercase().starts_with("warning")
+    }
+}
+
+/// The regex matching a whole line in `format`, or `None` if it has no `%f`
+/// and `%l` or uses an unknown `%` item.
+fn format_regex(format: &str) -> Option<Regex> {
+    let mut pattern = String::from("^");
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pattern.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+        pattern.push_str(match chars.next()? {
+            'f' => r"(?P<file>.+?)",
+            'l' => r"(?P<line>\d+)",
+            'c' => r"(?P<column>\d+)",
+            'm' => r"(?P<message>.*)",
+            '%' => "%",
+            _ => return None,
+        });
+    }
+    pattern.push('$');
+    if !pattern.contains("(?P<file>") || !pattern.contains("(?P<line>") {
+        return None;
+    }
+    Regex::new(&pattern).ok()
+}
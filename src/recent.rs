@@ -0,0 +1,51 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Most files `~/.cim/recent_files` remembers.
+const MAX_RECENT_FILES: usize = 100;
+
+/// Files opened in earlier sessions, most recent first, kept in
+/// `~/.cim/recent_files` one path per line.
+pub struct RecentFiles;
+
+impl RecentFiles {
+    /// The remembered files that still exist.
+    pub fn load() -> Vec<PathBuf> {
+        let Some(content) = recent_files_path().and_then(|p| std::fs::read_to_string(p).ok())
+        else {
+            return Vec::new();
+        };
+        let mut files: Vec<PathBuf> = Vec::new();
+        for path in content.lines().map(PathBuf::from) {
+            if path.exists() && !files.contains(&path) {
+                files.push(path);
+            }
+        }
+        files
+    }
+
+    /// Moves `path` to the top of the list, adding it if it is new.
+    pub fn push(path: &Path) -> io::Result<()> {
+        let list_path = recent_files_path()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+        let path = std::fs::canonicalize(path)?;
+        let mut files = Self::load();
+        files.retain(|file| file != &path);
+        files.insert(0, path);
+        files.truncate(MAX_RECENT_FILES);
+
+        if let Some(dir) = list_path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let content: String = files
+            .iter()
+            .map(|file| format!("{}\n", file.display()))
+            .collect();
+        std::fs::write(list_path, content)
+    }
+}
+
+fn recent_files_path() -> Option<PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    Some(PathBuf::from(home).join(".cim").join("recent_files"))
+}
@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+/// Text captured by a yank or delete, along with how it should be put back.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Register {
+    pub text: String,
+    pub linewise: bool,
+}
+
+/// Vim-style register storage keyed by register name.
+#[derive(Default)]
+pub struct Registers {
+    contents: HashMap<char, Register>,
+}
+
+impl Registers {
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.contents.get(&name)
+    }
+
+    /// Stores text into register `name`, also making it the unnamed register's
+    /// contents. An uppercase name appends to its lowercase register and `_` discards.
+    pub fn set(&mut self, name: char, register: Register) {
+        let register = match name {
+            '_' => return,
+            '"' => return self.yank(register),
+            'A'..='Z' => {
+                let target = self.contents.entry(name.to_ascii_lowercase()).or_default();
+                target.text.push_str(&register.text);
+                target.linewise |= register.linewise;
+                target.clone()
+            }
+            name => {
+                self.contents.insert(name, register.clone());
+                register
+            }
+        };
+        self.contents.insert('"', register);
+    }
+
+    /// Records yanked text in the unnamed register and `"0`.
+    pub fn yank(&mut self, register: Register) {
+        self.contents.insert('0', register.clone());
+        self.contents.insert('"', register);
+    }
+
+    /// Records deleted text in the unnamed register. Multi-line deletes shift
+    /// through `"1`-`"9`; smaller ones go to `"-`.
+    pub fn delete(&mut self, register: Register) {
+        if register.linewise || register.text.contains('\n') {
+            for n in (1..9).rev() {
+                if let Some(older) = self.contents.remove(&digit(n)) {
+                    self.contents.insert(digit(n + 1), older);
+                }
+            }
+            self.contents.insert('1', register.clone());
+        } else {
+            self.contents.insert('-', register.clone());
+        }
+        self.contents.insert('"', register);
+    }
+}
+
+fn digit(n: u32) -> char {
+    char::from_digit(n, 10).unwrap_or('0')
+}
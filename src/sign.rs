@@ -0,0 +1,62 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use tui::style::Style;
+
+/// A marker drawn in the sign column, such as a git change or a diagnostic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sign {
+    pub name: String,
+    /// One or two characters.
+    pub text: String,
+    pub highlight: Style,
+}
+
+/// Signs placed by `CimEditor::update_git_signs`.
+pub const GIT_SIGNS: [&str; 3] = ["GitAdd", "GitChange", "GitDelete"];
+
+/// Lines changed in `path` since the git index, as 0-based line numbers paired
+/// with the name of the sign that marks them. Deleted lines are marked on the
+/// line before them.
+pub fn git_changes(path: &Path) -> io::Result<Vec<(usize, &'static str)>> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let mut git = Command::new("git");
+    if let Some(dir) = dir {
+        git.arg("-C").arg(dir);
+    }
+    let output = git
+        .args(["diff", "--no-color", "--no-ext-diff", "-U0", "--"])
+        .arg(path.file_name().unwrap_or(path.as_os_str()))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        return Ok(Vec::new());
+    }
+
+    let mut changes = Vec::new();
+    for header in String::from_utf8_lossy(&output.stdout).lines() {
+        let Some(ranges) = header.strip_prefix("@@ -") else {
+            continue;
+        };
+        let mut ranges = ranges.split(' ');
+        let (Some(old), Some(new)) = (ranges.next(), ranges.next()) else {
+            continue;
+        };
+        let (_, old_count) = hunk_range(old);
+        let (start, new_count) = hunk_range(new.trim_start_matches('+'));
+        if new_count == 0 {
+            changes.push((start.saturating_sub(1), GIT_SIGNS[2]));
+        } else {
+            let name = if old_count == 0 { GIT_SIGNS[0] } else { GIT_SIGNS[1] };
+            changes.extend((start..start + new_count).map(|line| (line - 1, name)));
+        }
+    }
+    Ok(changes)
+}
+
+/// Parses `start,count` from a hunk header, where a missing count means 1.
+fn hunk_range(range: &str) -> (usize, usize) {
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    (start.parse().unwrap_or(0), count.parse().unwrap_or(0))
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Text inserted in place of a trigger word by Tab in Insert mode, with the
+/// places Tab moves to next. Bodies are written like `for ${1:i} in $2 {$0}`:
+/// `$N` marks a tab stop and `${N:text}` one with placeholder text, visited in
+/// order from `$1` with `$0` last. `\` makes the next character literal.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snippet {
+    /// The text to insert, with placeholder text in place of each `${N:text}`.
+    pub body: String,
+    /// Byte ranges of the tab stops in `body`, in the order Tab visits them. The
+    /// last is `$0`, or the end of the body when there is no `$0`.
+    pub tab_stops: Vec<Range<usize>>,
+}
+
+impl Snippet {
+    pub fn parse(source: &str) -> Snippet {
+        let mut body = String::new();
+        let mut stops: Vec<(usize, Range<usize>)> = Vec::new();
+        let mut chars = source.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => body.extend(chars.next()),
+                '$' if chars.peek().is_some_and(|c| c.is_ascii_digit() || *c == '{') => {
+                    let braced = chars.next_if_eq(&'{').is_some();
+                    let mut number = String::new();
+                    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                        number.push(digit);
+                    }
+                    let start = body.len();
+                    if braced && chars.next_if_eq(&':').is_some() {
+                        while let Some(c) = chars.next_if(|&c| c != '}') {
+                            match c {
+                                '\\' => body.extend(chars.next()),
+                                c => body.push(c),
+                            }
+                        }
+                    }
+                    if braced {
+                        chars.next_if_eq(&'}');
+                    }
+                    let number: usize = number.parse().unwrap_or(0);
+                    // A repeated number keeps the first stop, so Tab visits it once.
+                    if !stops.iter().any(|&(n, _)| n == number) {
+                        stops.push((number, start..body.len()));
+                    }
+                }
+                c => body.push(c),
+            }
+        }
+
+        if !stops.iter().any(|&(n, _)| n == 0) {
+            stops.push((0, body.len()..body.len()));
+        }
+        // `$0` sorts last.
+        stops.sort_by_key(|&(n, _)| n.wrapping_sub(1));
+        Snippet {
+            body,
+            tab_stops: stops.into_iter().map(|(_, range)| range).collect(),
+        }
+    }
+
+    /// The snippet with `indent` added after each newline, so that a multi-line
+    /// body lines up with the line it is expanded on.
+    pub fn indented(&self, indent: &str) -> Snippet {
+        if indent.is_empty() {
+            return self.clone();
+        }
+        let shift = |offset: usize| {
+            offset + self.body[..offset].matches('\n').count() * indent.len()
+        };
+        Snippet {
+            body: self.body.replace('\n', &format!("\n{}", indent)),
+            tab_stops: self
+                .tab_stops
+                .iter()
+                .map(|stop| shift(stop.start)..shift(stop.end))
+                .collect(),
+        }
+    }
+}
+
+/// An expanded snippet whose tab stops Tab is moving through, in
+/// `EditorMode::SnippetExpand`.
+pub struct SnippetSession {
+    /// Byte ranges of the tab stops in the buffer, kept up to date as text is typed.
+    pub stops: Vec<Range<usize>>,
+    pub current: usize,
+    /// Set on arriving at a stop with placeholder text, which typing replaces.
+    pub placeholder_pending: bool,
+}
+
+impl SnippetSession {
+    /// Moves the stops after byte `offset` by `delta` bytes for text inserted or
+    /// removed there, growing or shrinking the current stop if it holds `offset`.
+    pub fn shift(&mut self, offset: usize, delta: isize) {
+        for (i, stop) in self.stops.iter_mut().enumerate() {
+            if stop.start > offset || (stop.start == offset && i != self.current) {
+                stop.start = stop.start.saturating_add_signed(delta);
+                stop.end = stop.end.saturating_add_signed(delta);
+            } else if stop.end >= offset {
+                stop.end = stop.end.saturating_add_signed(delta).max(stop.start);
+            }
+        }
+    }
+}
+
+/// The snippets in `~/.cim/snippets/<filetype>.toml`, a table of triggers and
+/// bodies such as `fn = "fn ${1:name}() {\n    $0\n}"`.
+pub fn load(filetype: &str) -> HashMap<String, Snippet> {
+    let Some(table) = std::env::var_os("HOME")
+        .map(|home| {
+            PathBuf::from(home)
+                .join(".cim")
+                .join("snippets")
+                .join(format!("{}.toml", filetype))
+        })
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .and_then(|content| content.parse::<toml::Table>().ok())
+    else {
+        return HashMap::new();
+    };
+    table
+        .iter()
+        .filter_map(|(trigger, body)| Some((trigger.clone(), Snippet::parse(body.as_str()?))))
+        .collect()
+}
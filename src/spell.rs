@@ -0,0 +1,332 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Most suggestions `suggest` returns.
+const MAX_SUGGESTIONS: usize = 10;
+
+/// A prefix or suffix rule from a Hunspell `.aff` file, such as
+/// `SFX D y ied [^aeiou]y`.
+struct Affix {
+    flag: char,
+    /// Whether it combines with affixes of the other kind.
+    cross_product: bool,
+    /// Removed from the stem before `add` is attached.
+    strip: String,
+    add: String,
+    /// What the stem must look like for the rule to apply.
+    condition: Option<Regex>,
+}
+
+impl Affix {
+    /// The stem `word` was made from by this suffix, if it was.
+    fn stem_for_suffix(&self, word: &str) -> Option<String> {
+        let stem = format!("{}{}", word.strip_suffix(self.add.as_str())?, self.strip);
+        self.matches(&stem).then_some(stem)
+    }
+
+    /// The stem `word` was made from by this prefix, if it was.
+    fn stem_for_prefix(&self, word: &str) -> Option<String> {
+        let stem = format!("{}{}", self.strip, word.strip_prefix(self.add.as_str())?);
+        self.matches(&stem).then_some(stem)
+    }
+
+    fn matches(&self, stem: &str) -> bool {
+        !stem.is_empty() && self.condition.as_ref().is_none_or(|re| re.is_match(stem))
+    }
+}
+
+/// Checks words against a Hunspell dictionary. Only single-character affix
+/// flags are understood; `FLAG long` and `FLAG num` dictionaries load with their
+/// affixes ignored.
+pub struct SpellChecker {
+    pub lang: String,
+    /// Each word with its affix flags.
+    words: HashMap<String, String>,
+    prefixes: Vec<Affix>,
+    suffixes: Vec<Affix>,
+    /// Letters to try when making suggestions, from the `TRY` line.
+    try_chars: Vec<char>,
+}
+
+impl SpellChecker {
+    /// Loads `~/.cim/spell/<lang>.dic` and its `.aff` file.
+    pub fn load(lang: &str) -> io::Result<Self> {
+        let home = std::env::var_os("HOME")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+        let dir = PathBuf::from(home).join(".cim").join("spell");
+        let dic = std::fs::read_to_string(dir.join(format!("{}.dic", lang)))?;
+        let aff = std::fs::read_to_string(dir.join(format!("{}.aff", lang))).unwrap_or_default();
+        Ok(Self::parse(lang, &dic, &aff))
+    }
+
+    fn parse(lang: &str, dic: &str, aff: &str) -> Self {
+        let mut checker = SpellChecker {
+            lang: lang.to_string(),
+            words: HashMap::new(),
+            prefixes: Vec::new(),
+            suffixes: Vec::new(),
+            try_chars: Vec::new(),
+        };
+
+        let mut single_char_flags = true;
+        // Whether each affix flag's rules combine, from headers like `SFX D Y 4`.
+        let mut cross_products = HashMap::new();
+        for line in aff.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.as_slice() {
+                ["FLAG", "long" | "num"] => single_char_flags = false,
+                ["TRY", chars] => checker.try_chars = chars.chars().collect(),
+                [kind @ ("PFX" | "SFX"), flag, cross @ ("Y" | "N"), count]
+                    if count.parse::<usize>().is_ok() =>
+                {
+                    cross_products.insert((*kind, *flag), *cross == "Y");
+                }
+                [kind @ ("PFX" | "SFX"), flag, strip, add, rest @ ..] if single_char_flags => {
+                    let Some(flag_char) = flag.chars().next() else { continue };
+                    let prefix = *kind == "PFX";
+                    let condition = rest.first().filter(|&&c| c != ".").and_then(|c| {
+                        let pattern = if prefix {
+                            format!("^(?:{})", c)
+                        } else {
+                            format!("(?:{})$", c)
+                        };
+                        Regex::new(&pattern).ok()
+                    });
+                    // Continuation flags after a `/` in `add` aren't supported.
+                    let add = add.split('/').next().unwrap_or_default();
+                    let affix = Affix {
+                        flag: flag_char,
+                        cross_product: cross_products.get(&(*kind, *flag)) == Some(&true),
+                        strip: if *strip == "0" { String::new() } else { strip.to_string() },
+                        add: if add == "0" { String::new() } else { add.to_string() },
+                        condition,
+                    };
+                    if prefix {
+                        checker.prefixes.push(affix);
+                    } else {
+                        checker.suffixes.push(affix);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if checker.try_chars.is_empty() {
+            checker.try_chars = ('a'..='z').collect();
+        }
+
+        // The first line is the word count.
+        for line in dic.lines().skip(1) {
+            let Some(entry) = line.split_whitespace().next() else { continue };
+            let (word, flags) = entry.split_once('/').unwrap_or((entry, ""));
+            checker.words.insert(word.to_string(), flags.to_string());
+        }
+        checker
+    }
+
+    /// Whether `word` is spelled correctly. A capitalised or all-caps form of a
+    /// lowercase dictionary word is also accepted.
+    pub fn check_word(&self, word: &str) -> bool {
+        let lower = word.to_lowercase();
+        let mut capitalised = word.chars();
+        let capitalised = capitalised
+            .next()
+            .map(|first| first.to_lowercase().chain(capitalised).collect::<String>());
+        self.check_form(word)
+            || capitalised.is_some_and(|word| self.check_form(&word))
+            || (word.chars().all(|c| !c.is_lowercase()) && self.check_form(&lower))
+    }
+
+    fn check_form(&self, word: &str) -> bool {
+        if self.words.contains_key(word) {
+            return true;
+        }
+        let has_flags = |stem: &str, flags: &[char]| {
+            self.words
+                .get(stem)
+                .is_some_and(|stem_flags| flags.iter().all(|&f| stem_flags.contains(f)))
+        };
+        let with_suffix = |word: &str, prefix: Option<&Affix>| {
+            self.suffixes.iter().any(|suffix| {
+                if prefix.is_some() && !suffix.cross_product {
+                    return false;
+                }
+                suffix.stem_for_suffix(word).is_some_and(|stem| {
+                    let flags: Vec<char> =
+                        prefix.map(|p| p.flag).into_iter().chain([suffix.flag]).collect();
+                    has_flags(&stem, &flags)
+                })
+            })
+        };
+
+        with_suffix(word, None)
+            || self.prefixes.iter().any(|prefix| {
+                prefix.stem_for_prefix(word).is_some_and(|stem| {
+                    has_flags(&stem, &[prefix.flag])
+                        || (prefix.cross_product && with_suffix(&stem, Some(prefix)))
+                })
+            })
+    }
+
+    /// Correctly spelled words one edit away from `word`: a swapped pair of
+    /// letters, or a letter removed, changed or added.
+    pub fn suggest(&self, word: &str) -> Vec<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut candidates = Vec::new();
+        for i in 0..chars.len().saturating_sub(1) {
+            let mut swapped = chars.clone();
+            swapped.swap(i, i + 1);
+            candidates.push(swapped);
+        }
+        for i in 0..chars.len() {
+            let mut removed = chars.clone();
+            removed.remove(i);
+            candidates.push(removed);
+        }
+        for i in 0..chars.len() {
+            for &c in &self.try_chars {
+                let mut changed = chars.clone();
+                changed[i] = c;
+                candidates.push(changed);
+            }
+        }
+        for i in 0..=chars.len() {
+            for &c in &self.try_chars {
+                let mut added = chars.clone();
+                added.insert(i, c);
+                candidates.push(added);
+            }
+        }
+
+        let mut suggestions: Vec<String> = Vec::new();
+        for candidate in candidates {
+            let candidate: String = candidate.into_iter().collect();
+            if candidate != word && !suggestions.contains(&candidate) && self.check_word(&candidate)
+            {
+                suggestions.push(candidate);
+                if suggestions.len() == MAX_SUGGESTIONS {
+                    break;
+                }
+            }
+        }
+        suggestions
+    }
+}
+
+/// Byte ranges of the words in `line`: runs of letters, which may contain
+/// apostrophes, as in `don't`.
+pub fn words(line: &str) -> Vec<Range<usize>> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let inner_apostrophe = c == '\''
+            && start.is_some()
+            && chars.peek().is_some_and(|&(_, next)| next.is_alphabetic());
+        match (c.is_alphabetic() || inner_apostrophe, start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                words.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        words.push(s..line.len());
+    }
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AFF: &str = "\
+TRY esianrtolcdugmphbyfvkwz
+PFX U Y 1
+PFX U 0 un .
+SFX D Y 4
+SFX D 0 d e
+SFX D y ied [^aeiou]y
+SFX D 0 ed [^ey]
+SFX D 0 ed [aeiou]y
+SFX S N 1
+SFX S 0 s .
+";
+
+    const DIC: &str = "5\ntry/D\ndo/U\nlock/UDS\nParis\nNASA\n";
+
+    fn checker() -> SpellChecker {
+        SpellChecker::parse("test", DIC, AFF)
+    }
+
+    #[test]
+    fn parse_reads_affixes_and_words() {
+        let checker = checker();
+        assert_eq!(checker.prefixes.len(), 1);
+        assert_eq!(checker.suffixes.len(), 5);
+        assert!(checker.prefixes[0].cross_product);
+        assert!(!checker.suffixes[4].cross_product);
+        assert_eq!(checker.words.get("lock").map(String::as_str), Some("UDS"));
+        assert_eq!(checker.words.get("Paris").map(String::as_str), Some(""));
+        assert!(!checker.words.contains_key("5"));
+        assert_eq!(checker.try_chars[0], 'e');
+    }
+
+    #[test]
+    fn suffix_strips_before_adding() {
+        let checker = checker();
+        assert!(checker.check_word("tried"));
+        assert!(checker.check_word("locked"));
+        // `ed` needs a stem not ending in `y` after a consonant.
+        assert!(!checker.check_word("tryed"));
+        // The word must carry the flag.
+        assert!(!checker.check_word("doed"));
+    }
+
+    #[test]
+    fn prefix_and_suffix_combine_only_when_both_cross() {
+        let checker = checker();
+        assert!(checker.check_word("undo"));
+        assert!(checker.check_word("unlocked"));
+        assert!(checker.check_word("locks"));
+        assert!(!checker.check_word("unlocks"));
+        assert!(!checker.check_word("untried"));
+    }
+
+    #[test]
+    fn capitalised_and_all_caps_forms() {
+        let checker = checker();
+        assert!(checker.check_word("Tried"));
+        assert!(checker.check_word("TRIED"));
+        assert!(checker.check_word("Unlocked"));
+        assert!(checker.check_word("Paris"));
+        assert!(checker.check_word("NASA"));
+        assert!(!checker.check_word("tRied"));
+        assert!(!checker.check_word("paris"));
+        assert!(!checker.check_word("Nasa"));
+    }
+
+    #[test]
+    fn long_flags_are_ignored() {
+        let aff = "FLAG long\nSFX Dx Y 1\nSFX Dx 0 ed .\n";
+        let checker = SpellChecker::parse("test", "1\nwalk/Dx\n", aff);
+        assert!(checker.suffixes.is_empty());
+        assert!(checker.check_word("walk"));
+        assert!(!checker.check_word("walked"));
+    }
+
+    #[test]
+    fn suggestions_are_one_edit_away() {
+        let checker = checker();
+        assert!(checker.suggest("tyr").contains(&"try".to_string()));
+        assert!(checker.suggest("lokc").contains(&"lock".to_string()));
+        assert!(checker.suggest("lockd").contains(&"locked".to_string()));
+        assert!(checker.suggest("unlok").contains(&"unlock".to_string()));
+        assert!(!checker.suggest("lock").contains(&"lock".to_string()));
+        assert!(checker.suggest("zzzzzz").is_empty());
+    }
+}
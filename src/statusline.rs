@@ -0,0 +1,196 @@
+use crate::editor::{CimEditor, EditorMode};
+use crate::options::FilenameDisplay;
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use tui::text::Span;
+
+/// One piece of the status bar. Segments with nothing to show return `None`.
+pub trait StatusSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>>;
+
+    /// Separators are only drawn between segments that show something.
+    fn is_separator(&self) -> bool {
+        false
+    }
+}
+
+/// The segment called `name` in `~/.cim/config.toml`.
+pub fn segment_named(name: &str) -> Option<Box<dyn StatusSegment>> {
+    let segment: Box<dyn StatusSegment> = match name {
+        "mode" => Box::new(ModeSegment),
+        "filename" => Box::new(FilenameSegment),
+        "modified" => Box::new(ModifiedSegment),
+        "position" => Box::new(PositionSegment),
+        "encoding" => Box::new(EncodingSegment),
+        "git_branch" => Box::new(GitBranchSegment::default()),
+        "word_count" => Box::new(WordCountSegment),
+        _ => return None,
+    };
+    Some(segment)
+}
+
+/// Fixed text, used for the separators between segments.
+pub struct TextSegment(pub String);
+
+impl StatusSegment for TextSegment {
+    fn content(&self, _editor: &CimEditor) -> Option<Span<'static>> {
+        Some(Span::raw(self.0.clone()))
+    }
+
+    fn is_separator(&self) -> bool {
+        true
+    }
+}
+
+/// `NORMAL`, `INSERT` and so on, with ` ^K` while a digraph is pending.
+pub struct ModeSegment;
+
+impl StatusSegment for ModeSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        let mode = match editor.mode {
+            EditorMode::Insert => "INSERT",
+            EditorMode::Normal => "NORMAL",
+            EditorMode::Command => "COMMAND",
+            EditorMode::Search => "SEARCH",
+            EditorMode::SubstConfirm => "SUBSTITUTE",
+            EditorMode::Replace => "REPLACE",
+            EditorMode::SnippetExpand => "SNIPPET",
+        };
+        let pending = match (&editor.pending_digraph, &editor.pending_unicode) {
+            (Some(_), _) => " ^K".to_string(),
+            (_, Some(unicode)) => format!(" ^V{}", unicode),
+            _ => String::new(),
+        };
+        Some(Span::raw(format!("{}{}", mode, pending)))
+    }
+}
+
+/// The file's path as `filenamedisplay` asks, or in full when another file the
+/// editor knows of would show the same.
+pub struct FilenameSegment;
+
+impl StatusSegment for FilenameSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        let Some(path) = editor.file_path.as_deref() else {
+            return Some(Span::raw(editor.unnamed_label()));
+        };
+        let display = editor.options.filename_display;
+        let shown = display_path(path, display);
+        let full = display_path(path, FilenameDisplay::FullPath);
+        let ambiguous = editor
+            .arg_list
+            .iter()
+            .chain(editor.alternate_file.as_ref().map(|alternate| &alternate.path))
+            .any(|other| {
+                display_path(other, display) == shown
+                    && display_path(other, FilenameDisplay::FullPath) != full
+            });
+        Some(Span::raw(if ambiguous { full } else { shown }))
+    }
+}
+
+fn display_path(path: &Path, display: FilenameDisplay) -> String {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    match display {
+        FilenameDisplay::Basename => path
+            .file_name()
+            .map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into()),
+        FilenameDisplay::ShortPath => {
+            let absolute = cwd.join(path);
+            absolute.strip_prefix(&cwd).unwrap_or(&absolute).display().to_string()
+        }
+        FilenameDisplay::FullPath => std::fs::canonicalize(path)
+            .unwrap_or_else(|_| cwd.join(path))
+            .display()
+            .to_string(),
+    }
+}
+
+/// `[+]` for unsaved changes and `[RO]` for a read-only buffer.
+pub struct ModifiedSegment;
+
+impl StatusSegment for ModifiedSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        let indicator = match (editor.buffer.is_modified(), editor.read_only) {
+            (true, true) => "[+][RO]",
+            (true, false) => "[+]",
+            (false, true) => "[RO]",
+            (false, false) => return None,
+        };
+        Some(Span::raw(indicator))
+    }
+}
+
+pub struct PositionSegment;
+
+impl StatusSegment for PositionSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        Some(Span::raw(format!(
+            "Ln {}/{}, Col {}",
+            editor.cursor_position.row + 1,
+            editor.total_lines(),
+            editor.cursor_position.col + 1
+        )))
+    }
+}
+
+/// The encoding, always UTF-8, and line ending, like `utf-8 [unix]`.
+pub struct EncodingSegment;
+
+impl StatusSegment for EncodingSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        Some(Span::raw(format!("utf-8 [{}]", editor.buffer.line_ending())))
+    }
+}
+
+/// The git branch of the edited file's repository, read from `.git/HEAD`.
+#[derive(Default)]
+pub struct GitBranchSegment {
+    /// The branch last found, by file, so `.git/HEAD` isn't read on every draw.
+    cache: RefCell<Option<(Option<PathBuf>, Option<String>)>>,
+}
+
+impl StatusSegment for GitBranchSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        let mut cache = self.cache.borrow_mut();
+        if cache.as_ref().map(|(path, _)| path) != Some(&editor.file_path) {
+            let branch = editor.file_path.as_deref().and_then(git_branch);
+            *cache = Some((editor.file_path.clone(), branch));
+        }
+        let branch = cache.as_ref()?.1.clone()?;
+        Some(Span::raw(branch))
+    }
+}
+
+/// The branch checked out in the repository containing `path`, or the short
+/// commit hash when HEAD is detached.
+fn git_branch(path: &Path) -> Option<String> {
+    let path = std::fs::canonicalize(path).ok()?;
+    let head = path
+        .ancestors()
+        .skip(1)
+        .find_map(|dir| std::fs::read_to_string(dir.join(".git").join("HEAD")).ok())?;
+    let head = head.trim();
+    match head.strip_prefix("ref: refs/heads/") {
+        Some(branch) => Some(branch.to_string()),
+        None => Some(head.chars().take(7).collect()),
+    }
+}
+
+/// The buffer's word count and reading time, like `412 words ~3 min`, while
+/// `wordcount` is set.
+pub struct WordCountSegment;
+
+impl StatusSegment for WordCountSegment {
+    fn content(&self, editor: &CimEditor) -> Option<Span<'static>> {
+        if !editor.options.wordcount {
+            return None;
+        }
+        let (words, _, _) = editor.buffer_word_count();
+        Some(Span::raw(format!(
+            "{} words {}",
+            words,
+            editor.options.reading_time(words)
+        )))
+    }
+}
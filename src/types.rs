@@ -0,0 +1,45 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A place in the buffer: the character column on a line, both counted from 0.
+/// Positions order as they appear in the text, by row and then column.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CursorPos {
+    pub col: u16,
+    pub row: u16,
+}
+
+impl CursorPos {
+    pub const fn new(col: u16, row: u16) -> Self {
+        Self { col, row }
+    }
+
+    /// Moves `delta` columns left or right, stopping at column 0 and `u16::MAX`.
+    pub fn saturating_add_col(self, delta: i16) -> Self {
+        Self::new(self.col.saturating_add_signed(delta), self.row)
+    }
+
+    /// Moves `delta` lines up or down, stopping at line 0 and `u16::MAX`.
+    pub fn saturating_add_row(self, delta: i16) -> Self {
+        Self::new(self.col, self.row.saturating_add_signed(delta))
+    }
+}
+
+impl Ord for CursorPos {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.row, self.col).cmp(&(other.row, other.col))
+    }
+}
+
+impl PartialOrd for CursorPos {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// One-based `line:column`, as positions are shown to the user.
+impl fmt::Display for CursorPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.row as usize + 1, self.col as usize + 1)
+    }
+}
@@ -1,36 +1,64 @@
+use crate::buffer::RopeLineIter;
+use crate::color::nearest_color;
+use crate::completion::CompletionState;
+use crate::diff::{self, DiffKind, DiffPane};
 use crate::editor::CimEditor;
 use crate::editor::EditorMode;
+use crate::editor::{Overlay, OverlayKind, VirtualText};
+use crate::message::MessageKind;
+use crate::options::{InccommandMode, SearchOptions};
+use crate::popup::PopupWindow;
+use crate::quickfix::QuickfixEntry;
+use std::ops::Range;
+use xi_rope::Rope;
 use tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    buffer::Buffer,
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, Paragraph},
+    widgets::{Block, Borders, Clear, Paragraph, Widget},
     Frame,
 };
-use std::fs::OpenOptions;
-use std::io::Write;
-use chrono::Local;
 
 pub fn draw_ui<B: Backend>(f: &mut Frame<B>, app: &mut CimEditor) {
     let size = f.size();
     
     if app.text_changed {
-        app.update_line_numbers();
-        app.highlighted_lines = app.highlighter.highlight(app.buffer.rope());
+        let edits = app.buffer.take_edits();
+        app.highlighter
+            .highlight_incremental(app.buffer.rope(), &edits, &mut app.highlighted_lines);
+        if app.search_highlighted {
+            app.compute_search_matches();
+        }
+        app.compute_spell_errors();
+        app.word_count_cache.set(None);
         app.text_changed = false;
     }
 
-    let available_height = size.height.saturating_sub(2) as usize;
-
-    app.viewport_height = available_height;
-    app.viewport_width = size.width.saturating_sub(5) as usize;
+    let sign_width = if app.shows_sign_column() { 2 } else { 0 };
+    let text_width = app.text_width(size.width);
+    let quickfix_rows = app.quickfix_rows(size.height);
+    let wildmenu_rows = u16::from(
+        app.command_completion
+            .as_ref()
+            .is_some_and(|completion| completion.candidates.len() > 1),
+    );
+    let viewport_height = size.height.saturating_sub(2 + quickfix_rows + wildmenu_rows) as usize;
+    if viewport_height != app.viewport_height {
+        // Opening or closing the quickfix window can leave the cursor off screen.
+        app.viewport_height = viewport_height;
+        app.update_viewport();
+    }
+    app.viewport_width = text_width as usize;
 
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(1),
             Constraint::Min(1),
+            Constraint::Length(quickfix_rows),
+            Constraint::Length(wildmenu_rows),
             Constraint::Length(1),
         ])
         .split(size);
@@ -40,55 +68,332 @@ pub fn draw_ui<B: Backend>(f: &mut Frame<B>, app: &mut CimEditor) {
 
     let editor_chunks = Layout::default()
         .direction(Direction::Horizontal)
-        .constraints([Constraint::Length(5), Constraint::Min(1)])
+        .constraints([
+            Constraint::Length(sign_width),
+            Constraint::Length(app.gutter_width()),
+            Constraint::Length(text_width),
+            Constraint::Min(0),
+        ])
         .split(chunks[1]);
 
+    if sign_width > 0 {
+        f.render_widget(render_signs(app), editor_chunks[0]);
+    }
+
     let line_numbers = render_line_numbers(app);
-    f.render_widget(line_numbers, editor_chunks[0]);
+    f.render_widget(line_numbers, editor_chunks[1]);
 
     let text = build_highlighted_text(app);
     let paragraph = Paragraph::new(text).block(Block::default().borders(Borders::NONE));
-    f.render_widget(paragraph, editor_chunks[1]);
+    f.render_widget(paragraph, editor_chunks[2]);
 
-    let command = if app.mode == EditorMode::Command || !app.command_buffer.is_empty() {
-        let prefix = if app.mode == EditorMode::Command {
-            ":"
-        } else {
-            ""
+    if let Some(pane) = &app.diff_pane {
+        let block = Block::default().borders(Borders::LEFT);
+        let width = block.inner(editor_chunks[3]).width as usize;
+        let pane_text = build_diff_pane_text(app, pane, width);
+        f.render_widget(Paragraph::new(pane_text).block(block), editor_chunks[3]);
+    }
+
+    if app.quickfix_open {
+        let quickfix = QuickfixWidget {
+            entries: &app.quickfix_list,
+            cursor: app.quickfix_cursor,
+            focused: app.quickfix_focused,
         };
-        Paragraph::new(format!("{}{}", prefix, app.command_buffer))
-            .style(Style::default().fg(Color::Yellow))
+        f.render_widget(quickfix, chunks[2]);
+    }
+
+    if app.options.inccommand == InccommandMode::Split {
+        if let Some(preview) = &app.preview_rope {
+            render_substitute_preview(f, app, preview, chunks[1]);
+        }
+    }
+
+    if let Some(overlay) = &app.overlay {
+        render_overlay(f, overlay, chunks[1]);
+        return;
+    }
+
+    for popup in &app.popups {
+        render_popup(f, app, popup, editor_chunks[2]);
+    }
+
+    let prompt = match app.mode {
+        EditorMode::Command => Some(':'),
+        EditorMode::Search if app.search_backward => Some('?'),
+        EditorMode::Search => Some('/'),
+        _ => None,
+    };
+    let command = if let Some(prompt) = prompt {
+        let mut spans = vec![Span::styled(
+            format!("{}{}", prompt, app.command_buffer),
+            Style::default().fg(Color::Yellow),
+        )];
+        if app.mode == EditorMode::Search {
+            let ignore_case = SearchOptions::effective_case(&app.command_buffer, &app.options);
+            let case_mode = if ignore_case { "  [ignore case]" } else { "  [match case]" };
+            spans.push(Span::styled(case_mode, Style::default().fg(Color::DarkGray)));
+        }
+        Paragraph::new(Spans::from(spans))
+    } else if let Some((message, kind)) = app.messages.current() {
+        let color = match kind {
+            MessageKind::Info => Color::Yellow,
+            MessageKind::Warning => Color::LightRed,
+            MessageKind::Error => Color::Red,
+        };
+        Paragraph::new(message).style(Style::default().fg(color))
     } else {
         Paragraph::new("")
     };
-    f.render_widget(command, chunks[2]);
+    if let (1, Some(completion)) = (wildmenu_rows, &app.command_completion) {
+        f.render_widget(WildMenu { completion }, chunks[3]);
+    }
+    f.render_widget(command, chunks[4]);
 
     match app.mode {
-        EditorMode::Command => {
+        EditorMode::Command | EditorMode::Search => {
             let cmd_x = 1 + app.command_buffer.len() as u16;
-            f.set_cursor(cmd_x, chunks[2].y);
+            f.set_cursor(cmd_x, chunks[4].y);
+        }
+        _ if app.quickfix_focused => {
+            let row = app.quickfix_cursor.min(quickfix_rows.saturating_sub(2) as usize);
+            f.set_cursor(chunks[2].x, chunks[2].y + 1 + row as u16);
         }
         _ => {
-            let cursor_x = (app.cursor_position.0 as usize).saturating_sub(app.horizontal_offset);
-            let cursor_y = (app.cursor_position.1 as usize).saturating_sub(app.scroll_offset);
+            let cursor_x = (app.cursor_position.col as usize).saturating_sub(app.horizontal_offset);
+            let cursor_y = cursor_row(app);
 
             if cursor_x < app.viewport_width && cursor_y < app.viewport_height {
                 f.set_cursor(
-                    editor_chunks[1].x + cursor_x as u16,
-                    editor_chunks[1].y + cursor_y as u16,
+                    editor_chunks[2].x + cursor_x as u16,
+                    editor_chunks[2].y + cursor_y as u16,
                 );
             }
         }
     }
 }
 
-fn render_line_numbers(app: &CimEditor) -> Paragraph {
-    let start_line = app.scroll_offset;
-    let end_line =
-        (start_line + app.viewport_height).min(app.buffer.rope().measure::<xi_rope::LinesMetric>());
+fn render_overlay<B: Backend>(f: &mut Frame<B>, overlay: &Overlay, area: Rect) {
+    // Two rows for the border and one for the continue prompt.
+    let visible_rows = area.height.saturating_sub(3) as usize;
+
+    let mut lines: Vec<Spans> = overlay
+        .lines
+        .iter()
+        .skip(overlay.scroll)
+        .take(visible_rows)
+        .map(|line| Spans::from(line.as_str()))
+        .collect();
+    // The spelling suggestions scroll with their selection, so the first row is the
+    // selected one.
+    if let (OverlayKind::SpellSuggest, Some(selected)) = (overlay.kind, lines.first_mut()) {
+        for span in &mut selected.0 {
+            span.style = span.style.add_modifier(Modifier::REVERSED);
+        }
+    }
+    lines.resize(visible_rows, Spans::default());
+    lines.push(Spans::from(Span::styled(
+        "Press ENTER or type command to continue",
+        Style::default().fg(Color::Green),
+    )));
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(overlay.title.as_str());
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+/// `inccommand=split`: the lines a `:s` being typed changes, as they would
+/// read, in a window along the bottom of `area`.
+fn render_substitute_preview<B: Backend>(
+    f: &mut Frame<B>,
+    app: &CimEditor,
+    preview: &Rope,
+    area: Rect,
+) {
+    let mut lines: Vec<usize> = app.preview_matches.iter().map(|(line, _)| *line).collect();
+    lines.dedup();
+    let height = (lines.len() + 1).min(app.options.quickfix_height + 1) as u16;
+    let area = Rect {
+        y: area.y + area.height.saturating_sub(height),
+        height: height.min(area.height),
+        ..area
+    };
+    let rows: Vec<Spans> = lines
+        .iter()
+        .map(|&line| {
+            let text = preview
+                .slice(preview.offset_of_line(line)..preview.offset_of_line(line + 1))
+                .to_string();
+            Spans::from(vec![
+                Span::styled(format!("|{}| ", line + 1), Style::default().fg(Color::Yellow)),
+                Span::raw(text.trim_end_matches('\n').replace('\t', "    ")),
+            ])
+        })
+        .collect();
+    let block = Block::default()
+        .borders(Borders::TOP)
+        .title(Span::styled("[Preview]", Style::default().add_modifier(Modifier::BOLD)));
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(rows).block(block), area);
+}
+
+/// The candidates of command-line completion in a row above the command line,
+/// the selected one highlighted, with `<` and `>` where more are cut off.
+struct WildMenu<'a> {
+    completion: &'a CompletionState,
+}
+
+impl Widget for WildMenu<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let candidates = &self.completion.candidates;
+        let selected = self.completion.selected;
+        // Room for the candidates, two spaces apart, besides `< ` and ` >`.
+        let room = (area.width as usize).saturating_sub(4);
+        let width = |range: Range<usize>| -> usize {
+            candidates[range.clone()].iter().map(|c| c.chars().count()).sum::<usize>()
+                + 2 * range.len().saturating_sub(1)
+        };
+        let mut first = 0;
+        while first < selected && width(first..selected + 1) > room {
+            first += 1;
+        }
+        let mut end = selected + 1;
+        while end < candidates.len() && width(first..end + 1) <= room {
+            end += 1;
+        }
+
+        let bar = Style::default().fg(Color::White).bg(Color::DarkGray);
+        let mut spans = vec![Span::styled(if first > 0 { "< " } else { "  " }, bar)];
+        for (i, candidate) in candidates[first..end].iter().enumerate() {
+            if i > 0 {
+                spans.push(Span::styled("  ", bar));
+            }
+            let style = if first + i == selected {
+                Style::default().fg(Color::Black).bg(Color::LightBlue)
+            } else {
+                bar
+            };
+            spans.push(Span::styled(candidate.clone(), style));
+        }
+        if end < candidates.len() {
+            spans.push(Span::styled(" >", bar));
+        }
+        Paragraph::new(Spans::from(spans)).style(bar).render(area, buf);
+    }
+}
+
+/// The `:copen` window: one `file:line:col: message` row per quickfix entry, with
+/// the row under its cursor highlighted and kept in view.
+struct QuickfixWidget<'a> {
+    entries: &'a [QuickfixEntry],
+    cursor: usize,
+    focused: bool,
+}
+
+impl Widget for QuickfixWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let title_style = if self.focused {
+            Style::default().add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        let block = Block::default()
+            .borders(Borders::TOP)
+            .title(Span::styled("[Quickfix List]", title_style));
+        let rows = block.inner(area).height as usize;
+        let first = (self.cursor + 1).saturating_sub(rows);
+
+        let lines: Vec<Spans> = self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(rows)
+            .map(|(i, entry)| {
+                let mut spans = vec![
+                    Span::styled(
+                        entry.file.display().to_string(),
+                        Style::default().fg(Color::Cyan),
+                    ),
+                    Span::raw(":"),
+                    Span::styled(
+                        format!("{}:{}", entry.line, entry.column),
+                        Style::default().fg(Color::Yellow),
+                    ),
+                    Span::raw(": "),
+                    Span::styled(
+                        entry.message.trim().to_string(),
+                        Style::default().fg(Color::White),
+                    ),
+                ];
+                if i == self.cursor {
+                    for span in &mut spans {
+                        span.style = span.style.bg(Color::DarkGray);
+                    }
+                }
+                Spans::from(spans)
+            })
+            .collect();
+        Paragraph::new(lines).block(block).render(area, buf);
+    }
+}
+
+/// Draws a popup below the line it hangs from, or above it when there is no room.
+fn render_popup<B: Backend>(f: &mut Frame<B>, app: &CimEditor, popup: &PopupWindow, area: Rect) {
+    let width = popup.size.0.min(area.width);
+    let height = popup.size.1.min(area.height);
+    let row = screen_row(app, popup.position.row as usize) as u16;
+    let y = if row + 1 + height <= area.height {
+        row + 1
+    } else {
+        row.saturating_sub(height)
+    };
+    let x = ((popup.position.col as usize).saturating_sub(app.horizontal_offset) as u16)
+        .min(area.width - width);
+
+    let rect = Rect::new(area.x + x, area.y + y, width, height);
+    f.render_widget(Clear, rect);
+    let style = Style::default().fg(Color::White).bg(Color::DarkGray);
+    f.render_widget(Paragraph::new(popup.content.clone()).style(style), rect);
+}
+
+/// The sign column: the last sign placed on each visible line.
+fn render_signs(app: &CimEditor) -> Paragraph<'_> {
+    let lines: Vec<Spans> = app
+        .visible_lines()
+        .into_iter()
+        .map(|line_idx| match app.signs.get(&line_idx).and_then(|signs| signs.last()) {
+            Some(sign) => Spans::from(Span::styled(format!("{:2}", sign.text), sign.highlight)),
+            None => Spans::default(),
+        })
+        .collect();
+    Paragraph::new(lines)
+}
 
-    let line_numbers = (start_line..end_line)
-        .map(|line_idx| format!("{:4} ", line_idx + 1))
+/// The cursor's row in the text area, counting a closed fold as one row.
+fn cursor_row(app: &CimEditor) -> usize {
+    screen_row(app, app.cursor_position.row as usize)
+}
+
+/// The row in the text area showing `line`.
+fn screen_row(app: &CimEditor, line: usize) -> usize {
+    app.visible_lines()
+        .iter()
+        .position(|&line_idx| line_idx == line)
+        .unwrap_or_else(|| line.saturating_sub(app.scroll_offset))
+}
+
+fn render_line_numbers(app: &CimEditor) -> Paragraph<'_> {
+    let width = app.gutter_width() as usize - 1;
+    let line_numbers = app
+        .visible_lines()
+        .into_iter()
+        .map(|line_idx| match app.closed_fold_at(line_idx) {
+            Some(_) => format!("{:width$}╎", line_idx + 1),
+            None => format!("{:width$} ", line_idx + 1),
+        })
         .collect::<Vec<String>>()
         .join("\n");
 
@@ -97,51 +402,48 @@ fn render_line_numbers(app: &CimEditor) -> Paragraph {
         .alignment(tui::layout::Alignment::Right)
 }
 
-fn build_status_bar(app: &CimEditor) -> Paragraph {
-    let mode = match app.mode {
-        EditorMode::Insert => "INSERT",
-        EditorMode::Normal => "NORMAL",
-        EditorMode::Command => "COMMAND",
-    };
-
-    let filename = app
-        .file_path
-        .as_ref()
-        .and_then(|p| p.file_name())
-        .and_then(|n| n.to_str())
-        .unwrap_or("[No Name]");
-
-    let modified_indicator = if app.buffer.is_modified() { "[+]" } else { "" };
-
-    let total_lines = app.buffer.rope().measure::<xi_rope::LinesMetric>();
-    let position_info = format!(
-        "Ln {}/{}, Col {}",
-        app.cursor_position.1 + 1,
-        total_lines,
-        app.cursor_position.0 + 1
-    );
-
-    let status_text = format!(
-        " {}: {}{} | {} ",
-        mode, filename, modified_indicator, position_info
-    );
+fn build_status_bar(app: &CimEditor) -> Paragraph<'_> {
+    let mut spans = vec![Span::raw(" ")];
+    // Separators wait for the next segment that shows something, so an empty
+    // segment doesn't leave a dangling one.
+    let mut separators = Vec::new();
+    for segment in &app.config.status_segments {
+        match segment.content(app) {
+            Some(span) if segment.is_separator() => separators.push(span),
+            Some(span) => {
+                spans.append(&mut separators);
+                spans.push(span);
+            }
+            None => {}
+        }
+    }
+    spans.push(Span::raw(" "));
 
-    Paragraph::new(Spans::from(vec![Span::styled(
-        status_text,
-        Style::default()
-            .fg(Color::Black)
-            .bg(Color::LightBlue)
-            .add_modifier(Modifier::BOLD),
-    )]))
+    let background = if app.flash_time_left().is_some() {
+        Color::LightRed
+    } else {
+        Color::LightBlue
+    };
+    let style = Style::default()
+        .fg(Color::Black)
+        .bg(background)
+        .add_modifier(Modifier::BOLD);
+    for span in &mut spans {
+        span.style = style.patch(span.style);
+    }
+    Paragraph::new(Spans::from(spans))
 }
 
-fn build_highlighted_text(app: &CimEditor) -> Text {
+/// The buffer lines in view, highlighted and cut to the viewport.
+pub fn build_highlighted_text(app: &CimEditor) -> Text<'_> {
     let mut text = Text::default();
-    let rope = app.buffer.rope();
-    let start_line = app.scroll_offset;
-    let end_line = (start_line + app.viewport_height).min(rope.measure::<xi_rope::LinesMetric>());
+    let rope = app.preview_rope.as_ref().unwrap_or(app.buffer.rope());
+    let visible_lines = app.visible_lines();
 
-    if rope.len() == 0 {
+    if rope.is_empty() && app.file_path.is_none() && !app.buffer.is_modified() {
+        return build_welcome_text(app);
+    }
+    if rope.is_empty() {
         for _ in 0..app.viewport_height {
             text.lines.push(Spans::from(vec![Span::styled(
                 " ".repeat(app.viewport_width),
@@ -151,8 +453,8 @@ fn build_highlighted_text(app: &CimEditor) -> Text {
         return text;
     }
 
-    for line_num in start_line..end_line {
-        if line_num >= rope.measure::<xi_rope::LinesMetric>() {
+    for &line_num in &visible_lines {
+        if line_num >= app.total_lines() {
             text.lines.push(Spans::from(vec![Span::styled(
                 " ".repeat(app.viewport_width),
                 Style::default(),
@@ -160,28 +462,45 @@ fn build_highlighted_text(app: &CimEditor) -> Text {
             continue;
         }
 
-        let line_start = rope.offset_of_line(line_num);
-        let line_end = rope.offset_of_line(line_num + 1);
-        let line = rope.slice(line_start..line_end).to_string();
-        let line_length = line.trim_end_matches(&['\r', '\n'][..]).chars().count();
+        if let Some(fold) = app.closed_fold_at(line_num) {
+            text.lines.push(fold_placeholder(app, fold.start, fold.end));
+            continue;
+        }
+
+        let line = rope.iter_lines(line_num, line_num + 1).next().unwrap_or_default();
+
+        let matches = search_matches_on_line(app, line_num, &line);
+        let misspelled = spell_errors_on_line(app, line_num, &line);
 
         // Cap horizontal offset to avoid rendering issues
         let line_with_tabs_expanded = line.replace('\t', "    ");
         let effective_visual_offset = app.horizontal_offset.min(line_with_tabs_expanded.len());
-        if app.highlighted_lines.is_empty() || line_num >= app.highlighted_lines.len() {
+        // Syntax colours follow the real text, so a `:s` preview goes without.
+        if app.highlighted_lines.is_empty()
+            || line_num >= app.highlighted_lines.len()
+            || app.preview_rope.is_some()
+        {
             let visible_part: String = line_with_tabs_expanded
     .chars()
     .skip(effective_visual_offset)
     .take(app.viewport_width)
     .collect();
 
-            let visible_length = visible_part.chars().count();
             let visible_visual_width = visible_part.chars().count();
 let padding = " ".repeat(app.viewport_width.saturating_sub(visible_visual_width));
-            text.lines.push(Spans::from(vec![
-                Span::styled(visible_part, Style::default()),
-                Span::styled(padding, Style::default()),
-            ]));
+            let mut spans = Vec::new();
+            push_with_matches(
+                &mut spans,
+                visible_part,
+                effective_visual_offset,
+                Style::default(),
+                &matches,
+            );
+            spans.push(Span::styled(padding, Style::default()));
+            let spans = underline_misspelled(spans, effective_visual_offset, &misspelled);
+            let spans = show_list_chars(app, spans, effective_visual_offset, &line);
+            let spans = show_virtual_text(app, spans, effective_visual_offset, line_num, &line);
+            text.lines.push(with_diff_background(app, line_num, spans));
             continue;
         }
 
@@ -189,21 +508,17 @@ let padding = " ".repeat(app.viewport_width.saturating_sub(visible_visual_width)
         let mut current_column = 0;
         let mut visible_width = 0;
 
-        for (style, segment) in &app.highlighted_lines[line_num] {
-            let expanded_segment = segment.replace('\t', "    ");
-let segment_chars: Vec<char> = expanded_segment.chars().collect();
-            let segment_len = segment_chars.len();
+        for (style, custom, segment) in &app.highlighted_lines[line_num] {
+            // Measured without copying, so segments scrolled off to the left cost
+            // no more than counting their characters.
+            let segment_len: usize = segment.chars().map(|c| if c == '\t' { 4 } else { 1 }).sum();
 
             if current_column + segment_len <= effective_visual_offset {
                 current_column += segment_len;
                 continue;
             }
 
-            let offset_within_segment = if current_column < effective_visual_offset {
-                effective_visual_offset - current_column
-            } else {
-                0
-            };
+            let offset_within_segment = effective_visual_offset.saturating_sub(current_column);
 
             let chars_to_take = segment_len
                 .saturating_sub(offset_within_segment)
@@ -214,20 +529,27 @@ let segment_chars: Vec<char> = expanded_segment.chars().collect();
                 continue;
             }
 
-            let visible_text: String = segment_chars
-                .iter()
+            let visible_text: String = segment
+                .replace('\t', "    ")
+                .chars()
                 .skip(offset_within_segment)
                 .take(chars_to_take)
                 .collect();
 
-            spans.push(Span::styled(
+            let mut segment_style = Style::default()
+                .fg(Color::Rgb(style.foreground.r, style.foreground.g, style.foreground.b));
+            if let Some(custom) = custom {
+                segment_style = segment_style.patch(*custom);
+            }
+            segment_style.fg = segment_style.fg.map(|fg| nearest_color(fg, app.color_support));
+            segment_style.bg = segment_style.bg.map(|bg| nearest_color(bg, app.color_support));
+            push_with_matches(
+                &mut spans,
                 visible_text,
-                Style::default().fg(Color::Rgb(
-                    style.foreground.r,
-                    style.foreground.g,
-                    style.foreground.b,
-                )),
-            ));
+                current_column + offset_within_segment,
+                segment_style,
+                &matches,
+            );
 
             visible_width += chars_to_take;
             current_column += segment_len;
@@ -245,10 +567,13 @@ let segment_chars: Vec<char> = expanded_segment.chars().collect();
             ));
         }
 
-        text.lines.push(Spans::from(spans));
+        let spans = underline_misspelled(spans, effective_visual_offset, &misspelled);
+        let spans = show_list_chars(app, spans, effective_visual_offset, &line);
+        let spans = show_virtual_text(app, spans, effective_visual_offset, line_num, &line);
+        text.lines.push(with_diff_background(app, line_num, spans));
     }
 
-    let lines_added = (end_line - start_line) as usize;
+    let lines_added = visible_lines.len();
     for _ in lines_added..app.viewport_height {
         text.lines.push(Spans::from(vec![Span::styled(
             " ".repeat(app.viewport_width),
@@ -257,4 +582,385 @@ let segment_chars: Vec<char> = expanded_segment.chars().collect();
     }
 
     text
-}
\ No newline at end of file
+}
+
+/// What an empty, unnamed buffer shows at startup: the recent files, numbered as
+/// `:oldfiles` numbers them.
+fn build_welcome_text(app: &CimEditor) -> Text<'static> {
+    const SHOWN: usize = 9;
+    let mut lines = vec![
+        Spans::from(Span::styled("cim", Style::default().add_modifier(Modifier::BOLD))),
+        Spans::default(),
+    ];
+    if !app.oldfiles.is_empty() {
+        lines.push(Spans::from(Span::styled(
+            "Recent files",
+            Style::default().fg(Color::Cyan),
+        )));
+        for (i, path) in app.oldfiles.iter().take(SHOWN).enumerate() {
+            lines.push(Spans::from(vec![
+                Span::styled(format!("{:3}  ", i + 1), Style::default().fg(Color::Yellow)),
+                Span::raw(path.display().to_string()),
+            ]));
+        }
+        lines.push(Spans::default());
+        lines.push(Spans::from(Span::styled(
+            ":oldfiles to open one",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+
+    let top = app.viewport_height.saturating_sub(lines.len()) / 3;
+    let mut text = Text::from(vec![Spans::default(); top]);
+    text.lines.extend(lines);
+    text
+}
+
+/// Background for a line in a diff hunk, green, red or yellow as it was added,
+/// removed or changed.
+fn diff_background(app: &CimEditor, kind: DiffKind) -> Color {
+    let color = match kind {
+        DiffKind::Added => Color::Rgb(40, 80, 40),
+        DiffKind::Removed => Color::Rgb(90, 40, 40),
+        DiffKind::Changed => Color::Rgb(90, 80, 30),
+    };
+    nearest_color(color, app.color_support)
+}
+
+/// Sets the diff background on a rendered buffer line, keeping search match colours.
+fn with_diff_background(
+    app: &CimEditor,
+    line_num: usize,
+    mut spans: Vec<Span<'static>>,
+) -> Spans<'static> {
+    if let Some(kind) = app.diff_kind_at(line_num) {
+        for span in &mut spans {
+            if span.style.bg.is_none() {
+                span.style = span.style.bg(diff_background(app, kind));
+            }
+        }
+    }
+    Spans::from(spans)
+}
+
+/// The diff pane's file from the line matching the top of the buffer view.
+fn build_diff_pane_text(app: &CimEditor, pane: &DiffPane, width: usize) -> Text<'static> {
+    let rope = pane.buffer.rope();
+    let first_line = diff::new_line_for(&app.diff_hunks, app.scroll_offset);
+    let line_count = pane.buffer.line_count();
+
+    let lines = (first_line..line_count)
+        .take(app.viewport_height)
+        .map(|line_idx| {
+            let line = rope
+                .slice(rope.offset_of_line(line_idx)..rope.offset_of_line(line_idx + 1))
+                .to_string();
+            let visible: String = line
+                .trim_end_matches(&['\r', '\n'][..])
+                .replace('\t', "    ")
+                .chars()
+                .skip(app.horizontal_offset)
+                .take(width)
+                .collect();
+            let kind = app
+                .diff_hunks
+                .iter()
+                .find(|hunk| (hunk.new_start..hunk.new_start + hunk.new_len).contains(&line_idx))
+                .map(|hunk| hunk.kind);
+            match kind {
+                Some(kind) => {
+                    let padded = format!("{:width$}", visible, width = width);
+                    let style = Style::default().bg(diff_background(app, kind));
+                    Spans::from(Span::styled(padded, style))
+                }
+                None => Spans::from(visible),
+            }
+        })
+        .collect::<Vec<_>>();
+    Text::from(lines)
+}
+
+/// The line standing in for a closed fold, `+-- N lines: text ---` filled out with dashes.
+fn fold_placeholder(app: &CimEditor, start: usize, end: usize) -> Spans<'static> {
+    let summary = format!(
+        "+--{:3} lines: {} ",
+        end - start + 1,
+        app.line_text(start).trim().replace('\t', " ")
+    );
+    let summary: String = summary.chars().take(app.viewport_width).collect();
+    let fill = "-".repeat(app.viewport_width.saturating_sub(summary.chars().count()));
+    Spans::from(Span::styled(
+        summary + &fill,
+        Style::default().fg(Color::Cyan).bg(Color::DarkGray),
+    ))
+}
+
+/// Matches to highlight on `line_num`, as column ranges in the tab-expanded line
+/// with their background colour. A `:s///c` match awaiting confirmation comes first.
+fn search_matches_on_line(
+    app: &CimEditor,
+    line_num: usize,
+    line: &str,
+) -> Vec<(Range<usize>, Color)> {
+    let expanded_column = |column: usize| {
+        column + 3 * line.chars().take(column).filter(|&c| c == '\t').count()
+    };
+    let mut matches = Vec::new();
+
+    if app.preview_rope.is_some() {
+        let first = app.preview_matches.partition_point(|(l, _)| *l < line_num);
+        matches.extend(
+            app.preview_matches[first..]
+                .iter()
+                .take_while(|(l, _)| *l == line_num)
+                .map(|(_, columns)| {
+                    let range = expanded_column(columns.start)..expanded_column(columns.end);
+                    (range, Color::LightRed)
+                }),
+        );
+        return matches;
+    }
+
+    if let Some((_, range)) = app
+        .subst_pending
+        .as_ref()
+        .and_then(|state| state.current.as_ref())
+        .filter(|(l, _)| *l == line_num)
+    {
+        let start = line[..range.start].chars().count();
+        let end = line[..range.end].chars().count();
+        matches.push((expanded_column(start)..expanded_column(end), Color::LightRed));
+    }
+
+    let first = app.search_matches.partition_point(|(l, _)| *l < line_num);
+    matches.extend(
+        app.search_matches[first..]
+            .iter()
+            .take_while(|(l, _)| *l == line_num)
+            .map(|(_, columns)| {
+                let range = expanded_column(columns.start)..expanded_column(columns.end);
+                (range, Color::Yellow)
+            }),
+    );
+    matches
+}
+
+/// Pushes `text`, which starts at `column`, splitting out runs that fall inside `matches`.
+/// Misspelled words on `line_num`, as columns after tab expansion.
+fn spell_errors_on_line(app: &CimEditor, line_num: usize, line: &str) -> Vec<Range<usize>> {
+    let expanded_column = |column: usize| {
+        column + 3 * line.chars().take(column).filter(|&c| c == '\t').count()
+    };
+    let first = app.spell_errors.partition_point(|(l, _)| *l < line_num);
+    app.spell_errors[first..]
+        .iter()
+        .take_while(|(l, _)| *l == line_num)
+        .map(|(_, columns)| expanded_column(columns.start)..expanded_column(columns.end))
+        .collect()
+}
+
+/// Underlines the characters of a rendered line, which starts at `column`, that
+/// fall in `ranges`.
+fn underline_misspelled(
+    spans: Vec<Span<'static>>,
+    mut column: usize,
+    ranges: &[Range<usize>],
+) -> Vec<Span<'static>> {
+    if ranges.is_empty() {
+        return spans;
+    }
+    let mut underlined = Vec::new();
+    for span in spans {
+        let mut run = String::new();
+        let mut run_misspelled = false;
+        for c in span.content.chars() {
+            let misspelled = ranges.iter().any(|range| range.contains(&column));
+            if misspelled != run_misspelled && !run.is_empty() {
+                let text = std::mem::take(&mut run);
+                underlined.push(misspelled_span(text, span.style, run_misspelled));
+            }
+            run_misspelled = misspelled;
+            run.push(c);
+            column += 1;
+        }
+        if !run.is_empty() {
+            underlined.push(misspelled_span(run, span.style, run_misspelled));
+        }
+    }
+    underlined
+}
+
+/// With `list` set, draws the whitespace of `line` in `spans`, which start at
+/// `column`, as the `listchars` symbols, and marks the edges the line runs past.
+fn show_list_chars(
+    app: &CimEditor,
+    spans: Vec<Span<'static>>,
+    mut column: usize,
+    line: &str,
+) -> Vec<Span<'static>> {
+    if !app.options.list {
+        return spans;
+    }
+    let listchars = &app.options.listchars;
+    let body = line.strip_suffix('\n').unwrap_or(line);
+    let trail_start = body.trim_end_matches([' ', '\t']).chars().count();
+    let mut symbols: Vec<Option<char>> = Vec::new();
+    for (i, c) in body.chars().enumerate() {
+        let trail = listchars.trail.filter(|_| i >= trail_start);
+        match c {
+            '\t' => {
+                let (first, rest) = match (trail, listchars.tab) {
+                    (Some(trail), _) => (Some(trail), Some(trail)),
+                    (None, Some((first, rest))) => (Some(first), Some(rest)),
+                    (None, None) => (None, None),
+                };
+                symbols.push(first);
+                symbols.extend([rest; 3]);
+            }
+            ' ' => symbols.push(trail.or(listchars.space)),
+            _ => symbols.push(None),
+        }
+    }
+    let width = symbols.len();
+    symbols.push(listchars.eol);
+    if column > 0 && width > column {
+        symbols[column] = listchars.precedes.or(symbols[column]);
+    }
+    let last = column + app.viewport_width.saturating_sub(1);
+    if width > last + 1 {
+        symbols[last] = listchars.extends.or(symbols[last]);
+    }
+
+    let symbol_style = Style::default().fg(nearest_color(Color::DarkGray, app.color_support));
+    let mut shown = Vec::new();
+    for span in spans {
+        let mut run = String::new();
+        for c in span.content.chars() {
+            match symbols.get(column).copied().flatten() {
+                Some(symbol) => {
+                    if !run.is_empty() {
+                        shown.push(Span::styled(std::mem::take(&mut run), span.style));
+                    }
+                    shown.push(Span::styled(symbol.to_string(), span.style.patch(symbol_style)));
+                }
+                None => run.push(c),
+            }
+            column += 1;
+        }
+        if !run.is_empty() {
+            shown.push(Span::styled(run, span.style));
+        }
+    }
+    shown
+}
+
+/// Draws the virtual text of line `line_num` into `spans`, which start at
+/// `column`: inline text before its character and the rest one column after the
+/// end of the line. The line keeps its width, so anything pushed past the right
+/// edge is cut off.
+fn show_virtual_text(
+    app: &CimEditor,
+    spans: Vec<Span<'static>>,
+    mut column: usize,
+    line_num: usize,
+    line: &str,
+) -> Vec<Span<'static>> {
+    let Some(virtual_texts) = app.virtual_texts.get(&line_num) else {
+        return spans;
+    };
+    let body = line.trim_end_matches(['\r', '\n']);
+    let width_of = |chars: usize| {
+        body.chars().take(chars).map(|c| if c == '\t' { 4 } else { 1 }).sum::<usize>()
+    };
+    // The line ending takes a column in `spans`, though nothing is drawn for it.
+    let line_ending = line.chars().count() - body.chars().count();
+    let end = width_of(usize::MAX) + line_ending + 1;
+    let mut inserts: Vec<(usize, &VirtualText)> = virtual_texts
+        .iter()
+        .map(|virtual_text| (virtual_text.col.map_or(end, width_of), virtual_text))
+        .filter(|&(at, _)| at >= column)
+        .collect();
+    inserts.sort_by_key(|&(at, _)| at);
+    let mut inserts = inserts.into_iter().peekable();
+    let virtual_span = |virtual_text: &VirtualText| {
+        let mut style = virtual_text.style;
+        style.fg = style.fg.map(|fg| nearest_color(fg, app.color_support));
+        style.bg = style.bg.map(|bg| nearest_color(bg, app.color_support));
+        Span::styled(virtual_text.content.clone(), style)
+    };
+
+    let mut shown = Vec::new();
+    for span in spans {
+        let mut run = String::new();
+        for c in span.content.chars() {
+            while let Some((_, virtual_text)) = inserts.next_if(|&(at, _)| at <= column) {
+                if !run.is_empty() {
+                    shown.push(Span::styled(std::mem::take(&mut run), span.style));
+                }
+                shown.push(virtual_span(virtual_text));
+            }
+            run.push(c);
+            column += 1;
+        }
+        if !run.is_empty() {
+            shown.push(Span::styled(run, span.style));
+        }
+    }
+
+    let mut room = app.viewport_width;
+    shown
+        .into_iter()
+        .map_while(|mut span| {
+            let len = span.content.chars().count();
+            if room == 0 {
+                return None;
+            }
+            if len > room {
+                span.content = span.content.chars().take(room).collect::<String>().into();
+            }
+            room -= len.min(room);
+            Some(span)
+        })
+        .collect()
+}
+
+fn misspelled_span(text: String, style: Style, misspelled: bool) -> Span<'static> {
+    if misspelled {
+        Span::styled(text, style.add_modifier(Modifier::UNDERLINED))
+    } else {
+        Span::styled(text, style)
+    }
+}
+
+fn push_with_matches(
+    spans: &mut Vec<Span<'static>>,
+    text: String,
+    column: usize,
+    style: Style,
+    matches: &[(Range<usize>, Color)],
+) {
+    if matches.is_empty() {
+        spans.push(Span::styled(text, style));
+        return;
+    }
+
+    let style_at = |i: usize| {
+        matches
+            .iter()
+            .find(|(range, _)| range.contains(&(column + i)))
+            .map_or(style, |(_, color)| style.fg(Color::Black).bg(*color))
+    };
+    let mut run = String::new();
+    let mut run_style = style_at(0);
+    for (i, c) in text.chars().enumerate() {
+        let char_style = style_at(i);
+        if char_style != run_style && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
+        }
+        run_style = char_style;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, run_style));
+    }
+}
@@ -0,0 +1,154 @@
+use cim::buffer::RopeTextBuffer;
+use cim::editor::{CimEditor, EditorMode};
+use cim::types::CursorPos;
+use cim::event_loop::{run_editor, MockInputSource, MockTerminalControl};
+use crossterm::cursor::SetCursorStyle;
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+use std::io;
+use tui::{backend::TestBackend, Terminal};
+use xi_rope::Rope;
+
+/// Key presses for `keys`, with `\x1b` for Esc and `\r` for Enter.
+fn key_events(keys: &str) -> Vec<Event> {
+    keys.chars()
+        .map(|c| {
+            let code = match c {
+                '\x1b' => KeyCode::Esc,
+                '\r' => KeyCode::Enter,
+                c => KeyCode::Char(c),
+            };
+            Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+        })
+        .collect()
+}
+
+/// Runs `editor` in an 80 by 24 test terminal until `events` make it exit, or
+/// run out.
+fn session(editor: &mut CimEditor, events: Vec<Event>) -> io::Result<()> {
+    session_with_control(editor, events, &mut MockTerminalControl::default())
+}
+
+/// `session`, recording cursor style changes and suspends in `control`.
+fn session_with_control(
+    editor: &mut CimEditor,
+    events: Vec<Event>,
+    control: &mut MockTerminalControl,
+) -> io::Result<()> {
+    let mut terminal = Terminal::new(TestBackend::new(80, 24))?;
+    editor.resize(80, 24);
+    run_editor(&mut terminal, editor, &mut MockInputSource(events.into()), control)
+}
+
+fn editor(text: &str) -> CimEditor {
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from(text));
+    editor
+}
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|n| format!("line {}\n", n)).collect()
+}
+
+#[test]
+fn typed_text_ends_up_in_buffer() {
+    let mut editor = editor("");
+    session(&mut editor, key_events("ihello world\x1b:q!\r")).unwrap();
+    assert_eq!(editor.buffer.text(), "hello world");
+    assert_eq!(editor.mode, EditorMode::Normal);
+}
+
+#[test]
+fn normal_mode_edits() {
+    let mut editor = editor("one two three\nfour\nfive\n");
+    session(&mut editor, key_events("jddd$:q!\r")).unwrap();
+    assert_eq!(editor.buffer.text(), "one two three\n\n");
+}
+
+#[test]
+fn counts_repeat_motions() {
+    let mut editor = editor("a\nb\nc\nd\ne\n");
+    session(&mut editor, key_events("3jdd:q!\r")).unwrap();
+    assert_eq!(editor.buffer.text(), "a\nb\nc\ne\n");
+}
+
+#[test]
+fn counts_with_zero() {
+    let mut editor = editor(&numbered_lines(30));
+    // A 0 after a digit is part of the count; on its own it goes to column 0.
+    session(&mut editor, key_events("$10")).unwrap_err();
+    assert_eq!(editor.cursor_position, CursorPos::new(5, 0));
+    session(&mut editor, key_events("j")).unwrap_err();
+    assert_eq!(editor.cursor_position, CursorPos::new(5, 10));
+    session(&mut editor, key_events("0")).unwrap_err();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 10));
+}
+
+#[test]
+fn two_digit_counts() {
+    let mut editor = editor(&numbered_lines(30));
+    session(&mut editor, key_events("10j")).unwrap_err();
+    assert_eq!(editor.cursor_position.row, 10);
+    session(&mut editor, key_events("10jdd:q!\r")).unwrap();
+    assert_eq!(editor.buffer.text(), numbered_lines(30).replace("line 21\n", ""));
+}
+
+#[test]
+fn counts_with_goto_line() {
+    let mut editor = editor(&numbered_lines(30));
+    session(&mut editor, key_events("10G")).unwrap_err();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 9));
+    // 0 is the line-start motion here, so G is uncounted and goes to the end.
+    session(&mut editor, key_events("0G")).unwrap_err();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 29));
+    session(&mut editor, key_events("100G")).unwrap_err();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 29));
+}
+
+#[test]
+fn quit_refuses_modified_buffer() {
+    let mut editor = editor("text\n");
+    let result = session(&mut editor, key_events("dd:q\r"));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    assert!(editor.buffer.is_modified());
+}
+
+#[test]
+fn write_and_quit_saves_file() {
+    let path = std::env::temp_dir().join(format!("cim-session-{}.txt", std::process::id()));
+    let mut editor = editor("first\nsecond\n");
+    editor.file_path = Some(path.clone());
+    session(&mut editor, key_events("jccchanged\x1b:wq\r")).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "first\nchanged\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn enter_splits_line_in_insert_mode() {
+    let mut editor = editor("headtail\n");
+    session(&mut editor, key_events("$hhhi\rmid\r\x1b:q!\r")).unwrap();
+    assert_eq!(editor.buffer.text(), "head\nmid\ntail\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 2));
+}
+
+
+#[test]
+fn suspend_goes_through_terminal_control() {
+    let mut editor = editor("text\n");
+    let mut events = vec![Event::Key(KeyEvent::new(KeyCode::Char('z'), KeyModifiers::CONTROL))];
+    events.extend(key_events(":q\r"));
+    let mut control = MockTerminalControl::default();
+    session_with_control(&mut editor, events, &mut control).unwrap();
+    assert_eq!(control.suspends, 1);
+}
+
+#[test]
+fn replace_mode_cursor_style_goes_through_terminal_control() {
+    let mut editor = editor("text\n");
+    let mut control = MockTerminalControl::default();
+    session_with_control(&mut editor, key_events("Rab\x1b:q!\r"), &mut control).unwrap();
+    assert_eq!(editor.buffer.text(), "abxt\n");
+    assert_eq!(
+        control.cursor_styles,
+        [SetCursorStyle::SteadyUnderScore, SetCursorStyle::DefaultUserShape]
+    );
+}
@@ -0,0 +1,107 @@
+use cim::ex::{ExCommand, ExCommandKind, ExRange};
+
+fn offset(base: ExRange, n: isize) -> ExRange {
+    ExRange::Offset(Box::new(base), n)
+}
+
+fn pair(start: ExRange, end: ExRange) -> ExRange {
+    ExRange::Pair(Box::new(start), Box::new(end))
+}
+
+#[test]
+fn range_whole_file() {
+    assert_eq!(ExRange::parse("%s/a/b/"), (ExRange::All, "s/a/b/"));
+}
+
+#[test]
+fn range_current_line_to_relative_offset() {
+    assert_eq!(
+        ExRange::parse(".,+3d"),
+        (pair(ExRange::CurrentLine, offset(ExRange::CurrentLine, 3)), "d")
+    );
+}
+
+#[test]
+fn range_between_marks() {
+    assert_eq!(
+        ExRange::parse("'a,'bd"),
+        (pair(ExRange::Mark('a'), ExRange::Mark('b')), "d")
+    );
+    assert_eq!(ExRange::parse("'<,'>w"), (ExRange::VisualMarks, "w"));
+}
+
+#[test]
+fn range_last_line_minus_one() {
+    assert_eq!(ExRange::parse("$-1"), (offset(ExRange::LastLine, -1), ""));
+    assert_eq!(ExRange::parse(".+-"), (offset(ExRange::CurrentLine, 0), ""));
+}
+
+#[test]
+fn range_line_zero() {
+    assert_eq!(ExRange::parse("0"), (ExRange::LineNumber(0), ""));
+    assert_eq!(ExRange::parse("0r file"), (ExRange::LineNumber(0), "r file"));
+}
+
+#[test]
+fn malformed_range_is_left_in_input() {
+    assert_eq!(ExRange::parse("'"), (ExRange::None, "'"));
+    let overflowing = "+99999999999999999999d";
+    assert_eq!(ExRange::parse(overflowing), (ExRange::None, overflowing));
+    assert_eq!(ExRange::parse("3,d"), (ExRange::LineNumber(3), ",d"));
+    assert_eq!(ExRange::parse("w"), (ExRange::None, "w"));
+}
+
+#[test]
+fn command_with_range_and_bang() {
+    let command = ExCommand::parse("%sort! u");
+    assert_eq!(command.range, ExRange::All);
+    assert_eq!(command.command, ExCommandKind::Sort { reverse: true });
+    assert_eq!(command.args, vec!["u"]);
+}
+
+#[test]
+fn bare_range_is_goto() {
+    let command = ExCommand::parse("$-1");
+    assert_eq!(command.range, offset(ExRange::LastLine, -1));
+    assert_eq!(command.command, ExCommandKind::Goto);
+}
+
+#[test]
+fn unknown_command_keeps_its_text() {
+    let command = ExCommand::parse("1,2frobnicate x");
+    assert_eq!(command.command, ExCommandKind::Unknown("frobnicate x".to_string()));
+}
+
+#[test]
+fn file_arguments_keep_their_spaces() {
+    for (input, arg) in [
+        ("w /tmp/a  b.txt", "/tmp/a  b.txt"),
+        ("w! /tmp/a  b.txt", "/tmp/a  b.txt"),
+        ("w >> log  file.txt", ">> log  file.txt"),
+        ("e a  b.txt", "a  b.txt"),
+        ("r a  b.txt", "a  b.txt"),
+        ("so a  b.vim", "a  b.vim"),
+        ("make -C  dir", "-C  dir"),
+    ] {
+        assert_eq!(ExCommand::parse(input).args, vec![arg], "{}", input);
+    }
+    assert!(ExCommand::parse("w").args.is_empty());
+}
+
+#[test]
+fn other_arguments_split_on_whitespace() {
+    let command = ExCommand::parse("sign place  1 line=3");
+    assert_eq!(command.command, ExCommandKind::Sign);
+    assert_eq!(command.args, vec!["place", "1", "line=3"]);
+}
+
+#[test]
+fn read_bang_is_a_command_with_or_without_space() {
+    for input in ["r!echo hi", "r !echo hi", "read!echo hi", "r  !echo hi"] {
+        let command = ExCommand::parse(input);
+        assert_eq!(command.command, ExCommandKind::Read, "{}", input);
+        assert_eq!(command.args, vec!["!echo hi"], "{}", input);
+    }
+    assert_eq!(ExCommand::parse("r! echo hi").args, vec!["! echo hi"]);
+    assert_eq!(ExCommand::parse("r file.txt").args, vec!["file.txt"]);
+}
@@ -0,0 +1,764 @@
+use cim::buffer::{byte_to_char_col, char_to_byte_offset, RopeLineIter, RopeTextBuffer};
+use cim::editor::{CimEditor, EditorAction, EditorMode};
+use cim::highlight::Highlighter;
+use cim::types::CursorPos;
+use cim::ui::build_highlighted_text;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::path::{Path, PathBuf};
+use syntect::highlighting::Style;
+use tui::text::Text;
+use xi_rope::Rope;
+
+/// An editor on `text` with no file and a 20 by 80 viewport, as if drawn.
+fn editor(text: &str) -> CimEditor {
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from(text));
+    editor.viewport_height = 20;
+    editor.viewport_width = 80;
+    editor
+}
+
+fn numbered_lines(count: usize) -> String {
+    (1..=count).map(|n| format!("line {}\n", n)).collect()
+}
+
+/// Row `row` of rendered text, as plain text.
+fn rendered_row(text: &Text, row: usize) -> String {
+    text.lines[row].0.iter().map(|span| span.content.as_ref()).collect()
+}
+
+/// A path in the temporary directory, unique to this test run.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cim-test-{}-{}", std::process::id(), name))
+}
+
+#[test]
+fn insert_char_inserts_at_cursor() {
+    let mut editor = editor("hello\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.insert_char('X');
+    assert_eq!(editor.buffer.text(), "heXllo\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+    assert!(editor.buffer.is_modified());
+}
+
+#[test]
+fn insert_char_at_end_of_line_stays_on_line() {
+    let mut editor = editor("ab\ncd\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.insert_char('!');
+    assert_eq!(editor.buffer.text(), "ab!\ncd\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+}
+
+#[test]
+fn insert_newline_splits_line() {
+    let mut editor = editor("abcd\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.insert_char('\n');
+    assert_eq!(editor.buffer.text(), "ab\ncd\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 1));
+}
+
+#[test]
+fn insert_multibyte_char() {
+    let mut editor = editor("aé\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.insert_char('ü');
+    assert_eq!(editor.buffer.text(), "aéü\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+}
+
+#[test]
+fn delete_char_removes_character_before_cursor() {
+    let mut editor = editor("abc\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "ac\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(1, 0));
+}
+
+#[test]
+fn delete_char_at_start_of_line_merges_lines() {
+    let mut editor = editor("ab\ncd\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(0, 1);
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "abcd\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 0));
+}
+
+#[test]
+fn delete_char_at_start_of_buffer_does_nothing() {
+    let mut editor = editor("ab\n");
+    editor.mode = EditorMode::Insert;
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "ab\n");
+    assert!(!editor.buffer.is_modified());
+}
+
+#[test]
+fn delete_char_removes_whole_grapheme() {
+    let mut editor = editor("a🇩🇪\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(3, 0);
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "a\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(1, 0));
+}
+
+#[test]
+fn delete_char_removes_zwj_sequence_in_one_go() {
+    // Man, woman and girl joined by two zero-width joiners: five characters.
+    let mut editor = editor("x\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}y\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(6, 0);
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "xy\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(1, 0));
+}
+
+#[test]
+fn delete_char_removes_one_flag_of_two() {
+    let mut editor = editor("\u{1f1e9}\u{1f1ea}\u{1f1eb}\u{1f1f7}\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(4, 0);
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "\u{1f1e9}\u{1f1ea}\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 0));
+}
+
+#[test]
+fn delete_char_removes_letter_with_combining_accent() {
+    let mut editor = editor("cafe\u{301}\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(5, 0);
+    editor.delete_char();
+    assert_eq!(editor.buffer.text(), "caf\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+}
+
+#[test]
+fn backspace_after_typed_zwj_sequence_removes_it() {
+    let mut editor = editor("\n");
+    editor.mode = EditorMode::Insert;
+    for c in "\u{1f469}\u{200d}\u{1f4bb}".chars() {
+        editor.insert_char(c);
+    }
+    editor.handle_input(KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+    assert_eq!(editor.buffer.text(), "\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+}
+
+#[test]
+fn backspace_key_and_replayed_action_delete_the_same_way() {
+    let mut editor = editor("abc\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    let backspace = KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE);
+    let action = editor.handle_input(backspace).unwrap();
+    assert!(matches!(action, EditorAction::DeleteBack));
+    assert_eq!(editor.buffer.text(), "ac\n");
+    editor.handle_action(action);
+    assert_eq!(editor.buffer.text(), "c\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+}
+
+#[test]
+fn move_cursor_clamps_to_line_length() {
+    let mut editor = editor("abcdef\nxy\n");
+    editor.cursor_position = CursorPos::new(5, 0);
+    editor.move_cursor((0, 1));
+    assert_eq!(editor.cursor_position, CursorPos::new(1, 1));
+    editor.move_cursor((10, 0));
+    assert_eq!(editor.cursor_position, CursorPos::new(1, 1));
+}
+
+#[test]
+fn move_cursor_reaches_last_character_of_unterminated_line() {
+    let mut editor = editor("ab\nxyz");
+    editor.cursor_position = CursorPos::new(0, 1);
+    editor.move_cursor((10, 0));
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 1));
+    editor.go_to_line_end();
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 1));
+}
+
+#[test]
+fn cursor_stops_before_crlf() {
+    let mut editor = editor("abc\r\nd\r\n");
+    editor.move_cursor((10, 0));
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 0));
+    editor.mode = EditorMode::Insert;
+    editor.go_to_line_end();
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+}
+
+#[test]
+fn insert_mode_cursor_may_sit_after_last_character() {
+    let mut editor = editor("abc\n\nxy\n");
+    editor.mode = EditorMode::Insert;
+    editor.move_cursor((10, 0));
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+    editor.move_cursor((0, 1));
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 1));
+
+    editor.mode = EditorMode::Normal;
+    editor.cursor_position = CursorPos::new(3, 0);
+    editor.normalize_cursor();
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 0));
+    editor.cursor_position = CursorPos::new(5, 1);
+    editor.normalize_cursor();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 1));
+}
+
+#[test]
+fn move_cursor_clamps_to_last_line() {
+    let mut editor = editor("a\nb\nc");
+    editor.move_cursor((0, 10));
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 2));
+    editor.move_cursor((0, -10));
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+}
+
+#[test]
+fn move_cursor_word_forward() {
+    let mut editor = editor("one two three\n");
+    editor.move_cursor_word(1);
+    assert_eq!(editor.cursor_position, CursorPos::new(4, 0));
+    editor.move_cursor_word(1);
+    assert_eq!(editor.cursor_position, CursorPos::new(8, 0));
+}
+
+#[test]
+fn move_cursor_word_backward() {
+    let mut editor = editor("one two three\n");
+    editor.cursor_position = CursorPos::new(10, 0);
+    editor.move_cursor_word(-1);
+    assert_eq!(editor.cursor_position, CursorPos::new(8, 0));
+    editor.move_cursor_word(-1);
+    assert_eq!(editor.cursor_position, CursorPos::new(4, 0));
+}
+
+#[test]
+fn page_down_scrolls_a_page() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.page_down();
+    assert_eq!(editor.scroll_offset, 20);
+    assert_eq!(editor.cursor_position.row, 22);
+}
+
+#[test]
+fn page_down_stops_at_end_of_file() {
+    let mut editor = editor(&numbered_lines(30));
+    editor.page_down();
+    assert_eq!(editor.scroll_offset, 10);
+    editor.page_down();
+    assert_eq!(editor.scroll_offset, 10);
+    assert_eq!(editor.cursor_position.row, 29);
+}
+
+#[test]
+fn page_up_returns_to_top() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.page_down();
+    editor.page_down();
+    editor.page_up();
+    assert_eq!(editor.scroll_offset, 20);
+    editor.page_up();
+    assert_eq!(editor.scroll_offset, 0);
+    editor.page_up();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+}
+
+#[test]
+fn page_up_at_top_moves_cursor_to_first_line() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.cursor_position = CursorPos::new(3, 7);
+    editor.page_up();
+    assert_eq!(editor.scroll_offset, 0);
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+}
+
+#[test]
+fn page_up_keeps_old_top_line_clear_of_bottom_margin() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.scroll_offset = 50;
+    editor.cursor_position = CursorPos::new(3, 55);
+    editor.page_up();
+    assert_eq!(editor.scroll_offset, 30);
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 47));
+}
+
+#[test]
+fn page_down_at_end_moves_cursor_to_last_line() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.scroll_offset = 80;
+    editor.cursor_position = CursorPos::new(3, 85);
+    editor.page_down();
+    assert_eq!(editor.scroll_offset, 80);
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 99));
+}
+
+#[test]
+fn page_down_near_end_stops_at_last_page() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.scroll_offset = 75;
+    editor.cursor_position = CursorPos::new(3, 85);
+    editor.page_down();
+    assert_eq!(editor.scroll_offset, 80);
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 94));
+}
+
+#[test]
+fn page_down_in_file_shorter_than_viewport() {
+    let mut editor = editor(&numbered_lines(5));
+    editor.cursor_position = CursorPos::new(3, 1);
+    editor.page_down();
+    assert_eq!(editor.scroll_offset, 0);
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 4));
+}
+
+#[test]
+fn go_to_line_start_and_end() {
+    let mut editor = editor("  hello\n");
+    editor.cursor_position = CursorPos::new(4, 0);
+    editor.go_to_line_end();
+    assert_eq!(editor.cursor_position, CursorPos::new(6, 0));
+    editor.go_to_line_start();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+}
+
+#[test]
+fn go_to_line_end_in_insert_mode_goes_past_last_character() {
+    let mut editor = editor("hello\n");
+    editor.mode = EditorMode::Insert;
+    editor.go_to_line_end();
+    assert_eq!(editor.cursor_position, CursorPos::new(5, 0));
+}
+
+#[test]
+fn normalize_cursor_in_normal_mode_stops_on_last_character() {
+    let mut editor = editor("abc\n");
+    editor.cursor_position = CursorPos::new(10, 0);
+    editor.normalize_cursor();
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 0));
+}
+
+#[test]
+fn normalize_cursor_in_insert_mode_allows_end_of_line() {
+    let mut editor = editor("abc\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(10, 0);
+    editor.normalize_cursor();
+    assert_eq!(editor.cursor_position, CursorPos::new(3, 0));
+}
+
+#[test]
+fn normalize_cursor_clamps_line() {
+    let mut editor = editor("abc\ndef\n");
+    editor.cursor_position = CursorPos::new(1, 9);
+    editor.normalize_cursor();
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 1));
+}
+
+#[test]
+fn save_writes_file() {
+    let path = temp_path("save.txt");
+    let mut editor = editor("saved text\n");
+    editor.file_path = Some(path.clone());
+    editor.mode = EditorMode::Insert;
+    editor.insert_char('!');
+    editor.save().unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "!saved text\n");
+    assert!(!editor.buffer.is_modified());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn save_without_file_name_fails() {
+    let mut editor = editor("text\n");
+    assert!(editor.save().is_err());
+}
+
+#[test]
+fn line_numbers_follow_deleted_lines() {
+    let mut editor = editor(&numbered_lines(12));
+    assert_eq!(editor.gutter_width(), 3);
+    editor.execute_command("4,12d");
+    assert_eq!(editor.total_lines(), 3);
+    assert_eq!(editor.gutter_width(), 2);
+}
+
+#[test]
+fn update_viewport_clamps_scroll_offset() {
+    let mut editor = editor(&numbered_lines(30));
+    editor.cursor_position = CursorPos::new(0, 29);
+    editor.update_viewport();
+    assert_eq!(editor.scroll_offset, 10);
+    editor.scroll_offset = 25;
+    editor.update_viewport();
+    assert_eq!(editor.scroll_offset, 10);
+}
+
+#[test]
+fn update_viewport_follows_cursor_up() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.scroll_offset = 50;
+    editor.cursor_position = CursorPos::new(0, 10);
+    editor.update_viewport();
+    assert_eq!(editor.scroll_offset, 8);
+}
+
+#[test]
+fn deleting_every_line_scrolls_back_to_top() {
+    let mut editor = editor(&numbered_lines(5));
+    editor.viewport_height = 4;
+    editor.cursor_position = CursorPos::new(0, 4);
+    editor.update_viewport();
+    assert_eq!(editor.scroll_offset, 1);
+    editor.execute_command("%d");
+    assert_eq!(editor.scroll_offset, 0);
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+}
+
+#[test]
+fn deleting_lines_at_end_pulls_view_back() {
+    let mut editor = editor(&numbered_lines(30));
+    editor.viewport_height = 10;
+    editor.cursor_position = CursorPos::new(0, 29);
+    editor.update_viewport();
+    assert_eq!(editor.scroll_offset, 20);
+    editor.execute_command("25,30d");
+    assert!(editor.scroll_offset <= editor.total_lines() - editor.viewport_height);
+    assert_eq!(editor.cursor_position.row, 23);
+}
+
+#[test]
+fn iter_lines_yields_one_item_per_line() {
+    let rope = Rope::from("one\ntwo\r\nthree\n");
+    let lines: Vec<_> = rope.iter_lines(0, 4).collect();
+    assert_eq!(lines, ["one\n", "two\r\n", "three\n", ""]);
+    let lines: Vec<_> = rope.iter_lines(1, 2).collect();
+    assert_eq!(lines, ["two\r\n"]);
+}
+
+#[test]
+fn char_to_byte_offset_on_empty_lines() {
+    let rope = Rope::from("");
+    assert_eq!(char_to_byte_offset(&rope, 0, 0), 0);
+    assert_eq!(char_to_byte_offset(&rope, 0, 3), 0);
+    assert_eq!(byte_to_char_col(&rope, 0, 0), 0);
+
+    let rope = Rope::from("\nx\n");
+    assert_eq!(char_to_byte_offset(&rope, 0, 0), 0);
+    assert_eq!(char_to_byte_offset(&rope, 0, 1), 0);
+    assert_eq!(char_to_byte_offset(&rope, 2, 0), 3);
+    assert_eq!(byte_to_char_col(&rope, 1, 1), 0);
+}
+
+#[test]
+fn char_to_byte_offset_with_multi_byte_chars() {
+    let rope = Rope::from("h\u{e9}llo\na\u{1f600}b\n");
+    assert_eq!(char_to_byte_offset(&rope, 0, 1), 1);
+    assert_eq!(char_to_byte_offset(&rope, 0, 2), 3);
+    assert_eq!(char_to_byte_offset(&rope, 1, 2), 12);
+    assert_eq!(byte_to_char_col(&rope, 0, 3), 2);
+    assert_eq!(byte_to_char_col(&rope, 1, 12), 2);
+    assert_eq!(byte_to_char_col(&rope, 1, 13), 3);
+}
+
+#[test]
+fn char_to_byte_offset_at_and_past_line_end() {
+    let rope = Rope::from("ab\ncd");
+    assert_eq!(char_to_byte_offset(&rope, 0, 1), 1);
+    assert_eq!(char_to_byte_offset(&rope, 0, 2), 2);
+    assert_eq!(char_to_byte_offset(&rope, 0, 3), 2);
+    assert_eq!(char_to_byte_offset(&rope, 1, 2), 5);
+    assert_eq!(char_to_byte_offset(&rope, 1, 40), 5);
+    assert_eq!(byte_to_char_col(&rope, 0, 2), 2);
+    assert_eq!(byte_to_char_col(&rope, 1, 5), 2);
+    // An offset before the line's start counts as its first column.
+    assert_eq!(byte_to_char_col(&rope, 1, 0), 0);
+}
+
+#[test]
+fn char_to_byte_offset_stops_before_crlf() {
+    let rope = Rope::from("ab\r\ncd\r\n");
+    assert_eq!(char_to_byte_offset(&rope, 0, 2), 2);
+    assert_eq!(char_to_byte_offset(&rope, 0, 5), 2);
+    assert_eq!(char_to_byte_offset(&rope, 1, 0), 4);
+    assert_eq!(char_to_byte_offset(&rope, 1, 2), 6);
+    assert_eq!(byte_to_char_col(&rope, 1, 6), 2);
+}
+
+#[test]
+fn sort_lines_keeps_trailing_newline() {
+    let mut editor = editor("pear\napple\nfig\n");
+    editor.execute_command("sort");
+    assert_eq!(editor.buffer.text(), "apple\nfig\npear\n");
+    editor.execute_command("1,2sort!");
+    assert_eq!(editor.buffer.text(), "fig\napple\npear\n");
+}
+
+#[test]
+fn rendering_starts_at_scroll_offset() {
+    let mut editor = editor(&numbered_lines(100));
+    editor.highlighted_lines = editor.highlighter.highlight(editor.buffer.rope());
+    editor.scroll_offset = 50;
+    let text = build_highlighted_text(&editor);
+    assert_eq!(text.lines.len(), 20);
+    assert_eq!(rendered_row(&text, 0).trim_end(), "line 51");
+    assert_eq!(rendered_row(&text, 19).trim_end(), "line 70");
+}
+
+#[test]
+fn rendering_pads_rows_past_end_of_file() {
+    let mut editor = editor("only line\n");
+    editor.highlighted_lines = editor.highlighter.highlight(editor.buffer.rope());
+    let text = build_highlighted_text(&editor);
+    assert_eq!(text.lines.len(), 20);
+    assert_eq!(rendered_row(&text, 5), " ".repeat(80));
+}
+
+#[test]
+fn rendering_wide_line_takes_only_visible_segments() {
+    let line: String = (0..10000).map(|n| char::from(b'a' + (n % 26) as u8)).collect();
+    let mut editor = editor(&line);
+    editor.highlighted_lines =
+        vec![line.chars().map(|c| (Style::default(), None, c.to_string())).collect()];
+    editor.horizontal_offset = 5000;
+    let text = build_highlighted_text(&editor);
+    assert_eq!(rendered_row(&text, 0), line[5000..5080]);
+    assert_eq!(text.lines[0].0.len(), 80);
+}
+
+#[test]
+fn failed_forced_edit_keeps_buffer_modified() {
+    let mut editor = editor("text\n");
+    editor.mode = EditorMode::Insert;
+    editor.insert_char('!');
+    editor.mode = EditorMode::Normal;
+    editor.execute_command("e! /nonexistent/cim-test-file");
+    assert_eq!(editor.buffer.text(), "!text\n");
+    assert!(editor.buffer.is_modified());
+    assert!(editor.execute_command("q").is_none());
+}
+
+#[test]
+fn failed_forced_edit_of_alternate_keeps_buffer_modified() {
+    let mut editor = editor("text\n");
+    editor.mode = EditorMode::Insert;
+    editor.insert_char('!');
+    editor.execute_command("e! #");
+    assert!(editor.buffer.is_modified());
+}
+
+#[test]
+fn write_keeps_repeated_spaces_in_file_name() {
+    let path = temp_path("a  b.txt");
+    let mut editor = editor("text\n");
+    editor.execute_command(&format!("w {}", path.display()));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "text\n");
+    std::fs::remove_file(&path).unwrap();
+}
+
+
+/// Applies each step's edits to `text`, bringing the highlighting up to date
+/// incrementally after each step and checking it against highlighting the
+/// whole buffer again with a new `Highlighter`.
+fn check_incremental_highlight(text: &str, steps: &[&[(usize, usize, &str)]]) {
+    let path = Path::new("test.c");
+    let mut highlighter = Highlighter::new();
+    highlighter.set_syntax_for_file(Some(path));
+    let mut buffer = RopeTextBuffer::new(Rope::from(text));
+    let mut lines = highlighter.highlight(buffer.rope());
+    for (step, edits) in steps.iter().enumerate() {
+        for &(start, end, text) in edits.iter() {
+            buffer.edit(start..end, text);
+        }
+        let edits = buffer.take_edits();
+        highlighter.highlight_incremental(buffer.rope(), &edits, &mut lines);
+
+        let mut fresh = Highlighter::new();
+        fresh.set_syntax_for_file(Some(path));
+        let expected = fresh.highlight(buffer.rope());
+        assert_eq!(lines.len(), expected.len(), "line count after step {}", step);
+        for (line, (got, expected)) in lines.iter().zip(&expected).enumerate() {
+            assert_eq!(got, expected, "line {} after step {}: {:?}", line, step, buffer.text());
+        }
+    }
+}
+
+const C_SOURCE: &str = "int a = 1;\nint b = 2;\nint c = 3;\nint d = 4;\nint e = 5;\n";
+
+#[test]
+fn incremental_highlight_matches_full_when_comment_opens_and_closes() {
+    check_incremental_highlight(
+        C_SOURCE,
+        &[
+            // Open a comment on line 2 that runs to the end of the file.
+            &[(11, 11, "/* ")],
+            // Close it on line 4.
+            &[(46, 46, " */")],
+            // Remove the opening again, leaving a stray close.
+            &[(11, 14, "")],
+            // Remove the close too.
+            &[(43, 46, "")],
+        ],
+    );
+}
+
+#[test]
+fn incremental_highlight_matches_full_when_lines_join_and_split() {
+    check_incremental_highlight(
+        C_SOURCE,
+        &[
+            // Join lines 1 and 2, then split the line after them in two.
+            &[(10, 11, " ")],
+            &[(27, 27, "\n")],
+            // Open a comment at the end of line 1 and join the next line into it.
+            &[(21, 21, " /*"), (24, 25, "")],
+            // Split the comment opener, ending the comment.
+            &[(23, 23, "\n")],
+        ],
+    );
+}
+
+#[test]
+fn read_command_output_without_space_after_r() {
+    let mut editor = editor("first\nlast\n");
+    editor.execute_command("r!echo hi");
+    editor.execute_command("r !echo there");
+    assert_eq!(editor.buffer.text(), "first\nhi\nthere\nlast\n");
+}
+
+#[test]
+fn deleting_last_line_ending_in_multi_byte_char() {
+    for command in ["$d", "2d", "g/caf/d"] {
+        let mut editor = editor("abc\ncaf\u{e9}");
+        editor.execute_command(command);
+        assert_eq!(editor.buffer.text(), "abc", "{}", command);
+    }
+    let mut editor = editor("abc\ncaf\u{e9}");
+    editor.execute_command("%d");
+    assert_eq!(editor.buffer.text(), "");
+}
+
+#[test]
+fn deleting_last_line_after_multi_byte_char() {
+    let mut editor = editor("caf\u{e9}\nx");
+    editor.execute_command("$d");
+    assert_eq!(editor.buffer.text(), "caf\u{e9}");
+}
+
+#[test]
+fn dd_on_last_line_ending_in_multi_byte_char() {
+    let mut editor = editor("abc\ncaf\u{e9}");
+    editor.cursor_position = CursorPos::new(0, 1);
+    for c in "dd".chars() {
+        editor.handle_input(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+    }
+    assert_eq!(editor.buffer.text(), "abc");
+}
+
+#[test]
+fn move_to_just_above_itself_changes_nothing() {
+    let mut editor = editor("one\ntwo\nthree\nfour\nfive\n");
+    editor.execute_command("1m0");
+    assert_eq!(editor.buffer.text(), "one\ntwo\nthree\nfour\nfive\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+
+    editor.execute_command("3,4m2");
+    assert_eq!(editor.buffer.text(), "one\ntwo\nthree\nfour\nfive\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 3));
+
+    editor.execute_command("5m4");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 4));
+}
+
+#[test]
+fn move_lines_up_and_down() {
+    let mut editor = editor("one\ntwo\nthree\nfour\n");
+    editor.execute_command("3m0");
+    assert_eq!(editor.buffer.text(), "three\none\ntwo\nfour\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 0));
+    editor.execute_command("1,2m$");
+    assert_eq!(editor.buffer.text(), "two\nfour\nthree\none\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 3));
+}
+
+#[test]
+fn increment_keeps_minus_before_hex_and_binary() {
+    for (text, expected) in [
+        ("a-0x10\n", "a-0x11\n"),
+        ("x = -0b11\n", "x = -0b100\n"),
+        ("-0X1F\n", "-0X20\n"),
+        ("n = -0o7\n", "n = -0o10\n"),
+    ] {
+        let mut editor = editor(text);
+        editor.increment_number(1);
+        assert_eq!(editor.buffer.text(), expected, "{:?}", text);
+    }
+}
+
+#[test]
+fn increment_negative_decimal() {
+    let mut editor = editor("x = -5\n");
+    editor.increment_number(1);
+    assert_eq!(editor.buffer.text(), "x = -4\n");
+    editor.increment_number(-10);
+    assert_eq!(editor.buffer.text(), "x = -14\n");
+}
+
+#[test]
+fn transpose_swaps_characters_before_cursor() {
+    let mut editor = editor("abc\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.transpose_chars_at_cursor();
+    assert_eq!(editor.buffer.text(), "bac\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(2, 0));
+}
+
+#[test]
+fn transpose_keeps_emoji_modifier_with_its_base() {
+    let mut editor = editor("a\u{e9}\u{1f44d}\u{1f3fd}\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(4, 0);
+    editor.transpose_chars_at_cursor();
+    assert_eq!(editor.buffer.text(), "a\u{1f44d}\u{1f3fd}\u{e9}\n");
+    assert_eq!(editor.cursor_position, CursorPos::new(4, 0));
+}
+
+#[test]
+fn transpose_keeps_combining_accent_with_its_letter() {
+    let mut editor = editor("e\u{301}x\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(3, 0);
+    editor.transpose_chars_at_cursor();
+    assert_eq!(editor.buffer.text(), "xe\u{301}\n");
+}
+
+#[test]
+fn transpose_after_single_grapheme_does_nothing() {
+    let mut editor = editor("e\u{301}x\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(2, 0);
+    editor.transpose_chars_at_cursor();
+    assert!(!editor.buffer.is_modified());
+}
+
+#[test]
+fn transpose_at_line_start_swaps_across_lines() {
+    let mut editor = editor("ab\u{1f1e9}\u{1f1ea}\ne\u{301}f\n");
+    editor.mode = EditorMode::Insert;
+    editor.cursor_position = CursorPos::new(0, 1);
+    editor.transpose_chars_at_cursor();
+    assert_eq!(editor.buffer.text(), "abe\u{301}\n\u{1f1e9}\u{1f1ea}f\n");
+}
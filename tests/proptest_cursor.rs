@@ -0,0 +1,150 @@
+//! Cursor invariants checked after every action of many random action
+//! sequences. The sequences come from a fixed-seed generator, so a failure
+//! reproduces with the seed and step it reports.
+
+use cim::buffer::RopeTextBuffer;
+use cim::editor::{CimEditor, EditorAction, EditorMode, Operator};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::panic::{self, AssertUnwindSafe};
+use xi_rope::Rope;
+
+/// The buffers each sequence runs on. The second ends in a multi-byte
+/// character with no newline after it, where slicing a single byte at the end
+/// of the text would split that character.
+const TEXTS: [&str; 2] = [
+    "fn main() {\n\tlet x = 1;\n\n    println!(\"{}\", x);\n}\nlast line without newline",
+    "caf\u{e9}\r\n\tna\u{ef}ve \u{1f44d}\u{1f3fd}\n\nx\n  r\u{e9}sum\u{e9}",
+];
+
+const SEQUENCES: u64 = 300;
+const STEPS: usize = 60;
+
+/// A xorshift generator, enough to pick actions reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn signed(&mut self, max: i16) -> i16 {
+        self.below(2 * max as u64 + 1) as i16 - max
+    }
+}
+
+/// Any action that stays inside the buffer: no quitting, writing, suspending,
+/// switching files or typing on the command line.
+fn random_action(rng: &mut Rng) -> EditorAction {
+    let chars = ['a', 'Z', ' ', '\t', '\n', 'é', '{', '}'];
+    let char = chars[rng.below(chars.len() as u64) as usize];
+    match rng.below(33) {
+        0 => EditorAction::ChangeMode(rng.below(2) == 0),
+        1 => EditorAction::MoveCursor((rng.signed(3), 0)),
+        2 => EditorAction::MoveCursor((0, rng.signed(3))),
+        3 => EditorAction::MoveWord(rng.signed(2)),
+        4 => EditorAction::MoveBigWord(rng.signed(2)),
+        5 => EditorAction::MoveBigWordEnd(rng.signed(2)),
+        6 => EditorAction::LineStart,
+        7 => EditorAction::LineEnd,
+        8 => EditorAction::FirstNonBlank,
+        9 => EditorAction::FirstNonBlankLine(rng.signed(2)),
+        10 => EditorAction::GoToLine(rng.below(8) as usize),
+        11 => EditorAction::PageUp,
+        12 => EditorAction::PageDown,
+        13 => EditorAction::HalfPageUp,
+        14 => EditorAction::HalfPageDown,
+        15 => EditorAction::ScrollUp(rng.below(4) as usize),
+        16 => EditorAction::ScrollDown(rng.below(4) as usize),
+        17 => EditorAction::CursorScreenTop(0),
+        18 => EditorAction::CursorScreenMiddle,
+        19 => EditorAction::CursorScreenBottom(0),
+        20 => EditorAction::ParagraphBackward,
+        21 => EditorAction::ParagraphForward,
+        22 => EditorAction::InsertChar(char),
+        23 => EditorAction::DeleteBack,
+        24 => EditorAction::Tab,
+        25 => EditorAction::TransposeChars,
+        26 => EditorAction::Paste(rng.below(2) == 0),
+        27 => EditorAction::ToggleCase(1 + rng.below(3) as usize),
+        28 => EditorAction::ReplaceChar(char, 1),
+        29 => EditorAction::Increment(rng.signed(5) as i64),
+        30 => EditorAction::Operator(Operator::Delete),
+        31 => EditorAction::Operator(Operator::Yank),
+        _ => EditorAction::ScrollCursorCenter,
+    }
+}
+
+/// Normal mode line deletions, which only exist as key sequences and so go
+/// through `handle_input` rather than `handle_action`.
+const LINE_DELETIONS: [&str; 4] = ["dd", "2dd", "dj", "dG"];
+
+fn check_invariants(editor: &CimEditor, context: &str) {
+    let (x, y) = (editor.cursor_position.col as usize, editor.cursor_position.row as usize);
+    let line = editor.line_text(y);
+    let context = format!(
+        "{}, cursor {:?} in {:?} mode on {:?}, scrolled to {}",
+        context, editor.cursor_position, editor.mode, line, editor.scroll_offset
+    );
+    assert!(y < editor.total_lines(), "cursor below the last line: {}", context);
+
+    let length = line.chars().count();
+    if editor.mode == EditorMode::Insert {
+        assert!(x <= length, "cursor past the end of the line: {}", context);
+    } else {
+        assert!(x < length.max(1), "cursor past the last character: {}", context);
+    }
+
+    assert!(editor.scroll_offset <= y, "cursor above the view: {}", context);
+    assert!(
+        editor.scroll_offset + editor.viewport_height > y,
+        "cursor below the view: {}",
+        context
+    );
+    assert!(
+        editor.horizontal_offset + editor.viewport_width > x,
+        "cursor right of the view: {}",
+        context
+    );
+}
+
+#[test]
+fn cursor_stays_in_buffer_and_view() {
+    for (seed, text) in (1..=SEQUENCES).flat_map(|seed| TEXTS.map(|text| (seed, text))) {
+        let mut editor = CimEditor::new(None).unwrap();
+        editor.buffer = RopeTextBuffer::new(Rope::from(text));
+        editor.viewport_height = 4;
+        editor.viewport_width = 12;
+        let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        for step in 0..STEPS {
+            let keys = (editor.mode == EditorMode::Normal && rng.below(8) == 0)
+                .then(|| LINE_DELETIONS[rng.below(LINE_DELETIONS.len() as u64) as usize]);
+            let action = random_action(&mut rng);
+            let context = match keys {
+                Some(keys) => format!("seed {} step {} after {}", seed, step, keys),
+                None => format!("seed {} step {} after {}", seed, step, action),
+            };
+            // A panic inside the editor names the step that caused it too.
+            let run = panic::catch_unwind(AssertUnwindSafe(|| match keys {
+                Some(keys) => {
+                    for c in keys.chars() {
+                        editor.handle_input(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
+                    }
+                }
+                None => {
+                    editor.handle_action(action);
+                }
+            }));
+            if run.is_err() {
+                panic!("editor panicked: {}", context);
+            }
+            check_invariants(&editor, &context);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use cim::buffer::RopeTextBuffer;
+use cim::editor::CimEditor;
+use cim::quickfix::{ErrorFormat, QuickfixEntry};
+use cim::types::CursorPos;
+use std::path::PathBuf;
+use xi_rope::Rope;
+
+fn entry(file: &str, line: usize, column: usize, message: &str) -> QuickfixEntry {
+    QuickfixEntry {
+        file: PathBuf::from(file),
+        line,
+        column,
+        message: message.to_string(),
+    }
+}
+
+#[test]
+fn grep_line_parses_without_column() {
+    assert_eq!(
+        QuickfixEntry::parse("src/main.rs:12:fn main() {"),
+        Some(entry("src/main.rs", 12, 1, "fn main() {"))
+    );
+    assert_eq!(QuickfixEntry::parse("a.txt:3:x: y"), Some(entry("a.txt", 3, 1, "x: y")));
+}
+
+#[test]
+fn grep_line_needs_positive_line_number() {
+    assert_eq!(QuickfixEntry::parse("a.txt:0:text"), None);
+    assert_eq!(QuickfixEntry::parse("a.txt:abc:text"), None);
+    assert_eq!(QuickfixEntry::parse("no colons here"), None);
+}
+
+#[test]
+fn error_format_reads_file_line_column_and_message() {
+    let format = "%f:%l:%c: %m";
+    assert_eq!(
+        ErrorFormat::parse_line(format, "src/lib.rs:4:17: error: oops"),
+        Some(entry("src/lib.rs", 4, 17, "error: oops"))
+    );
+    assert_eq!(ErrorFormat::parse_line(format, "nothing to see"), None);
+}
+
+#[test]
+fn error_format_treats_column_zero_as_first_column() {
+    assert_eq!(
+        ErrorFormat::parse_line("%f:%l:%c: %m", "f:2:0: oops"),
+        Some(entry("f", 2, 1, "oops"))
+    );
+}
+
+#[test]
+fn error_format_tries_each_alternative() {
+    let format = "%f:%l:%c: %m,%f(%l): %m";
+    assert_eq!(
+        ErrorFormat::parse_line(format, "main.c(9): warning: unused"),
+        Some(entry("main.c", 9, 1, "warning: unused"))
+    );
+}
+
+#[test]
+fn error_format_rejects_formats_without_file_and_line() {
+    assert_eq!(ErrorFormat::parse_line("%l: %m", "3: oops"), None);
+    assert_eq!(ErrorFormat::parse_line("%f:%l:%x", "f:3:1"), None);
+    assert_eq!(
+        ErrorFormat::parse_line("%f:%l: 100%% %m", "f:3: 100% done"),
+        Some(entry("f", 3, 1, "done"))
+    );
+}
+
+#[test]
+fn jump_to_entry_with_column_one_goes_to_line_start() {
+    let path = std::env::temp_dir().join(format!("cim-quickfix-{}.txt", std::process::id()));
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from("one\ntwo\nthree\n"));
+    editor.viewport_height = 20;
+    editor.viewport_width = 80;
+    editor.file_path = Some(path.clone());
+    editor.quickfix_list = vec![entry(path.to_str().unwrap(), 2, 1, "oops")];
+    editor.jump_to_quickfix(0);
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 1));
+}
+
+#[test]
+fn make_with_zero_based_column_jumps_to_line_start() {
+    let path = std::env::temp_dir().join(format!("cim-make-{}.txt", std::process::id()));
+    let mut editor = CimEditor::new(None).unwrap();
+    editor.buffer = RopeTextBuffer::new(Rope::from("one\ntwo\nthree\n"));
+    editor.viewport_height = 20;
+    editor.viewport_width = 80;
+    editor.file_path = Some(path.clone());
+    editor.options.make_program = format!("echo {}:2:0: oops", path.display());
+    editor.options.error_format = "%f:%l:%c: %m".to_string();
+    editor.execute_command("make");
+    assert_eq!(editor.quickfix_list.len(), 1);
+    assert_eq!(editor.cursor_position, CursorPos::new(0, 1));
+}